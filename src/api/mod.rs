@@ -0,0 +1,416 @@
+use std::str::FromStr;
+
+use axum::{
+    Json, Router,
+    extract::{
+        Request, State,
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+    },
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use ratatui::style::Color;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::{
+    api::types::{PrewarmRequest, PromptRequest, SetColorRequest, SetViewRequest},
+    config::CONFIG,
+    events::{AppEvent, AppQuery, LogEventPayload},
+    services::elevenlabs::voices::VoiceChange,
+    types::{
+        diagnostics::Diagnostics,
+        logs::{Log, LogLevel},
+        message::Message,
+    },
+};
+
+pub mod types;
+
+#[derive(Clone)]
+struct ApiState {
+    event_sender: mpsc::Sender<AppEvent>,
+    observers: broadcast::Sender<String>,
+    /// Bearer token every request must carry, if any.
+    token: Option<String>,
+}
+
+/// Local HTTP + WebSocket API for monitoring and controlling the app remotely.
+///
+/// Every command is forwarded onto the event bus, so remote input goes through
+/// the same handling as the keyboard and microphone. With `api.token` set,
+/// requests without `Authorization: Bearer <token>` are rejected; `/events`
+/// also accepts it as `?token=` or a `Sec-WebSocket-Protocol` value.
+pub struct ApiServer;
+
+impl ApiServer {
+    pub async fn start(
+        event_sender: mpsc::Sender<AppEvent>,
+        observers: broadcast::Sender<String>,
+    ) -> Result<(), anyhow::Error> {
+        let router = router(ApiState {
+            event_sender: event_sender.clone(),
+            observers,
+            token: CONFIG.api.token.clone(),
+        });
+
+        let listener = tokio::net::TcpListener::bind(&CONFIG.api.bind).await?;
+
+        tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, router).await {
+                let payload = LogEventPayload {
+                    level: LogLevel::Error,
+                    message: format!("Control API stopped: {err}"),
+                };
+                let _ = event_sender.send(AppEvent::Log(payload)).await;
+            }
+        });
+
+        Ok(())
+    }
+}
+
+fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/prompt", post(prompt))
+        .route("/recording/start", post(start_recording))
+        .route("/recording/stop", post(stop_recording))
+        .route("/view", post(set_view))
+        .route("/color", post(set_color))
+        .route("/voice", post(set_voice))
+        .route("/tts/prewarm", post(prewarm_tts))
+        .route("/messages", get(get_messages))
+        .route("/logs", get(get_logs))
+        .route("/diagnostics", get(get_diagnostics))
+        .route("/events", get(events))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state)
+}
+
+async fn authorize(
+    State(state): State<ApiState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if let Some(token) = &state.token
+        && !presented_tokens(&request).any(|presented| tokens_match(presented, token))
+    {
+        let message = String::from("missing or wrong bearer token");
+        return Err(ApiError(StatusCode::UNAUTHORIZED, message));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Every token the request carries. Browsers can't set headers on a WebSocket,
+/// so `/events` also takes it as `?token=` or a `Sec-WebSocket-Protocol` value.
+fn presented_tokens(request: &Request) -> impl Iterator<Item = &str> {
+    let headers = request.headers();
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let events = request.uri().path() == "/events";
+    let query = request
+        .uri()
+        .query()
+        .filter(move |_| events)
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter_map(|pair| pair.strip_prefix("token="));
+    let protocols = headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter(move |_| events)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim);
+
+    bearer.into_iter().chain(query).chain(protocols)
+}
+
+/// Compares without returning early, so the time taken doesn't reveal how much
+/// of the token was right.
+fn tokens_match(presented: &str, token: &str) -> bool {
+    let (presented, token) = (presented.as_bytes(), token.as_bytes());
+    let difference = presented
+        .iter()
+        .zip(token)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+
+    presented.len() == token.len() && difference == 0
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        Self(StatusCode::INTERNAL_SERVER_ERROR, err.into().to_string())
+    }
+}
+
+async fn send(state: &ApiState, event: AppEvent) -> Result<StatusCode, ApiError> {
+    state.event_sender.send(event).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn prompt(
+    State(state): State<ApiState>,
+    Json(body): Json<PromptRequest>,
+) -> Result<StatusCode, ApiError> {
+    if body.text.trim().is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "text is empty".into()));
+    }
+
+    send(&state, AppEvent::TextPrompt(body.text)).await
+}
+
+async fn start_recording(State(state): State<ApiState>) -> Result<StatusCode, ApiError> {
+    send(&state, AppEvent::StartRecording).await
+}
+
+async fn stop_recording(State(state): State<ApiState>) -> Result<StatusCode, ApiError> {
+    send(&state, AppEvent::StopRecording).await
+}
+
+async fn set_view(
+    State(state): State<ApiState>,
+    Json(body): Json<SetViewRequest>,
+) -> Result<StatusCode, ApiError> {
+    send(&state, AppEvent::SetView(body.view)).await
+}
+
+async fn set_color(
+    State(state): State<ApiState>,
+    Json(body): Json<SetColorRequest>,
+) -> Result<StatusCode, ApiError> {
+    let Ok(color) = Color::from_str(&body.color) else {
+        let message = format!("unknown color `{}`", body.color);
+        return Err(ApiError(StatusCode::BAD_REQUEST, message));
+    };
+
+    send(&state, AppEvent::SetColor(color)).await
+}
+
+async fn set_voice(
+    State(state): State<ApiState>,
+    Json(body): Json<VoiceChange>,
+) -> Result<StatusCode, ApiError> {
+    send(&state, AppEvent::SetVoice(body)).await
+}
+
+async fn prewarm_tts(
+    State(state): State<ApiState>,
+    Json(body): Json<PrewarmRequest>,
+) -> Result<StatusCode, ApiError> {
+    send(&state, AppEvent::TTSPrewarm(body.phrases)).await
+}
+
+async fn get_messages(State(state): State<ApiState>) -> Result<Json<Vec<Message>>, ApiError> {
+    let (tx, rx) = oneshot::channel();
+    state
+        .event_sender
+        .send(AppEvent::Query(AppQuery::RecentMessages(tx)))
+        .await?;
+    Ok(Json(rx.await?))
+}
+
+async fn get_logs(State(state): State<ApiState>) -> Result<Json<Vec<Log>>, ApiError> {
+    let (tx, rx) = oneshot::channel();
+    state
+        .event_sender
+        .send(AppEvent::Query(AppQuery::RecentLogs(tx)))
+        .await?;
+    Ok(Json(rx.await?))
+}
+
+async fn get_diagnostics(State(state): State<ApiState>) -> Result<Json<Diagnostics>, ApiError> {
+    let (tx, rx) = oneshot::channel();
+    state
+        .event_sender
+        .send(AppEvent::Query(AppQuery::Diagnostics(tx)))
+        .await?;
+    Ok(Json(rx.await?))
+}
+
+async fn events(ws: WebSocketUpgrade, State(state): State<ApiState>) -> Response {
+    let receiver = state.observers.subscribe();
+    // Browsers drop the socket unless one of their offered protocols is echoed.
+    ws.protocols(state.token)
+        .on_upgrade(move |socket| stream_events(socket, receiver))
+}
+
+async fn stream_events(mut socket: WebSocket, mut receiver: broadcast::Receiver<String>) {
+    loop {
+        match receiver.recv().await {
+            Ok(json) => {
+                if socket.send(WsMessage::Text(json.into())).await.is_err() {
+                    break;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, to_bytes};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn test_router(token: Option<&str>) -> (Router, mpsc::Receiver<AppEvent>) {
+        let (event_sender, receiver) = mpsc::channel(10);
+        let router = router(ApiState {
+            event_sender,
+            observers: broadcast::channel(10).0,
+            token: token.map(String::from),
+        });
+        (router, receiver)
+    }
+
+    fn post_json(uri: &str, body: &'static str) -> Request {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn forwards_commands_onto_the_event_bus() {
+        let (router, mut receiver) = test_router(None);
+
+        let request = post_json("/prompt", r#"{"text": "Hello"}"#);
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(
+            matches!(receiver.recv().await, Some(AppEvent::TextPrompt(text)) if text == "Hello")
+        );
+
+        let request = post_json("/prompt", r#"{"text": "  "}"#);
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = post_json("/color", r#"{"color": "not a color"}"#);
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(text(response).await, "unknown color `not a color`");
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_queries_through_the_app() {
+        let (router, mut receiver) = test_router(None);
+
+        tokio::spawn(async move {
+            if let Some(AppEvent::Query(AppQuery::RecentLogs(reply))) = receiver.recv().await {
+                let _ = reply.send(vec![Log::new("Hello", LogLevel::Info)]);
+            }
+        });
+
+        let request = Request::get("/logs").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let logs: Vec<Log> = serde_json::from_str(&text(response).await).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].text, "Hello");
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_the_token() {
+        let (router, mut receiver) = test_router(Some("secret"));
+
+        for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+            let mut request = Request::post("/recording/start");
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+
+            let request = request.body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(receiver.try_recv().is_err());
+
+        // Only `/events` takes the token outside the header.
+        let request = Request::post("/recording/start?token=secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let request = Request::post("/recording/start")
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(matches!(
+            receiver.recv().await,
+            Some(AppEvent::StartRecording)
+        ));
+    }
+
+    #[tokio::test]
+    async fn streams_events_to_a_socket_with_the_query_token() {
+        use futures::StreamExt;
+        use tokio_tungstenite::{connect_async, tungstenite::client::IntoClientRequest};
+
+        let observers = broadcast::channel(10).0;
+        let router = router(ApiState {
+            event_sender: mpsc::channel(10).0,
+            observers: observers.clone(),
+            token: Some(String::from("secret")),
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        assert!(connect_async(format!("{url}/events")).await.is_err());
+        assert!(
+            connect_async(format!("{url}/events?token=wrong"))
+                .await
+                .is_err()
+        );
+
+        let mut request = format!("{url}/events").into_client_request().unwrap();
+        let protocol = "secret".parse().unwrap();
+        request
+            .headers_mut()
+            .insert(header::SEC_WEBSOCKET_PROTOCOL, protocol);
+        assert!(connect_async(request).await.is_ok());
+
+        let (mut socket, _) = connect_async(format!("{url}/events?token=secret"))
+            .await
+            .unwrap();
+        observers
+            .send(String::from(r#"{"type":"StartRecording"}"#))
+            .unwrap();
+        let message = socket.next().await.unwrap().unwrap();
+        assert_eq!(message.into_text().unwrap(), r#"{"type":"StartRecording"}"#);
+    }
+
+    #[test]
+    fn compares_tokens_exactly() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secre", "secret"));
+        assert!(!tokens_match("secret!", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+    }
+}
@@ -0,0 +1,24 @@
+use serde::Deserialize;
+
+use crate::state::View;
+
+#[derive(Debug, Deserialize)]
+pub struct PromptRequest {
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetViewRequest {
+    pub view: View,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetColorRequest {
+    /// A color name such as `red` or `lightblue`, or a hex value like `#ff8800`.
+    pub color: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrewarmRequest {
+    pub phrases: Vec<String>,
+}
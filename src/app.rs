@@ -1,30 +1,62 @@
-use std::{io::Stdout, mem::take, time::Duration};
+use std::{
+    io::Stdout,
+    mem::take,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind};
 use futures_util::StreamExt;
 use mongodb::bson::{DateTime, oid::ObjectId};
 use ratatui::{Terminal, prelude::CrosstermBackend};
+use tokio::signal::unix::{SignalKind, signal};
 use tui_input::backend::crossterm::EventHandler;
 
 use crate::{
-    audio::player::AudioPlayer,
-    camera::Camera,
+    api::ApiServer,
+    audio::{
+        archive::{AudioArchive, ReplyAudio},
+        devices::{AudioDevices, AudioDirection, device_names, reconnect_delay},
+        echo::EchoReference,
+        player::AudioPlayer,
+    },
+    camera::{
+        CapturePolicy,
+        presence::{self, in_quiet_hours, parse_quiet_hours},
+        preview_image,
+    },
+    config::CONFIG,
+    environment::Environment,
     events::EventBus,
-    features::Features,
     memory::MemoryManager,
-    services::anthropic::types::{AnthropicContentBlockDelta, AnthropicMessageStreamEvent},
-    state::View,
+    replay::{EventRecorder, EventReplayer},
+    state::{ConversationState, ListeningOrigin, ListeningSession, View},
     text_processor::TextProcessor,
-    tools::tools::ToolType,
+    tools::{Tool, tools::ToolType, update::UpdateTool},
     types::{
+        diagnostics::Diagnostics,
         logs::{Log, LogLevel},
         message::{ContentBlock, ImageSource, MediaType, Message, Role},
+        spoken::SpokenReply,
+    },
+    voice_commands::{VoiceCommand, VoiceCommandMatcher},
+    widgets::{
+        app_layout::AppLayout,
+        views::{camera::decode_image, chat::ChatViewMode},
     },
-    widgets::{app_layout::AppLayout, views::chat::ChatViewMode},
 };
-use crate::{audio::recorder::AudioRecorder, events::AppEvent};
 use crate::{
-    services::{anthropic::AnthropicService, elevenlabs::ElevenLabsService},
+    audio::recorder::AudioRecorder,
+    events::{AppEvent, AppQuery},
+};
+use crate::{
+    services::{
+        anthropic::AnthropicService,
+        elevenlabs::{
+            ElevenLabsService,
+            voices::{SpeakingVoice, VoiceChange, built_in_voices},
+        },
+    },
     state::AppState,
 };
 
@@ -34,25 +66,79 @@ pub struct App {
     elevenlabs: ElevenLabsService,
     audio_recorder: AudioRecorder,
     audio_player: AudioPlayer,
-    camera: Camera,
     text_processor: TextProcessor,
-    terminal: Terminal<CrosstermBackend<Stdout>>,
+    voice_commands: VoiceCommandMatcher,
+    /// `None` when running headless, in which case nothing is rendered and
+    /// logs are written to stdout instead.
+    terminal: Option<Terminal<CrosstermBackend<Stdout>>>,
     state: AppState,
     memory: MemoryManager,
+    /// Set when replaying a recorded session instead of listening live.
+    replay: Option<EventReplayer>,
+    audio_archive: AudioArchive,
+    /// TTS audio of the reply being spoken, when it's archived
+    reply_audio: Option<ReplyAudio>,
 }
 
 const FRAMES_PER_SECOND: f32 = 60.0;
 
+/// Input right after a listening session opens is mostly the chime, so it doesn't count as speech.
+const CHIME_GRACE: Duration = Duration::from_millis(300);
+
+/// How much "louder" and "quieter" change the output volume.
+const VOLUME_STEP: f32 = 0.25;
+
+/// How long after "update yourself" the update can be confirmed.
+const UPDATE_CONFIRM_WINDOW: Duration = Duration::from_secs(30);
+
+/// Spoken when an update is asked for by voice.
+const UPDATE_CONFIRMATION: &str = "Say 'yes, update' to continue.";
+
+/// Sent to the LLM, with a snapshot, when someone comes into view.
+const ARRIVAL_PROMPT: &str =
+    "[Someone just appeared in front of your camera. Greet them briefly if it feels natural.]";
+
 impl App {
-    pub async fn new(terminal: Terminal<CrosstermBackend<Stdout>>) -> Result<Self, anyhow::Error> {
-        let event_bus = EventBus::new();
-        let anthropic = AnthropicService::new(event_bus.sender());
-        let elevenlabs = ElevenLabsService::new(event_bus.sender());
-        let audio_recorder = AudioRecorder::new(event_bus.sender());
-        let audio_player = AudioPlayer::new(event_bus.sender());
-        let camera = Camera::new();
+    pub async fn new(
+        terminal: Option<Terminal<CrosstermBackend<Stdout>>>,
+        replay: Option<EventReplayer>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut event_bus = EventBus::new();
+        let echo_reference = CONFIG
+            .echo_cancellation
+            .enabled
+            .then(|| EchoReference::new(CONFIG.audio.recording_sample_rate));
+        let audio_recorder = AudioRecorder::new(event_bus.sender(), echo_reference.clone());
+        let audio_player = AudioPlayer::new(event_bus.sender(), echo_reference);
         let text_processor = TextProcessor::new(event_bus.sender());
-        let memory = MemoryManager::new(event_bus.sender()).await?;
+        let voice_commands = VoiceCommandMatcher::from_config()?;
+
+        let (anthropic, elevenlabs, memory) = if replay.is_some() {
+            (
+                AnthropicService::stubbed(event_bus.sender()),
+                ElevenLabsService::stubbed(event_bus.sender()),
+                MemoryManager::stubbed(),
+            )
+        } else {
+            (
+                AnthropicService::new(event_bus.sender()),
+                ElevenLabsService::new(event_bus.sender()),
+                MemoryManager::new(event_bus.sender()).await?,
+            )
+        };
+
+        if CONFIG.recording.enabled && replay.is_none() {
+            let recorder = EventRecorder::create(Path::new(&CONFIG.recording.dir))?;
+            event_bus.set_recorder(recorder);
+        }
+
+        let state = AppState {
+            voice: SpeakingVoice::from_config(),
+            voices: built_in_voices(),
+            diagnostics: Diagnostics::new(),
+            conversation: ConversationState::new(CONFIG.conversation.enabled),
+            ..AppState::default()
+        };
 
         Ok(Self {
             terminal,
@@ -61,10 +147,13 @@ impl App {
             elevenlabs,
             audio_recorder,
             audio_player,
-            camera,
             text_processor,
-            state: AppState::default(),
+            voice_commands,
+            state,
             memory,
+            replay,
+            audio_archive: AudioArchive::new(&CONFIG.audio_archive),
+            reply_audio: None,
         })
     }
 
@@ -76,38 +165,139 @@ impl App {
 
         self.state.is_app_running = true;
 
-        tokio::try_join!(self.audio_player.start(), self.audio_recorder.start(),)?;
+        // A missing device shouldn't keep the app from starting, it's picked
+        // up once it's plugged in
+        let mut directions = vec![AudioDirection::Output];
+        if self.replay.is_none() {
+            directions.push(AudioDirection::Input);
+        }
 
-        let messages = self.memory.mongodb.messages.get_recent_messages().await?;
-        self.state.messages = messages;
+        for direction in directions {
+            if let Err(err) = self.connect_audio_device(direction).await {
+                self.log_error(&format!("Failed to open audio {direction} device: {err}"))
+                    .await?;
+                self.schedule_audio_reconnect(direction, 0);
+            }
+        }
 
-        let message_count = self.state.get_assistant_message_count();
-        self.state.home_view.message_index = if message_count > 0 {
-            message_count - 1
-        } else {
-            0
-        };
+        if self.replay.is_none() {
+            self.state.camera.start(self.event_bus.sender());
+
+            if CONFIG.presence.enabled {
+                presence::spawn(self.state.camera.clone(), self.event_bus.sender());
+            }
+        }
 
-        let logs = self.memory.mongodb.logs.get_recent_logs().await?;
-        self.state.logs_view.logs = logs;
+        match self.memory.get_recent_messages().await {
+            // Newest first, the history reads the other way
+            Ok(messages) => self.state.messages = messages.into_iter().rev().collect(),
+            Err(e) => {
+                self.log_error(&format!("Failed to load messages: {e}"))
+                    .await?
+            }
+        }
+
+        self.state.home_view.message_index = self.state.get_turn_count().saturating_sub(1);
+
+        match self.memory.get_recent_logs().await {
+            Ok(logs) => self.state.logs_view.logs = logs,
+            Err(e) => self.log_error(&format!("Failed to load logs: {e}")).await?,
+        }
+
+        self.prune_audio_archive().await?;
+        self.elevenlabs
+            .prewarm_cache(&self.state.voice, CONFIG.tts_cache.prewarm.clone());
+        self.elevenlabs.list_voices();
+
+        if CONFIG.api.enabled {
+            let bind = &CONFIG.api.bind;
+            match ApiServer::start(self.event_bus.sender(), self.event_bus.observers()).await {
+                Ok(()) => {
+                    self.log_info(&format!("Control API listening on {bind}"))
+                        .await?
+                }
+                Err(err) => {
+                    self.log_error(&format!("Failed to start control API on {bind}: {err}"))
+                        .await?
+                }
+            }
+        }
 
-        self.log_info("App started").await?;
+        let profile = CONFIG.environment.profile_name();
+        self.log_info(&format!("App started ({profile} profile)"))
+            .await?;
+
+        if let Some(session_dir) = self.event_bus.recording_dir() {
+            let session_dir = session_dir.display().to_string();
+            self.log_info(&format!("Recording events to {session_dir}"))
+                .await?;
+        }
+
+        if let Some(replay) = &self.replay {
+            let session_dir = replay.session_dir().display().to_string();
+            self.log_info(&format!("Replaying session {session_dir}"))
+                .await?;
+        }
 
         let period = Duration::from_secs_f32(1.0 / FRAMES_PER_SECOND);
         let mut interval = tokio::time::interval(period);
-        let mut events = EventStream::new();
+        let mut events = self.terminal.is_some().then(EventStream::new);
+
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sigint = signal(SignalKind::interrupt())?;
 
         while self.state.is_app_running {
             tokio::select! {
-                _ = interval.tick() => self.render()?,
-                Some(event) = self.event_bus.recv() => self.handle_app_event(event).await?,
-                Some(Ok(event)) = events.next() => self.handle_terminal_event(&event).await?,
+                _ = interval.tick(), if self.terminal.is_some() => self.render()?,
+                Some(Some(event)) = next_bus_event(&mut self.event_bus, self.replay.is_some()) => {
+                    self.handle_app_event(event).await?
+                }
+                event = next_replay_event(&mut self.replay) => self.handle_replay_event(event).await?,
+                Some(Ok(event)) = next_terminal_event(&mut events) => self.handle_terminal_event(&event).await?,
+                _ = sigterm.recv() => self.shutdown("SIGTERM").await?,
+                _ = sigint.recv() => self.shutdown("SIGINT").await?,
             }
         }
 
         Ok(())
     }
 
+    async fn handle_replay_event(
+        &mut self,
+        event: Option<Result<AppEvent, anyhow::Error>>,
+    ) -> Result<(), anyhow::Error> {
+        match event {
+            Some(Ok(event)) => {
+                // Background tasks may have filled the bus while waiting for this event
+                self.event_bus.drain();
+                self.event_bus.publish(&event);
+                self.handle_app_event(event).await?;
+                self.event_bus.drain();
+            }
+            Some(Err(err)) => {
+                self.log_error(&format!("Failed to replay event: {err}"))
+                    .await?;
+            }
+            None => {
+                self.replay = None;
+                self.log_info("Replay finished").await?;
+
+                if self.terminal.is_none() {
+                    self.quit().await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn shutdown(&mut self, signal_name: &str) -> Result<(), anyhow::Error> {
+        self.log_info(&format!("Received {signal_name}, shutting down"))
+            .await?;
+        self.quit().await?;
+        Ok(())
+    }
+
     async fn handle_app_event(&mut self, event: AppEvent) -> Result<(), anyhow::Error> {
         match event {
             // audio events
@@ -122,11 +312,35 @@ impl App {
                 self.state.is_audio_recording_running = false;
                 self.state.input_volume = 0.0;
 
-                self.elevenlabs.transcribe(audio_bytes);
+                let session = self.state.listening.take();
+
+                if take(&mut self.state.wake_word.enrolling) {
+                    self.save_wake_word_template(audio_bytes).await?;
+                } else if let Some(session) = session
+                    && !session.heard_speech
+                {
+                    match session.origin {
+                        ListeningOrigin::WakeWord => {
+                            self.record_wake_word_false_trigger("no speech followed")
+                                .await?
+                        }
+                        ListeningOrigin::Conversation => self.end_conversation("no reply").await?,
+                    }
+                } else {
+                    self.state.transcript_origin = session.map(|session| session.origin);
+                    self.state.transcript_audio_id = if CONFIG.audio_archive.recordings {
+                        self.archive_audio(ObjectId::new().to_hex(), &audio_bytes)
+                            .await?
+                    } else {
+                        None
+                    };
+                    self.elevenlabs.transcribe(audio_bytes);
 
-                if Features::video_capture_enabled() {
-                    if let Ok(Some(img)) = self.camera.capture() {
-                        self.state.img_base64 = Some(img);
+                    if CONFIG.camera.capture == CapturePolicy::EveryTurn {
+                        match self.state.camera.latest_frame() {
+                            Ok(img) => self.state.img_base64 = img,
+                            Err(e) => self.log_error(&format!("Camera frame failed: {e}")).await?,
+                        }
                     }
                 }
             }
@@ -142,25 +356,108 @@ impl App {
                 self.state.error = Some(error.to_string());
                 self.state.input_volume = 0.0;
                 self.state.is_audio_recording_running = false;
+                self.state.listening = None;
+                self.end_conversation("recording failed").await?;
             }
             AppEvent::AudioDetected(volume) => {
                 self.state.input_volume = volume;
                 self.state.audio_detected = true;
+                self.update_listening_session(volume);
             }
             AppEvent::AudioNotDetected => {
                 self.state.audio_detected = false;
             }
+            AppEvent::EchoStats(stats) => {
+                self.state.diagnostics.echo.last = Some(stats);
+            }
             AppEvent::AudioPlaybackError(error) => {
                 self.log_error(&format!("Audio playback error: {error}"))
                     .await?;
                 self.state.error = Some(error.to_string());
+                self.check_audio_device(AudioDirection::Output).await?;
+            }
+            AppEvent::AudioInputStreamError(error) => {
+                self.log_error(&format!("Audio input error: {error}"))
+                    .await?;
+                self.check_audio_device(AudioDirection::Input).await?;
+            }
+            AppEvent::PlaybackStarted => {
+                self.state.is_audio_playback_running = true;
+                self.state.playback_played_ms = 0;
+            }
+            AppEvent::PlaybackProgress(payload) => {
+                self.state.playback_played_ms = payload.played_ms;
+            }
+            AppEvent::PlaybackDrained => {
+                self.state.is_audio_playback_running = false;
+
+                if !self.state.is_tts_running {
+                    self.log_info("Finished speaking").await?;
+                    self.listen_for_reply().await?;
+                }
             }
             AppEvent::AudioSetInputDevice(device_name) => {
+                if let Some(name) = &device_name {
+                    self.log_info(&format!("Audio input device: {name}"))
+                        .await?;
+                }
                 self.state.audio_input_device = device_name;
             }
             AppEvent::AudioSetOutputDevice(device_name) => {
+                if let Some(name) = &device_name {
+                    self.log_info(&format!("Audio output device: {name}"))
+                        .await?;
+                }
                 self.state.audio_output_device = device_name;
             }
+            AppEvent::AudioDevicesListed(devices) => {
+                let settings = &mut self.state.settings_view;
+                settings.devices = devices;
+                settings.selected = settings
+                    .selected
+                    .min(settings.item_count(self.state.voices.len()) - 1);
+            }
+            AppEvent::AudioReconnect { direction, attempt } => {
+                // Already back, e.g. after picking another device in settings
+                if self.replay.is_some() || self.is_audio_device_running(direction) {
+                    return Ok(());
+                }
+
+                match self.connect_audio_device(direction).await {
+                    Ok(()) => {
+                        self.log_info(&format!("Audio {direction} device reconnected"))
+                            .await?;
+                    }
+                    Err(_) => self.schedule_audio_reconnect(direction, attempt + 1),
+                }
+            }
+            AppEvent::WakeWordDetected(score) => {
+                self.state.diagnostics.wake_word.triggers += 1;
+                self.state.diagnostics.wake_word.last_score = Some(score);
+                self.log_info(&format!("Wake word detected (score {score:.2})"))
+                    .await?;
+
+                if self.replay.is_none() && !self.audio_recorder.is_recording() {
+                    self.start_listening(ListeningOrigin::WakeWord);
+                }
+            }
+            AppEvent::EndConversation => {
+                if self.state.conversation.active {
+                    self.state.conversation.ending = true;
+                }
+            }
+
+            AppEvent::PersonArrived(region) => {
+                self.state.presence.present = true;
+                self.state.presence.region = Some(region);
+                self.log_info("Person arrived").await?;
+                self.greet_arrival().await?;
+            }
+            AppEvent::PersonLeft => {
+                self.state.presence.present = false;
+                self.state.presence.region = None;
+                self.log_info("Person left").await?;
+            }
 
             // transcription events
             AppEvent::TranscriptionStarted => {
@@ -170,30 +467,33 @@ impl App {
             AppEvent::TranscriptionCompleted(text) => {
                 self.state.is_audio_transcription_running = false;
 
-                let mut message_content = vec![ContentBlock::Text { text: text.clone() }];
-
-                if let Some(img) = take(&mut self.state.img_base64) {
-                    message_content.push(ContentBlock::Image {
-                        source: ImageSource {
-                            image_type: String::from("base64"),
-                            media_type: MediaType::JPEG,
-                            data: img,
-                        },
-                    })
-                };
+                let origin = self.state.transcript_origin.take();
+                let audio_id = self.state.transcript_audio_id.take();
 
-                let message = Message {
-                    _id: ObjectId::new(),
-                    role: Role::User,
-                    content: message_content,
-                    created_at: DateTime::now(),
-                };
-
-                self.anthropic
-                    .prompt(&message, &self.state.messages, &self.state);
-                self.state.current_exchange.push(message);
+                if text.trim().is_empty() && origin == Some(ListeningOrigin::WakeWord) {
+                    self.record_wake_word_false_trigger("empty transcript")
+                        .await?;
+                } else if text.trim().is_empty() && origin == Some(ListeningOrigin::Conversation) {
+                    // Probably noise, keep listening until the idle timeout ends it
+                    self.state.conversation.reply_finished = true;
+                    self.listen_for_reply().await?;
+                } else if let Some(command) = self.voice_commands.match_command(&text) {
+                    let text = text.trim();
+                    self.log_info(&format!("Voice command `{command}` (\"{text}\")"))
+                        .await?;
+                    self.run_voice_command(command).await?;
 
-                self.log_info("Transcription complete").await?;
+                    // The command is the whole reply, keep the conversation going
+                    if self.state.conversation.active {
+                        self.state.conversation.user_turn();
+                        self.state.conversation.reply_finished = true;
+                        self.listen_for_reply().await?;
+                    }
+                } else {
+                    self.state.conversation.user_turn();
+                    self.prompt_user_message(text, audio_id);
+                    self.log_info("Transcription complete").await?;
+                }
             }
             AppEvent::TranscriptionFailed(error) => {
                 self.state.error = Some(error.to_string());
@@ -205,6 +505,7 @@ impl App {
             // llm events
             AppEvent::LLMGenerationStarted(payload) => {
                 self.state.is_llm_message_running = true;
+                self.state.is_speech_muted = false;
 
                 self.log_info("LLM message started").await?;
 
@@ -213,90 +514,28 @@ impl App {
                     role: Role::Assistant,
                     content: vec![],
                     created_at: DateTime::now(),
+                    audio_id: None,
+                    context: false,
                 };
 
-                self.state.messages.push(message);
-                self.elevenlabs.start_stream().await?;
+                self.add_to_exchange(message);
+                self.state.spoken_reply = SpokenReply::new(payload.message_id);
+                self.elevenlabs.start_stream(&self.state.voice).await?;
 
-                let assistant_message_count = self
-                    .state
-                    .messages
-                    .iter()
-                    .filter(|message| message.role == Role::Assistant)
-                    .count();
-                self.state.home_view.message_index = assistant_message_count - 1;
-            }
-            AppEvent::LLMStreamEvent(payload) => match payload.event {
-                AnthropicMessageStreamEvent::ContentBlockStart {
-                    index,
-                    content_block,
-                } => {
-                    if let ContentBlock::ToolUse { .. } = &content_block {
-                        let key = (payload.message_id.clone(), index);
-                        self.state.tool_input_buffers.insert(key, String::new());
-                    }
+                self.reply_audio = (self.is_archiving_audio() && CONFIG.audio_archive.tts)
+                    .then(|| ReplyAudio::new(payload.message_id));
 
-                    if let Some(message) = self.state.get_message_mut(&payload.message_id) {
-                        message.content.insert(index, content_block);
-                    }
-                }
-
-                AnthropicMessageStreamEvent::ContentBlockDelta { index, delta } => {
-                    if let Some(message) = self.state.get_message_mut(&payload.message_id) {
-                        if let Some(block) = message.content.get_mut(index) {
-                            match delta {
-                                AnthropicContentBlockDelta::Text { text } => {
-                                    if let ContentBlock::Text { text: block_text } = block {
-                                        block_text.push_str(&text);
-                                    }
-
-                                    self.text_processor.process_delta(&text).await?;
-                                }
-                                AnthropicContentBlockDelta::Thinking { text } => {
-                                    if let ContentBlock::Thinking {
-                                        content: block_content,
-                                    } = block
-                                    {
-                                        block_content.push_str(&text);
-                                    }
-                                }
-                                AnthropicContentBlockDelta::InputJson { partial_json } => {
-                                    let key = (payload.message_id.clone(), index);
-                                    let input_buffer = self.state.tool_input_buffers.get_mut(&key);
-
-                                    if let Some(input_buffer) = input_buffer {
-                                        input_buffer.push_str(&partial_json);
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                }
+                self.state.home_view.message_index = self.state.get_turn_count() - 1;
+            }
+            AppEvent::LLMStreamEvent(payload) => {
+                let spoken_text = self
+                    .state
+                    .apply_stream_event(&payload.message_id, payload.event);
 
-                AnthropicMessageStreamEvent::ContentBlockStop { index } => {
-                    let key = (payload.message_id.clone(), index);
-
-                    if let Some(buffer) = self.state.tool_input_buffers.remove(&key) {
-                        if let Some(message) = self.state.get_message_mut(&payload.message_id) {
-                            if let Some(ContentBlock::ToolUse { input, .. }) =
-                                message.content.get_mut(index)
-                            {
-                                match serde_json::from_str::<serde_json::Value>(&buffer) {
-                                    Ok(json_value) => {
-                                        *input = json_value;
-                                    }
-                                    Err(_) => {
-                                        *input = serde_json::Value::String(buffer);
-                                    }
-                                }
-                            }
-                        }
-                    }
+                if let Some(text) = spoken_text {
+                    self.text_processor.process_delta(&text).await?;
                 }
-
-                _ => {}
-            },
+            }
             AppEvent::LLMGenerationCompleted(payload) => {
                 self.state.is_llm_message_running = false;
 
@@ -335,15 +574,18 @@ impl App {
                         role: Role::User,
                         content: tool_result_blocks,
                         created_at: DateTime::now(),
+                        audio_id: None,
+                        context: false,
                     };
 
                     self.anthropic
                         .prompt(&message, &self.state.messages, &self.state);
+                    self.add_to_exchange(message);
                 } else {
-                    self.memory
-                        .process_exchange(&self.state.current_exchange)
-                        .await?;
-                    self.state.current_exchange.clear();
+                    self.finish_exchange();
+
+                    self.state.conversation.reply_finished = true;
+                    self.listen_for_reply().await?;
                 }
             }
             AppEvent::LLMGenerationFailed(error) => {
@@ -352,6 +594,7 @@ impl App {
                 self.state.error = Some(error.to_string());
                 self.state.is_llm_message_running = false;
                 self.state.current_exchange.clear();
+                self.end_conversation("LLM request failed").await?;
             }
 
             AppEvent::LLMGenerationError(error) => {
@@ -370,13 +613,56 @@ impl App {
             }
 
             // tts events
-            AppEvent::TTSChunk(audio_bytes) => {
-                if let Err(error) = self.audio_player.push_audio_chunk(&audio_bytes) {
+            AppEvent::TTSChunk(_) if self.state.is_speech_muted => {}
+            AppEvent::TTSChunk(payload) => {
+                if let Some(reply_audio) = &mut self.reply_audio {
+                    reply_audio.push(&payload.audio, payload.format);
+                }
+
+                // The player counts from zero again once it ran out of audio
+                let restarts_playback =
+                    !self.state.is_audio_playback_running && !self.audio_player.has_queued_audio();
+                if restarts_playback {
+                    self.state.playback_played_ms = 0;
+                }
+                self.state.spoken_reply.push_chunk(
+                    &payload.words,
+                    payload.format.duration_ms(payload.audio.len()),
+                    restarts_playback,
+                );
+
+                if let Err(error) = self
+                    .audio_player
+                    .push_audio_chunk(&payload.audio, payload.format)
+                {
                     self.log_error(&format!("TTS chunk failed: {error}"))
                         .await?;
                     self.state.error = Some(error.to_string());
                 }
             }
+            AppEvent::TTSStreamOpened => {
+                self.state.is_tts_running = true;
+            }
+            AppEvent::TTSStreamFinished => {
+                self.log_info("TTS stream finished").await?;
+                self.state.is_tts_running = false;
+                self.audio_player.finish_stream();
+
+                if let Some(reply_audio) = self.reply_audio.take() {
+                    self.archive_reply_audio(reply_audio).await?;
+                }
+
+                self.listen_for_reply().await?;
+            }
+            AppEvent::TTSPrewarm(phrases) => {
+                self.elevenlabs.prewarm_cache(&self.state.voice, phrases);
+            }
+            AppEvent::VoicesListed(voices) => {
+                // Keep the built-in voices rather than leave nothing to pick
+                if !voices.is_empty() {
+                    self.state.voices = voices;
+                }
+            }
             AppEvent::TTSError(error) => {
                 self.log_error(&format!("TTS error: {error}")).await?;
                 self.state.error = Some(error.to_string());
@@ -386,6 +672,7 @@ impl App {
                 self.log_error(&format!("TTS failed: {error}")).await?;
                 self.state.error = Some(error.to_string());
                 self.state.is_tts_running = false;
+                self.listen_for_reply().await?;
             }
 
             // log events
@@ -394,7 +681,7 @@ impl App {
             }
 
             AppEvent::SetView(view) => {
-                self.state.view = view.to_owned();
+                self.set_view(view);
             }
             AppEvent::ClearLogs => {
                 self.state.logs_view.logs.clear();
@@ -404,16 +691,46 @@ impl App {
             }
             AppEvent::SetColor(color) => {
                 self.state.color = color;
-
-                if let Err(err) = self.state.persist_state().await {
-                    self.log_error(&format!("Failed to persist state: {err}"))
-                        .await?;
-                }
+                self.persist_state().await?;
+            }
+            AppEvent::SetVoice(change) => {
+                self.set_voice(&change).await?;
             }
 
             AppEvent::EmbeddingSaved(_text) => {
                 // pass
             }
+            AppEvent::StorageStatus(status) => {
+                self.state.diagnostics.storage = status;
+            }
+
+            // control events
+            AppEvent::TextPrompt(text) => {
+                self.log_info("Text prompt received").await?;
+                self.prompt_user_message(text, None);
+            }
+            AppEvent::StartRecording => {
+                self.audio_recorder.start_recording();
+            }
+            AppEvent::StopRecording => {
+                self.audio_recorder.stop_recording();
+            }
+            AppEvent::Query(query) => match query {
+                AppQuery::RecentMessages(reply) => {
+                    let messages = self
+                        .state
+                        .messages
+                        .iter()
+                        .filter(|message| !message.context);
+                    let _ = reply.send(messages.cloned().collect());
+                }
+                AppQuery::RecentLogs(reply) => {
+                    let _ = reply.send(self.state.logs_view.logs.clone());
+                }
+                AppQuery::Diagnostics(reply) => {
+                    let _ = reply.send(self.state.diagnostics.clone());
+                }
+            },
         }
 
         Ok(())
@@ -428,27 +745,64 @@ impl App {
             Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
                 match key_event.code {
                     // general
-                    KeyCode::Esc => self.cancel(),
-                    KeyCode::Char('q') => self.quit(),
+                    KeyCode::Esc => self.cancel().await?,
+                    KeyCode::Char('q') => self.quit().await?,
 
                     // audio
                     KeyCode::Char(' ') => self.toggle_recording(),
+                    KeyCode::Char('w') => self.toggle_wake_word_enrollment(),
                     KeyCode::Char('i') => {
                         if self.state.chat_view.mode == ChatViewMode::Normal {
                             self.state.chat_view.mode = ChatViewMode::Insert;
                         }
                     }
 
+                    // settings
+                    KeyCode::Up if self.state.view == View::Settings => {
+                        let settings = &mut self.state.settings_view;
+                        settings.selected = settings.selected.saturating_sub(1);
+                    }
+                    KeyCode::Down if self.state.view == View::Settings => {
+                        let settings = &mut self.state.settings_view;
+                        if settings.selected + 1 < settings.item_count(self.state.voices.len()) {
+                            settings.selected += 1;
+                        }
+                    }
+                    KeyCode::Enter if self.state.view == View::Settings => {
+                        self.use_selected_setting().await?
+                    }
+                    KeyCode::Left if self.state.view == View::Settings => {
+                        self.adjust_selected_voice_setting(-1).await?
+                    }
+                    KeyCode::Right if self.state.view == View::Settings => {
+                        self.adjust_selected_voice_setting(1).await?
+                    }
+                    KeyCode::Char('r') if self.state.view == View::Settings => {
+                        self.list_audio_devices();
+                        self.elevenlabs.list_voices();
+                    }
+
+                    // camera
+                    KeyCode::Char('b') if self.state.view == View::Camera => {
+                        let camera_view = &mut self.state.camera_view;
+                        camera_view.art = camera_view.art.toggle();
+                    }
+
                     // navigation
-                    KeyCode::Char('1') => self.state.view = View::Home,
-                    KeyCode::Char('2') => self.state.view = View::Logs,
-                    KeyCode::Char('3') => self.state.view = View::Chat,
+                    KeyCode::Char('1') => self.set_view(View::Home),
+                    KeyCode::Char('2') => self.set_view(View::Logs),
+                    KeyCode::Char('3') => self.set_view(View::Chat),
+                    KeyCode::Char('4') => self.set_view(View::Settings),
+                    KeyCode::Char('5') => self.set_view(View::Camera),
                     KeyCode::Tab => self.tab_view_forward(),
                     KeyCode::BackTab => self.tab_view_backward(),
 
                     // messages
                     KeyCode::Right => self.next_message(),
                     KeyCode::Left => self.previous_message(),
+                    KeyCode::Char('p') if self.state.view == View::Home => {
+                        self.play_selected_message_audio().await?
+                    }
                     _ => {}
                 }
             }
@@ -458,24 +812,76 @@ impl App {
         Ok(())
     }
 
+    /// Sends text from the user to the LLM, attaching the latest camera frame if there is one.
+    fn prompt_user_message(&mut self, text: String, audio_id: Option<String>) {
+        let message = self.user_message(text, audio_id);
+
+        self.anthropic
+            .prompt(&message, &self.state.messages, &self.state);
+        self.add_to_exchange(message);
+    }
+
+    /// A user message with the latest camera frame if there is one.
+    fn user_message(&mut self, text: String, audio_id: Option<String>) -> Message {
+        let mut message_content = vec![ContentBlock::Text { text }];
+
+        if let Some(img) = take(&mut self.state.img_base64) {
+            message_content.push(ContentBlock::Image {
+                source: ImageSource {
+                    image_type: String::from("base64"),
+                    media_type: MediaType::JPEG,
+                    data: img,
+                },
+            })
+        };
+
+        Message {
+            _id: ObjectId::new(),
+            role: Role::User,
+            content: message_content,
+            created_at: DateTime::now(),
+            audio_id,
+            context: false,
+        }
+    }
+
+    /// Adds a message to the history as part of the exchange going on.
+    fn add_to_exchange(&mut self, message: Message) {
+        self.state.current_exchange.push(message._id);
+        self.state.messages.push(message);
+    }
+
+    fn set_view(&mut self, view: View) {
+        if view == View::Settings && self.state.view != View::Settings {
+            self.list_audio_devices();
+        }
+
+        self.state.camera.watch_preview(view == View::Camera);
+        self.state.view = view;
+    }
+
     fn tab_view_forward(&mut self) {
         match self.state.view {
-            View::Home => self.state.view = View::Logs,
-            View::Logs => self.state.view = View::Chat,
-            View::Chat => self.state.view = View::Home,
+            View::Home => self.set_view(View::Logs),
+            View::Logs => self.set_view(View::Chat),
+            View::Chat => self.set_view(View::Settings),
+            View::Settings => self.set_view(View::Camera),
+            View::Camera => self.set_view(View::Home),
         }
     }
 
     fn tab_view_backward(&mut self) {
         match self.state.view {
-            View::Home => self.state.view = View::Chat,
-            View::Logs => self.state.view = View::Home,
-            View::Chat => self.state.view = View::Logs,
+            View::Home => self.set_view(View::Camera),
+            View::Logs => self.set_view(View::Home),
+            View::Chat => self.set_view(View::Logs),
+            View::Settings => self.set_view(View::Chat),
+            View::Camera => self.set_view(View::Settings),
         }
     }
 
     fn next_message(&mut self) {
-        if self.state.home_view.message_index >= self.state.get_assistant_message_count() - 1 {
+        if self.state.home_view.message_index + 1 >= self.state.get_turn_count() {
             return;
         }
 
@@ -491,28 +897,286 @@ impl App {
     }
 
     fn render(&mut self) -> Result<(), anyhow::Error> {
-        self.terminal
-            .draw(|frame| frame.render_widget(AppLayout::new(&self.state), frame.area()))?;
+        if self.state.view == View::Camera {
+            self.refresh_camera_view();
+        }
+
+        if let Some(terminal) = &mut self.terminal {
+            terminal
+                .draw(|frame| frame.render_widget(AppLayout::new(&self.state), frame.area()))?;
+        }
         Ok(())
     }
 
-    fn quit(&mut self) {
-        self.cancel();
+    /// Picks up the camera preview, and decodes the image sent with the
+    /// selected message once it changes.
+    fn refresh_camera_view(&mut self) {
+        // The camera logs its own failures, the preview just goes blank
+        self.state.camera_view.preview = self.state.camera.preview();
+
+        let selected = self.state.get_selected_message().map(|message| message._id);
+        if selected != self.state.camera_view.sent_for {
+            let sent = self
+                .state
+                .get_selected_message_image()
+                .and_then(decode_image)
+                .map(preview_image);
+            let camera_view = &mut self.state.camera_view;
+            camera_view.sent = sent;
+            camera_view.sent_for = selected;
+        }
+    }
+
+    async fn quit(&mut self) -> Result<(), anyhow::Error> {
+        self.cancel().await?;
         self.state.is_app_running = false;
+        Ok(())
     }
 
-    fn cancel(&mut self) {
+    async fn cancel(&mut self) -> Result<(), anyhow::Error> {
         // self.audio_recorder.stop();
         // self.state.is_audio_recording_running = false;
 
+        self.stop_speaking().await?;
+
+        self.elevenlabs.cancel();
+        self.state.is_audio_transcription_running = false;
+
+        self.state.conversation.end();
+        Ok(())
+    }
+
+    /// Stops the reply, what's left of it is neither generated nor spoken.
+    async fn stop_speaking(&mut self) -> Result<(), anyhow::Error> {
+        if self.anthropic.cancel() {
+            self.state.is_llm_message_running = false;
+            self.text_processor.reset();
+            // It won't complete, the exchange so far is kept
+            self.finish_exchange();
+        }
+
+        // Audio for text already sent still arrives, muted
+        self.elevenlabs.end_stream().await?;
+
         self.audio_player.stop();
         self.state.is_audio_playback_running = false;
+        self.state.spoken_reply = SpokenReply::default();
+        self.state.is_speech_muted = true;
+        Ok(())
+    }
 
-        self.anthropic.cancel();
-        self.state.is_llm_message_running = false;
+    /// Saves the messages of the exchange once the reply is done.
+    fn finish_exchange(&mut self) {
+        let messages: Vec<Message> = take(&mut self.state.current_exchange)
+            .iter()
+            .filter_map(|id| self.state.get_message(id).cloned())
+            .collect();
+        self.memory.process_exchange(&messages);
+    }
 
-        self.elevenlabs.cancel();
-        self.state.is_audio_transcription_running = false;
+    async fn run_voice_command(&mut self, command: VoiceCommand) -> Result<(), anyhow::Error> {
+        match command {
+            VoiceCommand::Stop => self.stop_speaking().await?,
+            VoiceCommand::Cancel => self.cancel().await?,
+            VoiceCommand::Louder | VoiceCommand::Quieter => {
+                let delta = if command == VoiceCommand::Louder {
+                    VOLUME_STEP
+                } else {
+                    -VOLUME_STEP
+                };
+
+                let volume = self.audio_player.change_volume(delta);
+                self.log_info(&format!("Volume set to {:.0}%", volume * 100.0))
+                    .await?;
+            }
+            VoiceCommand::SetView(view) => self.set_view(view),
+            // Updating restarts the robot, a stray "update yourself" from
+            // the TV shouldn't be enough
+            VoiceCommand::Update => {
+                self.state.update_requested_at = Some(Instant::now());
+                self.log_info("Update asked for, waiting for the confirmation")
+                    .await?;
+                self.say(UPDATE_CONFIRMATION).await?;
+            }
+            VoiceCommand::ConfirmUpdate => {
+                let requested = self.state.update_requested_at.take();
+                if !requested.is_some_and(|at| at.elapsed() <= UPDATE_CONFIRM_WINDOW) {
+                    self.log_info("No update to confirm").await?;
+                    return Ok(());
+                }
+
+                let input = serde_json::json!({
+                    "production_build": CONFIG.environment == Environment::Pi,
+                });
+
+                let result = ToolType::execute_tool(
+                    UpdateTool::NAME,
+                    &input.to_string(),
+                    &self.state,
+                    self.event_bus.sender(),
+                )
+                .await;
+
+                match result {
+                    Ok(output) => self.log_info(&format!("Update: {output}")).await?,
+                    Err(e) => self.log_error(&format!("Update failed: {e}")).await?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Speaks `text` as is, without going through the LLM.
+    async fn say(&mut self, text: &str) -> Result<(), anyhow::Error> {
+        self.state.is_speech_muted = false;
+        self.state.spoken_reply = SpokenReply::default();
+
+        self.elevenlabs.start_stream(&self.state.voice).await?;
+        self.elevenlabs.send_text(text).await?;
+        self.elevenlabs.end_stream().await
+    }
+
+    async fn connect_audio_device(
+        &mut self,
+        direction: AudioDirection,
+    ) -> Result<(), anyhow::Error> {
+        match direction {
+            AudioDirection::Input => self.audio_recorder.start().await,
+            AudioDirection::Output => self.audio_player.start().await,
+        }
+    }
+
+    fn is_audio_device_running(&self, direction: AudioDirection) -> bool {
+        match direction {
+            AudioDirection::Input => self.audio_recorder.is_running(),
+            AudioDirection::Output => self.audio_player.is_running(),
+        }
+    }
+
+    fn schedule_audio_reconnect(&self, direction: AudioDirection, attempt: u32) {
+        let event_sender = self.event_bus.sender();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(reconnect_delay(attempt)).await;
+            let _ = event_sender
+                .send(AppEvent::AudioReconnect { direction, attempt })
+                .await;
+        });
+    }
+
+    /// Closes the stream and starts reconnecting when a stream error came from
+    /// its device disappearing rather than a passing glitch.
+    async fn check_audio_device(&mut self, direction: AudioDirection) -> Result<(), anyhow::Error> {
+        if self.replay.is_some() || !self.is_audio_device_running(direction) {
+            return Ok(());
+        }
+
+        let device_name = match direction {
+            AudioDirection::Input => self.state.audio_input_device.clone(),
+            AudioDirection::Output => self.state.audio_output_device.clone(),
+        };
+
+        let available = tokio::task::spawn_blocking(move || {
+            let names = device_names(direction);
+            device_name.is_some_and(|name| names.contains(&name))
+        })
+        .await?;
+
+        if available {
+            return Ok(());
+        }
+
+        match direction {
+            AudioDirection::Input => self.audio_recorder.close(),
+            AudioDirection::Output => self.audio_player.close(),
+        }
+
+        self.log(
+            &format!("Audio {direction} device disconnected, reconnecting"),
+            LogLevel::Warn,
+        )
+        .await?;
+        self.schedule_audio_reconnect(direction, 0);
+
+        Ok(())
+    }
+
+    /// Lists the audio devices in the background for the settings view.
+    fn list_audio_devices(&self) {
+        let event_sender = self.event_bus.sender();
+
+        tokio::task::spawn_blocking(move || {
+            let _ = event_sender.blocking_send(AppEvent::AudioDevicesListed(AudioDevices::list()));
+        });
+    }
+
+    /// Switches to the voice or device selected in the settings view. Devices
+    /// are only used until the app restarts.
+    async fn use_selected_setting(&mut self) -> Result<(), anyhow::Error> {
+        let voices = &self.state.voices;
+
+        if let Some(index) = self.state.settings_view.selected_voice(voices.len()) {
+            let change = VoiceChange {
+                voice: Some(voices[index].id.clone()),
+                ..VoiceChange::default()
+            };
+            return self.set_voice(&change).await;
+        }
+
+        if self.replay.is_some() {
+            return Ok(());
+        }
+
+        let Some((direction, name)) = self.state.settings_view.selected_device() else {
+            return Ok(());
+        };
+        let name = Some(name.to_string());
+
+        match direction {
+            AudioDirection::Input => self.audio_recorder.set_device(name),
+            AudioDirection::Output => self.audio_player.set_device(name),
+        }
+
+        if let Err(err) = self.connect_audio_device(direction).await {
+            self.log_error(&format!("Failed to open audio {direction} device: {err}"))
+                .await?;
+            self.schedule_audio_reconnect(direction, 0);
+        }
+
+        Ok(())
+    }
+
+    async fn adjust_selected_voice_setting(&mut self, steps: i32) -> Result<(), anyhow::Error> {
+        let voice_count = self.state.voices.len();
+
+        if let Some(setting) = self.state.settings_view.selected_voice_setting(voice_count) {
+            let change = setting.step(&self.state.voice.settings, steps);
+            self.set_voice(&change).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Used from the next reply on, and kept across restarts.
+    async fn set_voice(&mut self, change: &VoiceChange) -> Result<(), anyhow::Error> {
+        match self.state.voice.apply(change, &self.state.voices) {
+            Ok(voice) => {
+                self.log_info(&format!("Voice: {voice}")).await?;
+                self.state.voice = voice;
+                self.persist_state().await
+            }
+            Err(err) => self.log_error(&format!("Failed to set voice: {err}")).await,
+        }
+    }
+
+    async fn persist_state(&mut self) -> Result<(), anyhow::Error> {
+        if let Err(err) = self.state.persist_state().await {
+            self.log_error(&format!("Failed to persist state: {err}"))
+                .await?;
+        }
+
+        Ok(())
     }
 
     fn toggle_recording(&mut self) {
@@ -524,9 +1188,319 @@ impl App {
         }
     }
 
+    fn start_listening(&mut self, origin: ListeningOrigin) {
+        let cue = match origin {
+            ListeningOrigin::WakeWord => CONFIG.wake_word.chime,
+            ListeningOrigin::Conversation => CONFIG.conversation.cues,
+        };
+
+        if cue {
+            self.audio_player.play_chime();
+        }
+
+        let now = Instant::now();
+        self.state.listening = Some(ListeningSession {
+            origin,
+            started_at: now,
+            last_speech_at: now,
+            heard_speech: false,
+        });
+
+        self.audio_recorder.start_recording();
+    }
+
+    /// Ends the listening session once the speaker goes quiet, doesn't start
+    /// talking in a conversation's window or it runs too long.
+    fn update_listening_session(&mut self, volume: f32) {
+        let Some(session) = &mut self.state.listening else {
+            return;
+        };
+
+        let now = Instant::now();
+        let listening_for = now.duration_since(session.started_at);
+
+        if volume >= CONFIG.audio.input_volume_threshold && listening_for >= CHIME_GRACE {
+            session.last_speech_at = now;
+            session.heard_speech = true;
+        }
+
+        let (silence_timeout_ms, max_listen_ms) = match session.origin {
+            ListeningOrigin::WakeWord => (
+                CONFIG.wake_word.silence_timeout_ms,
+                CONFIG.wake_word.max_listen_ms,
+            ),
+            ListeningOrigin::Conversation => (
+                CONFIG.conversation.silence_timeout_ms,
+                CONFIG.conversation.max_listen_ms,
+            ),
+        };
+
+        let silent_for = now.duration_since(session.last_speech_at);
+        let timed_out = match (session.origin, session.heard_speech) {
+            // Nobody replied in the conversation's window
+            (ListeningOrigin::Conversation, false) => {
+                listening_for >= Duration::from_millis(CONFIG.conversation.listen_window_ms)
+            }
+            _ => silent_for >= Duration::from_millis(silence_timeout_ms),
+        };
+
+        if timed_out || listening_for >= Duration::from_millis(max_listen_ms) {
+            self.audio_recorder.stop_recording();
+        }
+    }
+
+    /// Prompts the LLM to greet someone who just came into view, unless the
+    /// robot is busy, it greeted recently or it's quiet hours.
+    async fn greet_arrival(&mut self) -> Result<(), anyhow::Error> {
+        let config = &CONFIG.presence;
+        if !config.greet || self.replay.is_some() {
+            return Ok(());
+        }
+
+        let busy = self.state.is_llm_message_running
+            || self.state.is_tts_running
+            || self.state.is_audio_playback_running
+            || self.state.is_audio_transcription_running
+            || self.state.listening.is_some()
+            || self.state.conversation.active
+            || self.audio_recorder.is_recording();
+        let cooling_down = self
+            .state
+            .presence
+            .last_greeted_at
+            .is_some_and(|at| at.elapsed() < Duration::from_millis(config.greet_cooldown_ms));
+        let quiet_hours = parse_quiet_hours(&config.quiet_hours).unwrap_or_default();
+
+        if busy || cooling_down || in_quiet_hours(quiet_hours, chrono::Local::now().time()) {
+            return Ok(());
+        }
+
+        self.greet().await
+    }
+
+    /// Prompts the LLM with a snapshot to greet whoever is in front of the camera.
+    async fn greet(&mut self) -> Result<(), anyhow::Error> {
+        self.state.presence.last_greeted_at = Some(Instant::now());
+        match self.state.camera.latest_frame() {
+            Ok(img) => self.state.img_base64 = img,
+            Err(e) => self.log_error(&format!("Camera frame failed: {e}")).await?,
+        }
+
+        self.log_info("Greeting someone who arrived").await?;
+        let message = Message {
+            context: true,
+            ..self.user_message(String::from(ARRIVAL_PROMPT), None)
+        };
+
+        self.anthropic
+            .prompt(&message, &self.state.messages, &self.state);
+        // Not something the user said, so it's only kept for the session
+        self.state.messages.push(message);
+
+        Ok(())
+    }
+
+    /// Opens the next listening window of a conversation once the reply is
+    /// generated and done playing, or ends the conversation if it's over.
+    async fn listen_for_reply(&mut self) -> Result<(), anyhow::Error> {
+        let conversation = self.state.conversation.clone();
+
+        if !conversation.active || !conversation.reply_finished || self.replay.is_some() {
+            return Ok(());
+        }
+
+        let busy = self.state.is_llm_message_running
+            || self.state.is_tts_running
+            || self.state.is_audio_playback_running
+            || self.audio_player.has_queued_audio()
+            || self.audio_recorder.is_recording();
+
+        if busy {
+            return Ok(());
+        }
+
+        self.state.conversation.reply_finished = false;
+
+        let idle_timeout = Duration::from_millis(CONFIG.conversation.idle_timeout_ms);
+        let idle = conversation
+            .last_turn_at
+            .is_none_or(|last_turn_at| last_turn_at.elapsed() >= idle_timeout);
+
+        if conversation.ending {
+            self.end_conversation("ended by the assistant").await
+        } else if idle {
+            self.end_conversation("idle").await
+        } else {
+            self.start_listening(ListeningOrigin::Conversation);
+            Ok(())
+        }
+    }
+
+    async fn end_conversation(&mut self, reason: &str) -> Result<(), anyhow::Error> {
+        if !self.state.conversation.active {
+            return Ok(());
+        }
+
+        self.state.conversation.end();
+
+        if CONFIG.conversation.cues {
+            self.audio_player.play_end_cue();
+        }
+
+        self.log_info(&format!("Conversation ended ({reason})"))
+            .await
+    }
+
+    async fn record_wake_word_false_trigger(&mut self, reason: &str) -> Result<(), anyhow::Error> {
+        self.state.diagnostics.wake_word.false_triggers += 1;
+        self.log(
+            &format!("Wake word false trigger ({reason})"),
+            LogLevel::Warn,
+        )
+        .await
+    }
+
+    /// Records the next recording as a wake word template, `w` again stops it.
+    fn toggle_wake_word_enrollment(&mut self) {
+        if self.audio_recorder.is_recording() {
+            if self.state.wake_word.enrolling {
+                self.audio_recorder.stop_recording();
+            }
+        } else {
+            self.state.wake_word.enrolling = true;
+            self.audio_recorder.start_recording();
+        }
+    }
+
+    async fn save_wake_word_template(&mut self, wav: Vec<u8>) -> Result<(), anyhow::Error> {
+        let dir = Path::new(&CONFIG.wake_word.templates_dir);
+        let file_name = format!("{}.wav", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+        let path = dir.join(file_name);
+
+        let result = async {
+            tokio::fs::create_dir_all(dir).await?;
+            tokio::fs::write(&path, wav).await
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                self.audio_recorder.reload_wake_word_templates();
+                self.log_info(&format!("Saved wake word template {}", path.display()))
+                    .await
+            }
+            Err(e) => {
+                self.log_error(&format!("Failed to save wake word template: {e}"))
+                    .await
+            }
+        }
+    }
+
+    fn is_archiving_audio(&self) -> bool {
+        CONFIG.audio_archive.enabled && self.replay.is_none()
+    }
+
+    /// Saves audio to the archive, returning the id to link it with or `None`
+    /// when archiving is off or failed.
+    async fn archive_audio(
+        &mut self,
+        id: String,
+        wav: &[u8],
+    ) -> Result<Option<String>, anyhow::Error> {
+        if !self.is_archiving_audio() {
+            return Ok(None);
+        }
+
+        if let Err(e) = self.audio_archive.save(&id, wav).await {
+            self.log_error(&format!("Failed to archive audio: {e}"))
+                .await?;
+            return Ok(None);
+        }
+
+        self.prune_audio_archive().await?;
+        Ok(Some(id))
+    }
+
+    async fn archive_reply_audio(&mut self, reply_audio: ReplyAudio) -> Result<(), anyhow::Error> {
+        let wav = match reply_audio.to_wav() {
+            Some(Ok(wav)) => wav,
+            Some(Err(e)) => {
+                return self
+                    .log_error(&format!("Failed to encode reply audio: {e}"))
+                    .await;
+            }
+            None => return Ok(()),
+        };
+
+        let message_id = reply_audio.message_id;
+        if let Some(audio_id) = self.archive_audio(message_id.to_hex(), &wav).await?
+            && let Some(message) = self.state.get_message_mut(&message_id)
+        {
+            // The reply may be saved already
+            self.memory.set_message_audio(message_id, &audio_id);
+            message.audio_id = Some(audio_id);
+        }
+
+        Ok(())
+    }
+
+    async fn prune_audio_archive(&mut self) -> Result<(), anyhow::Error> {
+        if !self.is_archiving_audio() {
+            return Ok(());
+        }
+
+        match self.audio_archive.prune().await {
+            Ok(0) => Ok(()),
+            Ok(count) => {
+                self.log_info(&format!("Deleted {count} old archived audio file(s)"))
+                    .await
+            }
+            Err(e) => {
+                self.log_error(&format!("Failed to prune audio archive: {e}"))
+                    .await
+            }
+        }
+    }
+
+    /// Plays the archived audio of the message selected in the home view,
+    /// cutting off whatever is playing.
+    async fn play_selected_message_audio(&mut self) -> Result<(), anyhow::Error> {
+        let Some(audio_id) = self
+            .state
+            .get_selected_message()
+            .and_then(|message| message.audio_id.clone())
+        else {
+            return self
+                .log_info("The selected message has no saved audio")
+                .await;
+        };
+
+        let (audio, format) = match self.audio_archive.load(&audio_id).await {
+            Ok(audio) => audio,
+            Err(e) => {
+                return self
+                    .log_error(&format!("Failed to load archived audio {audio_id}: {e}"))
+                    .await;
+            }
+        };
+
+        self.audio_player.stop();
+        self.state.spoken_reply = SpokenReply::default();
+        self.audio_player.push_audio_chunk(&audio, format)?;
+        self.audio_player.finish_stream();
+
+        self.log_info(&format!("Playing archived audio {audio_id}"))
+            .await
+    }
+
     async fn log(&mut self, text: &str, level: LogLevel) -> Result<(), anyhow::Error> {
         let log = Log::new(text, level);
-        self.memory.mongodb.logs.insert_one(&log).await?;
+
+        if self.terminal.is_none() {
+            println!("{} [{}] {}", log.created_at, log.level, log.text);
+        }
+
+        self.memory.insert_log(&log);
         self.state.log(log);
         Ok(())
     }
@@ -541,3 +1515,315 @@ impl App {
         Ok(())
     }
 }
+
+async fn next_terminal_event(
+    events: &mut Option<EventStream>,
+) -> Option<Result<Event, std::io::Error>> {
+    match events {
+        Some(events) => events.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// While replaying, what the handlers emit is already part of the recording.
+/// It's dropped rather than left on the bus, so their sends never wait on it.
+async fn next_bus_event(event_bus: &mut EventBus, replaying: bool) -> Option<Option<AppEvent>> {
+    if replaying {
+        event_bus.discard_next().await.map(|()| None)
+    } else {
+        event_bus.recv().await.map(Some)
+    }
+}
+
+async fn next_replay_event(
+    replay: &mut Option<EventReplayer>,
+) -> Option<Result<AppEvent, anyhow::Error>> {
+    match replay {
+        Some(replay) => replay.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use base64::{Engine, engine::general_purpose};
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        events::{LLMGenerationCompletedEventPayload, LLMGenerationStartedEventPayload},
+    };
+
+    const FIXTURE_SESSION: &str = "src/replay/fixtures/exchange";
+
+    async fn replaying(session: &str, speed: f32) -> App {
+        Config::init_for_tests();
+
+        let session_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(session);
+        let replay = EventReplayer::open(&session_dir, speed).unwrap();
+        App::new(None, Some(replay)).await.unwrap()
+    }
+
+    /// An app with the services stubbed out that acts as if it was live.
+    async fn live() -> App {
+        let mut app = replaying(FIXTURE_SESSION, 0.0).await;
+        app.replay = None;
+        app
+    }
+
+    /// A conversation whose last turn was `ago`, with a listening window open.
+    fn conversation(ago: Duration) -> ConversationState {
+        ConversationState {
+            enabled: true,
+            active: true,
+            last_turn_at: Instant::now().checked_sub(ago),
+            ..ConversationState::default()
+        }
+    }
+
+    fn event_types(json: &[String]) -> Vec<String> {
+        json.iter()
+            .map(|json| {
+                let event: serde_json::Value = serde_json::from_str(json).unwrap();
+                event["type"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replays_a_recorded_session_while_the_bus_fills_up() {
+        let mut app = replaying(FIXTURE_SESSION, 10.0).await;
+        let mut observed = app.event_bus.observers().subscribe();
+
+        // Like the recorder reporting volume, this fills the bus between
+        // replayed events while the handlers also send into it
+        let sender = app.event_bus.sender();
+        let mut replaying = app.event_bus.observers().subscribe();
+        tokio::spawn(async move {
+            let _ = replaying.recv().await;
+            while sender.send(AppEvent::AudioDetected(0.5)).await.is_ok() {}
+        });
+
+        timeout(Duration::from_secs(5), app.start())
+            .await
+            .expect("replay got stuck")
+            .unwrap();
+
+        let mut published = Vec::new();
+        while let Ok(json) = observed.try_recv() {
+            published.push(json);
+        }
+
+        let session_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(FIXTURE_SESSION);
+        let recorded = std::fs::read_to_string(session_dir.join("events.jsonl")).unwrap();
+        let recorded: Vec<String> = recorded
+            .lines()
+            .map(|line| {
+                let line: serde_json::Value = serde_json::from_str(line).unwrap();
+                line["event"].to_string()
+            })
+            .collect();
+        assert_eq!(event_types(&published), event_types(&recorded));
+
+        let reply = app.state.messages.last().unwrap();
+        assert_eq!(reply.role, Role::Assistant);
+        assert_eq!(
+            reply.content,
+            [ContentBlock::Text {
+                text: String::from("It's three o'clock. Anything else?")
+            }]
+        );
+        assert!(!app.state.is_llm_message_running);
+        assert!(!app.state.is_tts_running);
+        assert!(!app.state.is_app_running);
+
+        let last_log = app.state.logs_view.logs.last().unwrap();
+        assert_eq!(last_log.text, "Replay finished");
+    }
+
+    #[tokio::test]
+    async fn keeps_listening_after_an_empty_transcript() {
+        let mut app = live().await;
+        app.state.conversation = conversation(Duration::ZERO);
+        app.state.transcript_origin = Some(ListeningOrigin::Conversation);
+
+        let event = AppEvent::TranscriptionCompleted(String::from("  "));
+        app.handle_app_event(event).await.unwrap();
+
+        assert!(app.state.conversation.active);
+        let session = app.state.listening.as_ref().expect("no listening window");
+        assert_eq!(session.origin, ListeningOrigin::Conversation);
+    }
+
+    #[tokio::test]
+    async fn ends_idle_and_ended_conversations() {
+        let mut app = live().await;
+        let idle_timeout = Duration::from_millis(CONFIG.conversation.idle_timeout_ms);
+        app.state.conversation = conversation(idle_timeout + Duration::from_secs(1));
+        app.state.transcript_origin = Some(ListeningOrigin::Conversation);
+
+        let event = AppEvent::TranscriptionCompleted(String::new());
+        app.handle_app_event(event).await.unwrap();
+        assert!(!app.state.conversation.active);
+        assert!(app.state.listening.is_none());
+
+        let mut app = live().await;
+        app.state.conversation = ConversationState {
+            ending: true,
+            reply_finished: true,
+            ..conversation(Duration::ZERO)
+        };
+
+        app.listen_for_reply().await.unwrap();
+        assert!(!app.state.conversation.active);
+        assert!(app.state.listening.is_none());
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_reply_to_finish_playing() {
+        let mut app = live().await;
+        app.state.conversation = ConversationState {
+            reply_finished: true,
+            ..conversation(Duration::ZERO)
+        };
+        app.state.is_tts_running = true;
+
+        app.listen_for_reply().await.unwrap();
+        assert!(app.state.listening.is_none());
+        assert!(app.state.conversation.reply_finished);
+
+        app.handle_app_event(AppEvent::TTSStreamFinished)
+            .await
+            .unwrap();
+        assert!(app.state.listening.is_some());
+        assert!(!app.state.conversation.reply_finished);
+    }
+
+    #[tokio::test]
+    async fn only_updates_once_confirmed() {
+        let mut app = live().await;
+
+        // Nothing was asked for
+        app.run_voice_command(VoiceCommand::ConfirmUpdate)
+            .await
+            .unwrap();
+        assert!(app.state.update_requested_at.is_none());
+
+        app.run_voice_command(VoiceCommand::Update).await.unwrap();
+        assert!(app.state.update_requested_at.is_some());
+
+        // Too late, the request is dropped without updating
+        app.state.update_requested_at = Instant::now().checked_sub(Duration::from_secs(31));
+        app.run_voice_command(VoiceCommand::ConfirmUpdate)
+            .await
+            .unwrap();
+        assert!(app.state.update_requested_at.is_none());
+        assert!(
+            app.state
+                .logs_view
+                .logs
+                .iter()
+                .any(|log| log.text == "No update to confirm")
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_both_sides_of_the_exchange() {
+        let mut app = live().await;
+        let prompt = AppEvent::TextPrompt(String::from("Hello"));
+        app.handle_app_event(prompt).await.unwrap();
+
+        let message_id = ObjectId::new();
+        let started =
+            AppEvent::LLMGenerationStarted(LLMGenerationStartedEventPayload { message_id });
+        app.handle_app_event(started).await.unwrap();
+
+        assert_eq!(app.state.get_turn_count(), 2);
+        assert_eq!(app.state.current_exchange.len(), 2);
+        assert_eq!(app.state.get_selected_message().unwrap()._id, message_id);
+
+        app.previous_message();
+        let selected = app.state.get_selected_message().unwrap();
+        assert_eq!(selected.role, Role::User);
+        assert_eq!(
+            selected.content,
+            [ContentBlock::Text {
+                text: String::from("Hello")
+            }]
+        );
+
+        let completed = LLMGenerationCompletedEventPayload { message_id };
+        app.handle_app_event(AppEvent::LLMGenerationCompleted(completed))
+            .await
+            .unwrap();
+        assert!(app.state.current_exchange.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_arrival_prompt_out_of_the_turns() {
+        let mut app = live().await;
+        app.greet().await.unwrap();
+
+        let arrival = app.state.messages.last().unwrap();
+        assert!(arrival.context);
+        assert!(app.state.current_exchange.is_empty());
+        assert_eq!(app.state.get_turn_count(), 0);
+
+        let message_id = ObjectId::new();
+        let started =
+            AppEvent::LLMGenerationStarted(LLMGenerationStartedEventPayload { message_id });
+        app.handle_app_event(started).await.unwrap();
+
+        // Only the greeting is remembered and shown
+        assert_eq!(app.state.current_exchange, [message_id]);
+        assert_eq!(app.state.get_turn_count(), 1);
+        assert_eq!(app.state.messages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn shows_the_image_sent_with_the_selected_message() {
+        let mut app = live().await;
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(8, 8))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        let exchange = async |app: &mut App, text: &str| {
+            app.handle_app_event(AppEvent::TextPrompt(String::from(text)))
+                .await
+                .unwrap();
+            let message_id = ObjectId::new();
+            let started =
+                AppEvent::LLMGenerationStarted(LLMGenerationStartedEventPayload { message_id });
+            app.handle_app_event(started).await.unwrap();
+            let completed = LLMGenerationCompletedEventPayload { message_id };
+            app.handle_app_event(AppEvent::LLMGenerationCompleted(completed))
+                .await
+                .unwrap();
+        };
+
+        app.state.img_base64 = Some(general_purpose::STANDARD.encode(&jpeg));
+        exchange(&mut app, "What's this?").await;
+        exchange(&mut app, "Thanks!").await;
+        app.set_view(View::Camera);
+
+        // Newest first: the second reply, "Thanks!", the first reply and the
+        // question the image went with
+        let mut sent = Vec::new();
+        for _ in 0..4 {
+            app.render().unwrap();
+            let camera_view = &app.state.camera_view;
+            assert_eq!(
+                camera_view.sent_for,
+                app.state.get_selected_message().map(|message| message._id)
+            );
+            sent.push(camera_view.sent.is_some());
+            app.previous_message();
+        }
+        assert_eq!(sent, [false, false, true, true]);
+    }
+}
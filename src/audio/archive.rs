@@ -0,0 +1,248 @@
+//! Keeps recordings and spoken replies on disk so they can be listened to
+//! again, e.g. to check what was actually said when a transcription is wrong.
+
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use hound::{WavReader, WavSpec, WavWriter};
+use mongodb::bson::oid::ObjectId;
+use tokio_tungstenite::tungstenite::Bytes;
+
+use crate::{audio::PcmFormat, config::AudioArchiveConfig};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// WAV files named by the id messages link them with.
+#[derive(Debug, Clone)]
+pub struct AudioArchive {
+    dir: PathBuf,
+    max_age: Option<Duration>,
+    max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ArchivedFile {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+impl AudioArchive {
+    pub fn new(config: &AudioArchiveConfig) -> Self {
+        Self {
+            dir: PathBuf::from(&config.dir),
+            max_age: (config.max_age_days > 0)
+                .then(|| Duration::from_secs(config.max_age_days as u64 * SECONDS_PER_DAY)),
+            max_bytes: (config.max_size_mb > 0).then(|| config.max_size_mb * 1024 * 1024),
+        }
+    }
+
+    pub async fn save(&self, id: &str, wav: &[u8]) -> Result<PathBuf, anyhow::Error> {
+        let path = self.path(id);
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(&path, wav).await?;
+        Ok(path)
+    }
+
+    /// The samples saved as `id`, ready for [`AudioPlayer::push_audio_chunk`](crate::audio::player::AudioPlayer::push_audio_chunk).
+    pub async fn load(&self, id: &str) -> Result<(Bytes, PcmFormat), anyhow::Error> {
+        let wav = tokio::fs::read(self.path(id)).await?;
+        wav_to_pcm(&wav)
+    }
+
+    /// Deletes files past the age limit, then the oldest ones until the rest
+    /// fit the size limit. Returns how many were deleted.
+    pub async fn prune(&self) -> Result<usize, anyhow::Error> {
+        if !tokio::fs::try_exists(&self.dir).await? {
+            return Ok(0);
+        }
+
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let metadata = entry.metadata().await?;
+
+            if metadata.is_file() && path.extension().is_some_and(|ext| ext == "wav") {
+                files.push(ArchivedFile {
+                    path,
+                    modified: metadata.modified()?,
+                    size: metadata.len(),
+                });
+            }
+        }
+
+        let expired = expired_files(files, SystemTime::now(), self.max_age, self.max_bytes);
+        for path in &expired {
+            tokio::fs::remove_file(path).await?;
+        }
+
+        Ok(expired.len())
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        // Ids are generated, but don't let one point outside the archive
+        let name = Path::new(id).file_name().unwrap_or_default();
+        self.dir.join(name).with_extension("wav")
+    }
+}
+
+/// TTS audio of a reply, collected while it streams.
+#[derive(Debug)]
+pub struct ReplyAudio {
+    pub message_id: ObjectId,
+    format: Option<PcmFormat>,
+    pcm: Vec<u8>,
+}
+
+impl ReplyAudio {
+    pub fn new(message_id: ObjectId) -> Self {
+        Self {
+            message_id,
+            format: None,
+            pcm: Vec::new(),
+        }
+    }
+
+    /// Appends a chunk, chunks in another format than the first are dropped.
+    pub fn push(&mut self, pcm: &[u8], format: PcmFormat) {
+        if *self.format.get_or_insert(format) == format {
+            self.pcm.extend_from_slice(pcm);
+        }
+    }
+
+    /// The reply as a WAV file, `None` if nothing was spoken.
+    pub fn to_wav(&self) -> Option<Result<Vec<u8>, hound::Error>> {
+        let format = self.format.filter(|_| !self.pcm.is_empty())?;
+        Some(pcm_to_wav(&self.pcm, format))
+    }
+}
+
+/// Wraps signed 16-bit little-endian PCM, as the TTS streams it, in a WAV file.
+pub fn pcm_to_wav(pcm: &[u8], format: PcmFormat) -> Result<Vec<u8>, hound::Error> {
+    let spec = WavSpec {
+        channels: format.channels,
+        sample_rate: format.sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut buf = Vec::new();
+    let mut writer = WavWriter::new(Cursor::new(&mut buf), spec)?;
+
+    for sample in pcm.chunks_exact(2) {
+        writer.write_sample(i16::from_le_bytes([sample[0], sample[1]]))?;
+    }
+
+    writer.finalize()?;
+    Ok(buf)
+}
+
+fn wav_to_pcm(wav: &[u8]) -> Result<(Bytes, PcmFormat), anyhow::Error> {
+    let mut reader = WavReader::new(Cursor::new(wav))?;
+    let spec = reader.spec();
+
+    if spec.sample_format != hound::SampleFormat::Int || spec.bits_per_sample != 16 {
+        return Err(anyhow::anyhow!(
+            "Unsupported archived audio: {} bit {:?}",
+            spec.bits_per_sample,
+            spec.sample_format
+        ));
+    }
+
+    let mut pcm = Vec::with_capacity(reader.len() as usize * 2);
+    for sample in reader.samples::<i16>() {
+        pcm.extend_from_slice(&sample?.to_le_bytes());
+    }
+
+    let format = PcmFormat {
+        sample_rate: spec.sample_rate,
+        channels: spec.channels,
+    };
+
+    Ok((Bytes::from(pcm), format))
+}
+
+fn expired_files(
+    mut files: Vec<ArchivedFile>,
+    now: SystemTime,
+    max_age: Option<Duration>,
+    max_bytes: Option<u64>,
+) -> Vec<PathBuf> {
+    // Newest first, so whatever is past the limits is at the end
+    files.sort_by_key(|file| std::cmp::Reverse(file.modified));
+
+    let mut total = 0;
+    let mut expired = Vec::new();
+
+    for file in files {
+        let age = now.duration_since(file.modified).unwrap_or_default();
+        total += file.size;
+
+        let too_old = max_age.is_some_and(|max_age| age > max_age);
+        let too_big = max_bytes.is_some_and(|max_bytes| total > max_bytes);
+
+        if too_old || too_big {
+            expired.push(file.path);
+        }
+    }
+
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, age_secs: u64, size: u64, now: SystemTime) -> ArchivedFile {
+        ArchivedFile {
+            path: PathBuf::from(name),
+            modified: now - Duration::from_secs(age_secs),
+            size,
+        }
+    }
+
+    #[test]
+    fn expires_old_files_then_the_oldest_over_the_size_limit() {
+        let now = SystemTime::now();
+        let files = vec![
+            file("c.wav", 30, 400, now),
+            file("a.wav", 10, 400, now),
+            file("old.wav", 1000, 10, now),
+            file("b.wav", 20, 400, now),
+        ];
+
+        let expired = expired_files(files, now, Some(Duration::from_secs(500)), Some(1000));
+        assert_eq!(expired, [PathBuf::from("c.wav"), PathBuf::from("old.wav")]);
+    }
+
+    #[test]
+    fn keeps_everything_without_limits() {
+        let now = SystemTime::now();
+        let files = vec![file("a.wav", 1_000_000, 1 << 40, now)];
+
+        assert!(expired_files(files, now, None, None).is_empty());
+    }
+
+    #[test]
+    fn pcm_round_trips_through_wav() {
+        let format = PcmFormat {
+            sample_rate: 22050,
+            channels: 2,
+        };
+        let pcm: Vec<u8> = [0i16, 1000, -1000, i16::MAX]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+
+        let wav = pcm_to_wav(&pcm, format).unwrap();
+        let (decoded, decoded_format) = wav_to_pcm(&wav).unwrap();
+
+        assert_eq!(decoded_format, format);
+        assert_eq!(decoded.as_ref(), pcm.as_slice());
+    }
+}
@@ -0,0 +1,135 @@
+//! Finding audio devices by the names shown in the settings view and config.
+
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+use cpal::{
+    Device,
+    traits::{DeviceTrait, HostTrait},
+};
+use serde::{Deserialize, Serialize};
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioDirection {
+    Input,
+    Output,
+}
+
+impl Display for AudioDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioDirection::Input => write!(f, "input"),
+            AudioDirection::Output => write!(f, "output"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioDevices {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+impl AudioDevices {
+    /// Lists the devices of the default host. This blocks, on ALSA for a noticeable while.
+    pub fn list() -> Self {
+        Self {
+            inputs: device_names(AudioDirection::Input),
+            outputs: device_names(AudioDirection::Output),
+        }
+    }
+}
+
+pub fn device_names(direction: AudioDirection) -> Vec<String> {
+    let host = cpal::default_host();
+
+    let devices = match direction {
+        AudioDirection::Input => host.input_devices(),
+        AudioDirection::Output => host.output_devices(),
+    };
+
+    devices
+        .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// The device called `name`, or the default device without one. A name that
+/// isn't an exact match picks the first device containing it, ignoring case,
+/// so `"usb"` finds "USB PnP Sound Device".
+pub fn find_device(direction: AudioDirection, name: Option<&str>) -> Result<Device, anyhow::Error> {
+    let host = cpal::default_host();
+
+    let Some(name) = name else {
+        let device = match direction {
+            AudioDirection::Input => host.default_input_device(),
+            AudioDirection::Output => host.default_output_device(),
+        };
+
+        return device.ok_or_else(|| anyhow::anyhow!("No default {direction} audio device found"));
+    };
+
+    let devices: Vec<Device> = match direction {
+        AudioDirection::Input => host.input_devices()?.collect(),
+        AudioDirection::Output => host.output_devices()?.collect(),
+    };
+    let names: Vec<String> = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect();
+
+    match match_name(&names, name) {
+        Some(index) => Ok(devices.into_iter().nth(index).unwrap()),
+        None => Err(anyhow::anyhow!(
+            "No {direction} audio device named \"{name}\""
+        )),
+    }
+}
+
+/// Delay before reconnect `attempt`, doubling from half a second up to 30 seconds.
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    MIN_RECONNECT_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RECONNECT_DELAY)
+}
+
+fn match_name(names: &[String], wanted: &str) -> Option<usize> {
+    let wanted_lowercase = wanted.to_lowercase();
+
+    names.iter().position(|name| name == wanted).or_else(|| {
+        names
+            .iter()
+            .position(|name| name.to_lowercase().contains(&wanted_lowercase))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_exact_names_before_partial_ones() {
+        let names = vec![
+            String::from("USB PnP Sound Device: Audio (hw:1,0)"),
+            String::from("default"),
+            String::from("sysdefault"),
+        ];
+
+        assert_eq!(match_name(&names, "default"), Some(1));
+        assert_eq!(match_name(&names, "usb pnp"), Some(0));
+        assert_eq!(match_name(&names, "HDMI"), None);
+    }
+
+    #[test]
+    fn reconnect_delay_backs_off_up_to_the_limit() {
+        assert_eq!(reconnect_delay(0), Duration::from_millis(500));
+        assert_eq!(reconnect_delay(1), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(3), Duration::from_secs(4));
+        assert_eq!(reconnect_delay(10), MAX_RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(u32::MAX), MAX_RECONNECT_DELAY);
+    }
+}
@@ -0,0 +1,553 @@
+//! Echo suppression for the microphone, using what the speaker plays as a
+//! reference.
+//!
+//! The speaker's output is tapped in the player's output callback and queued
+//! at the microphone's processing rate. The canceller finds the bulk delay
+//! between the two by correlating their envelopes, removes the echo with a
+//! short NLMS filter and attenuates what's left while only the robot is
+//! talking. It's all time domain and a few million multiply-adds per second
+//! at 16 kHz, which a Pi 4 handles in the input callback.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use ringbuf::{
+    HeapRb,
+    traits::{Consumer, Observer, RingBuffer},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{
+    PcmFormat,
+    resample::{Resampler, downmix_into},
+};
+
+/// Decisions about who is talking are made per 10 ms block.
+const BLOCK_MS: usize = 10;
+/// Envelopes for delay estimation are averaged down to 1 kHz.
+const ENVELOPE_RATE: usize = 1000;
+/// Envelope samples correlated per delay estimate, one second.
+const ENVELOPE_WINDOW: usize = ENVELOPE_RATE;
+/// Below this mean square (about -50 dBFS) the reference counts as silent.
+const FAR_END_FLOOR: f32 = 1e-5;
+/// Mic energy this far above the filter's echo estimate means someone is
+/// talking over the robot.
+const NEAR_END_RATIO: f32 = 4.0;
+/// Echo reduction (6 dB) the filter needs before its estimate is trusted to
+/// tell near-end speech from echo.
+const CONVERGED_ERLE: f32 = 4.0;
+/// Weakest envelope correlation trusted as a delay estimate.
+const MIN_DELAY_CORRELATION: f32 = 0.4;
+const STEP_SIZE: f32 = 0.3;
+/// Far-end only audio needed before reporting stats, in seconds.
+const STATS_SECONDS: usize = 2;
+/// Reference kept queued before the canceller starts reading, covering jitter
+/// between the output and input callbacks.
+const REFERENCE_CUSHION_MS: usize = 50;
+/// More queued reference than this means the clocks drifted, the excess is dropped.
+const MAX_REFERENCE_BACKLOG_MS: usize = 200;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct EchoStats {
+    /// Echo return loss, how much quieter the echo is than the reference.
+    pub erl_db: f32,
+    /// Echo return loss enhancement, how much of the echo the filter removes.
+    pub erle_db: f32,
+    /// Estimated delay between playback and the echo reaching the mic.
+    pub delay_ms: f32,
+}
+
+/// What the speaker is playing, shared between the player and the recorder.
+#[derive(Clone)]
+pub struct EchoReference {
+    buffer: Arc<Mutex<HeapRb<f32>>>,
+    sample_rate: u32,
+}
+
+impl EchoReference {
+    /// Queues the reference at `sample_rate`, the rate the mic is processed at.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            buffer: Arc::new(Mutex::new(HeapRb::new(sample_rate as usize))),
+            sample_rate,
+        }
+    }
+
+    pub fn writer(&self, format: PcmFormat) -> ReferenceWriter {
+        ReferenceWriter {
+            reference: self.clone(),
+            channels: format.channels,
+            resampler: (format.sample_rate != self.sample_rate)
+                .then(|| Resampler::new(format.sample_rate, self.sample_rate)),
+            mono: Vec::new(),
+            resampled: Vec::new(),
+        }
+    }
+
+    pub fn reader(&self) -> ReferenceReader {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.clear();
+        }
+
+        ReferenceReader {
+            reference: self.clone(),
+            started: false,
+        }
+    }
+
+    fn ms_to_samples(&self, ms: usize) -> usize {
+        self.sample_rate as usize * ms / 1000
+    }
+}
+
+/// Feeds interleaved output samples into an [`EchoReference`].
+pub struct ReferenceWriter {
+    reference: EchoReference,
+    channels: u16,
+    resampler: Option<Resampler>,
+    mono: Vec<f32>,
+    resampled: Vec<f32>,
+}
+
+impl ReferenceWriter {
+    pub fn write(&mut self, interleaved: &[f32]) {
+        self.mono.clear();
+        downmix_into(interleaved, self.channels, &mut self.mono);
+
+        let samples = match &mut self.resampler {
+            Some(resampler) => {
+                self.resampled.clear();
+                resampler.process(&self.mono, &mut self.resampled);
+                &self.resampled
+            }
+            None => &self.mono,
+        };
+
+        if let Ok(mut buffer) = self.reference.buffer.lock() {
+            buffer.push_slice_overwrite(samples);
+        }
+    }
+}
+
+/// Takes reference samples one for one with the mic samples, so the offset
+/// between the two stays fixed unless the queue runs dry or overflows.
+pub struct ReferenceReader {
+    reference: EchoReference,
+    started: bool,
+}
+
+impl ReferenceReader {
+    /// Replaces `far` with the `len` reference samples that go with the next mic samples.
+    pub fn read(&mut self, len: usize, far: &mut Vec<f32>) {
+        far.clear();
+
+        let cushion = self.reference.ms_to_samples(REFERENCE_CUSHION_MS);
+        let max_backlog = self.reference.ms_to_samples(MAX_REFERENCE_BACKLOG_MS);
+
+        if let Ok(mut buffer) = self.reference.buffer.lock() {
+            if !self.started && buffer.occupied_len() >= cushion {
+                self.started = true;
+            }
+
+            if self.started {
+                let excess = buffer.occupied_len().saturating_sub(len + max_backlog);
+                buffer.skip(excess);
+                far.extend(buffer.pop_iter().take(len));
+            }
+        }
+
+        far.resize(len, 0.0);
+    }
+}
+
+pub struct EchoCanceller {
+    taps: usize,
+    max_delay: usize,
+    block_len: usize,
+    suppression: f32,
+    weights: Vec<f32>,
+    /// Reference history, newest last, always at least `max_delay + taps` long
+    far: Vec<f32>,
+    /// Samples between the newest reference and the newest one the filter sees
+    delay: usize,
+    /// Estimated delay of the echo, a little more than `delay`
+    echo_delay: usize,
+    /// Smoothed ratio of mic to error energy while only the robot talks
+    erle: f32,
+    far_active: bool,
+    near_talk: bool,
+    block: BlockEnergy,
+    envelopes: Envelopes,
+    far_active_samples: usize,
+    stats: StatsAccumulator,
+}
+
+#[derive(Debug, Default)]
+struct BlockEnergy {
+    samples: usize,
+    far: f32,
+    mic: f32,
+    estimate: f32,
+    error: f32,
+}
+
+#[derive(Debug, Default)]
+struct StatsAccumulator {
+    samples: usize,
+    far: f64,
+    mic: f64,
+    error: f64,
+}
+
+#[derive(Debug, Default)]
+struct Envelopes {
+    decimation: usize,
+    count: usize,
+    mic_sum: f32,
+    far_sum: f32,
+    mic: VecDeque<f32>,
+    far: VecDeque<f32>,
+}
+
+impl EchoCanceller {
+    /// `filter_ms` of echo path is modelled after a delay of up to `max_delay_ms`,
+    /// leftover echo is attenuated by `suppression_db`.
+    pub fn new(sample_rate: u32, filter_ms: u32, max_delay_ms: u32, suppression_db: f32) -> Self {
+        let rate = sample_rate as usize;
+        let taps = (rate * filter_ms as usize / 1000).max(1);
+        let max_delay = rate * max_delay_ms as usize / 1000;
+
+        Self {
+            taps,
+            max_delay,
+            block_len: (rate * BLOCK_MS / 1000).max(1),
+            suppression: 10f32.powf(-suppression_db / 20.0),
+            weights: vec![0.0; taps],
+            far: vec![0.0; max_delay + taps],
+            delay: 0,
+            echo_delay: 0,
+            erle: 1.0,
+            far_active: false,
+            near_talk: false,
+            block: BlockEnergy::default(),
+            envelopes: Envelopes {
+                decimation: (rate / ENVELOPE_RATE).max(1),
+                ..Envelopes::default()
+            },
+            far_active_samples: 0,
+            stats: StatsAccumulator::default(),
+        }
+    }
+
+    /// Removes the echo of `far` from `mic` in place, the two being the same
+    /// length and starting at the same time.
+    pub fn process(&mut self, mic: &mut [f32], far: &[f32]) {
+        for (sample, &far_sample) in mic.iter_mut().zip(far) {
+            *sample = self.process_sample(*sample, far_sample);
+        }
+
+        // Keep the history from growing without copying it every sample
+        let keep = self.max_delay + self.taps;
+        if self.far.len() > keep * 4 {
+            self.far.drain(..self.far.len() - keep);
+        }
+    }
+
+    /// Stats since the last call, once there was enough far-end only audio to measure.
+    pub fn take_stats(&mut self) -> Option<EchoStats> {
+        let stats = &self.stats;
+        let rate = self.block_len * 1000 / BLOCK_MS;
+
+        if stats.samples < rate * STATS_SECONDS {
+            return None;
+        }
+
+        let erl_db = 10.0 * (stats.far / stats.mic.max(1e-12)).log10();
+        let erle_db = 10.0 * (stats.mic / stats.error.max(1e-12)).log10();
+        self.stats = StatsAccumulator::default();
+
+        Some(EchoStats {
+            erl_db: erl_db as f32,
+            erle_db: erle_db as f32,
+            delay_ms: self.echo_delay as f32 * 1000.0 / rate as f32,
+        })
+    }
+
+    fn process_sample(&mut self, mic: f32, far: f32) -> f32 {
+        self.far.push(far);
+
+        let end = self.far.len() - self.delay;
+        let window = &self.far[end - self.taps..end];
+
+        let estimate: f32 = window.iter().zip(&self.weights).map(|(x, w)| x * w).sum();
+        let error = mic - estimate;
+
+        if self.far_active && !self.near_talk {
+            let norm: f32 = window.iter().map(|x| x * x).sum::<f32>() + 1e-6;
+            let step = STEP_SIZE * error / norm;
+
+            for (weight, x) in self.weights.iter_mut().zip(window) {
+                *weight += step * x;
+            }
+        }
+
+        let aligned_far = window[self.taps - 1];
+        self.block.far += aligned_far * aligned_far;
+        self.block.mic += mic * mic;
+        self.block.estimate += estimate * estimate;
+        self.block.error += error * error;
+        self.block.samples += 1;
+
+        self.push_envelopes(mic, far);
+
+        if self.block.samples == self.block_len {
+            self.end_block();
+        }
+
+        if self.far_active && !self.near_talk {
+            error * self.suppression
+        } else {
+            error
+        }
+    }
+
+    /// Decides who is talking for the next block and updates the estimates.
+    fn end_block(&mut self) {
+        let block = std::mem::take(&mut self.block);
+        let len = block.samples as f32;
+
+        self.far_active = block.far / len > FAR_END_FLOOR;
+        // Until the filter converged anything on the mic counts as echo, so
+        // it adapts instead of waiting for an estimate it can't make yet
+        self.near_talk = self.far_active
+            && self.erle > CONVERGED_ERLE
+            && block.mic > NEAR_END_RATIO * block.estimate;
+
+        if !self.far_active {
+            return;
+        }
+
+        self.far_active_samples += block.samples;
+
+        if !self.near_talk {
+            let erle = block.mic / block.error.max(1e-12);
+            self.erle = self.erle * 0.9 + erle.min(1000.0) * 0.1;
+
+            self.stats.samples += block.samples;
+            self.stats.far += block.far as f64;
+            self.stats.mic += block.mic as f64;
+            self.stats.error += block.error as f64;
+        }
+
+        if self.far_active_samples >= ENVELOPE_WINDOW * self.envelopes.decimation {
+            self.far_active_samples = 0;
+            self.estimate_delay();
+        }
+    }
+
+    fn push_envelopes(&mut self, mic: f32, far: f32) {
+        let envelopes = &mut self.envelopes;
+        envelopes.mic_sum += mic.abs();
+        envelopes.far_sum += far.abs();
+        envelopes.count += 1;
+
+        if envelopes.count < envelopes.decimation {
+            return;
+        }
+
+        let max_lag = self.max_delay / envelopes.decimation;
+        let window = ENVELOPE_WINDOW;
+
+        envelopes.mic.push_back(envelopes.mic_sum);
+        envelopes.far.push_back(envelopes.far_sum);
+        envelopes.mic_sum = 0.0;
+        envelopes.far_sum = 0.0;
+        envelopes.count = 0;
+
+        while envelopes.mic.len() > window {
+            envelopes.mic.pop_front();
+        }
+        while envelopes.far.len() > window + max_lag {
+            envelopes.far.pop_front();
+        }
+    }
+
+    /// Finds the lag where the mic envelope best matches the reference envelope
+    /// and moves the filter there, starting it over if it moved far.
+    fn estimate_delay(&mut self) {
+        let window = ENVELOPE_WINDOW;
+        let decimation = self.envelopes.decimation;
+        let max_lag = self.max_delay / decimation;
+
+        let envelopes = &mut self.envelopes;
+        if envelopes.mic.len() < window || envelopes.far.len() < window + max_lag {
+            return;
+        }
+
+        let mic = envelopes.mic.make_contiguous();
+        let mic_mean = mic.iter().sum::<f32>() / window as f32;
+        let mic_norm = mic
+            .iter()
+            .map(|m| (m - mic_mean).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        let mic = &*mic;
+        let far = &*envelopes.far.make_contiguous();
+
+        // The mic envelope is zero mean after subtracting its mean, so the
+        // reference only needs scaling, not centering, for the dot product
+        let best = (0..=max_lag)
+            .map(|lag| {
+                let far = &far[max_lag - lag..max_lag - lag + window];
+                let sum: f32 = far.iter().sum();
+                let sum_squares: f32 = far.iter().map(|f| f * f).sum();
+                let far_norm = (sum_squares - sum * sum / window as f32).max(0.0).sqrt();
+
+                let dot: f32 = mic.iter().zip(far).map(|(m, f)| (m - mic_mean) * f).sum();
+                (lag, dot / (mic_norm * far_norm).max(1e-9))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        let Some((lag, correlation)) = best else {
+            return;
+        };
+
+        if correlation < MIN_DELAY_CORRELATION {
+            return;
+        }
+
+        // Leave a little room before the main echo for the filter to model
+        self.echo_delay = lag * decimation;
+        let delay = self.echo_delay.saturating_sub(self.taps / 4);
+
+        if delay.abs_diff(self.delay) > self.taps / 4 {
+            self.delay = delay;
+            self.weights.fill(0.0);
+            self.erle = 1.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    /// Speech-like reference: noise bursts with pauses, deterministic.
+    fn far_end(seconds: f32) -> Vec<f32> {
+        let mut seed: u32 = 12345;
+        let len = (RATE as f32 * seconds) as usize;
+
+        (0..len)
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 32768.0 - 1.0;
+                let syllable = ((i as f32 / RATE as f32) * 4.0 * std::f32::consts::PI).sin();
+                0.3 * noise * syllable.max(0.0)
+            })
+            .collect()
+    }
+
+    /// The reference as it reaches the mic: delayed, quieter and smeared.
+    fn echo_of(far: &[f32], delay: usize) -> Vec<f32> {
+        (0..far.len())
+            .map(|i| {
+                let at = |offset: usize| {
+                    i.checked_sub(delay + offset)
+                        .map_or(0.0, |index| far[index])
+                };
+                0.5 * at(0) + 0.25 * at(3) - 0.1 * at(10)
+            })
+            .collect()
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum()
+    }
+
+    fn run(canceller: &mut EchoCanceller, mic: &[f32], far: &[f32]) -> Vec<f32> {
+        let mut output = mic.to_vec();
+        for (mic, far) in output.chunks_mut(441).zip(far.chunks(441)) {
+            canceller.process(mic, far);
+        }
+        output
+    }
+
+    #[test]
+    fn removes_delayed_echo() {
+        let far = far_end(8.0);
+        let mic = echo_of(&far, 640); // 40 ms
+
+        let mut canceller = EchoCanceller::new(RATE, 16, 250, 0.0);
+        let output = run(&mut canceller, &mic, &far);
+
+        let stats = canceller.take_stats().unwrap();
+        assert!((stats.delay_ms - 40.0).abs() < 3.0, "{stats:?}");
+        assert!(stats.erl_db > 3.0, "{stats:?}");
+
+        // Once converged the echo is mostly gone even without suppression
+        let tail = mic.len() - RATE as usize * 2..;
+        let erle = 10.0 * (energy(&mic[tail.clone()]) / energy(&output[tail])).log10();
+        assert!(erle > 20.0, "ERLE {erle} dB");
+    }
+
+    #[test]
+    fn keeps_speech_over_the_robot() {
+        let far = far_end(8.0);
+        let mut mic = echo_of(&far, 320);
+
+        let mut canceller = EchoCanceller::new(RATE, 16, 250, 30.0);
+        run(
+            &mut canceller,
+            &mic[..RATE as usize * 6],
+            &far[..RATE as usize * 6],
+        );
+
+        // Someone talks over the last two seconds
+        let near: Vec<f32> = (0..RATE as usize * 2)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 300.0 * i as f32 / RATE as f32).sin())
+            .collect();
+        let start = RATE as usize * 6;
+        for (sample, near) in mic[start..].iter_mut().zip(&near) {
+            *sample += near;
+        }
+
+        let output = run(&mut canceller, &mic[start..], &far[start..]);
+        let kept = energy(&output) / energy(&near);
+        assert!(kept > 0.5, "kept {kept}");
+    }
+
+    #[test]
+    fn passes_input_through_without_a_reference() {
+        let mic = far_end(1.0);
+        let far = vec![0.0; mic.len()];
+
+        let mut canceller = EchoCanceller::new(RATE, 16, 250, 30.0);
+        let output = run(&mut canceller, &mic, &far);
+
+        assert_eq!(output, mic);
+        assert!(canceller.take_stats().is_none());
+    }
+
+    #[test]
+    fn reference_reader_waits_for_a_cushion_then_pairs_samples() {
+        let reference = EchoReference::new(RATE);
+        let mut writer = reference.writer(PcmFormat {
+            sample_rate: RATE,
+            channels: 2,
+        });
+        let mut reader = reference.reader();
+        let mut far = Vec::new();
+
+        writer.write(&[0.5; 400]);
+        reader.read(100, &mut far);
+        assert_eq!(far, [0.0; 100]);
+
+        writer.write(&[0.5; 1600]);
+        reader.read(100, &mut far);
+        assert_eq!(far, [0.5; 100]);
+    }
+}
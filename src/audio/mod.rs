@@ -1,2 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+pub mod archive;
+pub mod devices;
+pub mod echo;
 pub mod player;
 pub mod recorder;
+pub mod resample;
+pub mod wake_word;
+
+/// Layout of interleaved signed 16-bit little-endian PCM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl PcmFormat {
+    /// How long `bytes` of audio in this format play for.
+    pub fn duration_ms(&self, bytes: usize) -> u64 {
+        let bytes_per_second = self.sample_rate as u64 * self.channels as u64 * 2;
+        (bytes as u64 * 1000) / bytes_per_second.max(1)
+    }
+}
@@ -1,84 +1,151 @@
-use std::sync::{Arc, Mutex, mpsc};
+use std::{
+    f32::consts::PI,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
 
 use cpal::{
-    BufferSize, SampleRate, StreamConfig,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, FromSample, Sample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig,
+    SupportedStreamConfig, SupportedStreamConfigRange,
+    traits::{DeviceTrait, StreamTrait},
 };
 use ringbuf::{
     HeapRb,
-    traits::{Consumer, Producer},
+    traits::{Consumer, Observer, Producer},
 };
 use tokio_tungstenite::tungstenite::Bytes;
 
-use crate::events::AppEvent;
+use crate::{
+    audio::{
+        PcmFormat,
+        devices::{AudioDirection, find_device},
+        echo::{EchoReference, ReferenceWriter},
+        resample::{Resampler, downmix_into},
+    },
+    config::CONFIG,
+    events::{AppEvent, LogEventPayload, PlaybackProgressEventPayload},
+    types::logs::LogLevel,
+};
 
 type AudioBuffer = Arc<Mutex<HeapRb<f32>>>;
 
+/// Rate the TTS audio arrives at, devices that support it play it without resampling.
+const PREFERRED_SAMPLE_RATE: u32 = 44100;
+const BUFFER_SECONDS: usize = 100;
+const MAX_VOLUME: f32 = 1.5;
+/// How long the buffer has to stay empty before playback counts as finished.
+const DRAIN_GRACE: Duration = Duration::from_millis(150);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct AudioPlayer {
     event_sender: tokio::sync::mpsc::Sender<AppEvent>,
     output_stream: Option<cpal::Stream>,
+    /// Interleaved samples in `output_format`
     buffer: AudioBuffer,
+    output_format: PcmFormat,
+    /// Converts chunks to the output rate, keyed by the source rate
+    resampler: Option<(u32, Resampler)>,
+    /// Device to play through by name, `None` for the system default
+    device_name: Option<String>,
     detection_buffer: AudioBuffer,
     volume_threshold: f32,
+    /// Output gain stored as `f32` bits so the output callback can read it without locking
+    volume: Arc<AtomicU32>,
+    /// Receives everything played, when echo cancellation is enabled
+    echo_reference: Option<EchoReference>,
 }
 
 impl AudioPlayer {
-    pub fn new(event_sender: tokio::sync::mpsc::Sender<AppEvent>) -> Self {
-        let buffer_size = 48000 * 100;
+    pub fn new(
+        event_sender: tokio::sync::mpsc::Sender<AppEvent>,
+        echo_reference: Option<EchoReference>,
+    ) -> Self {
+        let output_format = PcmFormat {
+            sample_rate: PREFERRED_SAMPLE_RATE,
+            channels: 1,
+        };
         let detection_buffer_size = 44100 * 2; // 2 seconds at 44.1kHz
 
         Self {
             event_sender,
             output_stream: None,
-            buffer: Arc::new(Mutex::new(HeapRb::new(buffer_size))),
+            buffer: new_buffer(output_format),
+            output_format,
+            resampler: None,
+            device_name: CONFIG.audio.output_device.clone(),
             detection_buffer: Arc::new(Mutex::new(HeapRb::new(detection_buffer_size))),
             volume_threshold: 0.01,
+            volume: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            echo_reference,
         }
     }
 
+    /// Opens the output stream, closing the current one first when restarting.
     pub async fn start(&mut self) -> Result<(), anyhow::Error> {
-        let host = cpal::default_host();
+        self.close();
 
-        let Some(device) = host.default_output_device() else {
-            return Err(anyhow::anyhow!("No default output audio device found"));
+        let device = find_device(AudioDirection::Output, self.device_name.as_deref())?;
+        let device_name = device.name().unwrap_or(String::from("<unknown>"));
+
+        // DACs often only support 48 kHz stereo, so use what the device offers
+        // and convert the audio to it instead of forcing the TTS format
+        let Some(config) = choose_output_config(device.supported_output_configs()?) else {
+            return Err(anyhow::anyhow!("Output device has no supported config"));
         };
 
-        let device_name = device.name().unwrap_or(String::from("<unknown>"));
+        let sample_format = config.sample_format();
+        self.output_format = PcmFormat {
+            sample_rate: config.sample_rate().0,
+            channels: config.channels(),
+        };
+        self.buffer = new_buffer(self.output_format);
+        self.resampler = None;
 
         self.event_sender
-            .send(AppEvent::AudioSetOutputDevice(device_name))
+            .send(AppEvent::Log(LogEventPayload {
+                level: LogLevel::Info,
+                message: format!(
+                    "Audio output: {} Hz, {} channel(s), {sample_format}",
+                    self.output_format.sample_rate, self.output_format.channels
+                ),
+            }))
             .await?;
 
-        let config = StreamConfig {
-            channels: 1,
-            sample_rate: SampleRate(44100),
-            buffer_size: BufferSize::Default,
+        let config: StreamConfig = config.into();
+        let (err_tx, mut err_rx) = tokio::sync::mpsc::unbounded_channel();
+        let output = Output {
+            buffer: self.buffer.clone(),
+            volume: self.volume.clone(),
+            tracker: PlaybackTracker::new(self.event_sender.clone(), self.output_format),
+            echo: self
+                .echo_reference
+                .as_ref()
+                .map(|reference| reference.writer(self.output_format)),
+            played: Vec::new(),
         };
 
-        let (err_tx, err_rx) = mpsc::channel();
-
-        let buffer = self.buffer.clone();
-
-        let output_stream = device.build_output_stream(
-            &config,
-            move |data: &mut [f32], _| {
-                let Ok(mut buffer) = buffer.lock() else {
-                    return;
-                };
-
-                for sample in data.iter_mut() {
-                    *sample = buffer.try_pop().unwrap_or(0.0);
-                }
-            },
-            move |err| {
-                let _ = err_tx.send(err);
-            },
-            None,
-        )?;
+        let output_stream = match sample_format {
+            SampleFormat::I8 => build_output_stream::<i8>(&device, &config, output, err_tx),
+            SampleFormat::I16 => build_output_stream::<i16>(&device, &config, output, err_tx),
+            SampleFormat::I32 => build_output_stream::<i32>(&device, &config, output, err_tx),
+            SampleFormat::I64 => build_output_stream::<i64>(&device, &config, output, err_tx),
+            SampleFormat::U8 => build_output_stream::<u8>(&device, &config, output, err_tx),
+            SampleFormat::U16 => build_output_stream::<u16>(&device, &config, output, err_tx),
+            SampleFormat::U32 => build_output_stream::<u32>(&device, &config, output, err_tx),
+            SampleFormat::U64 => build_output_stream::<u64>(&device, &config, output, err_tx),
+            SampleFormat::F32 => build_output_stream::<f32>(&device, &config, output, err_tx),
+            SampleFormat::F64 => build_output_stream::<f64>(&device, &config, output, err_tx),
+            format => {
+                return Err(anyhow::anyhow!("Unsupported sample format: {format:?}"));
+            }
+        }?;
 
         let event_sender = self.event_sender.clone();
         tokio::spawn(async move {
-            for err in err_rx {
+            while let Some(err) = err_rx.recv().await {
                 let _ = event_sender
                     .send(AppEvent::AudioPlaybackError(err.to_string()))
                     .await;
@@ -91,28 +158,507 @@ impl AudioPlayer {
             stream.play()?;
         }
 
+        self.event_sender
+            .send(AppEvent::AudioSetOutputDevice(Some(device_name)))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Closes the output stream, dropping any queued audio.
+    pub fn close(&mut self) {
+        self.stop();
+
+        if self.output_stream.take().is_some() {
+            let _ = self
+                .event_sender
+                .try_send(AppEvent::AudioSetOutputDevice(None));
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.output_stream.is_some()
+    }
+
+    /// Selects the device `start` opens next, `None` for the system default.
+    pub fn set_device(&mut self, device_name: Option<String>) {
+        self.device_name = device_name;
+    }
+
+    /// Queues PCM audio in `format`, converting it to the output device's format.
+    pub fn push_audio_chunk(
+        &mut self,
+        audio_bytes: &Bytes,
+        format: PcmFormat,
+    ) -> Result<(), anyhow::Error> {
+        if format.channels == 0 || format.sample_rate == 0 {
+            return Err(anyhow::anyhow!("Invalid audio chunk format: {format:?}"));
+        }
+
+        let mut samples = Vec::with_capacity(audio_bytes.len() / 2);
+        downmix_into(
+            &pcm_s16le_to_f32(audio_bytes),
+            format.channels,
+            &mut samples,
+        );
+
+        let output_rate = self.output_format.sample_rate;
+        if format.sample_rate != output_rate {
+            if !matches!(self.resampler, Some((rate, _)) if rate == format.sample_rate) {
+                self.resampler = Some((
+                    format.sample_rate,
+                    Resampler::new(format.sample_rate, output_rate),
+                ));
+            }
+
+            let mut resampled = Vec::with_capacity(samples.len() * 2);
+            if let Some((_, resampler)) = &mut self.resampler {
+                resampler.process(&samples, &mut resampled);
+            }
+            samples = resampled;
+        }
+
+        self.push_mono(&samples);
+
         Ok(())
     }
 
-    pub fn push_audio_chunk(&mut self, audio_bytes: &Bytes) -> Result<(), anyhow::Error> {
-        let samples: Vec<f32> = audio_bytes
-            .chunks_exact(2)
-            .map(|chunk| {
-                let sample = i16::from_le_bytes([chunk[0], chunk[1]]);
-                sample as f32 / i16::MAX as f32
+    /// Plays two short rising notes to signal that the robot is listening.
+    pub fn play_chime(&mut self) {
+        self.play_notes(&[880.0, 1320.0], 0.2);
+    }
+
+    /// Plays two soft falling notes to signal that the robot stopped listening.
+    pub fn play_end_cue(&mut self) {
+        self.play_notes(&[660.0, 440.0], 0.1);
+    }
+
+    /// Whether audio is queued that the output device hasn't played yet.
+    pub fn has_queued_audio(&self) -> bool {
+        self.buffer.lock().is_ok_and(|buf| !buf.is_empty())
+    }
+
+    fn play_notes(&mut self, notes: &[f32], amplitude: f32) {
+        let sample_rate = self.output_format.sample_rate;
+        let note_len = sample_rate as usize / 12;
+        let samples: Vec<f32> = notes
+            .iter()
+            .flat_map(|&hz| {
+                (0..note_len).map(move |i| {
+                    let t = i as f32 / sample_rate as f32;
+                    let envelope = (PI * i as f32 / note_len as f32).sin();
+                    amplitude * envelope * (2.0 * PI * hz * t).sin()
+                })
             })
             .collect();
 
+        self.push_mono(&samples);
+    }
+
+    /// Queues mono samples at the output rate, copied to every output channel.
+    fn push_mono(&mut self, samples: &[f32]) {
+        let channels = self.output_format.channels as usize;
+
         if let Ok(mut buf) = self.buffer.lock() {
-            buf.push_slice(&samples);
+            for &sample in samples {
+                for _ in 0..channels {
+                    let _ = buf.try_push(sample);
+                }
+            }
         }
+    }
 
-        Ok(())
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    /// Adjusts the output volume by `delta`, between silent and `MAX_VOLUME`, returning the new volume.
+    pub fn change_volume(&mut self, delta: f32) -> f32 {
+        let volume = (self.volume() + delta).clamp(0.0, MAX_VOLUME);
+        self.volume.store(volume.to_bits(), Ordering::Relaxed);
+        volume
+    }
+
+    /// Queues what the resampler still holds back once a stream has ended.
+    pub fn finish_stream(&mut self) {
+        if let Some((_, mut resampler)) = self.resampler.take() {
+            let mut tail = Vec::new();
+            resampler.flush(&mut tail);
+            self.push_mono(&tail);
+        }
     }
 
     pub fn stop(&mut self) {
         if let Ok(mut buf) = self.buffer.lock() {
             buf.clear();
         }
+
+        // Don't let the end of the cancelled audio leak into the next response
+        self.resampler = None;
+    }
+}
+
+fn new_buffer(format: PcmFormat) -> AudioBuffer {
+    let size = format.sample_rate as usize * format.channels as usize * BUFFER_SECONDS;
+    Arc::new(Mutex::new(HeapRb::new(size)))
+}
+
+/// Picks the config to play TTS audio with: one that takes the TTS sample rate
+/// as is if possible, then sample formats and channel counts that need the
+/// least conversion.
+fn choose_output_config(
+    configs: impl Iterator<Item = SupportedStreamConfigRange>,
+) -> Option<SupportedStreamConfig> {
+    configs
+        .max_by_key(|config| {
+            let plays_preferred_rate = config.min_sample_rate().0 <= PREFERRED_SAMPLE_RATE
+                && PREFERRED_SAMPLE_RATE <= config.max_sample_rate().0;
+            let format_rank = match config.sample_format() {
+                SampleFormat::F32 => 2,
+                SampleFormat::I16 => 1,
+                _ => 0,
+            };
+
+            (
+                plays_preferred_rate,
+                format_rank,
+                std::cmp::Reverse(config.channels()),
+            )
+        })
+        .map(|config| {
+            let rate =
+                PREFERRED_SAMPLE_RATE.clamp(config.min_sample_rate().0, config.max_sample_rate().0);
+            config.with_sample_rate(SampleRate(rate))
+        })
+}
+
+/// Everything the output callback needs.
+struct Output {
+    buffer: AudioBuffer,
+    volume: Arc<AtomicU32>,
+    tracker: PlaybackTracker,
+    echo: Option<ReferenceWriter>,
+    /// What went out this callback, for the echo reference
+    played: Vec<f32>,
+}
+
+impl Output {
+    fn fill<T: Sample + FromSample<f32>>(&mut self, data: &mut [T]) {
+        let Ok(mut buffer) = self.buffer.lock() else {
+            return;
+        };
+
+        let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
+        let mut played = 0;
+        self.played.clear();
+
+        for sample in data.iter_mut() {
+            let value = match buffer.try_pop() {
+                Some(value) => {
+                    played += 1;
+                    value
+                }
+                None => 0.0,
+            };
+
+            let value = (value * volume).clamp(-1.0, 1.0);
+            self.played.push(value);
+            *sample = T::from_sample(value);
+        }
+
+        let buffered = buffer.occupied_len();
+        drop(buffer);
+
+        if let Some(echo) = &mut self.echo {
+            echo.write(&self.played);
+        }
+
+        self.tracker.update(played, data.len(), buffered);
+    }
+}
+
+/// Turns buffer occupancy into playback events. Short underruns while TTS
+/// audio is still arriving don't count as the end of playback.
+struct PlaybackTracker {
+    event_sender: tokio::sync::mpsc::Sender<AppEvent>,
+    /// Interleaved samples per second of output
+    samples_per_second: usize,
+    playing: bool,
+    played: usize,
+    since_progress: usize,
+    /// Samples of silence output since the buffer last had audio
+    silent: usize,
+}
+
+impl PlaybackTracker {
+    fn new(event_sender: tokio::sync::mpsc::Sender<AppEvent>, format: PcmFormat) -> Self {
+        Self {
+            event_sender,
+            samples_per_second: format.sample_rate as usize * format.channels as usize,
+            playing: false,
+            played: 0,
+            since_progress: 0,
+            silent: 0,
+        }
+    }
+
+    /// Called after each output callback with how many of its `total` samples
+    /// came from the buffer and how many are left in it.
+    fn update(&mut self, played: usize, total: usize, buffered: usize) {
+        if played > 0 {
+            if !self.playing {
+                self.playing = true;
+                self.played = 0;
+                self.since_progress = 0;
+                self.send(AppEvent::PlaybackStarted);
+            }
+
+            self.played += played;
+            self.since_progress += played;
+            self.silent = 0;
+        }
+
+        if !self.playing {
+            return;
+        }
+
+        self.silent += total - played;
+
+        if buffered == 0 && self.silent >= self.to_samples(DRAIN_GRACE) {
+            self.playing = false;
+            self.send(AppEvent::PlaybackDrained);
+        } else if self.since_progress >= self.to_samples(PROGRESS_INTERVAL) {
+            self.since_progress = 0;
+            self.send(AppEvent::PlaybackProgress(PlaybackProgressEventPayload {
+                played_ms: self.to_ms(self.played),
+                buffered_ms: self.to_ms(buffered),
+            }));
+        }
+    }
+
+    fn to_samples(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.samples_per_second as f64) as usize
+    }
+
+    fn to_ms(&self, samples: usize) -> u64 {
+        (samples as u64 * 1000) / self.samples_per_second.max(1) as u64
+    }
+
+    /// The audio thread can't wait, events are dropped when the bus is full.
+    fn send(&self, event: AppEvent) {
+        let _ = self.event_sender.try_send(event);
+    }
+}
+
+fn build_output_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut output: Output,
+    err_tx: tokio::sync::mpsc::UnboundedSender<cpal::StreamError>,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| output.fill(data),
+        move |err| {
+            let _ = err_tx.send(err);
+        },
+        None,
+    )
+}
+
+/// Converts signed 16-bit little-endian PCM to `f32` samples, dropping a trailing odd byte.
+fn pcm_s16le_to_f32(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(2)
+        .map(|chunk| {
+            let sample = i16::from_le_bytes([chunk[0], chunk[1]]);
+            sample as f32 / i16::MAX as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use cpal::SupportedBufferSize;
+
+    use super::*;
+    use crate::config::Config;
+
+    const MONO_44100: PcmFormat = PcmFormat {
+        sample_rate: 44100,
+        channels: 1,
+    };
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min),
+            SampleRate(max),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    #[test]
+    fn converts_pcm_s16le_to_f32() {
+        let bytes = [0x00, 0x00, 0xff, 0x7f, 0x01, 0x80, 0x00, 0x40];
+        let samples = pcm_s16le_to_f32(&bytes);
+
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[1], 1.0);
+        assert_eq!(samples[2], -1.0);
+        assert!((samples[3] - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn drops_trailing_odd_byte() {
+        assert_eq!(pcm_s16le_to_f32(&[0xff, 0x7f, 0x12]), [1.0]);
+    }
+
+    #[test]
+    fn push_audio_chunk_buffers_samples() {
+        Config::init_for_tests();
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let mut player = AudioPlayer::new(sender, None);
+
+        player
+            .push_audio_chunk(&Bytes::from_static(&[0xff, 0x7f, 0x00, 0x00]), MONO_44100)
+            .unwrap();
+
+        let mut buffer = player.buffer.lock().unwrap();
+        assert_eq!(buffer.occupied_len(), 2);
+        assert_eq!(buffer.try_pop(), Some(1.0));
+
+        drop(buffer);
+        player.stop();
+        assert!(player.buffer.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn prefers_configs_that_play_the_tts_rate() {
+        let config = choose_output_config(
+            [
+                range(2, 48000, 48000, SampleFormat::F32),
+                range(2, 8000, 96000, SampleFormat::I16),
+                range(1, 8000, 96000, SampleFormat::I16),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+
+        assert_eq!(config.channels(), 1);
+        assert_eq!(config.sample_rate(), SampleRate(44100));
+        assert_eq!(config.sample_format(), SampleFormat::I16);
+    }
+
+    #[test]
+    fn falls_back_to_the_closest_rate() {
+        let config =
+            choose_output_config([range(2, 48000, 48000, SampleFormat::I16)].into_iter()).unwrap();
+
+        assert_eq!(config.channels(), 2);
+        assert_eq!(config.sample_rate(), SampleRate(48000));
+        assert!(choose_output_config(std::iter::empty()).is_none());
+    }
+
+    #[test]
+    fn converts_chunks_to_the_output_format() {
+        Config::init_for_tests();
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let mut player = AudioPlayer::new(sender, None);
+        player.output_format = PcmFormat {
+            sample_rate: 48000,
+            channels: 2,
+        };
+        player.buffer = new_buffer(player.output_format);
+
+        // 0.1 seconds of stereo 22.05 kHz audio
+        let chunk: Vec<u8> = (0..2205).flat_map(|_| [0x00, 0x40, 0x00, 0x40]).collect();
+        let format = PcmFormat {
+            sample_rate: 22050,
+            channels: 2,
+        };
+        player
+            .push_audio_chunk(&Bytes::from(chunk), format)
+            .unwrap();
+
+        let buffer = player.buffer.lock().unwrap();
+        let frames: Vec<f32> = buffer.iter().copied().collect();
+
+        // Everything but the resampler's lookahead comes out as 48 kHz stereo
+        assert_eq!(frames.len() % 2, 0);
+        assert!(
+            (4800 - frames.len() / 2) < 40,
+            "{} frames",
+            frames.len() / 2
+        );
+        assert!(
+            frames[2000..4000]
+                .iter()
+                .all(|sample| (sample - 0.5).abs() < 0.01)
+        );
+    }
+
+    #[test]
+    fn rejects_chunks_without_channels() {
+        Config::init_for_tests();
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let mut player = AudioPlayer::new(sender, None);
+        let format = PcmFormat {
+            sample_rate: 44100,
+            channels: 0,
+        };
+
+        assert!(player.push_audio_chunk(&Bytes::new(), format).is_err());
+    }
+
+    #[test]
+    fn tracks_playback_until_the_buffer_stays_empty() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let mut tracker = PlaybackTracker::new(sender, MONO_44100);
+        let mut events = || {
+            std::iter::from_fn(|| receiver.try_recv().ok())
+                .map(|event| match event {
+                    AppEvent::PlaybackStarted => String::from("started"),
+                    AppEvent::PlaybackProgress(progress) => {
+                        format!("progress {}/{}", progress.played_ms, progress.buffered_ms)
+                    }
+                    AppEvent::PlaybackDrained => String::from("drained"),
+                    other => panic!("unexpected {other:?}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        tracker.update(0, 441, 0);
+        assert!(events().is_empty());
+
+        tracker.update(441, 441, 4410);
+        assert_eq!(events(), ["started"]);
+
+        tracker.update(4410, 4410, 441);
+        assert_eq!(events(), ["progress 110/10"]);
+
+        // A short underrun while more audio is on its way
+        tracker.update(441, 4410, 0);
+        tracker.update(441, 441, 0);
+        assert!(events().is_empty());
+
+        tracker.update(0, 4410, 0);
+        assert!(events().is_empty());
+        tracker.update(0, 4410, 0);
+        assert_eq!(events(), ["drained"]);
+
+        tracker.update(0, 4410, 0);
+        assert!(events().is_empty());
     }
 }
@@ -1,6 +1,6 @@
 use cpal::{
-    Stream,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, FromSample, Sample, SampleFormat, SizedSample, Stream, StreamConfig,
+    traits::{DeviceTrait, StreamTrait},
 };
 use crossbeam_channel::{Receiver, Sender, bounded};
 use hound::{WavSpec, WavWriter};
@@ -21,7 +21,16 @@ use std::{
 };
 use tokio::sync::mpsc;
 
-use crate::events::AppEvent;
+use crate::{
+    audio::{
+        devices::{AudioDirection, find_device},
+        echo::{EchoCanceller, EchoReference},
+        resample::{Resampler, downmix_into},
+        wake_word::{self, WakeWordInput},
+    },
+    config::CONFIG,
+    events::AppEvent,
+};
 
 enum RecordingEvent {
     Samples(Vec<f32>),
@@ -32,57 +41,72 @@ enum RecordingEvent {
 pub struct AudioRecorder {
     event_sender: mpsc::Sender<AppEvent>,
     is_recording: Arc<AtomicBool>,
-    channels: u16,
-    sample_rate: u32,
-    samples_tx: Sender<Result<RecordingEvent, String>>,
-    samples_rx: Receiver<Result<RecordingEvent, String>>,
+    samples_tx: Sender<RecordingEvent>,
+    samples_rx: Receiver<RecordingEvent>,
+    /// Device to record from by name, `None` for the system default
+    device_name: Option<String>,
     /// keep stream alive to avoid closing device
     stream: Option<Stream>,
+    /// Feeds the wake word detector when it's enabled
+    wake_word_tx: Option<Sender<WakeWordInput>>,
+    /// What the player outputs, when echo cancellation is enabled
+    echo_reference: Option<EchoReference>,
 }
 
 impl AudioRecorder {
-    pub fn new(event_sender: mpsc::Sender<AppEvent>) -> Self {
+    pub fn new(
+        event_sender: mpsc::Sender<AppEvent>,
+        echo_reference: Option<EchoReference>,
+    ) -> Self {
         let (samples_tx, samples_rx) = bounded(50);
 
         Self {
             event_sender,
             is_recording: Arc::new(AtomicBool::new(false)),
-            channels: 2,
-            sample_rate: 44100,
             samples_tx,
             samples_rx,
+            device_name: CONFIG.audio.input_device.clone(),
             stream: None,
+            wake_word_tx: None,
+            echo_reference,
         }
     }
 
+    /// Opens the input stream, closing the current one first when restarting.
     pub async fn start(&mut self) -> Result<(), anyhow::Error> {
-        let event_sender = self.event_sender.clone();
-
-        let host = cpal::default_host();
+        self.close();
 
-        let Some(device) = host.default_input_device() else {
-            return Err(anyhow::anyhow!("No default input audio device found"));
-        };
+        let event_sender = self.event_sender.clone();
 
+        let device = find_device(AudioDirection::Input, self.device_name.as_deref())?;
         let device_name = device.name().unwrap_or(String::from("<unknown>"));
 
-        let _ = event_sender
-            .send(AppEvent::AudioSetInputDevice(device_name))
-            .await;
-
         let config = device.default_input_config()?;
-        let sample_rate = config.sample_rate().0;
+        let device_rate = config.sample_rate().0;
         let channels = config.channels();
 
-        self.channels = channels;
-        self.sample_rate = sample_rate;
+        // Everything past the callback works on mono audio at the rate
+        // recordings are uploaded at, which is also cheap enough to cancel
+        // echo at on a Pi
+        let sample_rate = CONFIG.audio.recording_sample_rate;
+        let mut resampler =
+            (device_rate != sample_rate).then(|| Resampler::new(device_rate, sample_rate));
+        let mut echo = self.echo_reference.as_ref().map(|reference| {
+            let config = &CONFIG.echo_cancellation;
+            let canceller = EchoCanceller::new(
+                sample_rate,
+                config.filter_ms,
+                config.max_delay_ms,
+                config.suppression_db,
+            );
+            (reference.reader(), canceller)
+        });
 
         // Create ring buffer for 2 seconds of audio at sample rate
         let buffer_size = (sample_rate * 2) as usize; // 2 seconds
         let detection_buffer: Arc<Mutex<HeapRb<f32>>> =
             Arc::new(Mutex::new(HeapRb::new(buffer_size)));
 
-        let _volume_threshold = 0.03f32; // Adjust this threshold as needed
         let window_size = 1024; // Analysis window size
 
         // Debounce mechanism
@@ -90,110 +114,217 @@ impl AudioRecorder {
         let cooldown_duration = Duration::from_millis(5);
 
         let samples_tx = self.samples_tx.clone();
-        let err_tx = self.samples_tx.clone();
-
-        let input_stream = match config.sample_format() {
-            cpal::SampleFormat::F32 => {
-                let detection_buffer_clone = detection_buffer.clone();
-                let last_event_time_clone = last_event_time.clone();
-                device.build_input_stream(
-                    &config.into(),
-                    move |data: &[f32], _: &_| {
-                        let _ = samples_tx.send(Ok(RecordingEvent::Samples(data.to_vec())));
-
-                        // ---------- Volume monitoring ----------
-                        if let Ok(mut buf) = detection_buffer_clone.lock() {
-                            for &sample in data {
-                                buf.push_overwrite(sample);
-                            }
+        let (err_tx, mut err_rx) = mpsc::unbounded_channel();
+        let is_recording = self.is_recording.clone();
+
+        self.wake_word_tx = CONFIG.wake_word.enabled.then(|| {
+            wake_word::spawn(
+                event_sender.clone(),
+                sample_rate,
+                1,
+                self.is_recording.clone(),
+            )
+        });
+        let wake_word_tx = self.wake_word_tx.clone();
+        let stats_sender = event_sender.clone();
+
+        let mut mono = Vec::new();
+        let mut resampled = Vec::new();
+        let mut far = Vec::new();
+
+        let on_samples = move |data: &[f32]| {
+            mono.clear();
+            downmix_into(data, channels, &mut mono);
+
+            let data = match &mut resampler {
+                Some(resampler) => {
+                    resampled.clear();
+                    resampler.process(&mono, &mut resampled);
+                    &mut resampled
+                }
+                None => &mut mono,
+            };
+
+            // Before anything listens, so neither the wake word nor the
+            // transcription hear the robot itself
+            if let Some((reference, canceller)) = &mut echo {
+                reference.read(data.len(), &mut far);
+                canceller.process(data, &far);
+
+                if let Some(stats) = canceller.take_stats() {
+                    let _ = stats_sender.try_send(AppEvent::EchoStats(stats));
+                }
+            }
+
+            let data: &[f32] = data;
+
+            if let Some(wake_word_tx) = &wake_word_tx {
+                // Never block the audio thread, dropping input is fine here
+                let _ = wake_word_tx.try_send(WakeWordInput::Samples(data.to_vec()));
+            }
+
+            // Nothing reads the samples between recordings
+            if !is_recording.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let _ = samples_tx.send(RecordingEvent::Samples(data.to_vec()));
+
+            // ---------- Volume monitoring ----------
+            if let Ok(mut buf) = detection_buffer.lock() {
+                for &sample in data {
+                    buf.push_overwrite(sample);
+                }
+
+                if buf.occupied_len() >= window_size {
+                    let mut sum_squares = 0.0;
+                    let sample_count = window_size.min(buf.occupied_len());
+
+                    let buffer_data: Vec<f32> = buf
+                        .as_slices()
+                        .0
+                        .iter()
+                        .chain(buf.as_slices().1.iter())
+                        .rev() // Get most recent samples first
+                        .take(sample_count)
+                        .copied()
+                        .collect();
+
+                    for sample in buffer_data {
+                        sum_squares += sample * sample;
+                    }
+
+                    if sample_count > 0 {
+                        let rms_volume = (sum_squares / sample_count as f32).sqrt();
+                        let now = Instant::now();
 
-                            if buf.occupied_len() >= window_size {
-                                let mut sum_squares = 0.0;
-                                let sample_count = window_size.min(buf.occupied_len());
-
-                                let buffer_data: Vec<f32> = buf
-                                    .as_slices()
-                                    .0
-                                    .iter()
-                                    .chain(buf.as_slices().1.iter())
-                                    .rev() // Get most recent samples first
-                                    .take(sample_count)
-                                    .copied()
-                                    .collect();
-
-                                for sample in buffer_data {
-                                    sum_squares += sample * sample;
-                                }
-
-                                if sample_count > 0 {
-                                    let rms_volume = (sum_squares / sample_count as f32).sqrt();
-                                    let now = Instant::now();
-
-                                    // debounce
-                                    if let Ok(mut last_time) = last_event_time_clone.lock() {
-                                        if now.duration_since(*last_time) >= cooldown_duration {
-                                            let _ = samples_tx
-                                                .send(Ok(RecordingEvent::Volume(rms_volume)));
-                                            *last_time = now;
-                                        }
-                                    }
-                                }
+                        // debounce
+                        if let Ok(mut last_time) = last_event_time.lock() {
+                            if now.duration_since(*last_time) >= cooldown_duration {
+                                let _ = samples_tx.send(RecordingEvent::Volume(rms_volume));
+                                *last_time = now;
                             }
                         }
-                    },
-                    move |err| {
-                        let _ = err_tx.send(Err(err.to_string()));
-                    },
-                    None,
-                )?
-            }
-            _ => {
-                return Err(anyhow::anyhow!(
-                    "Unsupported sample format: {:?}",
-                    config.sample_format()
-                ));
+                    }
+                }
             }
         };
 
-        input_stream.play().unwrap();
+        let sample_format = config.sample_format();
+        let config: StreamConfig = config.into();
+
+        // Cheap USB mics are usually integer only, convert everything to f32
+        let input_stream = match sample_format {
+            SampleFormat::I8 => build_input_stream::<i8>(&device, &config, on_samples, err_tx),
+            SampleFormat::I16 => build_input_stream::<i16>(&device, &config, on_samples, err_tx),
+            SampleFormat::I32 => build_input_stream::<i32>(&device, &config, on_samples, err_tx),
+            SampleFormat::I64 => build_input_stream::<i64>(&device, &config, on_samples, err_tx),
+            SampleFormat::U8 => build_input_stream::<u8>(&device, &config, on_samples, err_tx),
+            SampleFormat::U16 => build_input_stream::<u16>(&device, &config, on_samples, err_tx),
+            SampleFormat::U32 => build_input_stream::<u32>(&device, &config, on_samples, err_tx),
+            SampleFormat::U64 => build_input_stream::<u64>(&device, &config, on_samples, err_tx),
+            SampleFormat::F32 => build_input_stream::<f32>(&device, &config, on_samples, err_tx),
+            SampleFormat::F64 => build_input_stream::<f64>(&device, &config, on_samples, err_tx),
+            format => {
+                return Err(anyhow::anyhow!("Unsupported sample format: {format:?}"));
+            }
+        }?;
+
+        input_stream.play()?;
         self.stream = Some(input_stream);
 
+        // Errors after this point mostly mean the device went away, the app
+        // decides whether to reconnect
+        tokio::spawn(async move {
+            while let Some(err) = err_rx.recv().await {
+                let _ = event_sender
+                    .send(AppEvent::AudioInputStreamError(err))
+                    .await;
+            }
+        });
+
+        let _ = self
+            .event_sender
+            .send(AppEvent::AudioSetInputDevice(Some(device_name)))
+            .await;
+
         Ok(())
     }
 
+    /// Closes the input stream, ending any recording with what was captured so far.
+    pub fn close(&mut self) {
+        self.stop_recording();
+
+        if self.stream.take().is_some() {
+            self.wake_word_tx = None;
+            let _ = self
+                .event_sender
+                .try_send(AppEvent::AudioSetInputDevice(None));
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Selects the device `start` opens next, `None` for the system default.
+    pub fn set_device(&mut self, device_name: Option<String>) {
+        self.device_name = device_name;
+    }
+
     pub fn start_recording(&mut self) {
         if self.is_recording.load(Ordering::Relaxed) {
             return;
         }
 
+        if self.stream.is_none() {
+            let _ = self
+                .event_sender
+                .try_send(AppEvent::AudioRecordingFailed(String::from(
+                    "No input audio device",
+                )));
+            return;
+        }
+
         self.is_recording.store(true, Ordering::Relaxed);
 
         let event_sender = self.event_sender.clone();
         let samples_rx = self.samples_rx.clone();
 
-        let sample_rate = self.sample_rate;
-        let channels = self.channels;
-
         tokio::spawn(async move {
-            let send_error = async |message: &str| {
-                let _ = event_sender
-                    .send(AppEvent::AudioRecordingError(message.to_string()))
-                    .await;
-            };
-
             let _ = event_sender.send(AppEvent::AudioRecordingStarted).await;
 
-            let spec = WavSpec {
-                channels,
-                sample_rate,
-                bits_per_sample: 32,
-                sample_format: hound::SampleFormat::Float,
-            };
+            // Waiting on the stream's channel blocks, keep it off the runtime's workers
+            let volume_sender = event_sender.clone();
+            let encoded = tokio::task::spawn_blocking(move || {
+                let mut samples = Vec::new();
 
-            let mut buf = Vec::new();
-            let cursor = Cursor::new(&mut buf);
-            let mut writer = match WavWriter::new(cursor, spec) {
-                Ok(writer) => writer,
+                for event in samples_rx {
+                    match event {
+                        RecordingEvent::Samples(data) => {
+                            samples.extend_from_slice(&data);
+                        }
+                        RecordingEvent::Stop => {
+                            break;
+                        }
+                        RecordingEvent::Volume(volume) => {
+                            let _ = volume_sender.blocking_send(AppEvent::AudioDetected(volume));
+                        }
+                    }
+                }
+
+                encode_wav(&samples, CONFIG.audio.recording_sample_rate)
+            })
+            .await;
+
+            let buf = match encoded {
+                Ok(Ok(buf)) => buf,
+                Ok(Err(e)) => {
+                    let _ = event_sender
+                        .send(AppEvent::AudioRecordingFailed(e.to_string()))
+                        .await;
+                    return;
+                }
                 Err(e) => {
                     let _ = event_sender
                         .send(AppEvent::AudioRecordingFailed(e.to_string()))
@@ -202,34 +333,6 @@ impl AudioRecorder {
                 }
             };
 
-            for event in samples_rx {
-                match event {
-                    Ok(RecordingEvent::Samples(data)) => {
-                        for sample in data {
-                            if let Err(e) = writer.write_sample(sample) {
-                                send_error(&format!("Failed to write sample: {e}")).await;
-                            }
-                        }
-                    }
-                    Ok(RecordingEvent::Stop) => {
-                        break;
-                    }
-                    Ok(RecordingEvent::Volume(volume)) => {
-                        let _ = event_sender.send(AppEvent::AudioDetected(volume)).await;
-                    }
-                    Err(err) => {
-                        send_error(&format!("Failed to write sample: {err}")).await;
-                    }
-                }
-            }
-
-            if let Err(e) = writer.finalize() {
-                let _ = event_sender
-                    .send(AppEvent::AudioRecordingFailed(e.to_string()))
-                    .await;
-                return;
-            }
-
             let _ = event_sender
                 .send(AppEvent::AudioRecordingCompleted(buf))
                 .await;
@@ -242,10 +345,64 @@ impl AudioRecorder {
         }
 
         self.is_recording.store(false, Ordering::Release);
-        let _ = self.samples_tx.send(Ok(RecordingEvent::Stop));
+        let _ = self.samples_tx.send(RecordingEvent::Stop);
+    }
+
+    /// Makes the wake word detector pick up newly recorded templates.
+    pub fn reload_wake_word_templates(&self) {
+        if let Some(wake_word_tx) = &self.wake_word_tx {
+            let _ = wake_word_tx.send(WakeWordInput::ReloadTemplates);
+        }
     }
 
     pub fn is_recording(&self) -> bool {
         self.is_recording.load(Ordering::Relaxed)
     }
 }
+
+fn build_input_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut on_samples: impl FnMut(&[f32]) + Send + 'static,
+    err_tx: mpsc::UnboundedSender<String>,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let mut converted = Vec::new();
+
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &_| {
+            converted.clear();
+            converted.extend(data.iter().map(|&sample| f32::from_sample(sample)));
+            on_samples(&converted);
+        },
+        move |err| {
+            let _ = err_tx.send(err.to_string());
+        },
+        None,
+    )
+}
+
+/// Encodes mono samples as 16-bit PCM, plenty for speech to text and a
+/// fraction of the size of the 32-bit float input.
+fn encode_wav(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, hound::Error> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut buf = Vec::new();
+    let mut writer = WavWriter::new(Cursor::new(&mut buf), spec)?;
+
+    for &sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+
+    writer.finalize()?;
+    Ok(buf)
+}
@@ -0,0 +1,236 @@
+//! Channel downmixing and sample rate conversion for recorded audio.
+
+use std::f64::consts::PI;
+
+/// Zero crossings of the sinc kernel on each side, more is sharper but slower.
+const ZERO_CROSSINGS: f64 = 16.0;
+/// Passband edge as a fraction of the lower Nyquist frequency, leaving room for the filter roll off.
+const PASSBAND: f64 = 0.95;
+
+/// Averages interleaved frames of `channels` samples into `mono`.
+pub fn downmix_into(interleaved: &[f32], channels: u16, mono: &mut Vec<f32>) {
+    let channels = channels.max(1) as usize;
+    mono.extend(
+        interleaved
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+    );
+}
+
+/// Converts mono audio that arrives in chunks from one rate to another with a
+/// windowed sinc filter, which also removes everything above the new Nyquist
+/// frequency. The filter needs a few samples of lookahead, so output lags the
+/// input slightly until [`Resampler::flush`].
+pub struct Resampler {
+    up: usize,
+    down: usize,
+    half_taps: usize,
+    kernel: Vec<Vec<f32>>,
+    /// Input that later output still needs, starting at input index `offset`
+    pending: Vec<f32>,
+    offset: usize,
+    /// Index of the next output sample
+    next: usize,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        let from_rate = from_rate.max(1);
+        let to_rate = to_rate.max(1);
+
+        // The output position advances `down / up` input samples per sample, so
+        // there are only `up` distinct filter phases to precompute.
+        let divisor = gcd(from_rate, to_rate);
+        let up = (to_rate / divisor) as usize;
+        let down = (from_rate / divisor) as usize;
+
+        let cutoff = (to_rate as f64 / from_rate as f64).min(1.0) * PASSBAND;
+        let half_taps = (ZERO_CROSSINGS / cutoff).ceil() as usize;
+        let taps = 2 * half_taps;
+
+        let kernel = (0..up)
+            .map(|phase| {
+                let fraction = phase as f64 / up as f64;
+                let weights: Vec<f64> = (0..taps)
+                    .map(|tap| {
+                        let x = fraction - (tap as f64 - half_taps as f64 + 1.0);
+                        cutoff * sinc(cutoff * x) * blackman(x / half_taps as f64)
+                    })
+                    .collect();
+
+                // Normalize so every phase passes DC at unity gain
+                let sum: f64 = weights.iter().sum();
+                weights.iter().map(|weight| (weight / sum) as f32).collect()
+            })
+            .collect();
+
+        Self {
+            up,
+            down,
+            half_taps,
+            kernel,
+            pending: Vec::new(),
+            offset: 0,
+            next: 0,
+        }
+    }
+
+    /// Appends the output for `input` that can be computed so far.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.pending.extend_from_slice(input);
+
+        let available = self.offset + self.pending.len();
+        while self.first_tap(self.next) + 2 * self.half_taps as isize <= available as isize {
+            output.push(self.sample(self.next));
+            self.next += 1;
+        }
+
+        let needed = self.first_tap(self.next).max(0) as usize;
+        if needed > self.offset {
+            let consumed = (needed - self.offset).min(self.pending.len());
+            self.pending.drain(..consumed);
+            self.offset += consumed;
+        }
+    }
+
+    /// Appends the rest of the output, treating the input as ended.
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        let total = self.output_len(self.offset + self.pending.len());
+        while self.next < total {
+            output.push(self.sample(self.next));
+            self.next += 1;
+        }
+    }
+
+    fn output_len(&self, input_len: usize) -> usize {
+        input_len * self.up / self.down
+    }
+
+    fn first_tap(&self, n: usize) -> isize {
+        ((n * self.down / self.up) as isize) - self.half_taps as isize + 1
+    }
+
+    /// Computes output sample `n`, input outside what was received counts as silence.
+    fn sample(&self, n: usize) -> f32 {
+        let first = self.first_tap(n);
+
+        self.kernel[n * self.down % self.up]
+            .iter()
+            .enumerate()
+            .filter_map(|(tap, weight)| {
+                let index = usize::try_from(first + tap as isize).ok()?;
+                let sample = self.pending.get(index.checked_sub(self.offset)?)?;
+                Some(sample * weight)
+            })
+            .sum()
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `-1.0..=1.0`.
+fn blackman(t: f64) -> f64 {
+    if t.abs() > 1.0 {
+        0.0
+    } else {
+        0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos()
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(input: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+        let mut resampler = Resampler::new(from_rate, to_rate);
+        let mut output = Vec::new();
+        resampler.process(input, &mut output);
+        resampler.flush(&mut output);
+        output
+    }
+
+    fn sine(hz: f32, rate: u32, seconds: f32) -> Vec<f32> {
+        let len = (rate as f32 * seconds) as usize;
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * hz * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn downmixes_interleaved_channels() {
+        let mut mono = Vec::new();
+        downmix_into(&[1.0, 0.0, 0.5, 0.5, -1.0, 1.0], 2, &mut mono);
+        assert_eq!(mono, [0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn keeps_tones_below_the_new_nyquist() {
+        for from_rate in [44100, 48000] {
+            let input = sine(1000.0, from_rate, 0.5);
+            let output = resample(&input, from_rate, 16000);
+
+            assert_eq!(output.len(), 8000);
+
+            // Compare away from the edges, where the filter runs out of input
+            let expected = sine(1000.0, 16000, 0.5);
+            let error = output[200..7800]
+                .iter()
+                .zip(&expected[200..7800])
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f32::max);
+            assert!(error < 0.01, "{from_rate}Hz max error {error}");
+        }
+    }
+
+    #[test]
+    fn removes_tones_above_the_new_nyquist() {
+        let input = sine(10000.0, 48000, 0.5);
+        let output = resample(&input, 48000, 16000);
+
+        assert!(rms(&output[200..7800]) < 0.01);
+    }
+
+    #[test]
+    fn upsamples() {
+        let input = sine(1000.0, 16000, 0.5);
+        let output = resample(&input, 16000, 48000);
+
+        assert_eq!(output.len(), 24000);
+        assert!((rms(&output[600..23400]) - 0.707).abs() < 0.01);
+    }
+
+    #[test]
+    fn chunked_output_matches_one_shot() {
+        let input = sine(440.0, 22050, 0.2);
+        let expected = resample(&input, 22050, 48000);
+
+        let mut resampler = Resampler::new(22050, 48000);
+        let mut output = Vec::new();
+        for chunk in input.chunks(317) {
+            resampler.process(chunk, &mut output);
+        }
+        resampler.flush(&mut output);
+
+        assert_eq!(output.len(), expected.len());
+        assert!(
+            output
+                .iter()
+                .zip(&expected)
+                .all(|(a, b)| (a - b).abs() < 1e-6)
+        );
+    }
+}
@@ -0,0 +1,615 @@
+//! Template matching keyword spotter for the wake word.
+//!
+//! Input audio is downmixed, decimated to roughly 16kHz and cut into 25ms
+//! frames every 10ms. Each frame is reduced to log energies in mel spaced
+//! bands, and the most recent frames are compared against recordings of the
+//! phrase with dynamic time warping. Cheap enough to run all the time on a Pi.
+
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crossbeam_channel::{Sender, bounded};
+use hound::{SampleFormat, WavReader};
+use tokio::sync::mpsc;
+
+use crate::{
+    config::CONFIG,
+    events::{AppEvent, LogEventPayload},
+    types::logs::LogLevel,
+};
+
+const TARGET_SAMPLE_RATE: u32 = 16000;
+const FRAME_MS: f32 = 25.0;
+const HOP_MS: f32 = 10.0;
+const BANDS: usize = 20;
+const BINS_PER_BAND: usize = 3;
+const MIN_BAND_HZ: f32 = 100.0;
+const MAX_BAND_HZ: f32 = 4000.0;
+
+/// Templates shorter than this after trimming silence are ignored (frames).
+const MIN_TEMPLATE_FRAMES: usize = 20;
+/// Compare against the templates every this many frames.
+const EVALUATE_EVERY: usize = 5;
+/// Frames to ignore after firing, otherwise the same utterance fires repeatedly (2s).
+const COOLDOWN_FRAMES: usize = 200;
+
+type Frame = [f32; BANDS];
+
+pub enum WakeWordInput {
+    /// Interleaved samples straight from the input stream.
+    Samples(Vec<f32>),
+    /// Re-read the templates directory, e.g. after enrolling a new recording.
+    ReloadTemplates,
+}
+
+/// Turns interleaved samples into band energy frames.
+struct FeatureExtractor {
+    channels: usize,
+    decimation: usize,
+    frame_len: usize,
+    hop_len: usize,
+    window: Vec<f32>,
+    /// Goertzel coefficients, a few bins spread across each band.
+    coefficients: [[f32; BINS_PER_BAND]; BANDS],
+    mix: (f32, usize),
+    decimated: (f32, usize),
+    pending: Vec<f32>,
+}
+
+impl FeatureExtractor {
+    fn new(sample_rate: u32, channels: u16) -> Self {
+        let decimation = (sample_rate / TARGET_SAMPLE_RATE).max(1) as usize;
+        let rate = sample_rate as f32 / decimation as f32;
+        let frame_len = (rate * FRAME_MS / 1000.0) as usize;
+        let hop_len = (rate * HOP_MS / 1000.0) as usize;
+
+        let window = (0..frame_len)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (frame_len - 1) as f32).cos())
+            .collect();
+
+        let to_mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+        let from_mel = |mel: f32| 700.0 * (10f32.powf(mel / 2595.0) - 1.0);
+        let (min_mel, max_mel) = (to_mel(MIN_BAND_HZ), to_mel(MAX_BAND_HZ));
+        let edge = |i: usize| from_mel(min_mel + (max_mel - min_mel) * i as f32 / BANDS as f32);
+
+        let mut coefficients = [[0.0; BINS_PER_BAND]; BANDS];
+        for (band, bins) in coefficients.iter_mut().enumerate() {
+            let (low, high) = (edge(band), edge(band + 1));
+            for (bin, coefficient) in bins.iter_mut().enumerate() {
+                let hz = low + (high - low) * (bin as f32 + 0.5) / BINS_PER_BAND as f32;
+                *coefficient = 2.0 * (2.0 * PI * hz / rate).cos();
+            }
+        }
+
+        Self {
+            channels: channels.max(1) as usize,
+            decimation,
+            frame_len,
+            hop_len,
+            window,
+            coefficients,
+            mix: (0.0, 0),
+            decimated: (0.0, 0),
+            pending: Vec::with_capacity(frame_len * 2),
+        }
+    }
+
+    /// Appends a `(frame, rms)` pair for every complete frame in `samples`.
+    fn push(&mut self, samples: &[f32], frames: &mut Vec<(Frame, f32)>) {
+        for &sample in samples {
+            self.mix.0 += sample;
+            self.mix.1 += 1;
+            if self.mix.1 < self.channels {
+                continue;
+            }
+
+            self.decimated.0 += self.mix.0 / self.channels as f32;
+            self.decimated.1 += 1;
+            self.mix = (0.0, 0);
+            if self.decimated.1 < self.decimation {
+                continue;
+            }
+
+            self.pending.push(self.decimated.0 / self.decimation as f32);
+            self.decimated = (0.0, 0);
+
+            if self.pending.len() >= self.frame_len {
+                frames.push(self.frame());
+                self.pending.drain(..self.hop_len);
+            }
+        }
+    }
+
+    fn frame(&self) -> (Frame, f32) {
+        let samples = &self.pending[..self.frame_len];
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+
+        let mut frame = [0.0; BANDS];
+        for (energy, bins) in frame.iter_mut().zip(&self.coefficients) {
+            let mut power = 0.0;
+
+            for coefficient in bins {
+                let (mut s1, mut s2) = (0.0, 0.0);
+                for (sample, weight) in samples.iter().zip(&self.window) {
+                    let s0 = sample * weight + coefficient * s1 - s2;
+                    s2 = s1;
+                    s1 = s0;
+                }
+                power += s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+            }
+
+            *energy = (power + 1e-10).ln();
+        }
+
+        (frame, rms)
+    }
+
+    fn reset(&mut self) {
+        self.mix = (0.0, 0);
+        self.decimated = (0.0, 0);
+        self.pending.clear();
+    }
+}
+
+pub struct Template {
+    frames: Vec<Frame>,
+}
+
+impl Template {
+    /// Builds a template from a recording of the phrase, trimming silence around it.
+    pub fn from_samples(samples: &[f32], sample_rate: u32, channels: u16) -> Option<Self> {
+        let mut frames = Vec::new();
+        FeatureExtractor::new(sample_rate, channels).push(samples, &mut frames);
+
+        let loudest = frames.iter().map(|(_, rms)| *rms).fold(0.0, f32::max);
+        if loudest < 1e-4 {
+            return None;
+        }
+
+        let is_voiced = |(_, rms): &(Frame, f32)| *rms >= loudest * 0.1;
+        let start = frames.iter().position(is_voiced)?;
+        let end = frames.iter().rposition(is_voiced)?;
+
+        let mut frames: Vec<Frame> = frames[start..=end]
+            .iter()
+            .map(|(frame, _)| *frame)
+            .collect();
+
+        if frames.len() < MIN_TEMPLATE_FRAMES {
+            return None;
+        }
+
+        normalize(&mut frames);
+        Some(Self { frames })
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        let mut reader = WavReader::open(path)?;
+        let spec = reader.spec();
+
+        let samples = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+            SampleFormat::Int => {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 / scale))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+
+        Ok(Self::from_samples(
+            &samples,
+            spec.sample_rate,
+            spec.channels,
+        ))
+    }
+}
+
+/// The templates found in a directory.
+#[derive(Default)]
+pub struct LoadedTemplates {
+    pub templates: Vec<Template>,
+    /// `file: error` for every file that couldn't be read.
+    pub skipped: Vec<String>,
+}
+
+/// Loads every usable `.wav` template in `dir`, skipping files that can't be
+/// read so one bad recording doesn't disable the rest. A missing directory has
+/// no templates.
+pub fn load_templates(dir: &Path) -> Result<LoadedTemplates, anyhow::Error> {
+    let mut loaded = LoadedTemplates::default();
+
+    if !dir.exists() {
+        return Ok(loaded);
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                loaded.skipped.push(format!("{}: {e}", dir.display()));
+                continue;
+            }
+        };
+
+        if path.extension().is_none_or(|extension| extension != "wav") {
+            continue;
+        }
+
+        match Template::load(&path) {
+            Ok(Some(template)) => loaded.templates.push(template),
+            Ok(None) => {}
+            Err(e) => loaded.skipped.push(format!("{}: {e}", path.display())),
+        }
+    }
+
+    Ok(loaded)
+}
+
+pub struct WakeWordDetector {
+    templates: Vec<Template>,
+    extractor: FeatureExtractor,
+    history: VecDeque<(Frame, f32)>,
+    history_len: usize,
+    min_score: f32,
+    volume_threshold: f32,
+    frames_since_evaluation: usize,
+    cooldown: usize,
+    /// Best score since the match crossed `min_score`, fired once the score drops again.
+    peak: Option<f32>,
+    new_frames: Vec<(Frame, f32)>,
+}
+
+impl WakeWordDetector {
+    /// `sensitivity` is between 0.0 and 1.0, higher values accept looser matches.
+    pub fn new(
+        templates: Vec<Template>,
+        sample_rate: u32,
+        channels: u16,
+        sensitivity: f32,
+        volume_threshold: f32,
+    ) -> Self {
+        let mut detector = Self {
+            templates: Vec::new(),
+            extractor: FeatureExtractor::new(sample_rate, channels),
+            history: VecDeque::new(),
+            history_len: 0,
+            min_score: 0.8 - 0.4 * sensitivity.clamp(0.0, 1.0),
+            volume_threshold,
+            frames_since_evaluation: 0,
+            cooldown: 0,
+            peak: None,
+            new_frames: Vec::new(),
+        };
+        detector.set_templates(templates);
+        detector
+    }
+
+    pub fn set_templates(&mut self, templates: Vec<Template>) {
+        self.history_len = templates
+            .iter()
+            .map(|template| template.frames.len())
+            .max()
+            .unwrap_or(0);
+        self.templates = templates;
+        self.reset();
+    }
+
+    pub fn template_count(&self) -> usize {
+        self.templates.len()
+    }
+
+    /// Forgets buffered audio, e.g. while a recording is running.
+    pub fn reset(&mut self) {
+        self.extractor.reset();
+        self.history.clear();
+        self.frames_since_evaluation = 0;
+        self.peak = None;
+    }
+
+    /// Feeds interleaved input samples, returning the match score when the wake word fires.
+    pub fn process(&mut self, samples: &[f32]) -> Option<f32> {
+        if self.templates.is_empty() {
+            return None;
+        }
+
+        let mut new_frames = std::mem::take(&mut self.new_frames);
+        self.extractor.push(samples, &mut new_frames);
+
+        let mut fired = None;
+
+        for frame in new_frames.drain(..) {
+            self.history.push_back(frame);
+            if self.history.len() > self.history_len {
+                self.history.pop_front();
+            }
+
+            if self.cooldown > 0 {
+                self.cooldown -= 1;
+                continue;
+            }
+
+            self.frames_since_evaluation += 1;
+            if self.frames_since_evaluation < EVALUATE_EVERY {
+                continue;
+            }
+            self.frames_since_evaluation = 0;
+
+            let score = self.evaluate().unwrap_or(0.0);
+
+            // Keep going while the match improves so the window lines up with the phrase
+            match self.peak {
+                Some(peak) if score <= peak => {
+                    self.cooldown = COOLDOWN_FRAMES;
+                    self.history.clear();
+                    self.peak = None;
+                    fired = Some(peak);
+                }
+                _ if score >= self.min_score => self.peak = Some(score),
+                _ => {}
+            }
+        }
+
+        self.new_frames = new_frames;
+        fired
+    }
+
+    /// The best score of the recent frames against any template.
+    fn evaluate(&self) -> Option<f32> {
+        self.templates
+            .iter()
+            .filter_map(|template| {
+                let len = template.frames.len();
+                if self.history.len() < len {
+                    return None;
+                }
+
+                let recent = self.history.iter().skip(self.history.len() - len);
+                let loudest = recent.clone().map(|(_, rms)| *rms).fold(0.0, f32::max);
+                if loudest < self.volume_threshold {
+                    return None;
+                }
+
+                let mut frames: Vec<Frame> = recent.map(|(frame, _)| *frame).collect();
+                normalize(&mut frames);
+
+                Some(1.0 - 2.0 * dtw_distance(&frames, &template.frames))
+            })
+            .reduce(f32::max)
+    }
+}
+
+/// Removes the per-band mean (so loudness and mic coloring don't matter) and
+/// scales every frame to unit length.
+fn normalize(frames: &mut [Frame]) {
+    let mut mean = [0.0; BANDS];
+    for frame in frames.iter() {
+        for (mean, energy) in mean.iter_mut().zip(frame) {
+            *mean += energy / frames.len() as f32;
+        }
+    }
+
+    for frame in frames.iter_mut() {
+        for (energy, mean) in frame.iter_mut().zip(mean) {
+            *energy -= mean;
+        }
+
+        let length = frame.iter().map(|e| e * e).sum::<f32>().sqrt().max(1e-6);
+        for energy in frame.iter_mut() {
+            *energy /= length;
+        }
+    }
+}
+
+/// Cost of the cheapest alignment of two normalized sequences, divided by
+/// their combined length. Identical sequences give 0.0, unrelated ones ~0.5.
+fn dtw_distance(a: &[Frame], b: &[Frame]) -> f32 {
+    let cosine_distance =
+        |x: &Frame, y: &Frame| 1.0 - x.iter().zip(y).map(|(x, y)| x * y).sum::<f32>();
+
+    let mut previous = vec![f32::INFINITY; b.len() + 1];
+    let mut current = vec![f32::INFINITY; b.len() + 1];
+    previous[0] = 0.0;
+
+    for x in a {
+        current[0] = f32::INFINITY;
+        for (j, y) in b.iter().enumerate() {
+            let best = previous[j].min(previous[j + 1]).min(current[j]);
+            current[j + 1] = cosine_distance(x, y) + best;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()] / (a.len() + b.len()) as f32
+}
+
+/// Runs a detector on its own thread fed by the returned sender. Input is
+/// ignored while `is_recording` is set.
+pub fn spawn(
+    event_sender: mpsc::Sender<AppEvent>,
+    sample_rate: u32,
+    channels: u16,
+    is_recording: Arc<AtomicBool>,
+) -> Sender<WakeWordInput> {
+    let (input_tx, input_rx) = bounded(64);
+
+    std::thread::spawn(move || {
+        let mut detector = WakeWordDetector::new(
+            Vec::new(),
+            sample_rate,
+            channels,
+            CONFIG.wake_word.sensitivity,
+            CONFIG.audio.input_volume_threshold,
+        );
+
+        reload_templates(&mut detector, &event_sender);
+
+        for input in input_rx {
+            match input {
+                WakeWordInput::Samples(samples) => {
+                    if is_recording.load(Ordering::Relaxed) {
+                        detector.reset();
+                        continue;
+                    }
+
+                    if let Some(score) = detector.process(&samples) {
+                        let _ = event_sender.blocking_send(AppEvent::WakeWordDetected(score));
+                    }
+                }
+                WakeWordInput::ReloadTemplates => reload_templates(&mut detector, &event_sender),
+            }
+        }
+    });
+
+    input_tx
+}
+
+fn reload_templates(detector: &mut WakeWordDetector, event_sender: &mpsc::Sender<AppEvent>) {
+    let dir = &CONFIG.wake_word.templates_dir;
+
+    let (level, message) = match load_templates(Path::new(dir)) {
+        Ok(loaded) => {
+            detector.set_templates(loaded.templates);
+            let (level, message) = match detector.template_count() {
+                0 => (
+                    LogLevel::Warn,
+                    format!("No wake word templates in {dir}, press `w` to record one"),
+                ),
+                count => (
+                    LogLevel::Info,
+                    format!("Loaded {count} wake word template(s) from {dir}"),
+                ),
+            };
+
+            if loaded.skipped.is_empty() {
+                (level, message)
+            } else {
+                let skipped = loaded.skipped.join(", ");
+                (LogLevel::Warn, format!("{message}, skipped {skipped}"))
+            }
+        }
+        Err(e) => (
+            LogLevel::Error,
+            format!("Failed to load wake word templates from {dir}: {e}"),
+        ),
+    };
+
+    let _ = event_sender.blocking_send(AppEvent::Log(LogEventPayload { level, message }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 16000;
+
+    /// A crude "word": a tone sweeping between two frequencies with a syllable envelope.
+    fn word(from_hz: f32, to_hz: f32, seconds: f32) -> Vec<f32> {
+        let len = (SAMPLE_RATE as f32 * seconds) as usize;
+        let mut phase = 0.0;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / len as f32;
+                let hz = from_hz + (to_hz - from_hz) * t;
+                phase += 2.0 * PI * hz / SAMPLE_RATE as f32;
+                let envelope = (PI * t * 2.0).sin().abs();
+                0.5 * envelope * phase.sin()
+            })
+            .collect()
+    }
+
+    fn silence(seconds: f32) -> Vec<f32> {
+        vec![0.0; (SAMPLE_RATE as f32 * seconds) as usize]
+    }
+
+    fn detector(phrase: &[f32]) -> WakeWordDetector {
+        let template = Template::from_samples(phrase, SAMPLE_RATE, 1).unwrap();
+        WakeWordDetector::new(vec![template], SAMPLE_RATE, 1, 0.5, 0.01)
+    }
+
+    fn feed(detector: &mut WakeWordDetector, samples: &[f32]) -> Option<f32> {
+        samples
+            .chunks(512)
+            .filter_map(|chunk| detector.process(chunk))
+            .reduce(f32::max)
+    }
+
+    #[test]
+    fn fires_on_the_template_phrase() {
+        let phrase = word(300.0, 2500.0, 0.6);
+        let mut detector = detector(&phrase);
+
+        let input = [silence(0.5), phrase.clone(), silence(0.3)].concat();
+        let score = feed(&mut detector, &input).expect("wake word should fire");
+
+        assert!(score > 0.9, "score {score}");
+    }
+
+    #[test]
+    fn ignores_other_sounds_and_silence() {
+        let mut detector = detector(&word(300.0, 2500.0, 0.6));
+
+        let input = [silence(0.5), word(2500.0, 300.0, 0.6), silence(0.5)].concat();
+
+        assert_eq!(feed(&mut detector, &input), None);
+    }
+
+    #[test]
+    fn fires_once_per_utterance() {
+        let phrase = word(300.0, 2500.0, 0.6);
+        let mut detector = detector(&phrase);
+
+        let input = [silence(0.5), phrase.clone(), silence(0.3)].concat();
+        let fired = input
+            .chunks(512)
+            .filter(|chunk| detector.process(chunk).is_some())
+            .count();
+
+        assert_eq!(fired, 1);
+    }
+
+    #[test]
+    fn templates_of_silence_are_rejected() {
+        assert!(Template::from_samples(&silence(1.0), SAMPLE_RATE, 1).is_none());
+    }
+
+    #[test]
+    fn skips_templates_that_cannot_be_read() {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("jumo-wake-word-{nanos}"));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(dir.join("good.wav"), spec).unwrap();
+        for sample in word(300.0, 2500.0, 0.6) {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let good = std::fs::read(dir.join("good.wav")).unwrap();
+        std::fs::write(dir.join("truncated.wav"), &good[..good.len() / 2]).unwrap();
+
+        let loaded = load_templates(&dir).unwrap();
+        assert_eq!(loaded.templates.len(), 1);
+        assert_eq!(loaded.skipped.len(), 1);
+        assert!(loaded.skipped[0].contains("truncated.wav"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,444 @@
+use std::{
+    io::Cursor,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use base64::{Engine, engine::general_purpose};
+use image::{DynamicImage, RgbImage, codecs::jpeg::JpegEncoder, imageops::FilterType};
+use nokhwa::{
+    Buffer,
+    pixel_format::RgbFormat,
+    utils::{
+        CameraFormat, CameraIndex, FrameFormat, RequestedFormat, RequestedFormatType, Resolution,
+    },
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    audio::devices::reconnect_delay,
+    config::{CONFIG, CameraConfig},
+    events::{AppEvent, LogEventPayload},
+    types::logs::LogLevel,
+};
+
+pub mod presence;
+
+/// Frames older than this are from a camera that stopped delivering.
+const MAX_FRAME_AGE: Duration = Duration::from_secs(2);
+
+/// How often the preview is updated while the camera view is open.
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Previews are scaled to this size, about as many pixels as a terminal
+/// shows.
+const PREVIEW_DIMENSION: u32 = 160;
+
+/// When a camera frame goes to the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CapturePolicy {
+    /// With every message from the user
+    EveryTurn,
+    /// Only when the model asks for one
+    OnRequest,
+    #[default]
+    Never,
+}
+
+impl CapturePolicy {
+    /// Also takes the `true`/`false` of `VIDEO_CAPTURE_ENABLED`.
+    pub fn deserialize_setting<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Setting {
+            Enabled(bool),
+            Policy(CapturePolicy),
+        }
+
+        Ok(match Setting::deserialize(deserializer)? {
+            Setting::Enabled(true) => CapturePolicy::EveryTurn,
+            Setting::Enabled(false) => CapturePolicy::Never,
+            Setting::Policy(policy) => policy,
+        })
+    }
+}
+
+/// How frames are scaled down, from fastest to sharpest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    Nearest,
+    #[default]
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Part of a frame, in fractions of its width and height from the top left.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Region {
+    pub fn is_valid(&self) -> bool {
+        let in_range = |value: f32| (0.0..=1.0).contains(&value);
+
+        in_range(self.x)
+            && in_range(self.y)
+            && self.width > 0.0
+            && self.height > 0.0
+            && in_range(self.x + self.width)
+            && in_range(self.y + self.height)
+    }
+
+    /// The region of a `width` by `height` image, at least a pixel in size.
+    fn to_pixels(self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let scale = |fraction: f32, size: u32| (fraction.clamp(0.0, 1.0) * size as f32) as u32;
+
+        let x = scale(self.x, width).min(width.saturating_sub(1));
+        let y = scale(self.y, height).min(height.saturating_sub(1));
+        (
+            x,
+            y,
+            scale(self.width, width).clamp(1, (width - x).max(1)),
+            scale(self.height, height).clamp(1, (height - y).max(1)),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    buffer: Buffer,
+    captured_at: Instant,
+}
+
+/// A downscaled copy of the latest frame for the camera view, kept up to
+/// date by the capture thread while it's watched so the UI never decodes.
+#[derive(Debug, Default)]
+struct Preview {
+    watched: AtomicBool,
+    latest: Mutex<Option<(Arc<RgbImage>, Instant)>>,
+}
+
+impl Preview {
+    fn is_due(&self, now: Instant) -> bool {
+        self.watched.load(Ordering::Relaxed)
+            && self.latest.lock().is_ok_and(|latest| {
+                latest
+                    .as_ref()
+                    .is_none_or(|(_, at)| now.duration_since(*at) >= PREVIEW_INTERVAL)
+            })
+    }
+
+    fn set(&self, image: RgbImage, now: Instant) {
+        if let Ok(mut latest) = self.latest.lock() {
+            *latest = Some((Arc::new(image), now));
+        }
+    }
+}
+
+/// Streams from the camera on a background thread, keeping the most recent
+/// frame. Frames are only decoded when one is asked for, or for the preview.
+#[derive(Debug, Clone, Default)]
+pub struct Camera {
+    latest: Arc<Mutex<Option<Frame>>>,
+    preview: Arc<Preview>,
+}
+
+impl Camera {
+    /// Opens the camera when frames may be sent or presence is detected,
+    /// reopening it with backoff when it fails.
+    pub fn start(&self, event_sender: mpsc::Sender<AppEvent>) {
+        if CONFIG.camera.capture == CapturePolicy::Never && !CONFIG.presence.enabled {
+            return;
+        }
+
+        let latest = self.latest.clone();
+        let preview = self.preview.clone();
+        std::thread::spawn(move || run_capture(&CONFIG.camera, &latest, &preview, &event_sender));
+    }
+
+    /// Has the capture thread keep the preview up to date while `watched`.
+    pub fn watch_preview(&self, watched: bool) {
+        self.preview.watched.store(watched, Ordering::Relaxed);
+    }
+
+    /// The latest frame downscaled for the camera view, `None` when the
+    /// camera stopped delivering.
+    pub fn preview(&self) -> Option<Arc<RgbImage>> {
+        let latest = self.preview.latest.lock().ok()?;
+
+        latest
+            .as_ref()
+            .filter(|(_, at)| at.elapsed() <= MAX_FRAME_AGE)
+            .map(|(image, _)| image.clone())
+    }
+
+    /// The latest frame as a base64 JPEG, scaled to fit the configured size.
+    pub fn latest_frame(&self) -> Result<Option<String>, anyhow::Error> {
+        self.snapshot(CONFIG.camera.max_dimension, None)
+    }
+
+    /// The latest frame, or the `region` of it, as a base64 JPEG scaled to
+    /// fit in `max_dimension`.
+    pub fn snapshot(
+        &self,
+        max_dimension: u32,
+        region: Option<Region>,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let Some(buffer) = self.latest_buffer() else {
+            return Ok(None);
+        };
+
+        let Some(image) = decode(&buffer)? else {
+            return Ok(None);
+        };
+
+        let mut image = DynamicImage::ImageRgb8(image);
+        if let Some(region) = region {
+            let (x, y, width, height) = region.to_pixels(image.width(), image.height());
+            image = image.crop_imm(x, y, width, height);
+        }
+
+        let config = &CONFIG.camera;
+        let jpeg = encode_jpeg(
+            image,
+            max_dimension,
+            config.jpeg_quality,
+            config.resize_filter,
+        )?;
+
+        Ok(Some(general_purpose::STANDARD.encode(&jpeg)))
+    }
+
+    /// The latest frame, decoded but at full size.
+    pub fn latest_image(&self) -> Result<Option<RgbImage>, anyhow::Error> {
+        match self.latest_buffer() {
+            Some(buffer) => decode(&buffer),
+            None => Ok(None),
+        }
+    }
+
+    fn latest_buffer(&self) -> Option<Buffer> {
+        let latest = self.latest.lock().ok()?;
+
+        latest
+            .as_ref()
+            .filter(|frame| frame.captured_at.elapsed() <= MAX_FRAME_AGE)
+            .map(|frame| frame.buffer.clone())
+    }
+
+    pub fn start_nokhwa() -> Result<(), anyhow::Error> {
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        nokhwa::nokhwa_initialize(move |_| {
+            let _ = tx.send(());
+        });
+
+        rx.recv()?;
+
+        Ok(())
+    }
+}
+
+fn run_capture(
+    config: &CameraConfig,
+    latest: &Mutex<Option<Frame>>,
+    preview: &Preview,
+    event_sender: &mpsc::Sender<AppEvent>,
+) {
+    let log = |level: LogLevel, message: String| {
+        let _ = event_sender.blocking_send(AppEvent::Log(LogEventPayload { level, message }));
+    };
+    let mut attempt = 0;
+
+    loop {
+        match open_camera(config) {
+            Ok(mut camera) => {
+                attempt = 0;
+                log(
+                    LogLevel::Info,
+                    format!(
+                        "Camera {} streaming {}",
+                        config.device_index,
+                        camera.camera_format()
+                    ),
+                );
+
+                loop {
+                    match camera.frame() {
+                        Ok(buffer) => {
+                            let now = Instant::now();
+                            // A frame that doesn't decode keeps the last preview
+                            if preview.is_due(now)
+                                && let Ok(Some(image)) = decode(&buffer)
+                            {
+                                preview.set(preview_image(DynamicImage::ImageRgb8(image)), now);
+                            }
+
+                            if let Ok(mut latest) = latest.lock() {
+                                *latest = Some(Frame {
+                                    buffer,
+                                    captured_at: now,
+                                });
+                            }
+                        }
+                        Err(e) => {
+                            log(LogLevel::Warn, format!("Camera stopped streaming: {e}"));
+                            break;
+                        }
+                    }
+                }
+
+                let _ = camera.stop_stream();
+            }
+            // Only the first failure is logged while the camera stays away
+            Err(e) if attempt == 0 => log(
+                LogLevel::Warn,
+                format!("Failed to open camera {}: {e}", config.device_index),
+            ),
+            Err(_) => {}
+        }
+
+        std::thread::sleep(reconnect_delay(attempt));
+        attempt = attempt.saturating_add(1);
+    }
+}
+
+fn open_camera(config: &CameraConfig) -> Result<nokhwa::Camera, anyhow::Error> {
+    let format = CameraFormat::new(
+        Resolution::new(config.width, config.height),
+        FrameFormat::MJPEG,
+        config.frame_rate,
+    );
+    let requested = RequestedFormat::new::<RgbFormat>(RequestedFormatType::Closest(format));
+
+    let mut camera = nokhwa::Camera::new(CameraIndex::Index(config.device_index), requested)?;
+    camera.open_stream()?;
+
+    Ok(camera)
+}
+
+fn decode(buffer: &Buffer) -> Result<Option<RgbImage>, anyhow::Error> {
+    let decoded = buffer.decode_image::<RgbFormat>()?;
+
+    Ok(RgbImage::from_raw(
+        decoded.width(),
+        decoded.height(),
+        decoded.into_raw(),
+    ))
+}
+
+/// Scales `image` down to what the camera view can show.
+pub fn preview_image(image: DynamicImage) -> RgbImage {
+    image
+        .resize(PREVIEW_DIMENSION, PREVIEW_DIMENSION, FilterType::Triangle)
+        .to_rgb8()
+}
+
+/// Scales `image` down to fit in `max_dimension` and encodes it as JPEG.
+fn encode_jpeg(
+    image: DynamicImage,
+    max_dimension: u32,
+    quality: u8,
+    filter: ResizeFilter,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let image = if image.width() > max_dimension || image.height() > max_dimension {
+        image.resize(max_dimension, max_dimension, filter.into())
+    } else {
+        image
+    };
+
+    let mut jpeg = Vec::new();
+    image.write_with_encoder(JpegEncoder::new_with_quality(
+        &mut Cursor::new(&mut jpeg),
+        quality,
+    ))?;
+
+    Ok(jpeg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refreshes_the_preview_only_while_watched() {
+        let preview = Preview::default();
+        let now = Instant::now();
+        assert!(!preview.is_due(now));
+
+        preview.watched.store(true, Ordering::Relaxed);
+        assert!(preview.is_due(now));
+
+        preview.set(RgbImage::new(1, 1), now);
+        assert!(!preview.is_due(now + PREVIEW_INTERVAL / 2));
+        assert!(preview.is_due(now + PREVIEW_INTERVAL));
+    }
+
+    #[test]
+    fn scales_frames_down_to_fit() {
+        let frame = DynamicImage::ImageRgb8(RgbImage::new(640, 480));
+
+        let jpeg = encode_jpeg(frame, 320, 80, ResizeFilter::Triangle).unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+
+        assert_eq!((decoded.width(), decoded.height()), (320, 240));
+    }
+
+    #[test]
+    fn crops_regions_inside_the_frame() {
+        let region = Region {
+            x: 0.5,
+            y: 0.25,
+            width: 0.5,
+            height: 0.5,
+        };
+        assert!(region.is_valid());
+        assert_eq!(region.to_pixels(640, 480), (320, 120, 320, 240));
+
+        let outside = Region { x: 0.8, ..region };
+        assert!(!outside.is_valid());
+        assert_eq!(outside.to_pixels(640, 480), (512, 120, 128, 240));
+    }
+
+    #[test]
+    fn reads_the_old_video_capture_flag_as_a_policy() {
+        #[derive(Deserialize)]
+        struct Section {
+            #[serde(deserialize_with = "CapturePolicy::deserialize_setting")]
+            capture: CapturePolicy,
+        }
+
+        let policy = |toml: &str| toml::from_str::<Section>(toml).unwrap().capture;
+
+        assert_eq!(policy("capture = true"), CapturePolicy::EveryTurn);
+        assert_eq!(policy("capture = false"), CapturePolicy::Never);
+        assert_eq!(policy("capture = \"on_request\""), CapturePolicy::OnRequest);
+    }
+}
@@ -0,0 +1,347 @@
+//! Notices people coming and going by comparing camera frames a few times a
+//! second. Motion alone also fires for light changes and pets, so the `skin`
+//! detector can require skin coloured pixels where something moved.
+
+use std::time::{Duration, Instant};
+
+use chrono::NaiveTime;
+use image::{DynamicImage, GrayImage, RgbImage, imageops::FilterType};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    camera::{Camera, Region},
+    config::{CONFIG, PresenceConfig},
+    events::AppEvent,
+};
+
+/// Frames are compared at this size, enough to see a person, cheap on a Pi.
+const SAMPLE_WIDTH: u32 = 80;
+const SAMPLE_HEIGHT: u32 = 60;
+
+/// What confirms that motion is a person.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PersonDetector {
+    /// Any motion counts
+    #[default]
+    None,
+    /// Skin coloured pixels where the motion is
+    Skin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motion {
+    /// Fraction of the pixels that changed
+    pub changed: f32,
+    /// Box around the changed pixels
+    pub region: Region,
+}
+
+/// Compares each frame with the one before.
+#[derive(Debug)]
+pub struct MotionDetector {
+    previous: Option<GrayImage>,
+    /// How much a pixel's brightness has to change to count, 0 - 255
+    pixel_threshold: u8,
+}
+
+impl MotionDetector {
+    pub fn new(pixel_threshold: u8) -> Self {
+        Self {
+            previous: None,
+            pixel_threshold,
+        }
+    }
+
+    pub fn process(&mut self, frame: GrayImage) -> Option<Motion> {
+        let previous = self.previous.replace(frame);
+        let (Some(previous), Some(frame)) = (previous, &self.previous) else {
+            return None;
+        };
+
+        if previous.dimensions() != frame.dimensions() {
+            return None;
+        }
+
+        let (width, height) = frame.dimensions();
+        let mut changed = 0;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (width, height, 0, 0);
+
+        for (x, y, pixel) in frame.enumerate_pixels() {
+            let before = previous.get_pixel(x, y);
+            if pixel[0].abs_diff(before[0]) > self.pixel_threshold {
+                changed += 1;
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+
+        if changed == 0 {
+            return None;
+        }
+
+        Some(Motion {
+            changed: changed as f32 / (width * height) as f32,
+            region: Region {
+                x: min_x as f32 / width as f32,
+                y: min_y as f32 / height as f32,
+                width: (max_x - min_x + 1) as f32 / width as f32,
+                height: (max_y - min_y + 1) as f32 / height as f32,
+            },
+        })
+    }
+}
+
+/// Fraction of the pixels in `region` that look like skin, using a simple
+/// RGB rule that holds up in daylight and indoor lighting.
+pub fn skin_fraction(frame: &RgbImage, region: Region) -> f32 {
+    let (width, height) = frame.dimensions();
+    let x0 = (region.x * width as f32) as u32;
+    let y0 = (region.y * height as f32) as u32;
+    let x1 = (((region.x + region.width) * width as f32) as u32).min(width);
+    let y1 = (((region.y + region.height) * height as f32) as u32).min(height);
+
+    let mut total = 0;
+    let mut skin = 0;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let [r, g, b] = frame.get_pixel(x, y).0.map(i32::from);
+            let spread = r.max(g).max(b) - r.min(g).min(b);
+
+            total += 1;
+            if r > 95 && g > 40 && b > 20 && spread > 15 && (r - g).abs() > 15 && r > g && r > b {
+                skin += 1;
+            }
+        }
+    }
+
+    if total == 0 {
+        0.0
+    } else {
+        skin as f32 / total as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PresenceChange {
+    Arrived(Region),
+    Left,
+}
+
+/// Turns samples where someone was seen or not into arrivals and departures.
+#[derive(Debug)]
+pub struct PresenceTracker {
+    present: bool,
+    /// Samples in a row where someone was seen
+    streak: u32,
+    last_seen: Option<Instant>,
+    arrive_samples: u32,
+    leave_after: Duration,
+}
+
+impl PresenceTracker {
+    pub fn new(arrive_samples: u32, leave_after: Duration) -> Self {
+        Self {
+            present: false,
+            streak: 0,
+            last_seen: None,
+            arrive_samples,
+            leave_after,
+        }
+    }
+
+    pub fn update(&mut self, seen: Option<Region>, now: Instant) -> Option<PresenceChange> {
+        match seen {
+            Some(region) => {
+                self.streak += 1;
+                self.last_seen = Some(now);
+
+                if !self.present && self.streak >= self.arrive_samples {
+                    self.present = true;
+                    return Some(PresenceChange::Arrived(region));
+                }
+            }
+            None => {
+                self.streak = 0;
+
+                let gone = self
+                    .last_seen
+                    .is_none_or(|last_seen| now.duration_since(last_seen) >= self.leave_after);
+                if self.present && gone {
+                    self.present = false;
+                    return Some(PresenceChange::Left);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Parses `"22:00-07:00"`, an empty string means no quiet hours.
+pub fn parse_quiet_hours(text: &str) -> Result<Option<(NaiveTime, NaiveTime)>, anyhow::Error> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let (start, end) = text
+        .split_once('-')
+        .ok_or_else(|| anyhow::anyhow!("expected a range like 22:00-07:00, got `{text}`"))?;
+    let parse = |time: &str| {
+        NaiveTime::parse_from_str(time.trim(), "%H:%M")
+            .map_err(|e| anyhow::anyhow!("invalid time `{}`: {e}", time.trim()))
+    };
+
+    Ok(Some((parse(start)?, parse(end)?)))
+}
+
+/// Whether `now` falls in the quiet hours, which may span midnight.
+pub fn in_quiet_hours(quiet_hours: Option<(NaiveTime, NaiveTime)>, now: NaiveTime) -> bool {
+    match quiet_hours {
+        Some((start, end)) if start <= end => start <= now && now < end,
+        Some((start, end)) => now >= start || now < end,
+        None => false,
+    }
+}
+
+/// Samples the camera's latest frame on its own thread and sends
+/// `PersonArrived` / `PersonLeft`.
+pub fn spawn(camera: Camera, event_sender: mpsc::Sender<AppEvent>) {
+    let config = &CONFIG.presence;
+
+    std::thread::spawn(move || {
+        let mut motion = MotionDetector::new(config.pixel_threshold);
+        let mut tracker = PresenceTracker::new(
+            config.arrive_samples,
+            Duration::from_millis(config.leave_after_ms),
+        );
+
+        loop {
+            std::thread::sleep(Duration::from_millis(config.sample_interval_ms));
+
+            // Errors are the camera's to report, it logs when it stops streaming
+            let Ok(Some(frame)) = camera.latest_image() else {
+                continue;
+            };
+
+            let small = DynamicImage::ImageRgb8(frame)
+                .resize_exact(SAMPLE_WIDTH, SAMPLE_HEIGHT, FilterType::Triangle)
+                .to_rgb8();
+            let seen = person_seen(config, &mut motion, &small);
+
+            let event = match tracker.update(seen, Instant::now()) {
+                Some(PresenceChange::Arrived(region)) => AppEvent::PersonArrived(region),
+                Some(PresenceChange::Left) => AppEvent::PersonLeft,
+                None => continue,
+            };
+
+            if event_sender.blocking_send(event).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+fn person_seen(
+    config: &PresenceConfig,
+    motion: &mut MotionDetector,
+    frame: &RgbImage,
+) -> Option<Region> {
+    let gray = DynamicImage::ImageRgb8(frame.clone()).to_luma8();
+    let moved = motion
+        .process(gray)
+        .filter(|moved| moved.changed >= config.motion_threshold)?;
+
+    match config.detector {
+        PersonDetector::None => Some(moved.region),
+        PersonDetector::Skin => {
+            (skin_fraction(frame, moved.region) >= config.skin_threshold).then_some(moved.region)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, Rgb};
+
+    use super::*;
+
+    #[test]
+    fn finds_the_box_around_what_moved() {
+        let mut detector = MotionDetector::new(25);
+        let still = GrayImage::new(10, 10);
+        let mut moved = still.clone();
+        for (x, y) in [(2, 3), (5, 6)] {
+            moved.put_pixel(x, y, Luma([200]));
+        }
+
+        assert_eq!(detector.process(still.clone()), None);
+        assert_eq!(detector.process(still), None);
+
+        let motion = detector.process(moved).unwrap();
+        assert_eq!(motion.changed, 0.02);
+        assert_eq!(
+            motion.region,
+            Region {
+                x: 0.2,
+                y: 0.3,
+                width: 0.4,
+                height: 0.4
+            }
+        );
+    }
+
+    #[test]
+    fn counts_skin_coloured_pixels_in_the_region() {
+        let mut frame = RgbImage::from_pixel(4, 2, Rgb([40, 80, 160]));
+        frame.put_pixel(0, 0, Rgb([220, 160, 130]));
+        let left_half = Region {
+            x: 0.0,
+            y: 0.0,
+            width: 0.5,
+            height: 1.0,
+        };
+
+        assert_eq!(skin_fraction(&frame, left_half), 0.25);
+    }
+
+    #[test]
+    fn arrives_after_a_few_samples_and_leaves_after_a_while() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let region = Region {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        };
+        let mut tracker = PresenceTracker::new(2, Duration::from_secs(10));
+
+        assert_eq!(tracker.update(Some(region), at(0)), None);
+        assert_eq!(
+            tracker.update(Some(region), at(500)),
+            Some(PresenceChange::Arrived(region))
+        );
+        assert_eq!(tracker.update(Some(region), at(1000)), None);
+        assert_eq!(tracker.update(None, at(5000)), None);
+        assert_eq!(tracker.update(None, at(11_000)), Some(PresenceChange::Left));
+    }
+
+    #[test]
+    fn quiet_hours_can_span_midnight() {
+        let time = |text| NaiveTime::parse_from_str(text, "%H:%M").unwrap();
+        let night = parse_quiet_hours("22:00-07:00").unwrap();
+
+        assert!(in_quiet_hours(night, time("23:30")));
+        assert!(in_quiet_hours(night, time("06:59")));
+        assert!(!in_quiet_hours(night, time("12:00")));
+        assert!(!in_quiet_hours(
+            parse_quiet_hours("").unwrap(),
+            time("23:30")
+        ));
+        assert!(parse_quiet_hours("late").is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+#[derive(Debug, Parser)]
+#[command(name = "jumo", version, about = "Jumo, a Rust based robot companion")]
+pub struct Cli {
+    /// Path to the TOML config file. Defaults to `jumo.toml` when it exists.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Config profile to apply on top of the base config (`dev` or `pi`). Defaults to `$ENVIRONMENT`.
+    #[arg(long, value_name = "ENV")]
+    pub env: Option<String>,
+
+    /// Override a single config value, e.g. `--set anthropic.model=claude-opus-4-20250514`.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+
+    /// Run without the terminal UI, logging to stdout. Intended for running as a service.
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Replay a recorded session directory instead of listening, with network services stubbed.
+    #[arg(long, value_name = "SESSION_DIR")]
+    pub replay: Option<PathBuf>,
+
+    /// Playback speed for `--replay`. `0` replays as fast as possible.
+    #[arg(long, default_value_t = 1.0, value_name = "SPEED")]
+    pub replay_speed: f32,
+
+    /// Copy the messages and logs in MongoDB into the SQLite database at `storage.sqlite_path`, then exit.
+    #[arg(long)]
+    pub migrate_mongodb: bool,
+}
@@ -1,29 +1,1155 @@
+use std::{collections::HashMap, env, net::SocketAddr, path::Path, sync::OnceLock};
+
 use lazy_static::lazy_static;
+use serde::{Deserialize, de::DeserializeOwned};
+use toml::{Table, Value};
+
+use crate::{
+    camera::{
+        CapturePolicy, ResizeFilter,
+        presence::{PersonDetector, parse_quiet_hours},
+    },
+    cli::Cli,
+    environment::{Environment, get_environment},
+    memory::storage::StorageKind,
+    services::elevenlabs::voices::Voice,
+    voice_commands::VoiceCommandMatcher,
+};
+
+const DEFAULT_CONFIG_PATH: &str = "jumo.toml";
+
+/// Prefix for generic overrides, e.g. `JUMO__ANTHROPIC__MODEL` sets `anthropic.model`.
+const ENV_PREFIX: &str = "JUMO__";
+
+/// Environment variables kept from before the config file existed.
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("ANTHROPIC_API_KEY", "anthropic.api_key"),
+    ("ELEVENLABS_API_KEY", "elevenlabs.api_key"),
+    ("OPENAI_API_KEY", "openai.api_key"),
+    ("QDRANT_URL", "qdrant.url"),
+    ("MONGODB_URL", "mongodb.url"),
+    ("MONGODB_DATABASE", "mongodb.database"),
+    ("VIDEO_CAPTURE_ENABLED", "camera.capture"),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersonaConfig {
+    /// The robot's name.
+    pub name: String,
+    /// The name of the person the robot mostly talks to.
+    pub user_name: String,
+}
+
+impl Default for PersonaConfig {
+    fn default() -> Self {
+        Self {
+            name: String::from("Jumo"),
+            user_name: String::from("Ryan Walker"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnthropicConfig {
+    pub api_key: String,
+    pub base_url: String,
+    pub model: String,
+    pub max_tokens: u32,
+}
+
+impl Default for AnthropicConfig {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            base_url: String::from("https://api.anthropic.com"),
+            model: String::from("claude-sonnet-4-20250514"),
+            max_tokens: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ElevenLabsConfig {
+    pub api_key: String,
+    /// HTTP base URL, the text to speech websocket uses the matching ws(s) scheme.
+    pub base_url: String,
+    pub voice: Voice,
+    pub stability: f32,
+    pub similarity_boost: f32,
+    pub style: f32,
+    pub speed: f32,
+}
+
+impl Default for ElevenLabsConfig {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            base_url: String::from("https://api.elevenlabs.io"),
+            voice: Voice::default(),
+            stability: 0.5,
+            similarity_boost: 0.8,
+            style: 0.0,
+            speed: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAIConfig {
+    pub api_key: String,
+    pub base_url: String,
+}
+
+impl Default for OpenAIConfig {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            base_url: String::from("https://api.openai.com"),
+        }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QdrantConfig {
+    /// Store embeddings of the messages, needs a Qdrant server at `url`.
+    pub enabled: bool,
+    pub url: String,
+}
+
+impl Default for QdrantConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::from("http://localhost:6334"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MongodbConfig {
+    pub url: String,
+    pub database: String,
+}
+
+impl Default for MongodbConfig {
+    fn default() -> Self {
+        Self {
+            url: String::from("mongodb://localhost:27017"),
+            database: String::from("jumo_rs"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Where messages and logs are kept, `sqlite` or `mongodb`.
+    pub backend: StorageKind,
+    pub sqlite_path: String,
+    /// Writes wait here while a database is unavailable.
+    pub queue_path: String,
+    /// Past this many queued writes the oldest are dropped.
+    pub max_queued_writes: usize,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageKind::Sqlite,
+            sqlite_path: String::from("./data/jumo.db"),
+            queue_path: String::from("./data/write_queue.jsonl"),
+            max_queued_writes: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// RMS level above which microphone input counts as sound.
+    pub input_volume_threshold: f32,
+    /// Sample rate recordings are converted to before transcription. Speech
+    /// models expect 16 kHz, higher only makes the uploads bigger.
+    pub recording_sample_rate: u32,
+    /// Microphone to record from by name, the system default when unset.
+    pub input_device: Option<String>,
+    /// Speaker to play through by name, the system default when unset.
+    pub output_device: Option<String>,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            input_volume_threshold: 0.03,
+            recording_sample_rate: 16000,
+            input_device: None,
+            output_device: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WakeWordConfig {
+    /// Listen for the wake word and open a recording session when it's heard.
+    pub enabled: bool,
+    /// The phrase the templates were recorded saying, used in logs and diagnostics.
+    pub phrase: String,
+    /// Directory of WAV recordings of the phrase that the detector matches against.
+    pub templates_dir: String,
+    /// Between 0.0 and 1.0, higher values fire on looser matches.
+    pub sensitivity: f32,
+    /// Play a short chime when the wake word fires.
+    pub chime: bool,
+    /// Stop the session after this long without speech.
+    pub silence_timeout_ms: u64,
+    /// Stop the session after this long regardless of speech.
+    pub max_listen_ms: u64,
+}
+
+impl Default for WakeWordConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            phrase: String::from("Hey Jumo"),
+            templates_dir: String::from("./data/wake_word"),
+            sensitivity: 0.5,
+            chime: true,
+            silence_timeout_ms: 1500,
+            max_listen_ms: 10000,
+        }
+    }
+}
+
+/// Keeps listening after the robot answers so each turn of a conversation
+/// doesn't need the wake word or a key press.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConversationConfig {
+    /// Open a listening window once the robot finishes speaking.
+    pub enabled: bool,
+    /// How long a window waits for speech before the conversation ends.
+    pub listen_window_ms: u64,
+    /// Stop recording after this long without speech once the user started talking.
+    pub silence_timeout_ms: u64,
+    /// Stop recording after this long regardless of speech.
+    pub max_listen_ms: u64,
+    /// End the conversation when nothing was said to the robot for this long,
+    /// e.g. when background noise keeps the windows open.
+    pub idle_timeout_ms: u64,
+    /// Play sounds when a window opens and when the conversation ends.
+    pub cues: bool,
+}
+
+impl Default for ConversationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_window_ms: 5000,
+            silence_timeout_ms: 1500,
+            max_listen_ms: 20000,
+            idle_timeout_ms: 60000,
+            cues: true,
+        }
+    }
+}
+
+/// Removes the robot's own voice from the microphone input, using what the
+/// speaker plays as the reference.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EchoCancellationConfig {
+    pub enabled: bool,
+    /// Length of the echo path the adaptive filter models, after the delay.
+    pub filter_ms: u32,
+    /// Longest delay between playback and its echo reaching the mic.
+    pub max_delay_ms: u32,
+    /// Extra attenuation of what's left of the echo while only the robot talks.
+    pub suppression_db: f32,
+}
+
+impl Default for EchoCancellationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            filter_ms: 16,
+            max_delay_ms: 250,
+            suppression_db: 25.0,
+        }
+    }
+}
+
+/// Phrases that run an app action directly instead of going to the LLM. An
+/// utterance matches when it is exactly one of the phrases, ignoring case,
+/// punctuation, a leading wake phrase or robot name and "please".
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceCommandsConfig {
+    pub enabled: bool,
+    /// Stop speaking.
+    pub stop: Vec<String>,
+    /// Drop whatever is in flight: playback, generation and transcription.
+    pub cancel: Vec<String>,
+    pub louder: Vec<String>,
+    pub quieter: Vec<String>,
+    pub show_home: Vec<String>,
+    pub show_logs: Vec<String>,
+    pub show_chat: Vec<String>,
+    /// Asks for a confirmation before pulling, rebuilding and restarting.
+    pub update: Vec<String>,
+    /// Confirms an update asked for in the last 30 seconds.
+    pub confirm_update: Vec<String>,
+}
+
+impl Default for VoiceCommandsConfig {
+    fn default() -> Self {
+        let phrases = |phrases: &[&str]| phrases.iter().map(|p| p.to_string()).collect();
+
+        Self {
+            enabled: true,
+            stop: phrases(&["stop", "stop talking", "be quiet", "shut up", "quiet"]),
+            cancel: phrases(&["cancel", "never mind", "nevermind", "forget it"]),
+            louder: phrases(&["louder", "speak up", "volume up", "turn it up"]),
+            quieter: phrases(&["quieter", "softer", "volume down", "turn it down"]),
+            show_home: phrases(&["switch to home", "show home", "go home"]),
+            show_logs: phrases(&["switch to logs", "show logs", "show the logs"]),
+            show_chat: phrases(&["switch to chat", "show chat", "show the chat"]),
+            update: phrases(&["update yourself"]),
+            confirm_update: phrases(&["yes update", "yes update yourself"]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Serve the local HTTP + WebSocket control API.
+    pub enabled: bool,
+    pub bind: String,
+    /// Requests must send `Authorization: Bearer <token>` when set.
+    pub token: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: String::from("127.0.0.1:8080"),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// Record every app event to a new session directory inside `dir`.
+    pub enabled: bool,
+    pub dir: String,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: String::from("./data/recordings"),
+        }
+    }
+}
+
+/// Synthesized phrases kept on disk, so repeated ones skip the websocket.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TtsCacheConfig {
+    pub enabled: bool,
+    pub dir: String,
+    pub max_size_mb: u64,
+    /// Phrases synthesized at startup unless already cached, e.g. status cues.
+    pub prewarm: Vec<String>,
+}
+
+impl Default for TtsCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: String::from("./data/tts_cache"),
+            max_size_mb: 50,
+            prewarm: Vec::new(),
+        }
+    }
+}
+
+/// Saves recordings and spoken replies so they can be played again from the
+/// home view. A limit of 0 turns it off.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioArchiveConfig {
+    pub enabled: bool,
+    pub dir: String,
+    /// Save what the microphone recorded for each utterance.
+    pub recordings: bool,
+    /// Save the TTS audio of each reply.
+    pub tts: bool,
+    pub max_age_days: u32,
+    pub max_size_mb: u64,
+}
+
+impl Default for AudioArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: String::from("./data/audio"),
+            recordings: true,
+            tts: true,
+            max_age_days: 30,
+            max_size_mb: 500,
+        }
+    }
+}
+
+/// The camera streams in the background while frames may be sent, so one is
+/// ready without opening the device each turn.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    /// When a frame goes to the model: `every_turn`, `on_request` or `never`.
+    #[serde(deserialize_with = "CapturePolicy::deserialize_setting")]
+    pub capture: CapturePolicy,
+    pub device_index: u32,
+    /// Resolution and frame rate asked of the camera, the closest supported
+    /// format is used.
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: u32,
+    /// Frames are scaled down to fit in a square of this size before they're sent.
+    pub max_dimension: u32,
+    /// 1 to 100.
+    pub jpeg_quality: u8,
+    pub resize_filter: ResizeFilter,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            capture: CapturePolicy::Never,
+            device_index: 0,
+            width: 640,
+            height: 480,
+            frame_rate: 15,
+            max_dimension: 320,
+            jpeg_quality: 80,
+            resize_filter: ResizeFilter::Triangle,
+        }
+    }
+}
+
+/// Notices people coming into view and greets them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PresenceConfig {
+    pub enabled: bool,
+    /// How often a frame is compared with the one before.
+    pub sample_interval_ms: u64,
+    /// How much a pixel's brightness has to change to count as motion, 0 - 255.
+    pub pixel_threshold: u8,
+    /// Fraction of the frame that has to change.
+    pub motion_threshold: f32,
+    /// Samples in a row with motion before someone counts as arrived.
+    pub arrive_samples: u32,
+    /// Time without motion before they count as gone.
+    pub leave_after_ms: u64,
+    /// `none` or `skin`, which also needs skin coloured pixels where the
+    /// motion is.
+    pub detector: PersonDetector,
+    /// Fraction of the moving area that has to look like skin.
+    pub skin_threshold: f32,
+    pub greet: bool,
+    /// Time after a greeting before the next one.
+    pub greet_cooldown_ms: u64,
+    /// No greetings in this range, e.g. `22:00-07:00`, empty for none.
+    pub quiet_hours: String,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample_interval_ms: 500,
+            pixel_threshold: 25,
+            motion_threshold: 0.02,
+            arrive_samples: 2,
+            leave_after_ms: 30_000,
+            detector: PersonDetector::None,
+            skin_threshold: 0.05,
+            greet: true,
+            greet_cooldown_ms: 600_000,
+            quiet_hours: String::from("22:00-07:00"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Config {
-    pub elevenlabs_api_key: String,
-    pub anthropic_api_key: String,
-    pub openai_api_key: String,
-    pub qdrant_url: String,
-    pub mongodb_url: String,
-    pub mongodb_database: String,
+    pub environment: Environment,
+    pub persona: PersonaConfig,
+    pub anthropic: AnthropicConfig,
+    pub elevenlabs: ElevenLabsConfig,
+    pub tts_cache: TtsCacheConfig,
+    pub openai: OpenAIConfig,
+    pub qdrant: QdrantConfig,
+    pub mongodb: MongodbConfig,
+    pub storage: StorageConfig,
+    pub audio: AudioConfig,
+    pub wake_word: WakeWordConfig,
+    pub conversation: ConversationConfig,
+    pub echo_cancellation: EchoCancellationConfig,
+    pub voice_commands: VoiceCommandsConfig,
+    pub api: ApiConfig,
+    pub recording: RecordingConfig,
+    pub audio_archive: AudioArchiveConfig,
+    pub camera: CameraConfig,
+    pub presence: PresenceConfig,
+}
+
+/// Whether this run talks to the hosted services, and so needs their API keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Live,
+    /// `--replay` and `--migrate-mongodb`, which stub or skip the services.
+    Offline,
+}
+
+impl RunMode {
+    fn of(cli: &Cli) -> Self {
+        if cli.replay.is_some() || cli.migrate_mongodb {
+            Self::Offline
+        } else {
+            Self::Live
+        }
+    }
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, anyhow::Error> {
-        Ok(Self {
-            elevenlabs_api_key: std::env::var("ELEVENLABS_API_KEY").unwrap_or("".to_string()),
-            anthropic_api_key: std::env::var("ANTHROPIC_API_KEY").unwrap_or("".to_string()),
-            openai_api_key: std::env::var("OPENAI_API_KEY").unwrap_or("".to_string()),
-            qdrant_url: std::env::var("QDRANT_URL").unwrap_or("http://localhost:6333".to_string()),
-            mongodb_url: std::env::var("MONGODB_URL")
-                .unwrap_or("mongodb://localhost:27017".to_string()),
-            mongodb_database: std::env::var("MONGODB_DATABASE").unwrap_or("jumo_rs".to_string()),
+    /// Loads and validates the config, making it available through [`CONFIG`].
+    ///
+    /// Layers are applied in order: built-in defaults, the config file, the
+    /// `[profiles.<env>]` table for the current environment, environment
+    /// variables and finally `--set` arguments.
+    pub fn init(cli: &Cli) -> Result<&'static Config, anyhow::Error> {
+        let config = Self::load(cli)?;
+        Ok(LOADED_CONFIG.get_or_init(|| config))
+    }
+
+    /// Makes the built-in defaults with placeholder API keys available through
+    /// [`CONFIG`], for tests that never talk to the real services.
+    #[cfg(test)]
+    pub fn init_for_tests() -> &'static Config {
+        LOADED_CONFIG.get_or_init(|| Config {
+            anthropic: AnthropicConfig {
+                api_key: String::from("test-anthropic-key"),
+                ..Default::default()
+            },
+            elevenlabs: ElevenLabsConfig {
+                api_key: String::from("test-elevenlabs-key"),
+                ..Default::default()
+            },
+            openai: OpenAIConfig {
+                api_key: String::from("test-openai-key"),
+                ..Default::default()
+            },
+            ..Default::default()
         })
     }
+
+    fn load(cli: &Cli) -> Result<Self, anyhow::Error> {
+        let mut errors = Vec::new();
+        let environment = match &cli.env {
+            Some(name) => Environment::from_name(name),
+            None => get_environment(),
+        }
+        .unwrap_or_else(|e| {
+            errors.push(e.to_string());
+            Environment::default()
+        });
+
+        let table = read_config_file(cli.config.as_deref())?;
+
+        Self::from_layers(
+            environment,
+            RunMode::of(cli),
+            table,
+            env::vars(),
+            &cli.overrides,
+            errors,
+        )
+    }
+
+    /// Builds the config from the file's `table`, its profile for
+    /// `environment`, the environment variables `vars` and the `--set`
+    /// `overrides`, each layer winning over the one before.
+    fn from_layers(
+        environment: Environment,
+        mode: RunMode,
+        mut table: Table,
+        vars: impl IntoIterator<Item = (String, String)>,
+        overrides: &[String],
+        mut errors: Vec<String>,
+    ) -> Result<Self, anyhow::Error> {
+        if let Some(Value::Table(mut profiles)) = table.remove("profiles")
+            && let Some(Value::Table(profile)) = profiles.remove(environment.profile_name())
+        {
+            merge_tables(&mut table, profile);
+        }
+
+        let vars: HashMap<String, String> = vars.into_iter().collect();
+
+        for (var, key) in ENV_OVERRIDES {
+            if let Some(raw) = vars.get(*var) {
+                set_key(&mut table, key, raw, &mut errors);
+            }
+        }
+
+        for (var, raw) in &vars {
+            if let Some(path) = var.strip_prefix(ENV_PREFIX) {
+                let key = path.to_lowercase().replace("__", ".");
+                set_key(&mut table, &key, raw, &mut errors);
+            }
+        }
+
+        for item in overrides {
+            match item.split_once('=') {
+                Some((key, raw)) => set_key(&mut table, key.trim(), raw.trim(), &mut errors),
+                None => errors.push(format!("`--set {item}` must be in the form KEY=VALUE")),
+            }
+        }
+
+        let config = Self {
+            environment,
+            persona: take_section(&mut table, "persona", &mut errors),
+            anthropic: take_section(&mut table, "anthropic", &mut errors),
+            elevenlabs: take_section(&mut table, "elevenlabs", &mut errors),
+            tts_cache: take_section(&mut table, "tts_cache", &mut errors),
+            openai: take_section(&mut table, "openai", &mut errors),
+            qdrant: take_section(&mut table, "qdrant", &mut errors),
+            mongodb: take_section(&mut table, "mongodb", &mut errors),
+            storage: take_section(&mut table, "storage", &mut errors),
+            audio: take_section(&mut table, "audio", &mut errors),
+            wake_word: take_section(&mut table, "wake_word", &mut errors),
+            conversation: take_section(&mut table, "conversation", &mut errors),
+            echo_cancellation: take_section(&mut table, "echo_cancellation", &mut errors),
+            voice_commands: take_section(&mut table, "voice_commands", &mut errors),
+            api: take_section(&mut table, "api", &mut errors),
+            recording: take_section(&mut table, "recording", &mut errors),
+            audio_archive: take_section(&mut table, "audio_archive", &mut errors),
+            camera: take_section(&mut table, "camera", &mut errors),
+            presence: take_section(&mut table, "presence", &mut errors),
+        };
+
+        for key in table.keys() {
+            errors.push(format!("unknown config section `{key}`"));
+        }
+
+        config.validate(mode, &mut errors);
+
+        if !errors.is_empty() {
+            let details = errors
+                .iter()
+                .map(|error| format!("  - {error}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            return Err(anyhow::anyhow!("Invalid configuration:\n{details}"));
+        }
+
+        Ok(config)
+    }
+
+    fn validate_api_keys(&self, errors: &mut Vec<String>) {
+        if self.anthropic.api_key.is_empty() {
+            errors.push(String::from(
+                "anthropic.api_key is missing (set ANTHROPIC_API_KEY)",
+            ));
+        }
+
+        if self.elevenlabs.api_key.is_empty() {
+            errors.push(String::from(
+                "elevenlabs.api_key is missing (set ELEVENLABS_API_KEY)",
+            ));
+        }
+
+        // Only message embeddings use OpenAI.
+        if self.qdrant.enabled && self.openai.api_key.is_empty() {
+            errors.push(String::from(
+                "openai.api_key is missing (set OPENAI_API_KEY)",
+            ));
+        }
+    }
+
+    fn validate(&self, mode: RunMode, errors: &mut Vec<String>) {
+        if self.persona.name.trim().is_empty() {
+            errors.push(String::from("persona.name must not be empty"));
+        }
+
+        if mode == RunMode::Live {
+            self.validate_api_keys(errors);
+        }
+
+        if self.anthropic.max_tokens == 0 {
+            errors.push(String::from("anthropic.max_tokens must be greater than 0"));
+        }
+
+        for (key, value) in [
+            ("elevenlabs.stability", self.elevenlabs.stability),
+            (
+                "elevenlabs.similarity_boost",
+                self.elevenlabs.similarity_boost,
+            ),
+            ("elevenlabs.style", self.elevenlabs.style),
+            ("wake_word.sensitivity", self.wake_word.sensitivity),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                errors.push(format!("{key} must be between 0.0 and 1.0, got {value}"));
+            }
+        }
+
+        if !(0.7..=1.2).contains(&self.elevenlabs.speed) {
+            errors.push(format!(
+                "elevenlabs.speed must be between 0.7 and 1.2, got {}",
+                self.elevenlabs.speed
+            ));
+        }
+
+        for (key, url) in [
+            ("anthropic.base_url", &self.anthropic.base_url),
+            ("elevenlabs.base_url", &self.elevenlabs.base_url),
+            ("openai.base_url", &self.openai.base_url),
+            ("qdrant.url", &self.qdrant.url),
+        ] {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                errors.push(format!("{key} must be an http(s) URL, got `{url}`"));
+            }
+        }
+
+        if !self.mongodb.url.starts_with("mongodb://")
+            && !self.mongodb.url.starts_with("mongodb+srv://")
+        {
+            errors.push(format!(
+                "mongodb.url must be a mongodb:// URL, got `{}`",
+                self.mongodb.url
+            ));
+        }
+
+        if self.mongodb.database.is_empty() {
+            errors.push(String::from("mongodb.database must not be empty"));
+        }
+
+        if self.storage.sqlite_path.is_empty() {
+            errors.push(String::from("storage.sqlite_path must not be empty"));
+        }
+
+        if self.storage.queue_path.is_empty() {
+            errors.push(String::from("storage.queue_path must not be empty"));
+        }
+
+        if self.storage.max_queued_writes == 0 {
+            errors.push(String::from("storage.max_queued_writes must be at least 1"));
+        }
+
+        let threshold = self.audio.input_volume_threshold;
+        if threshold <= 0.0 || threshold > 1.0 {
+            errors.push(format!(
+                "audio.input_volume_threshold must be in (0.0, 1.0], got {threshold}"
+            ));
+        }
+
+        let sample_rate = self.audio.recording_sample_rate;
+        if !(8000..=48000).contains(&sample_rate) {
+            errors.push(format!(
+                "audio.recording_sample_rate must be in 8000..=48000, got {sample_rate}"
+            ));
+        }
+
+        if self.wake_word.enabled && self.wake_word.silence_timeout_ms == 0 {
+            errors.push(String::from(
+                "wake_word.silence_timeout_ms must be greater than 0",
+            ));
+        }
+
+        if self.conversation.enabled {
+            let conversation = &self.conversation;
+            for (key, value) in [
+                ("listen_window_ms", conversation.listen_window_ms),
+                ("silence_timeout_ms", conversation.silence_timeout_ms),
+                ("max_listen_ms", conversation.max_listen_ms),
+                ("idle_timeout_ms", conversation.idle_timeout_ms),
+            ] {
+                if value == 0 {
+                    errors.push(format!("conversation.{key} must be greater than 0"));
+                }
+            }
+        }
+
+        if self.echo_cancellation.enabled {
+            let echo = &self.echo_cancellation;
+            if echo.filter_ms == 0 || echo.filter_ms > 64 {
+                errors.push(format!(
+                    "echo_cancellation.filter_ms must be in 1..=64, got {}",
+                    echo.filter_ms
+                ));
+            }
+            if echo.max_delay_ms > 1000 {
+                errors.push(format!(
+                    "echo_cancellation.max_delay_ms must be at most 1000, got {}",
+                    echo.max_delay_ms
+                ));
+            }
+            if !(0.0..=60.0).contains(&echo.suppression_db) {
+                errors.push(format!(
+                    "echo_cancellation.suppression_db must be in 0..=60, got {}",
+                    echo.suppression_db
+                ));
+            }
+        }
+
+        if self.tts_cache.enabled && self.tts_cache.max_size_mb == 0 {
+            errors.push(String::from("tts_cache.max_size_mb must be greater than 0"));
+        }
+
+        if self.audio_archive.enabled && self.audio_archive.dir.trim().is_empty() {
+            errors.push(String::from("audio_archive.dir must not be empty"));
+        }
+
+        if let Err(e) = VoiceCommandMatcher::new(
+            &self.voice_commands,
+            &self.persona.name,
+            &self.wake_word.phrase,
+        ) {
+            errors.push(format!("voice_commands: {e}"));
+        }
+
+        if self.camera.capture != CapturePolicy::Never {
+            let camera = &self.camera;
+            for (key, value) in [
+                ("width", camera.width),
+                ("height", camera.height),
+                ("frame_rate", camera.frame_rate),
+                ("max_dimension", camera.max_dimension),
+            ] {
+                if value == 0 {
+                    errors.push(format!("camera.{key} must be greater than 0"));
+                }
+            }
+            if !(1..=100).contains(&camera.jpeg_quality) {
+                errors.push(format!(
+                    "camera.jpeg_quality must be in 1..=100, got {}",
+                    camera.jpeg_quality
+                ));
+            }
+        }
+
+        if self.presence.enabled {
+            let presence = &self.presence;
+            if presence.sample_interval_ms == 0 {
+                errors.push(String::from(
+                    "presence.sample_interval_ms must be greater than 0",
+                ));
+            }
+            if presence.arrive_samples == 0 {
+                errors.push(String::from(
+                    "presence.arrive_samples must be greater than 0",
+                ));
+            }
+            for (key, value) in [
+                ("motion_threshold", presence.motion_threshold),
+                ("skin_threshold", presence.skin_threshold),
+            ] {
+                if !(0.0..=1.0).contains(&value) {
+                    errors.push(format!(
+                        "presence.{key} must be between 0.0 and 1.0, got {value}"
+                    ));
+                }
+            }
+            if let Err(e) = parse_quiet_hours(&presence.quiet_hours) {
+                errors.push(format!("presence.quiet_hours: {e}"));
+            }
+        }
+
+        match self.api.bind.parse::<SocketAddr>() {
+            Ok(bind) => {
+                // Anyone on the network could prompt the robot and read its history
+                if self.api.enabled && !bind.ip().is_loopback() && self.api.token.is_none() {
+                    errors.push(format!(
+                        "api.token must be set when api.bind isn't a loopback address, got `{}`",
+                        self.api.bind
+                    ));
+                }
+            }
+            Err(_) => errors.push(format!(
+                "api.bind must be an address like 127.0.0.1:8080, got `{}`",
+                self.api.bind
+            )),
+        }
+        if self
+            .api
+            .token
+            .as_ref()
+            .is_some_and(|token| token.trim().is_empty())
+        {
+            errors.push(String::from("api.token must not be empty"));
+        }
+    }
 }
 
+fn read_config_file(path: Option<&Path>) -> Result<Table, anyhow::Error> {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let default_path = Path::new(DEFAULT_CONFIG_PATH);
+            if !default_path.exists() {
+                return Ok(Table::new());
+            }
+            default_path
+        }
+    };
+
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read config file {}: {e}", path.display()))?;
+
+    contents
+        .parse::<Table>()
+        .map_err(|e| anyhow::anyhow!("Failed to parse config file {}: {e}", path.display()))
+}
+
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Sets a dotted `section.key` path, parsing the raw string as a TOML value
+/// when possible so `true` and `0.5` keep their types.
+fn set_key(table: &mut Table, key: &str, raw: &str, errors: &mut Vec<String>) {
+    let value = format!("value = {raw}")
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()));
+
+    let mut parts = key.split('.').collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        return;
+    };
+
+    let mut current = table;
+    for part in parts {
+        let entry = current
+            .entry(part)
+            .or_insert_with(|| Value::Table(Table::new()));
+
+        match entry {
+            Value::Table(next) => current = next,
+            _ => {
+                errors.push(format!("cannot override `{key}`: `{part}` is not a table"));
+                return;
+            }
+        }
+    }
+
+    current.insert(last.to_string(), value);
+}
+
+fn take_section<T: DeserializeOwned + Default>(
+    table: &mut Table,
+    name: &str,
+    errors: &mut Vec<String>,
+) -> T {
+    let Some(value) = table.remove(name) else {
+        return T::default();
+    };
+
+    match value.try_into() {
+        Ok(section) => section,
+        Err(e) => {
+            errors.push(format!("[{name}] {}", e.message()));
+            T::default()
+        }
+    }
+}
+
+static LOADED_CONFIG: OnceLock<Config> = OnceLock::new();
+
 lazy_static! {
-    pub static ref CONFIG: Config = Config::from_env().expect("Failed to load config");
+    pub static ref CONFIG: &'static Config = LOADED_CONFIG
+        .get()
+        .expect("Config accessed before Config::init");
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    const API_KEYS: [(&str, &str); 3] = [
+        ("ANTHROPIC_API_KEY", "anthropic-key"),
+        ("ELEVENLABS_API_KEY", "elevenlabs-key"),
+        ("OPENAI_API_KEY", "openai-key"),
+    ];
+
+    fn load(
+        environment: Environment,
+        file: &str,
+        vars: &[(&str, &str)],
+        overrides: &[&str],
+    ) -> Result<Config, anyhow::Error> {
+        let vars = API_KEYS
+            .iter()
+            .chain(vars)
+            .map(|(var, value)| (var.to_string(), value.to_string()));
+        let overrides: Vec<String> = overrides.iter().map(|item| item.to_string()).collect();
+
+        Config::from_layers(
+            environment,
+            RunMode::Live,
+            file.parse().unwrap(),
+            vars,
+            &overrides,
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn layers_file_profile_env_and_overrides() {
+        let file = r#"
+            [persona]
+            name = "File"
+            user_name = "File"
+
+            [anthropic]
+            model = "file"
+            max_tokens = 100
+
+            [profiles.pi.persona]
+            name = "Pi"
+
+            [profiles.pi.anthropic]
+            model = "pi"
+
+            [profiles.dev.anthropic]
+            model = "dev"
+        "#;
+        let vars = [("JUMO__ANTHROPIC__MODEL", "env")];
+
+        let config = load(Environment::Pi, file, &vars, &["anthropic.max_tokens=200"]).unwrap();
+
+        assert_eq!(config.environment, Environment::Pi);
+        assert_eq!(config.persona.user_name, "File");
+        assert_eq!(config.persona.name, "Pi");
+        assert_eq!(config.anthropic.model, "env");
+        assert_eq!(config.anthropic.max_tokens, 200);
+        assert_eq!(config.anthropic.api_key, "anthropic-key");
+
+        let config = load(Environment::Pi, file, &vars, &["anthropic.model=set"]).unwrap();
+        assert_eq!(config.anthropic.model, "set");
+        assert_eq!(config.anthropic.max_tokens, 100);
+
+        let config = load(Environment::Dev, file, &[], &[]).unwrap();
+        assert_eq!(config.persona.name, "File");
+        assert_eq!(config.anthropic.model, "dev");
+    }
+
+    #[test]
+    fn reports_every_invalid_setting() {
+        let file = r#"
+            [anthropic]
+            max_tokens = "many"
+
+            [mongodb]
+            url = "localhost"
+
+            [unknown]
+            key = 1
+        "#;
+
+        let error = load(
+            Environment::Dev,
+            file,
+            &[],
+            &[
+                "audio.input_volume_threshold=2",
+                "api",
+                "api.enabled=true",
+                "api.bind=0.0.0.0:8080",
+            ],
+        )
+        .unwrap_err()
+        .to_string();
+
+        for expected in [
+            "[anthropic]",
+            "mongodb.url must be a mongodb:// URL",
+            "unknown config section `unknown`",
+            "audio.input_volume_threshold must be in (0.0, 1.0]",
+            "`--set api` must be in the form KEY=VALUE",
+            "api.token must be set when api.bind isn't a loopback address",
+        ] {
+            assert!(
+                error.contains(expected),
+                "`{expected}` missing from:\n{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn requires_api_keys_and_a_known_environment() {
+        let from_defaults = |mode| {
+            Config::from_layers(
+                Environment::Dev,
+                mode,
+                Table::new(),
+                Vec::new(),
+                &[],
+                Vec::new(),
+            )
+        };
+
+        let error = from_defaults(RunMode::Live).unwrap_err().to_string();
+        assert!(error.contains("anthropic.api_key is missing"));
+        assert!(error.contains("elevenlabs.api_key is missing"));
+        assert!(!error.contains("openai.api_key is missing"));
+        assert!(from_defaults(RunMode::Offline).is_ok());
+
+        let error = Config::from_layers(
+            Environment::Dev,
+            RunMode::Live,
+            Table::new(),
+            Vec::new(),
+            &[String::from("qdrant.enabled=true")],
+            Vec::new(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("openai.api_key is missing"));
+
+        let mode = |args: &[&str]| RunMode::of(&Cli::parse_from([&["jumo"], args].concat()));
+        assert_eq!(mode(&["--headless"]), RunMode::Live);
+        assert_eq!(mode(&["--replay", "recordings/1"]), RunMode::Offline);
+        assert_eq!(mode(&["--migrate-mongodb"]), RunMode::Offline);
+
+        assert_eq!(Environment::from_name("PI").unwrap(), Environment::Pi);
+        assert!(Environment::from_name("pie").is_err());
+    }
 }
@@ -1,14 +1,31 @@
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Environment {
+    #[default]
     Dev,
     Pi,
 }
 
-pub fn get_environment() -> Environment {
-    let env = std::env::var("ENVIRONMENT").unwrap_or(String::from("dev"));
+impl Environment {
+    pub fn from_name(name: &str) -> Result<Self, anyhow::Error> {
+        match name.to_lowercase().as_str() {
+            "dev" => Ok(Environment::Dev),
+            "pi" => Ok(Environment::Pi),
+            _ => Err(anyhow::anyhow!(
+                "unknown environment `{name}`, expected `dev` or `pi`"
+            )),
+        }
+    }
 
-    match env.to_lowercase().as_str() {
-        "pi" => Environment::Pi,
-        _ => Environment::Dev,
+    /// Name of the `[profiles.<name>]` table in the config file for this environment.
+    pub fn profile_name(&self) -> &'static str {
+        match self {
+            Environment::Dev => "dev",
+            Environment::Pi => "pi",
+        }
     }
 }
+
+pub fn get_environment() -> Result<Environment, anyhow::Error> {
+    let env = std::env::var("ENVIRONMENT").unwrap_or(String::from("dev"));
+    Environment::from_name(&env)
+}
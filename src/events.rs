@@ -1,11 +1,32 @@
 use mongodb::bson::oid::ObjectId;
 use ratatui::style::Color;
-use tokio::sync::mpsc;
+use std::{path::Path, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio_tungstenite::tungstenite::Bytes;
 
 use crate::{
-    emote::Emote, services::anthropic::types::AnthropicMessageStreamEvent, state::View,
-    types::logs::LogLevel,
+    audio::{
+        PcmFormat,
+        devices::{AudioDevices, AudioDirection},
+        echo::EchoStats,
+    },
+    camera::Region,
+    emote::Emote,
+    memory::StorageStatus,
+    replay::EventRecorder,
+    services::{
+        anthropic::types::AnthropicMessageStreamEvent,
+        elevenlabs::voices::{VoiceChange, VoiceOption},
+    },
+    state::View,
+    types::{
+        diagnostics::Diagnostics,
+        logs::{Log, LogLevel},
+        message::Message,
+        spoken::SpokenWord,
+    },
 };
 
 #[derive(Debug, Clone)]
@@ -32,49 +53,105 @@ pub struct LLMMessageCompletedEventPayload {
     pub full_text: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextProcessorChunkEventPayload {
     pub text: String,
     pub flush: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEventPayload {
     pub level: LogLevel,
     pub message: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMGenerationStartedEventPayload {
     pub message_id: ObjectId,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMStreamEventPayload {
     pub message_id: ObjectId,
     pub event: AnthropicMessageStreamEvent,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMGenerationCompletedEventPayload {
     pub message_id: ObjectId,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct TTSChunkEventPayload {
+    #[serde(serialize_with = "serialize_byte_len")]
+    pub audio: Bytes,
+    pub format: PcmFormat,
+    /// Words heard in the audio, when the TTS service reports them
+    pub words: Vec<SpokenWord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackProgressEventPayload {
+    /// Audio played since playback started
+    pub played_ms: u64,
+    /// Audio queued but not played yet
+    pub buffered_ms: u64,
+}
+
+/// Requests that need a reply from the app, e.g. from the control API.
 #[derive(Debug)]
+pub enum AppQuery {
+    RecentMessages(oneshot::Sender<Vec<Message>>),
+    RecentLogs(oneshot::Sender<Vec<Log>>),
+    Diagnostics(oneshot::Sender<Diagnostics>),
+}
+
+/// Events serialize as `{"type": ..., "payload": ...}`. Audio payloads can't be
+/// deserialized since only their length is serialized.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
 pub enum AppEvent {
     // Audio events
     AudioRecordingStarted,
-    AudioRecordingCompleted(Vec<u8>),
+    #[serde(skip_deserializing)]
+    AudioRecordingCompleted(#[serde(serialize_with = "serialize_byte_len")] Vec<u8>),
     AudioRecordingError(String),
     /// There was an error recording the audio causing the recording to stop
     AudioRecordingFailed(String),
     /// There was an error playing the audio but it did not stop the recording
     AudioPlaybackError(String),
+    /// The input stream reported an error, e.g. because the device was unplugged
+    AudioInputStreamError(String),
     AudioDetected(f32),
     AudioNotDetected,
+    /// Echo cancellation measurements, sent every few seconds while the robot talks
+    EchoStats(EchoStats),
+
+    /// The player started outputting queued audio
+    PlaybackStarted,
+    PlaybackProgress(PlaybackProgressEventPayload),
+    /// Everything queued has been played
+    PlaybackDrained,
+
+    /// The device a stream was opened on, `None` once it's closed
+    AudioSetInputDevice(Option<String>),
+    AudioSetOutputDevice(Option<String>),
+    AudioDevicesListed(AudioDevices),
+    /// Try to reopen a lost device, `attempt` counts up for the backoff
+    AudioReconnect {
+        direction: AudioDirection,
+        attempt: u32,
+    },
+
+    /// The wake word was heard, with how closely it matched a template (0.0 - 1.0)
+    WakeWordDetected(f32),
+    /// The assistant is done talking, stop listening once its reply is played
+    EndConversation,
 
-    AudioSetInputDevice(String),
-    AudioSetOutputDevice(String),
+    /// Someone came into view of the camera, where they moved
+    PersonArrived(Region),
+    /// Nobody has moved in view of the camera for a while
+    PersonLeft,
 
     // Transcription events
     TranscriptionStarted,
@@ -92,7 +169,15 @@ pub enum AppEvent {
     TextProcessorFlushed,
 
     // Text to speech events
-    TTSChunk(Bytes),
+    #[serde(skip_deserializing)]
+    TTSChunk(TTSChunkEventPayload),
+    TTSStreamOpened,
+    /// ElevenLabs sent the last audio of the stream
+    TTSStreamFinished,
+    /// Synthesize these phrases into the TTS cache ahead of time
+    TTSPrewarm(Vec<String>),
+    /// Voices of the ElevenLabs account, replacing the built-in ones
+    VoicesListed(Vec<VoiceOption>),
     TTSError(String),
     TTSFailed(String),
 
@@ -102,27 +187,115 @@ pub enum AppEvent {
     SetView(View),
 
     SetEmote(Emote),
+    #[serde(
+        serialize_with = "serialize_color",
+        deserialize_with = "deserialize_color"
+    )]
     SetColor(Color),
+    SetVoice(VoiceChange),
 
     EmbeddingSaved(String),
+    /// Writes stopped or started reaching a database, or the queue changed
+    StorageStatus(StorageStatus),
+
+    // Control events
+    /// A text prompt typed in rather than spoken
+    TextPrompt(String),
+    StartRecording,
+    StopRecording,
+    #[serde(skip)]
+    Query(AppQuery),
+}
+
+/// Audio payloads are only summarized by their size when serialized.
+fn serialize_byte_len<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(bytes.len() as u64)
+}
+
+fn serialize_color<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(color)
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Color::from_str(&name).map_err(serde::de::Error::custom)
 }
 
 pub struct EventBus {
     sender: mpsc::Sender<AppEvent>,
     receiver: mpsc::Receiver<AppEvent>,
+    /// JSON copies of every received event for observers such as the control API
+    observers: broadcast::Sender<String>,
+    recorder: Option<EventRecorder>,
 }
 
 impl EventBus {
     pub fn new() -> Self {
         let (sender, receiver) = mpsc::channel(100);
-        Self { sender, receiver }
+        let (observers, _) = broadcast::channel(256);
+
+        Self {
+            sender,
+            receiver,
+            observers,
+            recorder: None,
+        }
+    }
+
+    /// Records every event received from now on.
+    pub fn set_recorder(&mut self, recorder: EventRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn recording_dir(&self) -> Option<&Path> {
+        self.recorder
+            .as_ref()
+            .map(|recorder| recorder.session_dir())
+    }
+
+    /// Discards all queued events without handling them.
+    pub fn drain(&mut self) {
+        while self.receiver.try_recv().is_ok() {}
     }
 
     pub fn sender(&self) -> mpsc::Sender<AppEvent> {
         self.sender.clone()
     }
 
+    pub fn observers(&self) -> broadcast::Sender<String> {
+        self.observers.clone()
+    }
+
     pub async fn recv(&mut self) -> Option<AppEvent> {
-        self.receiver.recv().await
+        let event = self.receiver.recv().await?;
+
+        if let Some(recorder) = &mut self.recorder
+            && let Err(err) = recorder.record(&event)
+        {
+            self.recorder = None;
+
+            let _ = self.sender.try_send(AppEvent::Log(LogEventPayload {
+                level: LogLevel::Error,
+                message: format!("Event recording stopped: {err}"),
+            }));
+        }
+
+        self.publish(&event);
+
+        Some(event)
+    }
+
+    /// Waits for the next event and drops it without recording or publishing it.
+    pub async fn discard_next(&mut self) -> Option<()> {
+        self.receiver.recv().await.map(|_| ())
+    }
+
+    /// Sends a JSON copy of `event` to the observers.
+    pub fn publish(&self, event: &AppEvent) {
+        if self.observers.receiver_count() > 0
+            && let Ok(json) = serde_json::to_string(event)
+        {
+            let _ = self.observers.send(json);
+        }
     }
 }
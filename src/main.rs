@@ -1,48 +1,84 @@
 use std::panic;
 
-use crate::{app::App, camera::Camera};
+use crate::{
+    app::App,
+    camera::Camera,
+    cli::Cli,
+    config::{CONFIG, Config},
+    memory::storage::migrate_mongodb_to_sqlite,
+    replay::EventReplayer,
+};
+use clap::Parser;
 use colored::Colorize;
 
+mod api;
 mod app;
 mod audio;
 mod camera;
+mod cli;
 mod config;
 mod emote;
 mod environment;
 mod events;
-mod features;
 mod memory;
 mod prompts;
+mod replay;
 mod services;
 mod state;
+#[cfg(test)]
+mod testing;
 mod text_processor;
 mod tools;
 mod types;
+mod voice_commands;
 mod widgets;
 
 #[tokio::main]
 async fn main() {
-    let result = run().await;
+    let cli = Cli::parse();
+    let result = run(&cli).await;
 
-    ratatui::restore();
+    if !cli.headless {
+        ratatui::restore();
+    }
 
     if let Err(e) = result {
         eprintln!("{}", format!("[Error]: {e}").red());
+        std::process::exit(1);
     }
 }
 
-async fn run() -> Result<(), anyhow::Error> {
-    panic::set_hook(Box::new(|e| {
-        ratatui::restore();
+async fn run(cli: &Cli) -> Result<(), anyhow::Error> {
+    dotenv::dotenv().ok();
+    Config::init(cli)?;
+
+    if cli.migrate_mongodb {
+        let (messages, logs) = migrate_mongodb_to_sqlite().await?;
+        println!(
+            "Copied {messages} messages and {logs} logs from MongoDB to {}",
+            CONFIG.storage.sqlite_path
+        );
+        return Ok(());
+    }
+
+    let headless = cli.headless;
+    panic::set_hook(Box::new(move |e| {
+        if !headless {
+            ratatui::restore();
+        }
         eprintln!("{}", format!("[Error]: {e}").red());
     }));
 
     Camera::start_nokhwa()?;
-    dotenv::dotenv()?;
 
-    let terminal = ratatui::init();
+    let terminal = (!cli.headless).then(ratatui::init);
+
+    let replay = match &cli.replay {
+        Some(session_dir) => Some(EventReplayer::open(session_dir, cli.replay_speed)?),
+        None => None,
+    };
 
-    let mut app = App::new(terminal).await?;
+    let mut app = App::new(terminal, replay).await?;
     app.start().await?;
 
     Ok(())
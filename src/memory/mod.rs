@@ -1,36 +1,551 @@
-use tokio::sync::mpsc;
+use std::sync::Arc;
+
+use ::mongodb::{
+    bson::oid::ObjectId,
+    error::{ErrorKind, WriteFailure},
+};
+use futures::future::BoxFuture;
+use reqwest::StatusCode;
+use rusqlite::ErrorCode;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{Mutex, mpsc},
+    time::Instant,
+};
 
 use crate::{
-    events::AppEvent, memory::mongodb::MongodbMemory, services::qdrant::QdrantService,
-    types::message::Message,
+    audio::devices::reconnect_delay,
+    config::CONFIG,
+    events::{AppEvent, LogEventPayload},
+    memory::{
+        queue::{PendingWrite, WriteQueue, WriteTarget},
+        storage::{Storage, StorageBackend},
+    },
+    services::qdrant::QdrantService,
+    types::{
+        logs::{Log, LogLevel},
+        message::Message,
+    },
 };
 
 pub mod mongodb;
+pub mod queue;
+pub mod sqlite;
+pub mod storage;
+
+/// Whether writes are reaching the databases.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageStatus {
+    /// A database failed the last write, writes are queued until it's back.
+    pub degraded: bool,
+    /// Writes waiting in the queue.
+    pub queued: usize,
+}
+
+/// How many times a write the database rejects is tried before it's set
+/// aside.
+const MAX_REJECTIONS: u32 = 3;
+
+type SharedStorage = Arc<Mutex<Option<StorageBackend>>>;
+
+/// Opens the storage, retried by the writer while it's unavailable.
+type OpenStorage =
+    Box<dyn Fn() -> BoxFuture<'static, Result<StorageBackend, anyhow::Error>> + Send + Sync>;
 
 pub struct MemoryManager {
-    qdrant: QdrantService,
-    pub mongodb: MongodbMemory,
+    storage: SharedStorage,
+    /// `None` when stubbed out, e.g. while replaying a recorded session.
+    writes: Option<mpsc::UnboundedSender<PendingWrite>>,
 }
 
 impl MemoryManager {
-    pub async fn new(_event_sender: mpsc::Sender<AppEvent>) -> Result<Self, anyhow::Error> {
+    /// Starts writing in the background. A database that's down doesn't stop
+    /// the app, its writes wait in the queue on disk.
+    pub async fn new(event_sender: mpsc::Sender<AppEvent>) -> Result<Self, anyhow::Error> {
+        let queue = WriteQueue::open(&CONFIG.storage.queue_path, CONFIG.storage.max_queued_writes)?;
+
+        // The writer keeps trying to open it when this fails
+        let storage = match StorageBackend::open().await {
+            Ok(storage) => Some(storage),
+            Err(e) => {
+                let message = format!("Storage unavailable, queueing writes: {e}");
+                log(&event_sender, LogLevel::Warn, message).await;
+                None
+            }
+        };
+        let storage: SharedStorage = Arc::new(Mutex::new(storage));
+
+        let (writes, receiver) = mpsc::unbounded_channel();
+        let open_storage: OpenStorage = Box::new(|| Box::pin(StorageBackend::open()));
+        tokio::spawn(run_writer(
+            storage.clone(),
+            open_storage,
+            queue,
+            receiver,
+            event_sender,
+        ));
+
         Ok(Self {
-            qdrant: QdrantService::new(),
-            mongodb: MongodbMemory::new().await?,
+            storage,
+            writes: Some(writes),
         })
     }
 
-    pub async fn process_exchange(&self, messages: &[Message]) -> Result<(), anyhow::Error> {
+    /// A memory manager that doesn't connect to any database and drops all writes.
+    pub fn stubbed() -> Self {
+        Self {
+            storage: Arc::default(),
+            writes: None,
+        }
+    }
+
+    pub fn process_exchange(&self, messages: &[Message]) {
         for message in messages {
-            self.mongodb.messages.insert_one(message).await?;
-            self.qdrant.insert_message(message).await?;
+            self.write(PendingWrite::Message(message.clone()));
+
+            if CONFIG.qdrant.enabled {
+                self.write(PendingWrite::Embedding(message.clone()));
+            }
+        }
+    }
+
+    pub fn set_message_audio(&self, message_id: ObjectId, audio_id: &str) {
+        self.write(PendingWrite::MessageAudio {
+            message_id,
+            audio_id: audio_id.to_string(),
+        });
+    }
+
+    pub fn insert_log(&self, log: &Log) {
+        self.write(PendingWrite::Log(log.clone()));
+    }
+
+    fn write(&self, write: PendingWrite) {
+        if let Some(writes) = &self.writes {
+            // Only fails once the writer is gone, when the app is shutting down
+            let _ = writes.send(write);
         }
+    }
 
-        Ok(())
+    pub async fn get_recent_messages(&self) -> Result<Vec<Message>, anyhow::Error> {
+        match &*self.storage.lock().await {
+            Some(storage) => storage.get_recent_messages().await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub async fn get_recent_logs(&self) -> Result<Vec<Log>, anyhow::Error> {
+        match &*self.storage.lock().await {
+            Some(storage) => storage.get_recent_logs().await,
+            None => Ok(Vec::new()),
+        }
     }
 
     pub async fn gather_memory(&self) -> Result<Vec<Message>, anyhow::Error> {
-        let recent_messages = self.mongodb.messages.get_recent_messages().await?;
-        Ok(recent_messages)
+        self.get_recent_messages().await
+    }
+}
+
+/// Writes what's queued, retrying with backoff while a database is down,
+/// and reports whenever that changes.
+async fn run_writer(
+    storage: SharedStorage,
+    open_storage: OpenStorage,
+    mut queue: WriteQueue,
+    mut receiver: mpsc::UnboundedReceiver<PendingWrite>,
+    event_sender: mpsc::Sender<AppEvent>,
+) {
+    let mut qdrant = QdrantService::new();
+    let mut status = StorageStatus::default();
+    // Already logged when the storage failed to open
+    let mut degraded = storage.lock().await.is_none();
+    let mut failures = 0;
+    let mut dropped = 0;
+
+    // Writes left from the last run, or the storage failed to open
+    let mut retry_at = Some(Instant::now());
+
+    loop {
+        let wait = async {
+            match retry_at {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            write = receiver.recv() => {
+                let Some(write) = write else {
+                    // Writes that haven't failed are only in memory
+                    if !degraded && !queue.is_empty() {
+                        flush(&storage, &open_storage, &mut qdrant, &mut queue).await;
+                    }
+                    return;
+                };
+
+                if let Err(e) = queue.push(write) {
+                    let message = format!("Failed to queue a write: {e}");
+                    log(&event_sender, LogLevel::Error, message).await;
+                }
+
+                // While degraded, writes wait for the next retry
+                if !degraded {
+                    retry_at = Some(Instant::now());
+                }
+            }
+            _ = wait => {
+                let errors = flush(&storage, &open_storage, &mut qdrant, &mut queue).await;
+                let unavailable = !errors.unavailable.is_empty();
+
+                for error in errors.rejected {
+                    let path = queue.rejected_path();
+                    let message = format!(
+                        "Gave up on a write after {MAX_REJECTIONS} tries, it's in {}: {error}",
+                        path.display()
+                    );
+                    log(&event_sender, LogLevel::Error, message).await;
+                }
+
+                // Rejected writes are tried again too, until they're set aside
+                if unavailable || !queue.is_empty() {
+                    failures += 1;
+                    retry_at = Some(Instant::now() + reconnect_delay(failures));
+                } else {
+                    failures = 0;
+                    retry_at = None;
+                }
+
+                if unavailable && !degraded {
+                    let errors = errors.unavailable.join(", ");
+                    let message = format!("Storage unavailable, queueing writes: {errors}");
+                    log(&event_sender, LogLevel::Warn, message).await;
+                } else if !unavailable && degraded {
+                    let message = String::from("Storage is back, queued writes are saved");
+                    log(&event_sender, LogLevel::Info, message).await;
+                }
+
+                degraded = unavailable;
+            }
+        }
+
+        if queue.dropped > dropped {
+            let count = queue.dropped - dropped;
+            dropped = queue.dropped;
+            log(
+                &event_sender,
+                LogLevel::Warn,
+                format!("Write queue is full, dropped the {count} oldest writes"),
+            )
+            .await;
+        }
+
+        // Writes only stay queued for long while degraded
+        let current = StorageStatus {
+            degraded,
+            queued: if degraded { queue.len() } else { 0 },
+        };
+        if current != status {
+            status = current;
+            let _ = event_sender.send(AppEvent::StorageStatus(status)).await;
+        }
+    }
+}
+
+/// What a flush couldn't write.
+#[derive(Debug, Default)]
+struct FlushErrors {
+    /// Why a database couldn't be reached, its writes wait for the next retry
+    unavailable: Vec<String>,
+    /// Why writes were set aside after being rejected too many times
+    rejected: Vec<String>,
+}
+
+/// Writes everything in the queue whose database is reachable. A write the
+/// database rejects doesn't hold up the ones after it, and is set aside once
+/// it was tried [`MAX_REJECTIONS`] times.
+async fn flush(
+    storage: &SharedStorage,
+    open_storage: &OpenStorage,
+    qdrant: &mut QdrantService,
+    queue: &mut WriteQueue,
+) -> FlushErrors {
+    let mut storage = storage.lock().await;
+    let mut errors = FlushErrors::default();
+    let mut unavailable_targets = Vec::new();
+    let mut failed = Vec::new();
+
+    if storage.is_none() {
+        match open_storage().await {
+            Ok(opened) => *storage = Some(opened),
+            Err(e) => {
+                errors
+                    .unavailable
+                    .push(format!("opening storage failed: {e}"));
+                unavailable_targets.push(WriteTarget::Storage);
+            }
+        }
+    }
+
+    for mut queued in queue.take() {
+        let target = queued.write.target();
+        if unavailable_targets.contains(&target) {
+            failed.push(queued);
+            continue;
+        }
+
+        let result = match (&queued.write, &*storage) {
+            (PendingWrite::Message(message), Some(storage)) => {
+                storage.insert_message(message).await
+            }
+            (
+                PendingWrite::MessageAudio {
+                    message_id,
+                    audio_id,
+                },
+                Some(storage),
+            ) => storage.set_message_audio(message_id, audio_id).await,
+            (PendingWrite::Log(log), Some(storage)) => storage.insert_log(log).await,
+            (PendingWrite::Embedding(message), _) => qdrant.insert_message(message).await,
+            (_, None) => Err(anyhow::anyhow!("storage isn't open")),
+        };
+
+        let Err(e) = result else {
+            continue;
+        };
+        let error = match target {
+            WriteTarget::Storage => format!("storage: {e}"),
+            WriteTarget::Qdrant => format!("Qdrant: {e}"),
+        };
+
+        if is_transient(&e) {
+            errors.unavailable.push(error);
+            unavailable_targets.push(target);
+            failed.push(queued);
+            continue;
+        }
+
+        queued.rejections += 1;
+        if queued.rejections < MAX_REJECTIONS {
+            failed.push(queued);
+        } else if let Err(e) = queue.reject(&queued.write, &error) {
+            errors
+                .rejected
+                .push(format!("{error}, and setting it aside failed: {e}"));
+        } else {
+            errors.rejected.push(error);
+        }
+    }
+
+    if let Err(e) = queue.restore(failed) {
+        errors
+            .unavailable
+            .push(format!("saving the write queue failed: {e}"));
+    }
+
+    errors
+}
+
+/// Whether `error` may go away on its own, like a database being down, rather
+/// than the write being rejected. Errors that can't be told apart count as
+/// transient.
+fn is_transient(error: &anyhow::Error) -> bool {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<rusqlite::Error>() {
+            return matches!(
+                e.sqlite_error_code(),
+                Some(
+                    ErrorCode::DatabaseBusy
+                        | ErrorCode::DatabaseLocked
+                        | ErrorCode::SystemIoFailure
+                        | ErrorCode::DiskFull
+                        | ErrorCode::CannotOpen
+                        | ErrorCode::OutOfMemory
+                )
+            );
+        }
+
+        if let Some(e) = cause.downcast_ref::<::mongodb::error::Error>() {
+            return !matches!(
+                *e.kind,
+                ErrorKind::InvalidArgument { .. }
+                    | ErrorKind::BsonSerialization(_)
+                    | ErrorKind::BsonDeserialization(_)
+                    | ErrorKind::Write(WriteFailure::WriteError(_))
+            );
+        }
+
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return !e.is_decode()
+                && e.status().is_none_or(|status| {
+                    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+                });
+        }
+
+        if cause.is::<serde_json::Error>() {
+            return false;
+        }
+    }
+
+    true
+}
+
+async fn log(event_sender: &mpsc::Sender<AppEvent>, level: LogLevel, message: String) {
+    let _ = event_sender
+        .send(AppEvent::Log(LogEventPayload { level, message }))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use ::mongodb::bson::DateTime;
+
+    use super::*;
+    use crate::{
+        memory::sqlite::SqliteMemory,
+        types::message::{ContentBlock, Role},
+    };
+
+    fn temp_dir() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("jumo-memory-{nanos}"))
+    }
+
+    fn open_sqlite(path: PathBuf) -> OpenStorage {
+        Box::new(move || {
+            let path = path.clone();
+            Box::pin(async move { Ok(StorageBackend::Sqlite(SqliteMemory::open(path)?)) })
+        })
+    }
+
+    fn log(text: &str) -> PendingWrite {
+        PendingWrite::Log(Log::new(text, LogLevel::Info))
+    }
+
+    async fn next_status(events: &mut mpsc::Receiver<AppEvent>) -> StorageStatus {
+        loop {
+            if let AppEvent::StorageStatus(status) = events.recv().await.unwrap() {
+                return status;
+            }
+        }
+    }
+
+    async fn logged(storage: &SharedStorage) -> Vec<String> {
+        let storage = storage.lock().await;
+        let logs = storage.as_ref().unwrap().get_recent_logs().await.unwrap();
+        logs.into_iter().rev().map(|log| log.text).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queues_writes_while_storage_is_down_and_flushes_them_once_back() {
+        let dir = temp_dir();
+        let queue_path = dir.join("queue.jsonl");
+        // A file where the database's directory should be keeps it from opening
+        let blocker = dir.join("db");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&blocker, "").unwrap();
+
+        let storage: SharedStorage = Arc::default();
+        let queue = WriteQueue::open(&queue_path, 100).unwrap();
+        let (writes, receiver) = mpsc::unbounded_channel();
+        let (event_sender, mut events) = mpsc::channel(100);
+        tokio::spawn(run_writer(
+            storage.clone(),
+            open_sqlite(blocker.join("jumo.db")),
+            queue,
+            receiver,
+            event_sender,
+        ));
+
+        writes.send(log("one")).unwrap();
+        writes.send(log("two")).unwrap();
+        let degraded = StorageStatus {
+            degraded: true,
+            queued: 2,
+        };
+        while next_status(&mut events).await != degraded {}
+        assert_eq!(
+            std::fs::read_to_string(&queue_path)
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+
+        std::fs::remove_file(&blocker).unwrap();
+        while next_status(&mut events).await != StorageStatus::default() {}
+        assert_eq!(logged(&storage).await, ["one", "two"]);
+        assert!(!queue_path.exists());
+
+        // Back to writing straight through without the queue file, what's
+        // left is written when the writer stops
+        writes.send(log("three")).unwrap();
+        drop(writes);
+        while events.recv().await.is_some() {}
+        assert_eq!(logged(&storage).await, ["one", "two", "three"]);
+        assert!(!queue_path.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sets_aside_writes_the_database_keeps_rejecting() {
+        let dir = temp_dir();
+        let db_path = dir.join("jumo.db");
+        let sqlite = SqliteMemory::open(&db_path).unwrap();
+        rusqlite::Connection::open(&db_path)
+            .unwrap()
+            .execute("DROP TABLE logs", [])
+            .unwrap();
+
+        let storage: SharedStorage = Arc::new(Mutex::new(Some(StorageBackend::Sqlite(sqlite))));
+        let open_storage = open_sqlite(db_path);
+        let mut qdrant = QdrantService::new();
+        let mut queue = WriteQueue::open(dir.join("queue.jsonl"), 100).unwrap();
+
+        let message = Message {
+            _id: ObjectId::new(),
+            role: Role::User,
+            content: vec![ContentBlock::Text {
+                text: String::from("Hello"),
+            }],
+            created_at: DateTime::now(),
+            audio_id: None,
+            context: false,
+        };
+        queue.push(log("lost")).unwrap();
+        queue.push(PendingWrite::Message(message)).unwrap();
+
+        for _ in 1..MAX_REJECTIONS {
+            let errors = flush(&storage, &open_storage, &mut qdrant, &mut queue).await;
+            assert!(errors.unavailable.is_empty() && errors.rejected.is_empty());
+            assert_eq!(queue.len(), 1);
+        }
+
+        let errors = flush(&storage, &open_storage, &mut qdrant, &mut queue).await;
+        assert!(errors.unavailable.is_empty());
+        assert_eq!(errors.rejected.len(), 1);
+        assert!(queue.is_empty());
+
+        let rejected = std::fs::read_to_string(queue.rejected_path()).unwrap();
+        assert!(rejected.contains("lost") && rejected.contains("no such table"));
+
+        let stored = storage
+            .lock()
+            .await
+            .as_ref()
+            .unwrap()
+            .get_recent_messages()
+            .await;
+        assert_eq!(stored.unwrap().len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,7 +1,7 @@
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
 use mongodb::{Database, bson::doc};
 
-use crate::types::logs::Log;
+use crate::{memory::mongodb::is_duplicate, types::logs::Log};
 
 const COLLECTION_NAME: &str = "logs";
 
@@ -16,9 +16,11 @@ impl LogCollection {
         }
     }
 
-    pub async fn insert_one(&self, message: &Log) -> Result<(), anyhow::Error> {
-        self.collection.insert_one(message).await?;
-        Ok(())
+    pub async fn insert_one(&self, log: &Log) -> Result<(), anyhow::Error> {
+        match self.collection.insert_one(log).await {
+            Err(e) if !is_duplicate(&e) => Err(e.into()),
+            _ => Ok(()),
+        }
     }
 
     pub async fn get_recent_logs(&self) -> Result<Vec<Log>, anyhow::Error> {
@@ -38,4 +40,15 @@ impl LogCollection {
 
         Ok(logs)
     }
+
+    /// Every log in the collection, oldest first.
+    pub async fn get_all(&self) -> Result<Vec<Log>, anyhow::Error> {
+        let cursor = self
+            .collection
+            .find(doc! {})
+            .sort(doc! { "created_at": 1 })
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
 }
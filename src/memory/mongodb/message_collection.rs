@@ -1,7 +1,10 @@
-use crate::types::message::Message;
+use crate::{memory::mongodb::is_duplicate, types::message::Message};
 
-use futures::StreamExt;
-use mongodb::{Database, bson::doc};
+use futures::{StreamExt, TryStreamExt};
+use mongodb::{
+    Database,
+    bson::{doc, oid::ObjectId},
+};
 
 const COLLECTION_NAME: &str = "messages";
 
@@ -17,7 +20,24 @@ impl MessageCollection {
     }
 
     pub async fn insert_one(&self, message: &Message) -> Result<(), anyhow::Error> {
-        self.collection.insert_one(message).await?;
+        match self.collection.insert_one(message).await {
+            Err(e) if !is_duplicate(&e) => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Links a message to its archived audio.
+    pub async fn set_audio_id(
+        &self,
+        message_id: &ObjectId,
+        audio_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.collection
+            .update_one(
+                doc! { "_id": message_id },
+                doc! { "$set": { "audio_id": audio_id } },
+            )
+            .await?;
         Ok(())
     }
 
@@ -38,4 +58,15 @@ impl MessageCollection {
 
         Ok(messages)
     }
+
+    /// Every message in the collection, oldest first.
+    pub async fn get_all(&self) -> Result<Vec<Message>, anyhow::Error> {
+        let cursor = self
+            .collection
+            .find(doc! {})
+            .sort(doc! { "created_at": 1 })
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
 }
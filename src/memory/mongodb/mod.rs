@@ -1,13 +1,31 @@
-use mongodb::Database;
+use std::time::Duration;
+
+use mongodb::{
+    Database,
+    bson::oid::ObjectId,
+    error::{Error, ErrorKind, WriteFailure},
+    options::ClientOptions,
+};
 
 use crate::{
     config::CONFIG,
-    memory::mongodb::{log_collection::LogCollection, message_collection::MessageCollection},
+    memory::{
+        mongodb::{log_collection::LogCollection, message_collection::MessageCollection},
+        storage::Storage,
+    },
+    types::{logs::Log, message::Message},
 };
 
 pub mod log_collection;
 pub mod message_collection;
 
+/// How long an operation waits for the server before failing, so the app
+/// isn't held up for the driver's default 30 seconds when it's down.
+const SERVER_SELECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error code for a document with an `_id` that's already stored.
+const DUPLICATE_KEY: i32 = 11000;
+
 pub struct MongodbMemory {
     db: Database,
     pub messages: MessageCollection,
@@ -16,8 +34,10 @@ pub struct MongodbMemory {
 
 impl MongodbMemory {
     pub async fn new() -> Result<Self, anyhow::Error> {
-        let connection = mongodb::Client::with_uri_str(&CONFIG.mongodb_url).await?;
-        let db = connection.database(&CONFIG.mongodb_database);
+        let mut options = ClientOptions::parse(&CONFIG.mongodb.url).await?;
+        options.server_selection_timeout = Some(SERVER_SELECTION_TIMEOUT);
+        let connection = mongodb::Client::with_options(options)?;
+        let db = connection.database(&CONFIG.mongodb.database);
 
         let messages = MessageCollection::new(&db);
         let logs = LogCollection::new(&db);
@@ -25,3 +45,38 @@ impl MongodbMemory {
         Ok(Self { db, messages, logs })
     }
 }
+
+/// Queued writes can be retried after they went through, so a document
+/// that's already stored counts as written.
+fn is_duplicate(error: &Error) -> bool {
+    matches!(
+        &*error.kind,
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == DUPLICATE_KEY
+    )
+}
+
+impl Storage for MongodbMemory {
+    async fn insert_message(&self, message: &Message) -> Result<(), anyhow::Error> {
+        self.messages.insert_one(message).await
+    }
+
+    async fn set_message_audio(
+        &self,
+        message_id: &ObjectId,
+        audio_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.messages.set_audio_id(message_id, audio_id).await
+    }
+
+    async fn get_recent_messages(&self) -> Result<Vec<Message>, anyhow::Error> {
+        self.messages.get_recent_messages().await
+    }
+
+    async fn insert_log(&self, log: &Log) -> Result<(), anyhow::Error> {
+        self.logs.insert_one(log).await
+    }
+
+    async fn get_recent_logs(&self) -> Result<Vec<Log>, anyhow::Error> {
+        self.logs.get_recent_logs().await
+    }
+}
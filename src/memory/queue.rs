@@ -0,0 +1,307 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use crate::types::{logs::Log, message::Message};
+
+/// A write that hasn't reached its database yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum PendingWrite {
+    Message(Message),
+    /// Link from a message to its archived audio
+    MessageAudio {
+        message_id: ObjectId,
+        audio_id: String,
+    },
+    Log(Log),
+    /// Embedding of a message for Qdrant
+    Embedding(Message),
+}
+
+/// Where a pending write goes, they're retried separately so one database
+/// being down doesn't hold up the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteTarget {
+    Storage,
+    Qdrant,
+}
+
+impl PendingWrite {
+    pub fn target(&self) -> WriteTarget {
+        match self {
+            PendingWrite::Message(_) | PendingWrite::MessageAudio { .. } | PendingWrite::Log(_) => {
+                WriteTarget::Storage
+            }
+            PendingWrite::Embedding(_) => WriteTarget::Qdrant,
+        }
+    }
+}
+
+/// A write waiting in the queue.
+#[derive(Debug)]
+pub struct QueuedWrite {
+    pub write: PendingWrite,
+    /// Times its database rejected it, counted from the start of this run.
+    pub rejections: u32,
+}
+
+impl From<PendingWrite> for QueuedWrite {
+    fn from(write: PendingWrite) -> Self {
+        Self {
+            write,
+            rejections: 0,
+        }
+    }
+}
+
+/// A write given up on, kept for someone to look at.
+#[derive(Debug, Serialize)]
+struct RejectedWrite<'a> {
+    error: &'a str,
+    write: &'a PendingWrite,
+}
+
+/// Writes waiting for their database. They're only kept in memory while
+/// writes go through, once one fails the queue is kept in a JSON lines file
+/// so it survives a restart. Past `max_len` the oldest are dropped.
+#[derive(Debug)]
+pub struct WriteQueue {
+    path: PathBuf,
+    max_len: usize,
+    pending: VecDeque<QueuedWrite>,
+    /// Whether the file holds the queue, rather than being empty or missing.
+    on_disk: bool,
+    /// Writes dropped because the queue was full.
+    pub dropped: usize,
+}
+
+impl WriteQueue {
+    /// Loads the writes left from the last run. Lines that can't be read are
+    /// skipped.
+    pub fn open(path: impl Into<PathBuf>, max_len: usize) -> Result<Self, anyhow::Error> {
+        let path = path.into();
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(dir)?;
+        }
+
+        let pending: VecDeque<QueuedWrite> = match fs::read_to_string(&path) {
+            Ok(text) => text
+                .lines()
+                .filter_map(|line| serde_json::from_str::<PendingWrite>(line).ok())
+                .map(QueuedWrite::from)
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut queue = Self {
+            path,
+            max_len,
+            on_disk: !pending.is_empty(),
+            pending,
+            dropped: 0,
+        };
+        if queue.trim() {
+            queue.save()?;
+        }
+
+        Ok(queue)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn push(&mut self, write: PendingWrite) -> Result<(), anyhow::Error> {
+        self.pending.push_back(write.into());
+
+        if self.trim() {
+            return self.save();
+        }
+
+        if self.on_disk {
+            let write = &self.pending.back().expect("just pushed").write;
+            append_line(&self.path, write)?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes everything out of the queue, to be written. What couldn't be
+    /// written goes back with [`WriteQueue::restore`].
+    pub fn take(&mut self) -> Vec<QueuedWrite> {
+        self.pending.drain(..).collect()
+    }
+
+    /// Puts back the writes that failed, ahead of any pushed since, and saves
+    /// the queue. When everything was written and nothing is on disk it
+    /// doesn't touch the file.
+    pub fn restore(&mut self, failed: Vec<QueuedWrite>) -> Result<(), anyhow::Error> {
+        if failed.is_empty() && !self.on_disk {
+            return Ok(());
+        }
+
+        for write in failed.into_iter().rev() {
+            self.pending.push_front(write);
+        }
+        self.trim();
+        self.save()
+    }
+
+    /// Sets a write its database keeps rejecting aside in a file next to the
+    /// queue, with why.
+    pub fn reject(&self, write: &PendingWrite, error: &str) -> Result<(), anyhow::Error> {
+        append_line(&self.rejected_path(), &RejectedWrite { error, write })
+    }
+
+    pub fn rejected_path(&self) -> PathBuf {
+        self.path.with_extension("rejected.jsonl")
+    }
+
+    /// Drops the oldest writes past `max_len`, returning whether any were.
+    fn trim(&mut self) -> bool {
+        let excess = self.pending.len().saturating_sub(self.max_len);
+        self.pending.drain(..excess);
+        self.dropped += excess;
+        excess > 0
+    }
+
+    fn save(&mut self) -> Result<(), anyhow::Error> {
+        if self.pending.is_empty() {
+            match fs::remove_file(&self.path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            self.on_disk = false;
+            return Ok(());
+        }
+
+        let mut text = String::new();
+        for queued in &self.pending {
+            text.push_str(&serde_json::to_string(&queued.write)?);
+            text.push('\n');
+        }
+
+        // Written next to it and renamed, so a crash can't leave half a file
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, text)?;
+        fs::rename(&temporary, &self.path)?;
+        self.on_disk = true;
+
+        Ok(())
+    }
+}
+
+fn append_line(path: &Path, value: &impl Serialize) -> Result<(), anyhow::Error> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let line = serde_json::to_string(value)?;
+    writeln!(file, "{line}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use crate::types::logs::LogLevel;
+
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("jumo-write-queue-{nanos}/queue.jsonl"))
+    }
+
+    fn log(text: &str) -> PendingWrite {
+        PendingWrite::Log(Log::new(text, LogLevel::Info))
+    }
+
+    fn texts(writes: &[QueuedWrite]) -> Vec<&str> {
+        writes
+            .iter()
+            .map(|queued| match &queued.write {
+                PendingWrite::Log(log) => log.text.as_str(),
+                _ => "",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_writes_across_restarts_up_to_its_size() {
+        let path = temp_path();
+        let mut queue = WriteQueue::open(&path, 3).unwrap();
+
+        for text in ["one", "two", "three", "four"] {
+            queue.push(log(text)).unwrap();
+        }
+        assert_eq!(queue.dropped, 1);
+
+        let mut queue = WriteQueue::open(&path, 3).unwrap();
+        assert_eq!(texts(&queue.take()), ["two", "three", "four"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_go_back_in_front() {
+        let path = temp_path();
+        let mut queue = WriteQueue::open(&path, 10).unwrap();
+        queue.push(log("one")).unwrap();
+        queue.push(log("two")).unwrap();
+
+        let mut writes = queue.take();
+        writes.remove(1);
+        queue.push(log("three")).unwrap();
+        queue.restore(writes).unwrap();
+
+        let mut queue = WriteQueue::open(&path, 10).unwrap();
+        assert_eq!(texts(&queue.take()), ["one", "three"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn only_uses_the_file_while_writes_fail() {
+        let path = temp_path();
+        let mut queue = WriteQueue::open(&path, 10).unwrap();
+
+        queue.push(log("one")).unwrap();
+        let written = queue.take();
+        queue.restore(Vec::new()).unwrap();
+        assert!(!path.exists());
+
+        queue.push(log("two")).unwrap();
+        let failed = queue.take();
+        queue.restore(failed).unwrap();
+        queue.push(log("three")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        queue.take();
+        queue.restore(Vec::new()).unwrap();
+        assert!(!path.exists());
+
+        queue.reject(&written[0].write, "no such table").unwrap();
+        let rejected = fs::read_to_string(queue.rejected_path()).unwrap();
+        assert!(rejected.contains("no such table") && rejected.contains("one"));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
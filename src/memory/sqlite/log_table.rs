@@ -0,0 +1,83 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use rusqlite::params;
+
+use crate::{
+    memory::sqlite::SqliteConnection,
+    types::logs::{Log, LogLevel},
+};
+
+pub struct LogTable {
+    connection: SqliteConnection,
+}
+
+impl LogTable {
+    pub fn new(connection: SqliteConnection) -> Self {
+        Self { connection }
+    }
+
+    /// Inserts the logs that aren't stored yet, returning how many were.
+    pub async fn insert_many(&self, logs: Vec<Log>) -> Result<usize, anyhow::Error> {
+        self.connection
+            .call(move |connection| {
+                let transaction = connection.transaction()?;
+                let mut inserted = 0;
+
+                {
+                    let mut statement = transaction.prepare(
+                        "INSERT OR IGNORE INTO logs (id, level, text, created_at)
+                         VALUES (?1, ?2, ?3, ?4)",
+                    )?;
+
+                    for log in &logs {
+                        inserted += statement.execute(params![
+                            log._id.to_hex(),
+                            log.level.to_string(),
+                            log.text,
+                            log.created_at.timestamp_millis(),
+                        ])?;
+                    }
+                }
+
+                transaction.commit()?;
+                Ok(inserted)
+            })
+            .await
+    }
+
+    pub async fn get_recent_logs(&self, limit: usize) -> Result<Vec<Log>, anyhow::Error> {
+        self.connection
+            .call(move |connection| {
+                let mut statement = connection.prepare(
+                    "SELECT id, level, text, created_at FROM logs
+                     ORDER BY created_at DESC LIMIT ?1",
+                )?;
+
+                let rows = statement.query_map([limit], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, i64>(3)?,
+                    ))
+                })?;
+
+                rows.map(|row| {
+                    let (id, level, text, created_at) = row?;
+
+                    Ok(Log {
+                        _id: ObjectId::parse_str(&id)?,
+                        level: match level.as_str() {
+                            "INFO" => LogLevel::Info,
+                            "WARN" => LogLevel::Warn,
+                            "ERROR" => LogLevel::Error,
+                            _ => anyhow::bail!("Unknown level `{level}` for log {id}"),
+                        },
+                        text,
+                        created_at: DateTime::from_millis(created_at),
+                    })
+                })
+                .collect()
+            })
+            .await
+    }
+}
@@ -0,0 +1,109 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use rusqlite::params;
+
+use crate::{
+    memory::sqlite::SqliteConnection,
+    types::message::{Message, Role},
+};
+
+pub struct MessageTable {
+    connection: SqliteConnection,
+}
+
+impl MessageTable {
+    pub fn new(connection: SqliteConnection) -> Self {
+        Self { connection }
+    }
+
+    /// Inserts the messages that aren't stored yet, returning how many were.
+    pub async fn insert_many(&self, messages: Vec<Message>) -> Result<usize, anyhow::Error> {
+        self.connection
+            .call(move |connection| {
+                let transaction = connection.transaction()?;
+                let mut inserted = 0;
+
+                {
+                    let mut statement = transaction.prepare(
+                        "INSERT OR IGNORE INTO messages (id, role, content, created_at, audio_id)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                    )?;
+
+                    for message in &messages {
+                        let role = match message.role {
+                            Role::User => "user",
+                            Role::Assistant => "assistant",
+                        };
+
+                        inserted += statement.execute(params![
+                            message._id.to_hex(),
+                            role,
+                            serde_json::to_string(&message.content)?,
+                            message.created_at.timestamp_millis(),
+                            message.audio_id,
+                        ])?;
+                    }
+                }
+
+                transaction.commit()?;
+                Ok(inserted)
+            })
+            .await
+    }
+
+    /// Links a message to its archived audio. Messages that aren't stored are
+    /// left alone.
+    pub async fn set_audio_id(
+        &self,
+        message_id: ObjectId,
+        audio_id: String,
+    ) -> Result<(), anyhow::Error> {
+        self.connection
+            .call(move |connection| {
+                connection.execute(
+                    "UPDATE messages SET audio_id = ?1 WHERE id = ?2",
+                    params![audio_id, message_id.to_hex()],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get_recent_messages(&self, limit: usize) -> Result<Vec<Message>, anyhow::Error> {
+        self.connection
+            .call(move |connection| {
+                let mut statement = connection.prepare(
+                    "SELECT id, role, content, created_at, audio_id FROM messages
+                     ORDER BY created_at DESC LIMIT ?1",
+                )?;
+
+                let rows = statement.query_map([limit], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, i64>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                    ))
+                })?;
+
+                rows.map(|row| {
+                    let (id, role, content, created_at, audio_id) = row?;
+
+                    Ok(Message {
+                        _id: ObjectId::parse_str(&id)?,
+                        role: match role.as_str() {
+                            "user" => Role::User,
+                            "assistant" => Role::Assistant,
+                            _ => anyhow::bail!("Unknown role `{role}` for message {id}"),
+                        },
+                        content: serde_json::from_str(&content)?,
+                        created_at: DateTime::from_millis(created_at),
+                        audio_id,
+                        context: false,
+                    })
+                })
+                .collect()
+            })
+            .await
+    }
+}
@@ -0,0 +1,69 @@
+use rusqlite::Connection;
+
+/// Schema changes, applied in order. The database's `user_version` is how
+/// many have been applied, so only ever append to this list.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE messages (
+        id TEXT PRIMARY KEY,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        audio_id TEXT
+    );
+    CREATE INDEX messages_created_at ON messages (created_at);
+
+    CREATE TABLE logs (
+        id TEXT PRIMARY KEY,
+        level TEXT NOT NULL,
+        text TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX logs_created_at ON logs (created_at);
+"];
+
+/// Applies the migrations the database doesn't have yet.
+pub fn migrate(connection: &mut Connection) -> Result<(), anyhow::Error> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    if version > MIGRATIONS.len() {
+        anyhow::bail!(
+            "Database schema version {version} is newer than this build ({})",
+            MIGRATIONS.len()
+        );
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_once() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        let version = |connection: &Connection| -> usize {
+            connection
+                .pragma_query_value(None, "user_version", |row| row.get(0))
+                .unwrap()
+        };
+
+        migrate(&mut connection).unwrap();
+        assert_eq!(version(&connection), MIGRATIONS.len());
+
+        migrate(&mut connection).unwrap();
+        assert_eq!(version(&connection), MIGRATIONS.len());
+
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(migrate(&mut connection).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use mongodb::bson::oid::ObjectId;
+use rusqlite::Connection;
+
+use crate::{
+    memory::{
+        sqlite::{log_table::LogTable, message_table::MessageTable},
+        storage::Storage,
+    },
+    types::{logs::Log, message::Message},
+};
+
+pub mod log_table;
+pub mod message_table;
+pub mod migrations;
+
+/// How many messages and logs are loaded at startup, as with MongoDB.
+const RECENT_LIMIT: usize = 20;
+
+/// A SQLite connection shared by the tables. Queries run on the blocking
+/// thread pool.
+#[derive(Debug, Clone)]
+pub struct SqliteConnection(Arc<Mutex<Connection>>);
+
+impl SqliteConnection {
+    pub async fn call<T, F>(&self, f: F) -> Result<T, anyhow::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, anyhow::Error> + Send + 'static,
+    {
+        let connection = self.0.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| anyhow::anyhow!("SQLite connection lock poisoned"))?;
+            f(&mut connection)
+        })
+        .await?
+    }
+}
+
+pub struct SqliteMemory {
+    pub messages: MessageTable,
+    pub logs: LogTable,
+}
+
+impl SqliteMemory {
+    /// Opens the database at `path`, creating it and bringing its schema up
+    /// to date.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut connection = Connection::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {e}", path.display()))?;
+        migrations::migrate(&mut connection)?;
+
+        let connection = SqliteConnection(Arc::new(Mutex::new(connection)));
+
+        Ok(Self {
+            messages: MessageTable::new(connection.clone()),
+            logs: LogTable::new(connection),
+        })
+    }
+}
+
+impl Storage for SqliteMemory {
+    async fn insert_message(&self, message: &Message) -> Result<(), anyhow::Error> {
+        self.messages.insert_many(vec![message.clone()]).await?;
+        Ok(())
+    }
+
+    async fn set_message_audio(
+        &self,
+        message_id: &ObjectId,
+        audio_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.messages
+            .set_audio_id(*message_id, audio_id.to_string())
+            .await
+    }
+
+    async fn get_recent_messages(&self) -> Result<Vec<Message>, anyhow::Error> {
+        self.messages.get_recent_messages(RECENT_LIMIT).await
+    }
+
+    async fn insert_log(&self, log: &Log) -> Result<(), anyhow::Error> {
+        self.logs.insert_many(vec![log.clone()]).await?;
+        Ok(())
+    }
+
+    async fn get_recent_logs(&self) -> Result<Vec<Log>, anyhow::Error> {
+        self.logs.get_recent_logs(RECENT_LIMIT).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{DateTime, oid::ObjectId};
+
+    use super::*;
+    use crate::types::{
+        logs::LogLevel,
+        message::{ContentBlock, Role},
+    };
+
+    fn message_at(millis: i64) -> Message {
+        Message {
+            _id: ObjectId::new(),
+            role: Role::User,
+            content: vec![ContentBlock::Text {
+                text: format!("at {millis}"),
+            }],
+            created_at: DateTime::from_millis(millis),
+            audio_id: Some(String::from("audio")),
+            context: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_messages_and_logs() {
+        let storage = SqliteMemory::open(":memory:").unwrap();
+        let messages: Vec<Message> = (0..25).map(message_at).collect();
+
+        let inserted = storage.messages.insert_many(messages.clone());
+        assert_eq!(inserted.await.unwrap(), 25);
+        // Already stored, e.g. when migrating again
+        let inserted = storage.messages.insert_many(messages.clone());
+        assert_eq!(inserted.await.unwrap(), 0);
+
+        let recent = storage.get_recent_messages().await.unwrap();
+        assert_eq!(recent.len(), RECENT_LIMIT);
+        assert_eq!(recent[0]._id, messages[24]._id);
+        assert_eq!(recent[0].content, messages[24].content);
+        assert_eq!(recent[0].created_at, messages[24].created_at);
+        assert_eq!(recent[0].audio_id.as_deref(), Some("audio"));
+
+        storage
+            .set_message_audio(&messages[24]._id, "reply")
+            .await
+            .unwrap();
+        let recent = storage.get_recent_messages().await.unwrap();
+        assert_eq!(recent[0].audio_id.as_deref(), Some("reply"));
+
+        let log = Log::new("Hello", LogLevel::Warn);
+        storage.insert_log(&log).await.unwrap();
+        let logs = storage.get_recent_logs().await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!((logs[0]._id, logs[0].text.as_str()), (log._id, "Hello"));
+    }
+}
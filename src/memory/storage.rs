@@ -0,0 +1,110 @@
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+
+use crate::{
+    config::CONFIG,
+    memory::{mongodb::MongodbMemory, sqlite::SqliteMemory},
+    types::{logs::Log, message::Message},
+};
+
+/// Where messages and logs are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageKind {
+    /// A file in `storage.sqlite_path`, nothing to run next to the robot
+    #[default]
+    Sqlite,
+    /// The server at `mongodb.url`
+    Mongodb,
+}
+
+pub trait Storage {
+    async fn insert_message(&self, message: &Message) -> Result<(), anyhow::Error>;
+    /// Links a stored message to its archived audio.
+    async fn set_message_audio(
+        &self,
+        message_id: &ObjectId,
+        audio_id: &str,
+    ) -> Result<(), anyhow::Error>;
+    /// The most recent messages, newest first.
+    async fn get_recent_messages(&self) -> Result<Vec<Message>, anyhow::Error>;
+    async fn insert_log(&self, log: &Log) -> Result<(), anyhow::Error>;
+    /// The most recent logs, newest first.
+    async fn get_recent_logs(&self) -> Result<Vec<Log>, anyhow::Error>;
+}
+
+pub enum StorageBackend {
+    Sqlite(SqliteMemory),
+    Mongodb(MongodbMemory),
+}
+
+impl StorageBackend {
+    /// Opens the backend picked in `storage.backend`.
+    pub async fn open() -> Result<Self, anyhow::Error> {
+        Ok(match CONFIG.storage.backend {
+            StorageKind::Sqlite => Self::Sqlite(SqliteMemory::open(&CONFIG.storage.sqlite_path)?),
+            StorageKind::Mongodb => Self::Mongodb(MongodbMemory::new().await?),
+        })
+    }
+}
+
+impl Storage for StorageBackend {
+    async fn insert_message(&self, message: &Message) -> Result<(), anyhow::Error> {
+        match self {
+            StorageBackend::Sqlite(storage) => storage.insert_message(message).await,
+            StorageBackend::Mongodb(storage) => storage.insert_message(message).await,
+        }
+    }
+
+    async fn set_message_audio(
+        &self,
+        message_id: &ObjectId,
+        audio_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            StorageBackend::Sqlite(storage) => {
+                storage.set_message_audio(message_id, audio_id).await
+            }
+            StorageBackend::Mongodb(storage) => {
+                storage.set_message_audio(message_id, audio_id).await
+            }
+        }
+    }
+
+    async fn get_recent_messages(&self) -> Result<Vec<Message>, anyhow::Error> {
+        match self {
+            StorageBackend::Sqlite(storage) => storage.get_recent_messages().await,
+            StorageBackend::Mongodb(storage) => storage.get_recent_messages().await,
+        }
+    }
+
+    async fn insert_log(&self, log: &Log) -> Result<(), anyhow::Error> {
+        match self {
+            StorageBackend::Sqlite(storage) => storage.insert_log(log).await,
+            StorageBackend::Mongodb(storage) => storage.insert_log(log).await,
+        }
+    }
+
+    async fn get_recent_logs(&self) -> Result<Vec<Log>, anyhow::Error> {
+        match self {
+            StorageBackend::Sqlite(storage) => storage.get_recent_logs().await,
+            StorageBackend::Mongodb(storage) => storage.get_recent_logs().await,
+        }
+    }
+}
+
+/// Copies everything in MongoDB into the SQLite database, returning how many
+/// messages and logs were copied. Rows that are already there are skipped,
+/// so it can be run again.
+pub async fn migrate_mongodb_to_sqlite() -> Result<(usize, usize), anyhow::Error> {
+    let mongodb = MongodbMemory::new().await?;
+    let sqlite = SqliteMemory::open(&CONFIG.storage.sqlite_path)?;
+
+    let messages = mongodb.messages.get_all().await?;
+    let message_count = sqlite.messages.insert_many(messages).await?;
+
+    let logs = mongodb.logs.get_all().await?;
+    let log_count = sqlite.logs.insert_many(logs).await?;
+
+    Ok((message_count, log_count))
+}
@@ -1,13 +1,14 @@
 use crate::{
     prompts::{
-        emoting::get_emote_prompt, overview::get_overview_prompt, status::get_status_prompt,
-        system_info::get_system_info_prompt,
+        emoting::get_emote_prompt, overview::get_overview_prompt, speech::get_speech_prompt,
+        status::get_status_prompt, system_info::get_system_info_prompt,
     },
     state::AppState,
 };
 
 pub mod emoting;
 pub mod overview;
+pub mod speech;
 pub mod status;
 pub mod system_info;
 
@@ -17,6 +18,7 @@ pub fn get_system_prompt(state: &AppState) -> String {
         get_status_prompt(state),
         get_system_info_prompt(),
         get_emote_prompt(state),
+        get_speech_prompt(),
     ]
     .join("\n\n")
 }
@@ -1,10 +1,15 @@
-pub const SYSTEM_PROMPT: &str = r#"
-You are a helpful robot assistant. You will be receiving messages that are transcribed from audio received from your audio input device.
-
-Your name is Jumo.
-The user's name is Ryan Walker.
-"#;
+use crate::config::CONFIG;
 
 pub fn get_overview_prompt() -> String {
-    SYSTEM_PROMPT.to_string()
+    let name = &CONFIG.persona.name;
+    let user_name = &CONFIG.persona.user_name;
+
+    format!(
+        r#"
+You are a helpful robot assistant. You will be receiving messages that are transcribed from audio received from your audio input device.
+
+Your name is {name}.
+The user's name is {user_name}.
+"#
+    )
 }
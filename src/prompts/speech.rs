@@ -0,0 +1,21 @@
+pub fn get_speech_prompt() -> String {
+    String::from(
+        r#"
+## Speech:
+
+Your replies are read out loud. You can use these tags to direct how they're spoken, they're hidden from the displayed text:
+
+- `<pause/>` for a short pause, or `<pause 2s/>` / `<pause 300ms/>` for a longer or shorter one (at most 3 seconds).
+- `<whisper>...</whisper>` to whisper.
+- `<emphasis>...</emphasis>` to stress a word or two.
+- `<rate slow>...</rate>` or `<rate fast>...</rate>` to change the speaking rate. It's a hint, not every voice backend can change it mid reply.
+- `<silent>...</silent>` for text that's shown but not spoken, like links, code or long lists.
+
+Use them sparingly, where they help the delivery.
+
+### Example:
+
+"Guess what? <pause/> I <emphasis>finally</emphasis> found it. <whisper>Don't tell anyone.</whisper> <silent>https://example.com/treasure</silent>"
+"#,
+    )
+}
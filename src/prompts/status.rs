@@ -3,6 +3,13 @@ use crate::state::AppState;
 pub fn get_status_prompt(state: &AppState) -> String {
     let date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
     let view = state.view;
+    let voice = &state.voice;
+    let voices = state
+        .voices
+        .iter()
+        .map(|voice| voice.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
 
     format!(
         r#"
@@ -15,6 +22,11 @@ pub fn get_status_prompt(state: &AppState) -> String {
 ### TUI state:
 
 - Current view is {view}.
+
+### Voice:
+
+- You speak with {voice}.
+- Available voices: {voices}.
 "#,
     )
 }
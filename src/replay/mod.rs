@@ -0,0 +1,293 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use tokio_tungstenite::tungstenite::Bytes;
+
+use crate::{
+    audio::PcmFormat,
+    events::{AppEvent, TTSChunkEventPayload},
+    replay::types::RecordedEvent,
+};
+
+pub mod types;
+
+const EVENTS_FILE: &str = "events.jsonl";
+const PAYLOADS_DIR: &str = "payloads";
+/// How long recorded events may sit in the write buffer.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes every event received on the event bus to a session directory so it
+/// can be replayed later with `--replay`.
+///
+/// Events are serialized on the caller's thread and written by a dedicated
+/// writer thread, so a slow disk never holds up the event loop. Dropping the
+/// recorder flushes whatever is still buffered.
+pub struct EventRecorder {
+    session_dir: PathBuf,
+    started_at: Instant,
+    payload_count: usize,
+    entries: Option<mpsc::Sender<Entry>>,
+    writer: Option<JoinHandle<Result<(), anyhow::Error>>>,
+}
+
+/// A recorded event and the audio to store next to it.
+struct Entry {
+    recorded: RecordedEvent,
+    payload: Option<Bytes>,
+}
+
+impl EventRecorder {
+    /// Creates a new timestamped session directory inside `dir`.
+    pub fn create(dir: &Path) -> Result<Self, anyhow::Error> {
+        let session_name = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let session_dir = dir.join(session_name);
+        fs::create_dir_all(session_dir.join(PAYLOADS_DIR))?;
+
+        let file = File::create(session_dir.join(EVENTS_FILE))?;
+        let (entries, receiver) = mpsc::channel();
+        let writer_dir = session_dir.clone();
+        let writer = thread::Builder::new()
+            .name(String::from("event-recorder"))
+            .spawn(move || write_entries(&writer_dir, BufWriter::new(file), receiver))?;
+
+        Ok(Self {
+            session_dir,
+            started_at: Instant::now(),
+            payload_count: 0,
+            entries: Some(entries),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn session_dir(&self) -> &Path {
+        &self.session_dir
+    }
+
+    /// Queues `event` for the writer thread. Fails with the writer's error once
+    /// it has stopped.
+    pub fn record(&mut self, event: &AppEvent) -> Result<(), anyhow::Error> {
+        // queries carry reply channels and can't be replayed
+        if let AppEvent::Query(_) = event {
+            return Ok(());
+        }
+
+        let payload = match event {
+            AppEvent::AudioRecordingCompleted(bytes) => Some((Bytes::from(bytes.clone()), "wav")),
+            AppEvent::TTSChunk(payload) => Some((payload.audio.clone(), "pcm")),
+            _ => None,
+        };
+
+        let payload_file = payload.as_ref().map(|(_, extension)| {
+            self.payload_count += 1;
+            format!("{PAYLOADS_DIR}/{:06}.{extension}", self.payload_count)
+        });
+
+        let entry = Entry {
+            recorded: RecordedEvent {
+                elapsed_ms: self.started_at.elapsed().as_millis() as u64,
+                recorded_at: chrono::Local::now().to_rfc3339(),
+                event: serde_json::to_value(event)?,
+                payload_file,
+            },
+            payload: payload.map(|(bytes, _)| bytes),
+        };
+
+        let sent = self
+            .entries
+            .as_ref()
+            .is_some_and(|entries| entries.send(entry).is_ok());
+
+        if !sent {
+            return Err(self.stop_writer());
+        }
+
+        Ok(())
+    }
+
+    /// Closes the queue and waits for the writer to finish, returning why it
+    /// stopped.
+    fn stop_writer(&mut self) -> anyhow::Error {
+        self.entries = None;
+
+        match self.writer.take().map(JoinHandle::join) {
+            Some(Ok(Err(err))) => err,
+            Some(Err(_)) => anyhow::anyhow!("event writer panicked"),
+            Some(Ok(Ok(()))) | None => anyhow::anyhow!("event writer stopped"),
+        }
+    }
+}
+
+impl Drop for EventRecorder {
+    fn drop(&mut self) {
+        let _ = self.stop_writer();
+    }
+}
+
+/// Runs on the writer thread until the recorder is dropped, flushing at least
+/// every [`FLUSH_INTERVAL`].
+fn write_entries(
+    session_dir: &Path,
+    mut writer: BufWriter<File>,
+    entries: mpsc::Receiver<Entry>,
+) -> Result<(), anyhow::Error> {
+    let mut flushed_at = Instant::now();
+
+    loop {
+        match entries.recv_timeout(FLUSH_INTERVAL) {
+            Ok(entry) => {
+                if let (Some(bytes), Some(name)) = (&entry.payload, &entry.recorded.payload_file) {
+                    fs::write(session_dir.join(name), bytes)?;
+                }
+
+                serde_json::to_writer(&mut writer, &entry.recorded)?;
+                writer.write_all(b"\n")?;
+
+                if flushed_at.elapsed() >= FLUSH_INTERVAL {
+                    writer.flush()?;
+                    flushed_at = Instant::now();
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                writer.flush()?;
+                flushed_at = Instant::now();
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                writer.flush()?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Feeds a recorded session back into the app, keeping the original timing
+/// scaled by `speed`. A speed of `0` replays as fast as possible.
+pub struct EventReplayer {
+    session_dir: PathBuf,
+    events: std::vec::IntoIter<RecordedEvent>,
+    speed: f32,
+    started_at: Instant,
+}
+
+impl EventReplayer {
+    pub fn open(session_dir: &Path, speed: f32) -> Result<Self, anyhow::Error> {
+        let file = File::open(session_dir.join(EVENTS_FILE))?;
+
+        let mut events = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let event = serde_json::from_str::<RecordedEvent>(&line)
+                .map_err(|e| anyhow::anyhow!("{EVENTS_FILE}:{}: {e}", index + 1))?;
+            events.push(event);
+        }
+
+        Ok(Self {
+            session_dir: session_dir.to_path_buf(),
+            events: events.into_iter(),
+            speed,
+            started_at: Instant::now(),
+        })
+    }
+
+    pub fn session_dir(&self) -> &Path {
+        &self.session_dir
+    }
+
+    /// Waits until the next event is due and returns it, or `None` once the session is over.
+    pub async fn next(&mut self) -> Option<Result<AppEvent, anyhow::Error>> {
+        let recorded = self.events.next()?;
+
+        if self.speed > 0.0 {
+            let due = Duration::from_millis(recorded.elapsed_ms).div_f32(self.speed);
+            tokio::time::sleep_until((self.started_at + due).into()).await;
+        }
+
+        Some(self.to_app_event(recorded))
+    }
+
+    fn to_app_event(&self, recorded: RecordedEvent) -> Result<AppEvent, anyhow::Error> {
+        let Some(payload_file) = recorded.payload_file else {
+            return Ok(serde_json::from_value(recorded.event)?);
+        };
+
+        let bytes = fs::read(self.session_dir.join(&payload_file))?;
+
+        match recorded.event.get("type").and_then(|t| t.as_str()) {
+            Some("AudioRecordingCompleted") => Ok(AppEvent::AudioRecordingCompleted(bytes)),
+            Some("TTSChunk") => {
+                // Sessions recorded before chunks carried a format are ElevenLabs output
+                let format = recorded
+                    .event
+                    .pointer("/payload/format")
+                    .and_then(|format| serde_json::from_value(format.clone()).ok())
+                    .unwrap_or(PcmFormat {
+                        sample_rate: 44100,
+                        channels: 1,
+                    });
+
+                let words = recorded
+                    .event
+                    .pointer("/payload/words")
+                    .and_then(|words| serde_json::from_value(words.clone()).ok())
+                    .unwrap_or_default();
+
+                Ok(AppEvent::TTSChunk(TTSChunkEventPayload {
+                    audio: Bytes::from(bytes),
+                    format,
+                    words,
+                }))
+            }
+            other => Err(anyhow::anyhow!(
+                "Unexpected payload file {payload_file} for event {other:?}"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replays_what_was_recorded() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("jumo-recording-{nanos}"));
+
+        let mut recorder = EventRecorder::create(&dir).unwrap();
+        let session_dir = recorder.session_dir().to_path_buf();
+        recorder
+            .record(&AppEvent::TextPrompt(String::from("Hello")))
+            .unwrap();
+        recorder
+            .record(&AppEvent::AudioRecordingCompleted(vec![1, 2, 3]))
+            .unwrap();
+        drop(recorder);
+
+        let mut replayer = EventReplayer::open(&session_dir, 0.0).unwrap();
+        assert!(matches!(
+            replayer.next().await,
+            Some(Ok(AppEvent::TextPrompt(text))) if text == "Hello"
+        ));
+        assert!(matches!(
+            replayer.next().await,
+            Some(Ok(AppEvent::AudioRecordingCompleted(bytes))) if bytes == [1, 2, 3]
+        ));
+        assert!(replayer.next().await.is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
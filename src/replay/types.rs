@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+/// One line of a recorded session's `events.jsonl`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Milliseconds since the recording started.
+    pub elapsed_ms: u64,
+    /// Wall clock time the event was received, RFC 3339.
+    pub recorded_at: String,
+    /// The serialized `AppEvent`.
+    pub event: serde_json::Value,
+    /// Audio payloads are stored next to `events.jsonl` and referenced by relative path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_file: Option<String>,
+}
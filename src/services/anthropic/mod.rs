@@ -1,22 +1,22 @@
 use eventsource_stream::Eventsource;
 use futures::StreamExt;
 use mongodb::bson::oid::ObjectId;
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, task::AbortHandle};
 
 use crate::{
+    camera::CapturePolicy,
     config::CONFIG,
     events::{
         AppEvent, LLMGenerationCompletedEventPayload, LLMGenerationStartedEventPayload,
         LLMStreamEventPayload, LogEventPayload,
     },
-    features::Features,
     prompts::get_system_prompt,
     services::anthropic::types::{AnthropicInput, AnthropicMessage, AnthropicMessageStreamEvent},
     state::AppState,
     tools::tools::ToolType,
     types::{
         logs::LogLevel,
-        message::{ContentBlock, Message},
+        message::{ContentBlock, Message, Role},
     },
 };
 
@@ -24,60 +24,56 @@ pub mod types;
 
 pub struct AnthropicService {
     event_sender: mpsc::Sender<AppEvent>,
+    base_url: String,
+    /// When stubbed no requests are made, e.g. while replaying a recorded session.
+    stubbed: bool,
+    /// The generation being streamed, stopped by [`AnthropicService::cancel`]
+    generation: Option<AbortHandle>,
 }
 
 impl AnthropicService {
     pub fn new(event_sender: mpsc::Sender<AppEvent>) -> Self {
-        Self { event_sender }
+        Self::with_base_url(event_sender, &CONFIG.anthropic.base_url)
+    }
+
+    pub fn with_base_url(event_sender: mpsc::Sender<AppEvent>, base_url: &str) -> Self {
+        Self {
+            event_sender,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            stubbed: false,
+            generation: None,
+        }
+    }
+
+    pub fn stubbed(event_sender: mpsc::Sender<AppEvent>) -> Self {
+        Self {
+            stubbed: true,
+            ..Self::new(event_sender)
+        }
     }
 
     pub fn prompt(&mut self, input: &Message, messages: &[Message], state: &AppState) {
+        if self.stubbed {
+            return;
+        }
+
         let message_id = ObjectId::new();
 
         let start_payload = LLMGenerationStartedEventPayload { message_id };
 
         let mut claude_messages = vec![];
 
-        let process_message = |message: &Message| {
-            let role = message.role;
-            let content = message
-                .content
-                .iter()
-                .map(|content| match content {
-                    ContentBlock::Text { text } => {
-                        let text = if text.is_empty() { "<empty>" } else { text };
-
-                        ContentBlock::Text {
-                            text: text.to_string(),
-                        }
-                    }
-                    _ => content.clone(),
-                })
-                .filter(|content| {
-                    if let ContentBlock::Image { .. } = content {
-                        if !Features::video_capture_enabled() {
-                            return false;
-                        }
-                    }
-
-                    true
-                })
-                .collect();
-
-            AnthropicMessage { role, content }
-        };
-
-        for message in messages {
-            claude_messages.push(process_message(message));
+        for message in history(messages) {
+            claude_messages.push(to_anthropic(message));
         }
 
-        claude_messages.push(process_message(input));
+        claude_messages.push(to_anthropic(input));
 
         let system = get_system_prompt(state);
 
         let body = AnthropicInput {
-            model: String::from("claude-sonnet-4-20250514"),
-            max_tokens: 5000,
+            model: CONFIG.anthropic.model.clone(),
+            max_tokens: CONFIG.anthropic.max_tokens,
             messages: claude_messages,
             stream: true,
             system: Some(system),
@@ -85,8 +81,9 @@ impl AnthropicService {
         };
 
         let event_sender = self.event_sender.clone();
+        let url = format!("{}/v1/messages", self.base_url);
 
-        tokio::spawn(async move {
+        let generation = tokio::spawn(async move {
             let _ = event_sender
                 .send(AppEvent::LLMGenerationStarted(start_payload))
                 .await;
@@ -100,8 +97,8 @@ impl AnthropicService {
             let client = reqwest::Client::new();
 
             let resp = client
-                .post("https://api.anthropic.com/v1/messages")
-                .header("x-api-key", &CONFIG.anthropic_api_key)
+                .post(url)
+                .header("x-api-key", &CONFIG.anthropic.api_key)
                 .header("anthropic-version", "2023-06-01")
                 .header("content-type", "application/json")
                 .json(&body)
@@ -177,9 +174,140 @@ impl AnthropicService {
                 .send(AppEvent::LLMGenerationCompleted(completed_payload))
                 .await;
         });
+
+        self.generation = Some(generation.abort_handle());
     }
 
-    pub fn cancel(&mut self) {
-        // TODO: cancel requests
+    /// Stops streaming the current generation, returning whether one was
+    /// still running. It's never completed, so no more events are sent for it.
+    pub fn cancel(&mut self) -> bool {
+        let Some(generation) = self.generation.take() else {
+            return false;
+        };
+
+        let running = !generation.is_finished();
+        generation.abort();
+        running
+    }
+}
+
+/// The message as sent to the API.
+fn to_anthropic(message: &Message) -> AnthropicMessage {
+    let content = message
+        .content
+        .iter()
+        .map(|content| match content {
+            ContentBlock::Text { text } => {
+                let text = if text.is_empty() { "<empty>" } else { text };
+
+                ContentBlock::Text {
+                    text: text.to_string(),
+                }
+            }
+            _ => content.clone(),
+        })
+        .filter(|content| {
+            // Snapshots taken for context, like on arrival, come from
+            // presence detection rather than the capture setting
+            !matches!(content, ContentBlock::Image { .. })
+                || message.context
+                || CONFIG.camera.capture != CapturePolicy::Never
+        })
+        .collect();
+
+    AnthropicMessage {
+        role: message.role,
+        content,
+    }
+}
+
+/// The messages sent as history. The loaded history can start part way
+/// through an exchange, but has to start with something the user said, and a
+/// reply stopped before it wrote anything has no content to send.
+fn history(messages: &[Message]) -> impl Iterator<Item = &Message> {
+    messages
+        .iter()
+        .skip_while(|message| message.role != Role::User || !(message.is_turn() || message.context))
+        .filter(|message| !message.content.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::DateTime;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        types::message::{ImageSource, MediaType, ToolResultContent},
+    };
+
+    fn message(role: Role, content: Vec<ContentBlock>) -> Message {
+        Message {
+            _id: ObjectId::new(),
+            role,
+            content,
+            created_at: DateTime::now(),
+            audio_id: None,
+            context: false,
+        }
+    }
+
+    #[test]
+    fn history_starts_with_a_user_turn() {
+        let text = |text: &str| {
+            vec![ContentBlock::Text {
+                text: String::from(text),
+            }]
+        };
+        let tool_result = vec![ContentBlock::ToolResult {
+            tool_use_id: String::from("toolu_1"),
+            content: ToolResultContent::Text(String::from("done")),
+        }];
+
+        let messages = vec![
+            message(Role::User, tool_result),
+            message(Role::Assistant, text("Done.")),
+            message(Role::User, text("Hello")),
+            message(Role::Assistant, vec![]),
+            message(Role::User, text("Hello?")),
+            message(Role::Assistant, text("Hi!")),
+        ];
+
+        let sent: Vec<&ObjectId> = history(&messages).map(|message| &message._id).collect();
+        assert_eq!(sent, [&messages[2]._id, &messages[4]._id, &messages[5]._id]);
+    }
+
+    #[test]
+    fn sends_context_snapshots_even_when_capture_is_off() {
+        let config = Config::init_for_tests();
+        assert_eq!(config.camera.capture, CapturePolicy::Never);
+
+        let content = vec![
+            ContentBlock::Text {
+                text: String::from("[Someone just appeared]"),
+            },
+            ContentBlock::Image {
+                source: ImageSource {
+                    image_type: String::from("base64"),
+                    media_type: MediaType::JPEG,
+                    data: String::from("snapshot"),
+                },
+            },
+        ];
+        let said = message(Role::User, content.clone());
+        let arrival = Message {
+            context: true,
+            ..message(Role::User, content)
+        };
+
+        assert_eq!(to_anthropic(&said).content.len(), 1);
+        assert_eq!(to_anthropic(&arrival).content.len(), 2);
+
+        // A greeting is history even though nobody said anything before it
+        let greeting = vec![ContentBlock::Text {
+            text: String::from("Hi there!"),
+        }];
+        let messages = vec![arrival, message(Role::Assistant, greeting)];
+        assert_eq!(history(&messages).count(), 2);
     }
 }
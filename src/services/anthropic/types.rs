@@ -22,7 +22,7 @@ pub struct AnthropicInput {
     pub tools: Vec<ToolInput>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum AnthropicMessageStreamEvent {
     MessageStart {
@@ -49,7 +49,7 @@ pub enum AnthropicMessageStreamEvent {
     },
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum AnthropicContentBlockDelta {
     /// The deltas for normal text output.
@@ -69,7 +69,7 @@ pub enum AnthropicContentBlockDelta {
     Signature { signature: String },
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AnthropicMessageDelta {
     pub stop_reason: Option<String>,
     pub usage: Option<AnthropicUsage>,
@@ -0,0 +1,578 @@
+//! Content addressed cache of synthesized phrases, so stock replies and cues
+//! don't go through the websocket again.
+//!
+//! Entries are raw PCM files named by a hash of everything that affects the
+//! audio, with the timings of the words in them kept in a JSON file next to
+//! each. The least recently used ones are deleted once the cache outgrows its
+//! size limit, with file modification times keeping the order across restarts.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use tokio_tungstenite::tungstenite::Bytes;
+
+use crate::{
+    services::elevenlabs::{OUTPUT_PCM_FORMAT, voices::VoiceSettings},
+    types::spoken::SpokenWord,
+};
+
+#[derive(Debug, Clone)]
+pub struct TtsCache {
+    dir: PathBuf,
+    index: Arc<Mutex<CacheIndex>>,
+}
+
+#[derive(Debug, Default)]
+struct CacheIndex {
+    max_bytes: u64,
+    total_bytes: u64,
+    entries: HashMap<u64, CacheEntry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CacheEntry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+impl TtsCache {
+    /// Opens the cache in `dir`, picking up the entries already there.
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        let dir = dir.into();
+        let mut index = CacheIndex {
+            max_bytes,
+            ..CacheIndex::default()
+        };
+
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            let key = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| u64::from_str_radix(stem, 16).ok());
+
+            if let (Some(key), Ok(metadata)) = (key, entry.metadata())
+                && path.extension().is_some_and(|ext| ext == "pcm")
+            {
+                index.insert(
+                    key,
+                    metadata.len(),
+                    metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                );
+            }
+        }
+
+        Self {
+            dir,
+            index: Arc::new(Mutex::new(index)),
+        }
+    }
+
+    pub fn contains(&self, key: u64) -> bool {
+        self.index
+            .lock()
+            .is_ok_and(|index| index.entries.contains_key(&key))
+    }
+
+    /// The audio for `key` and the words heard in it.
+    pub async fn get(&self, key: u64) -> Option<(Bytes, Vec<SpokenWord>)> {
+        if !self.contains(key) {
+            return None;
+        }
+
+        let path = self.path(key);
+        match tokio::fs::read(&path).await {
+            Ok(pcm) => {
+                let now = SystemTime::now();
+                if let Ok(mut index) = self.index.lock() {
+                    index.touch(key, now);
+                }
+                // Best effort, only the order after a restart depends on it
+                let _ = File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(now));
+
+                // Entries cached without timings still play, just without
+                // highlighting
+                let words = tokio::fs::read(self.words_path(key))
+                    .await
+                    .ok()
+                    .and_then(|json| serde_json::from_slice(&json).ok())
+                    .unwrap_or_default();
+
+                Some((Bytes::from(pcm), words))
+            }
+            Err(_) => {
+                if let Ok(mut index) = self.index.lock() {
+                    index.remove(key);
+                }
+                None
+            }
+        }
+    }
+
+    pub async fn insert(
+        &self,
+        key: u64,
+        pcm: &[u8],
+        words: &[SpokenWord],
+    ) -> Result<(), anyhow::Error> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // Written first, the PCM file is what makes it an entry
+        tokio::fs::write(self.words_path(key), serde_json::to_vec(words)?).await?;
+        tokio::fs::write(self.path(key), pcm).await?;
+
+        let evicted = match self.index.lock() {
+            Ok(mut index) => index.insert(key, pcm.len() as u64, SystemTime::now()),
+            Err(_) => Vec::new(),
+        };
+
+        for key in evicted {
+            tokio::fs::remove_file(self.path(key)).await?;
+            let _ = tokio::fs::remove_file(self.words_path(key)).await;
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.pcm"))
+    }
+
+    fn words_path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.json"))
+    }
+}
+
+impl CacheIndex {
+    /// Adds an entry, returning the least recently used ones that no longer fit.
+    fn insert(&mut self, key: u64, size: u64, last_used: SystemTime) -> Vec<u64> {
+        self.remove(key);
+        self.entries.insert(key, CacheEntry { size, last_used });
+        self.total_bytes += size;
+
+        let mut evicted = Vec::new();
+
+        while self.total_bytes > self.max_bytes {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .filter(|(candidate, _)| **candidate != key)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+
+            self.remove(oldest);
+            evicted.push(oldest);
+        }
+
+        evicted
+    }
+
+    fn touch(&mut self, key: u64, now: SystemTime) {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_used = now;
+        }
+    }
+
+    fn remove(&mut self, key: u64) {
+        if let Some(entry) = self.entries.remove(&key) {
+            self.total_bytes -= entry.size;
+        }
+    }
+}
+
+/// Whitespace doesn't change how a phrase is spoken.
+pub fn normalize_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Identifies the audio for `text`, `None` when there's nothing to say.
+pub fn cache_key(
+    voice_id: &str,
+    settings: &VoiceSettings,
+    format: &str,
+    text: &str,
+) -> Option<u64> {
+    let text = normalize_text(text);
+    if text.is_empty() {
+        return None;
+    }
+
+    let material = format!(
+        "{voice_id}\n{}\n{}\n{}\n{}\n{format}\n{text}",
+        settings.stability, settings.similarity_boost, settings.style, settings.speed
+    );
+
+    Some(fnv1a(material.as_bytes()))
+}
+
+/// FNV-1a, stable across builds unlike the std hashers, since keys end up on disk.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A text chunk of a TTS stream, played back at `gain`.
+#[derive(Debug)]
+pub enum Segment {
+    /// Audio from the cache, played once everything before it was.
+    Cached {
+        audio: Bytes,
+        words: Vec<SpokenWord>,
+        gain: f32,
+    },
+    /// Text sent over the websocket, complete once `chars` non-whitespace
+    /// characters of alignment came back.
+    Streamed {
+        key: Option<u64>,
+        chars: usize,
+        gain: f32,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SegmentOutput {
+    /// Audio to play and the words heard in it
+    Play(Bytes, Vec<SpokenWord>),
+    /// Audio to cache under a key and the words heard in it
+    Cache(u64, Bytes, Vec<SpokenWord>),
+}
+
+/// Puts cached and streamed chunks back in order. The websocket only reports
+/// which characters its audio covers, so streamed audio is attributed to
+/// chunks by counting those.
+#[derive(Debug, Default)]
+pub struct SegmentQueue {
+    segments: VecDeque<Segment>,
+    /// Audio and alignment received for the first streamed segment, with
+    /// word timings from the start of its audio
+    audio: Vec<u8>,
+    words: Vec<SpokenWord>,
+    chars: usize,
+    /// The audio of the first streamed segment can't be told apart from its
+    /// neighbour's, so it isn't cached
+    unattributed: bool,
+    outputs: Vec<SegmentOutput>,
+}
+
+impl SegmentQueue {
+    pub fn push(&mut self, segment: Segment) {
+        // Nothing to wait for
+        if matches!(segment, Segment::Streamed { chars: 0, .. }) {
+            return;
+        }
+
+        self.segments.push_back(segment);
+        self.release_cached();
+    }
+
+    /// Handles audio from the websocket, `chars` being the non-whitespace
+    /// characters it covers when the message had alignment.
+    pub fn push_audio(&mut self, audio: Bytes, chars: Option<usize>, words: Vec<SpokenWord>) {
+        let Some(&Segment::Streamed { gain, .. }) = self.segments.front() else {
+            self.outputs.push(SegmentOutput::Play(audio, words));
+            return;
+        };
+
+        let offset_ms = OUTPUT_PCM_FORMAT.duration_ms(self.audio.len());
+        self.words.extend(words.iter().map(|word| SpokenWord {
+            text: word.text.clone(),
+            start_ms: word.start_ms + offset_ms,
+            end_ms: word.end_ms + offset_ms,
+        }));
+        self.audio.extend_from_slice(&audio);
+
+        self.outputs
+            .push(SegmentOutput::Play(apply_gain(audio, gain), words));
+
+        let Some(chars) = chars else {
+            self.unattributed = true;
+            return;
+        };
+        self.chars += chars;
+
+        while let Some(&Segment::Streamed {
+            key,
+            chars: expected,
+            ..
+        }) = self.segments.front()
+            && self.chars >= expected
+        {
+            if self.chars == expected
+                && !self.unattributed
+                && let Some(key) = key
+            {
+                let audio = std::mem::take(&mut self.audio);
+                let words = std::mem::take(&mut self.words);
+                self.outputs
+                    .push(SegmentOutput::Cache(key, Bytes::from(audio), words));
+            }
+
+            // Alignment spilling into the next chunk means this message's
+            // audio belongs to both
+            self.unattributed = self.chars > expected;
+            self.chars -= expected;
+            self.audio.clear();
+            self.words.clear();
+            self.segments.pop_front();
+            self.release_cached();
+        }
+    }
+
+    /// The stream ended, whatever was cached still plays.
+    pub fn finish(&mut self) {
+        for segment in self.segments.drain(..) {
+            if let Segment::Cached { audio, words, gain } = segment {
+                self.outputs
+                    .push(SegmentOutput::Play(apply_gain(audio, gain), words));
+            }
+        }
+    }
+
+    pub fn take_outputs(&mut self) -> Vec<SegmentOutput> {
+        std::mem::take(&mut self.outputs)
+    }
+
+    fn release_cached(&mut self) {
+        while let Some(Segment::Cached { .. }) = self.segments.front() {
+            if let Some(Segment::Cached { audio, words, gain }) = self.segments.pop_front() {
+                self.outputs
+                    .push(SegmentOutput::Play(apply_gain(audio, gain), words));
+            }
+        }
+    }
+}
+
+/// Non-whitespace characters, which is what alignment is matched on since
+/// the service may trim the text.
+pub fn count_chars(text: &str) -> usize {
+    text.chars().filter(|c| !c.is_whitespace()).count()
+}
+
+/// Scales signed 16-bit PCM. The cache keeps audio as synthesized, so this
+/// is only applied on the way to the player.
+fn apply_gain(audio: Bytes, gain: f32) -> Bytes {
+    if gain == 1.0 {
+        return audio;
+    }
+
+    let mut scaled = Vec::with_capacity(audio.len());
+    for sample in audio.chunks(2) {
+        match sample {
+            &[low, high] => {
+                let value = i16::from_le_bytes([low, high]) as f32 * gain;
+                scaled.extend_from_slice(&(value as i16).to_le_bytes());
+            }
+            _ => scaled.extend_from_slice(sample),
+        }
+    }
+
+    Bytes::from(scaled)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn play(audio: &'static [u8]) -> SegmentOutput {
+        SegmentOutput::Play(Bytes::from_static(audio), Vec::new())
+    }
+
+    fn word(text: &str, start_ms: u64, end_ms: u64) -> SpokenWord {
+        SpokenWord {
+            text: text.to_string(),
+            start_ms,
+            end_ms,
+        }
+    }
+
+    #[test]
+    fn keys_ignore_whitespace_but_not_voice_or_wording() {
+        let settings = VoiceSettings::default();
+        let key = |voice, text| cache_key(voice, &settings, "pcm_44100", text);
+
+        assert_eq!(key("a", " One  moment.\n"), key("a", "One moment."));
+        assert_ne!(key("a", "One moment."), key("b", "One moment."));
+        assert_ne!(key("a", "One moment."), key("a", "One moment!"));
+        assert_eq!(key("a", "  "), None);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entries() {
+        let start = SystemTime::UNIX_EPOCH;
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut index = CacheIndex {
+            max_bytes: 300,
+            ..CacheIndex::default()
+        };
+
+        assert!(index.insert(1, 100, at(1)).is_empty());
+        assert!(index.insert(2, 100, at(2)).is_empty());
+        assert!(index.insert(3, 100, at(3)).is_empty());
+
+        index.touch(1, at(4));
+        assert_eq!(index.insert(4, 150, at(5)), [2, 3]);
+        assert_eq!(index.total_bytes, 250);
+    }
+
+    #[test]
+    fn holds_cached_audio_until_the_streamed_chunk_before_it_finished() {
+        let mut queue = SegmentQueue::default();
+
+        queue.push(Segment::Streamed {
+            key: Some(7),
+            chars: "Hithere.".len(),
+            gain: 1.0,
+        });
+        queue.push(Segment::Cached {
+            audio: Bytes::from_static(b"cached"),
+            words: Vec::new(),
+            gain: 1.0,
+        });
+        assert!(queue.take_outputs().is_empty());
+
+        queue.push_audio(Bytes::from_static(b"hi "), Some(3), Vec::new());
+        assert_eq!(queue.take_outputs(), [play(b"hi ")]);
+
+        queue.push_audio(Bytes::from_static(b"there"), Some(5), Vec::new());
+        assert_eq!(
+            queue.take_outputs(),
+            [
+                play(b"there"),
+                SegmentOutput::Cache(7, Bytes::from_static(b"hi there"), Vec::new()),
+                play(b"cached"),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_caches_audio_matched_by_alignment() {
+        let mut queue = SegmentQueue::default();
+
+        queue.push(Segment::Streamed {
+            key: Some(1),
+            chars: 2,
+            gain: 1.0,
+        });
+        queue.push(Segment::Streamed {
+            key: Some(2),
+            chars: 2,
+            gain: 1.0,
+        });
+        queue.push(Segment::Cached {
+            audio: Bytes::from_static(b"end"),
+            words: Vec::new(),
+            gain: 1.0,
+        });
+
+        // Spills into the second chunk, neither can be cached
+        queue.push_audio(Bytes::from_static(b"abc"), Some(3), Vec::new());
+        queue.push_audio(Bytes::from_static(b"d"), Some(1), Vec::new());
+        queue.finish();
+
+        assert_eq!(
+            queue.take_outputs(),
+            [play(b"abc"), play(b"d"), play(b"end")]
+        );
+    }
+
+    #[test]
+    fn plays_quietly_but_caches_as_synthesized() {
+        let mut queue = SegmentQueue::default();
+        let loud = Bytes::from_iter(1000i16.to_le_bytes());
+
+        queue.push(Segment::Streamed {
+            key: Some(3),
+            chars: 1,
+            gain: 0.5,
+        });
+        queue.push_audio(loud.clone(), Some(1), Vec::new());
+
+        assert_eq!(
+            queue.take_outputs(),
+            [
+                SegmentOutput::Play(Bytes::from_iter(500i16.to_le_bytes()), Vec::new()),
+                SegmentOutput::Cache(3, loud, Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn caches_word_timings_from_the_start_of_the_chunk() {
+        let mut queue = SegmentQueue::default();
+        // 100ms of audio each
+        let audio = Bytes::from(vec![0; 8820]);
+
+        queue.push(Segment::Streamed {
+            key: Some(5),
+            chars: "Onemoment.".len(),
+            gain: 1.0,
+        });
+        queue.push(Segment::Cached {
+            audio: Bytes::from_static(b"cached"),
+            words: vec![word("Sure.", 0, 300)],
+            gain: 1.0,
+        });
+
+        queue.push_audio(audio.clone(), Some(3), vec![word("One", 10, 90)]);
+        queue.push_audio(audio.clone(), Some(7), vec![word("moment.", 0, 80)]);
+
+        let mut both = audio.to_vec();
+        both.extend_from_slice(&audio);
+        assert_eq!(
+            queue.take_outputs(),
+            [
+                SegmentOutput::Play(audio.clone(), vec![word("One", 10, 90)]),
+                SegmentOutput::Play(audio, vec![word("moment.", 0, 80)]),
+                SegmentOutput::Cache(
+                    5,
+                    Bytes::from(both),
+                    vec![word("One", 10, 90), word("moment.", 100, 180)],
+                ),
+                SegmentOutput::Play(Bytes::from_static(b"cached"), vec![word("Sure.", 0, 300)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_word_timings_next_to_the_audio() {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("jumo-tts-cache-{nanos}"));
+        let words = vec![word("Hello.", 0, 400)];
+
+        let cache = TtsCache::open(&dir, 10);
+        cache.insert(1, b"hello", &words).await.unwrap();
+        cache.insert(2, b"bye", &[]).await.unwrap();
+        cache.insert(3, b"again", &words).await.unwrap();
+
+        // Reopened, the first entry was evicted along with its timings
+        let cache = TtsCache::open(&dir, 10);
+        assert_eq!(cache.get(1).await, None);
+        assert!(!dir.join(format!("{:016x}.json", 1)).exists());
+        assert_eq!(
+            cache.get(3).await,
+            Some((Bytes::from_static(b"again"), words))
+        );
+        assert_eq!(
+            cache.get(2).await,
+            Some((Bytes::from_static(b"bye"), Vec::new()))
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -9,50 +9,95 @@ use tokio_tungstenite::{
 
 use crate::config::CONFIG;
 use crate::services::elevenlabs::types::WebSocketEndMessage;
-use crate::services::elevenlabs::voices::{Voice, get_voice_id};
+use crate::services::elevenlabs::voices::{SpeakingVoice, VoiceOption};
 use crate::{
-    events::AppEvent,
-    services::elevenlabs::types::{
-        ElevenLabsTranscription, VoiceSettings, WebSocketAudioOutput, WebSocketInitMessage,
-        WebSocketTextChunk, WsSink, WsStream,
+    audio::PcmFormat,
+    events::{AppEvent, LogEventPayload, TTSChunkEventPayload},
+    services::elevenlabs::{
+        cache::{
+            Segment, SegmentOutput, SegmentQueue, TtsCache, cache_key, count_chars, normalize_text,
+        },
+        types::{
+            ElevenLabsTranscription, ElevenLabsVoices, TextToSpeechRequest, TimestampedSpeech,
+            WebSocketAudioOutput, WebSocketInitMessage, WebSocketTextChunk, WsSink,
+        },
     },
+    types::{logs::LogLevel, spoken::words_from_alignment},
 };
 
+pub mod cache;
+mod ssml;
 mod types;
 pub mod voices;
 
 const OUTPUT_FORMAT: &str = "pcm_44100";
+const OUTPUT_PCM_FORMAT: PcmFormat = PcmFormat {
+    sample_rate: 44100,
+    channels: 1,
+};
 
 #[derive(Debug)]
 pub struct ElevenLabsService {
     event_sender: mpsc::Sender<AppEvent>,
     ws_sink: Option<WsSink>,
-    ws_stream: Option<WsStream>,
+    base_url: String,
+    /// When stubbed no requests are made, e.g. while replaying a recorded session.
+    stubbed: bool,
+    cache: Option<TtsCache>,
+    /// Voice of the open stream, part of the cache key
+    stream_voice: SpeakingVoice,
+    /// Tells the stream's reader task about each text chunk, in order
+    segment_tx: Option<mpsc::UnboundedSender<Segment>>,
 }
 
 impl ElevenLabsService {
     pub fn new(event_sender: mpsc::Sender<AppEvent>) -> Self {
+        let config = &CONFIG.tts_cache;
+
+        Self {
+            cache: config
+                .enabled
+                .then(|| TtsCache::open(&config.dir, config.max_size_mb * 1024 * 1024)),
+            ..Self::with_base_url(event_sender, &CONFIG.elevenlabs.base_url)
+        }
+    }
+
+    pub fn with_base_url(event_sender: mpsc::Sender<AppEvent>, base_url: &str) -> Self {
         Self {
             event_sender,
             ws_sink: None,
-            ws_stream: None,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            stubbed: false,
+            cache: None,
+            stream_voice: SpeakingVoice::default(),
+            segment_tx: None,
         }
     }
 
-    pub async fn start_stream(&mut self) -> Result<(), anyhow::Error> {
-        if self.ws_stream.is_some() {
+    pub fn stubbed(event_sender: mpsc::Sender<AppEvent>) -> Self {
+        Self {
+            stubbed: true,
+            ..Self::new(event_sender)
+        }
+    }
+
+    pub async fn start_stream(&mut self, voice: &SpeakingVoice) -> Result<(), anyhow::Error> {
+        if self.stubbed {
             return Ok(());
         }
 
-        let voice_id = get_voice_id(Voice::Jules);
+        let voice_id = &voice.id;
 
+        // https -> wss, http -> ws
+        let ws_base_url = self.base_url.replacen("http", "ws", 1);
         let url = format!(
-            "wss://api.elevenlabs.io/v1/text-to-speech/{voice_id}/stream-input?output_format={OUTPUT_FORMAT}"
+            "{ws_base_url}/v1/text-to-speech/{voice_id}/stream-input\
+             ?output_format={OUTPUT_FORMAT}&sync_alignment=true"
         );
         let mut request = url.into_client_request()?;
         request
             .headers_mut()
-            .insert("xi-api-key", CONFIG.elevenlabs_api_key.parse()?);
+            .insert("xi-api-key", CONFIG.elevenlabs.api_key.parse()?);
 
         let Ok((ws_stream, _)) = connect_async(request).await else {
             let message = String::from("Failed to connect to elevenlabs");
@@ -64,19 +109,22 @@ impl ElevenLabsService {
 
         let init_message = WebSocketInitMessage {
             text: " ".to_string(), // Space to initialize
-            voice_settings: VoiceSettings {
-                stability: 0.5,
-                similarity_boost: 0.8,
-            },
-            xi_api_key: CONFIG.elevenlabs_api_key.clone(),
+            voice_settings: voice.settings,
+            xi_api_key: CONFIG.elevenlabs.api_key.clone(),
         };
 
         let init_json = serde_json::to_string(&init_message)?;
         ws_sink.send(Message::Text(init_json.into())).await?;
 
+        let (segment_tx, mut segment_rx) = mpsc::unbounded_channel();
+
         self.ws_sink = Some(ws_sink);
+        self.stream_voice = voice.clone();
+        self.segment_tx = Some(segment_tx);
+        self.event_sender.send(AppEvent::TTSStreamOpened).await?;
 
         let event_sender = self.event_sender.clone();
+        let cache = self.cache.clone();
 
         tokio::spawn(async move {
             let send_error = async |message: &str| {
@@ -85,9 +133,28 @@ impl ElevenLabsService {
                     .await;
             };
 
-            while let Some(msg) = ws_stream.next().await {
+            let mut finished = false;
+            let mut segments = SegmentQueue::default();
+            let mut segments_open = true;
+
+            loop {
+                let msg = tokio::select! {
+                    // A chunk is always queued before its audio can arrive
+                    biased;
+
+                    segment = segment_rx.recv(), if segments_open => {
+                        match segment {
+                            Some(segment) => segments.push(segment),
+                            None => segments_open = false,
+                        }
+                        send_segment_outputs(&mut segments, &event_sender, &cache).await;
+                        continue;
+                    }
+                    msg = ws_stream.next() => msg,
+                };
+
                 match msg {
-                    Ok(Message::Text(text)) => {
+                    Some(Ok(Message::Text(text))) => {
                         let json = match serde_json::from_str::<WebSocketAudioOutput>(&text) {
                             Ok(json) => json,
                             Err(e) => {
@@ -105,31 +172,87 @@ impl ElevenLabsService {
                                 }
                             };
 
-                            let audio_bytes = Bytes::from(decoded);
-                            let _ = event_sender.send(AppEvent::TTSChunk(audio_bytes)).await;
+                            let (chars, words) = match json.alignment {
+                                Some(alignment) => (
+                                    Some(alignment.chars.iter().map(|c| count_chars(c)).sum()),
+                                    words_from_alignment(
+                                        &alignment.chars,
+                                        &alignment.char_start_times_ms,
+                                        &alignment.char_durations_ms,
+                                    ),
+                                ),
+                                None => (None, Vec::new()),
+                            };
+                            segments.push_audio(Bytes::from(decoded), chars, words);
+                        }
+
+                        if json.is_final == Some(true) && !finished {
+                            segments.finish();
+                            send_segment_outputs(&mut segments, &event_sender, &cache).await;
+
+                            finished = true;
+                            let _ = event_sender.send(AppEvent::TTSStreamFinished).await;
                         }
                     }
-                    Ok(Message::Close(_)) => {
+                    Some(Ok(Message::Close(_))) | None => {
                         break;
                     }
-                    Ok(msg) => {
+                    Some(Ok(msg)) => {
                         send_error(&format!("Unexpected message type: {msg}")).await;
                     }
-                    Err(e) => {
+                    Some(Err(e)) => {
                         send_error(&format!("WebSocket error: {e}")).await;
                         break;
                     }
                 }
+
+                send_segment_outputs(&mut segments, &event_sender, &cache).await;
+            }
+
+            // Closing without a final message still ends the stream
+            if !finished {
+                segments.finish();
+                send_segment_outputs(&mut segments, &event_sender, &cache).await;
+                let _ = event_sender.send(AppEvent::TTSStreamFinished).await;
             }
         });
 
         Ok(())
     }
 
+    /// Speaks `text`, which may contain SSML from the text processor, as part
+    /// of the open stream, from the cache if it was spoken before.
     pub async fn send_text(&mut self, text: &str) -> Result<(), anyhow::Error> {
+        if self.ws_sink.is_none() {
+            return Ok(());
+        }
+
+        let chunk = ssml::render(text);
+        let key = self.cache.as_ref().and_then(|_| {
+            let voice = &self.stream_voice;
+            cache_key(&voice.id, &voice.settings, OUTPUT_FORMAT, &chunk.text)
+        });
+
+        if let (Some(cache), Some(key)) = (&self.cache, key)
+            && let Some((audio, words)) = cache.get(key).await
+        {
+            self.queue_segment(Segment::Cached {
+                audio,
+                words,
+                gain: chunk.gain,
+            });
+            return Ok(());
+        }
+
+        self.queue_segment(Segment::Streamed {
+            key,
+            chars: chunk.chars,
+            gain: chunk.gain,
+        });
+
         if let Some(ws_sink) = &mut self.ws_sink {
             let text_chunk = WebSocketTextChunk {
-                text: text.to_string(),
+                text: chunk.text,
                 flush: Some(true),
             };
             let json = serde_json::to_string(&text_chunk)?;
@@ -146,12 +269,143 @@ impl ElevenLabsService {
             let json = serde_json::to_string(&text_chunk)?;
             ws_sink.send(Message::Text(json.into())).await?;
             self.ws_sink = None;
+            self.segment_tx = None;
         }
         Ok(())
     }
 
+    /// Synthesizes the `phrases` that aren't cached yet in the background.
+    pub fn prewarm_cache(&self, voice: &SpeakingVoice, phrases: Vec<String>) {
+        let Some(cache) = self.cache.clone() else {
+            return;
+        };
+
+        if self.stubbed || phrases.is_empty() {
+            return;
+        }
+
+        let event_sender = self.event_sender.clone();
+        let voice = voice.clone();
+        let url = format!(
+            "{}/v1/text-to-speech/{}/with-timestamps?output_format={OUTPUT_FORMAT}",
+            self.base_url, voice.id
+        );
+
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut warmed = 0;
+
+            let log = async |level: LogLevel, message: String| {
+                let _ = event_sender
+                    .send(AppEvent::Log(LogEventPayload { level, message }))
+                    .await;
+            };
+
+            for phrase in phrases {
+                let Some(key) = cache_key(&voice.id, &voice.settings, OUTPUT_FORMAT, &phrase)
+                else {
+                    continue;
+                };
+
+                if cache.contains(key) {
+                    continue;
+                }
+
+                let result = async {
+                    let speech = client
+                        .post(&url)
+                        .header("xi-api-key", &CONFIG.elevenlabs.api_key)
+                        .json(&TextToSpeechRequest {
+                            text: normalize_text(&phrase),
+                            voice_settings: voice.settings,
+                        })
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json::<TimestampedSpeech>()
+                        .await?;
+
+                    let pcm = BASE64_STANDARD.decode(speech.audio_base64)?;
+                    let words = speech
+                        .alignment
+                        .map(|alignment| alignment.words())
+                        .unwrap_or_default();
+                    cache.insert(key, &pcm, &words).await
+                }
+                .await;
+
+                match result {
+                    Ok(()) => warmed += 1,
+                    Err(e) => {
+                        let message = format!("Failed to pre-warm TTS phrase \"{phrase}\": {e}");
+                        log(LogLevel::Warn, message).await;
+                    }
+                }
+            }
+
+            if warmed > 0 {
+                log(LogLevel::Info, format!("Pre-warmed {warmed} TTS phrase(s)")).await;
+            }
+        });
+    }
+
+    /// Lists the voices of the account in the background. The built-in voices
+    /// stay available when that fails.
+    pub fn list_voices(&self) {
+        if self.stubbed {
+            return;
+        }
+
+        let event_sender = self.event_sender.clone();
+        let url = format!("{}/v1/voices", self.base_url);
+
+        tokio::spawn(async move {
+            let result = async {
+                reqwest::Client::new()
+                    .get(url)
+                    .header("xi-api-key", &CONFIG.elevenlabs.api_key)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<ElevenLabsVoices>()
+                    .await
+            }
+            .await;
+
+            let event = match result {
+                Ok(response) => AppEvent::VoicesListed(
+                    response
+                        .voices
+                        .into_iter()
+                        .map(|voice| VoiceOption {
+                            id: voice.voice_id,
+                            name: voice.name,
+                        })
+                        .collect(),
+                ),
+                Err(e) => AppEvent::Log(LogEventPayload {
+                    level: LogLevel::Warn,
+                    message: format!("Failed to list ElevenLabs voices: {e}"),
+                }),
+            };
+
+            let _ = event_sender.send(event).await;
+        });
+    }
+
+    fn queue_segment(&self, segment: Segment) {
+        if let Some(segment_tx) = &self.segment_tx {
+            let _ = segment_tx.send(segment);
+        }
+    }
+
     pub fn transcribe(&mut self, buffer: Vec<u8>) {
+        if self.stubbed {
+            return;
+        }
+
         let event_sender = self.event_sender.clone();
+        let url = format!("{}/v1/speech-to-text", self.base_url);
 
         tokio::spawn(async move {
             let send_error = async |message: &str| {
@@ -177,8 +431,8 @@ impl ElevenLabsService {
                 .part("file", file_bytes);
 
             let resp = client
-                .post("https://api.elevenlabs.io/v1/speech-to-text")
-                .header("xi-api-key", &CONFIG.elevenlabs_api_key)
+                .post(url)
+                .header("xi-api-key", &CONFIG.elevenlabs.api_key)
                 .multipart(form)
                 .send()
                 .await;
@@ -207,3 +461,39 @@ impl ElevenLabsService {
         // TODO: cancel requests
     }
 }
+
+/// Plays what the stream has ready and caches chunks whose audio is complete.
+async fn send_segment_outputs(
+    segments: &mut SegmentQueue,
+    event_sender: &mpsc::Sender<AppEvent>,
+    cache: &Option<TtsCache>,
+) {
+    for output in segments.take_outputs() {
+        match output {
+            SegmentOutput::Play(audio, words) => {
+                let _ = event_sender
+                    .send(AppEvent::TTSChunk(TTSChunkEventPayload {
+                        audio,
+                        format: OUTPUT_PCM_FORMAT,
+                        words,
+                    }))
+                    .await;
+            }
+            SegmentOutput::Cache(key, audio, words) => {
+                if let Some(cache) = cache.clone() {
+                    let event_sender = event_sender.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = cache.insert(key, &audio, &words).await {
+                            let _ = event_sender
+                                .send(AppEvent::Log(LogEventPayload {
+                                    level: LogLevel::Warn,
+                                    message: format!("Failed to cache TTS audio: {e}"),
+                                }))
+                                .await;
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
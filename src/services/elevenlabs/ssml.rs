@@ -0,0 +1,135 @@
+//! Maps the SSML subset text chunks come with onto what the ElevenLabs
+//! websocket understands. Breaks are supported as they are, emphasis is
+//! written in capitals, which the models stress, and whispering is played back
+//! quietly. The speaking rate is fixed once a stream is open, so `rate` is
+//! dropped.
+
+/// How loud whispered chunks are played.
+const WHISPER_GAIN: f32 = 0.35;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedChunk {
+    pub text: String,
+    /// Spoken characters, without break tags, to match alignment against
+    pub chars: usize,
+    pub gain: f32,
+}
+
+pub fn render(ssml: &str) -> RenderedChunk {
+    let mut text = String::with_capacity(ssml.len());
+    let mut emphasis = 0usize;
+    // Whether each open prosody tag is soft
+    let mut prosody: Vec<bool> = Vec::new();
+    let mut chars = 0;
+    let mut soft_chars = 0;
+    let mut rest = ssml;
+
+    while !rest.is_empty() {
+        if let Some((tag, len)) = parse_tag(rest) {
+            match tag {
+                Tag::Break(time) => text.push_str(&format!("<break time=\"{time}\" />")),
+                Tag::Emphasis => emphasis += 1,
+                Tag::EmphasisEnd => emphasis = emphasis.saturating_sub(1),
+                Tag::Prosody { soft } => prosody.push(soft),
+                Tag::ProsodyEnd => {
+                    prosody.pop();
+                }
+            }
+            rest = &rest[len..];
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap_or_default();
+        rest = &rest[c.len_utf8()..];
+
+        if !c.is_whitespace() {
+            chars += 1;
+            if prosody.contains(&true) {
+                soft_chars += 1;
+            }
+        }
+
+        if emphasis > 0 {
+            text.extend(c.to_uppercase());
+        } else {
+            text.push(c);
+        }
+    }
+
+    RenderedChunk {
+        text,
+        chars,
+        gain: if chars > 0 && soft_chars == chars {
+            WHISPER_GAIN
+        } else {
+            1.0
+        },
+    }
+}
+
+enum Tag {
+    Break(String),
+    Emphasis,
+    EmphasisEnd,
+    Prosody { soft: bool },
+    ProsodyEnd,
+}
+
+/// The tag at the start of `text`, only the ones the text processor writes.
+fn parse_tag(text: &str) -> Option<(Tag, usize)> {
+    if !text.starts_with('<') {
+        return None;
+    }
+    let end = text.find('>')?;
+    let inner = &text[1..end];
+
+    let tag = match inner {
+        "emphasis" => Tag::Emphasis,
+        "/emphasis" => Tag::EmphasisEnd,
+        "/prosody" => Tag::ProsodyEnd,
+        _ => {
+            if let Some(time) = inner
+                .strip_prefix("break time=\"")
+                .and_then(|rest| rest.strip_suffix("\"/"))
+            {
+                Tag::Break(time.to_string())
+            } else if let Some(attributes) = inner.strip_prefix("prosody ") {
+                Tag::Prosody {
+                    soft: attributes.contains("volume=\"x-soft\""),
+                }
+            } else {
+                return None;
+            }
+        }
+    };
+
+    Some((tag, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_breaks_and_emphasis() {
+        let chunk =
+            render("I <emphasis>never</emphasis> said that. <break time=\"1.5s\"/> Vec<u8>");
+
+        assert_eq!(
+            chunk.text,
+            "I NEVER said that. <break time=\"1.5s\" /> Vec<u8>"
+        );
+        assert_eq!(chunk.chars, "INEVERsaidthat.Vec<u8>".len());
+        assert_eq!(chunk.gain, 1.0);
+    }
+
+    #[test]
+    fn whispers_chunks_that_are_soft_throughout() {
+        let soft = render("<prosody rate=\"80%\" volume=\"x-soft\">It's a secret</prosody>");
+        assert_eq!(soft.text, "It's a secret");
+        assert_eq!(soft.gain, WHISPER_GAIN);
+
+        let mixed = render("<prosody volume=\"x-soft\">Psst,</prosody> listen!");
+        assert_eq!(mixed.gain, 1.0);
+    }
+}
@@ -1,8 +1,13 @@
-use futures::stream::{SplitSink, SplitStream};
+use futures::stream::SplitSink;
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpStream;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
 
+use crate::{
+    services::elevenlabs::voices::VoiceSettings,
+    types::spoken::{SpokenWord, words_from_alignment},
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ElevenLabsTranscription {
     pub text: String,
@@ -14,12 +19,6 @@ pub struct ElevenLabsSendTextMessage {
     pub model_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct VoiceSettings {
-    pub stability: f32,
-    pub similarity_boost: f32,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WebSocketInitMessage {
     pub text: String,
@@ -43,7 +42,67 @@ pub struct WebSocketAudioOutput {
     pub audio: Option<String>, // Base64 encoded
     #[serde(rename = "isFinal")]
     pub is_final: Option<bool>,
+    /// Characters of the input text the audio covers
+    pub alignment: Option<WebSocketAlignment>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebSocketAlignment {
+    pub chars: Vec<String>,
+    /// When each character is heard, from the start of this message's audio
+    #[serde(rename = "charStartTimesMs", default)]
+    pub char_start_times_ms: Vec<u32>,
+    #[serde(rename = "charDurationsMs", default)]
+    pub char_durations_ms: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TextToSpeechRequest {
+    pub text: String,
+    pub voice_settings: VoiceSettings,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ElevenLabsVoices {
+    pub voices: Vec<ElevenLabsVoice>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ElevenLabsVoice {
+    pub voice_id: String,
+    pub name: String,
 }
 
 pub type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
-pub type WsStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+
+/// Response of the `with-timestamps` endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimestampedSpeech {
+    pub audio_base64: String,
+    pub alignment: Option<TimestampedAlignment>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimestampedAlignment {
+    pub characters: Vec<String>,
+    pub character_start_times_seconds: Vec<f64>,
+    pub character_end_times_seconds: Vec<f64>,
+}
+
+impl TimestampedAlignment {
+    pub fn words(&self) -> Vec<SpokenWord> {
+        let ms = |seconds: f64| (seconds * 1000.0).round() as u32;
+        let starts: Vec<u32> = self
+            .character_start_times_seconds
+            .iter()
+            .map(|&start| ms(start))
+            .collect();
+        let durations: Vec<u32> = starts
+            .iter()
+            .zip(&self.character_end_times_seconds)
+            .map(|(&start, &end)| ms(end).saturating_sub(start))
+            .collect();
+
+        words_from_alignment(&self.characters, &starts, &durations)
+    }
+}
@@ -1,9 +1,18 @@
+use std::ops::RangeInclusive;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::CONFIG;
+
 pub const FLYNN_VOICE_ID: &str = "OZ5NFxPCh40uGDshxKOi";
 pub const KOTA_VOICE_ID: &str = "pvxGJdhknm00gMyYHtET";
 pub const ARCHER_VOICE_ID: &str = "Fahco4VZzobUeiPqni1S";
 pub const JULES_VOICE_ID: &str = "kIC4kfVqgGXGVwgAx81Z";
 
-#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// The voices available without asking the API.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Voice {
     #[default]
     Jules,
@@ -12,6 +21,19 @@ pub enum Voice {
     Archer,
 }
 
+impl Voice {
+    pub const ALL: [Self; 4] = [Self::Jules, Self::Flynn, Self::Kota, Self::Archer];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Voice::Jules => "Jules",
+            Voice::Flynn => "Flynn",
+            Voice::Kota => "Kota",
+            Voice::Archer => "Archer",
+        }
+    }
+}
+
 pub fn get_voice_id(voice_name: Voice) -> &'static str {
     match voice_name {
         Voice::Flynn => FLYNN_VOICE_ID,
@@ -20,3 +42,291 @@ pub fn get_voice_id(voice_name: Voice) -> &'static str {
         Voice::Jules => JULES_VOICE_ID,
     }
 }
+
+/// A voice that can be picked, from the voices API or built in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoiceOption {
+    pub id: String,
+    pub name: String,
+}
+
+impl From<Voice> for VoiceOption {
+    fn from(voice: Voice) -> Self {
+        Self {
+            id: get_voice_id(voice).to_string(),
+            name: voice.name().to_string(),
+        }
+    }
+}
+
+pub fn built_in_voices() -> Vec<VoiceOption> {
+    Voice::ALL.into_iter().map(VoiceOption::from).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VoiceSettings {
+    pub stability: f32,
+    pub similarity_boost: f32,
+    pub style: f32,
+    pub speed: f32,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            stability: 0.5,
+            similarity_boost: 0.8,
+            style: 0.0,
+            speed: 1.0,
+        }
+    }
+}
+
+/// The voice replies are spoken with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpeakingVoice {
+    pub id: String,
+    pub name: String,
+    pub settings: VoiceSettings,
+}
+
+impl Default for SpeakingVoice {
+    fn default() -> Self {
+        let voice = VoiceOption::from(Voice::default());
+
+        Self {
+            id: voice.id,
+            name: voice.name,
+            settings: VoiceSettings::default(),
+        }
+    }
+}
+
+impl SpeakingVoice {
+    pub fn from_config() -> Self {
+        let config = &CONFIG.elevenlabs;
+        let voice = VoiceOption::from(config.voice);
+
+        Self {
+            id: voice.id,
+            name: voice.name,
+            settings: VoiceSettings {
+                stability: config.stability,
+                similarity_boost: config.similarity_boost,
+                style: config.style,
+                speed: config.speed,
+            },
+        }
+    }
+
+    /// The voice after `change`, which may only pick one of `voices`.
+    pub fn apply(
+        &self,
+        change: &VoiceChange,
+        voices: &[VoiceOption],
+    ) -> Result<Self, anyhow::Error> {
+        let mut voice = self.clone();
+
+        if let Some(wanted) = &change.voice {
+            let option = voices
+                .iter()
+                .find(|option| option.id == *wanted || option.name.eq_ignore_ascii_case(wanted))
+                .ok_or_else(|| {
+                    let names: Vec<_> = voices.iter().map(|option| option.name.as_str()).collect();
+                    anyhow::anyhow!("unknown voice `{wanted}`, pick one of {}", names.join(", "))
+                })?;
+
+            voice.id = option.id.clone();
+            voice.name = option.name.clone();
+        }
+
+        for setting in VoiceSetting::ALL {
+            if let Some(value) = setting.changed_value(change) {
+                let range = setting.range();
+                if !range.contains(&value) {
+                    return Err(anyhow::anyhow!(
+                        "{} must be between {} and {}, got {value}",
+                        setting.key(),
+                        range.start(),
+                        range.end()
+                    ));
+                }
+                *setting.value_mut(&mut voice.settings) = value;
+            }
+        }
+
+        Ok(voice)
+    }
+}
+
+impl std::fmt::Display for SpeakingVoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let settings = &self.settings;
+        write!(
+            f,
+            "{} (stability {:.2}, similarity {:.2}, style {:.2}, speed {:.2})",
+            self.name,
+            settings.stability,
+            settings.similarity_boost,
+            settings.style,
+            settings.speed
+        )
+    }
+}
+
+/// Changes to the voice, anything left out stays as it is.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VoiceChange {
+    /// Name or id of one of the available voices.
+    pub voice: Option<String>,
+    /// 0 to 1. Lower is more expressive, higher more even.
+    pub stability: Option<f32>,
+    /// 0 to 1. How closely to stick to the original voice.
+    pub similarity_boost: Option<f32>,
+    /// 0 to 1. Exaggerates the voice's style, 0 is the most stable.
+    pub style: Option<f32>,
+    /// 0.7 to 1.2. Speaking rate, 1 is normal.
+    pub speed: Option<f32>,
+}
+
+/// The adjustable settings, in the order the settings view lists them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceSetting {
+    Stability,
+    SimilarityBoost,
+    Style,
+    Speed,
+}
+
+impl VoiceSetting {
+    pub const ALL: [Self; 4] = [
+        Self::Stability,
+        Self::SimilarityBoost,
+        Self::Style,
+        Self::Speed,
+    ];
+
+    /// How much one key press in the settings view changes a setting.
+    pub const STEP: f32 = 0.05;
+
+    pub fn label(self) -> &'static str {
+        match self {
+            VoiceSetting::Stability => "Stability",
+            VoiceSetting::SimilarityBoost => "Similarity",
+            VoiceSetting::Style => "Style",
+            VoiceSetting::Speed => "Speed",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            VoiceSetting::Stability => "stability",
+            VoiceSetting::SimilarityBoost => "similarity_boost",
+            VoiceSetting::Style => "style",
+            VoiceSetting::Speed => "speed",
+        }
+    }
+
+    pub fn range(self) -> RangeInclusive<f32> {
+        match self {
+            VoiceSetting::Speed => 0.7..=1.2,
+            _ => 0.0..=1.0,
+        }
+    }
+
+    pub fn value(self, settings: &VoiceSettings) -> f32 {
+        match self {
+            VoiceSetting::Stability => settings.stability,
+            VoiceSetting::SimilarityBoost => settings.similarity_boost,
+            VoiceSetting::Style => settings.style,
+            VoiceSetting::Speed => settings.speed,
+        }
+    }
+
+    fn value_mut(self, settings: &mut VoiceSettings) -> &mut f32 {
+        match self {
+            VoiceSetting::Stability => &mut settings.stability,
+            VoiceSetting::SimilarityBoost => &mut settings.similarity_boost,
+            VoiceSetting::Style => &mut settings.style,
+            VoiceSetting::Speed => &mut settings.speed,
+        }
+    }
+
+    fn changed_value(self, change: &VoiceChange) -> Option<f32> {
+        match self {
+            VoiceSetting::Stability => change.stability,
+            VoiceSetting::SimilarityBoost => change.similarity_boost,
+            VoiceSetting::Style => change.style,
+            VoiceSetting::Speed => change.speed,
+        }
+    }
+
+    /// A change moving this setting `steps` steps, kept in its range.
+    pub fn step(self, settings: &VoiceSettings, steps: i32) -> VoiceChange {
+        let range = self.range();
+        let value = self.value(settings) + steps as f32 * Self::STEP;
+        // Rounded so repeated steps don't drift off the grid
+        let value = ((value * 100.0).round() / 100.0).clamp(*range.start(), *range.end());
+
+        let mut change = VoiceChange::default();
+        match self {
+            VoiceSetting::Stability => change.stability = Some(value),
+            VoiceSetting::SimilarityBoost => change.similarity_boost = Some(value),
+            VoiceSetting::Style => change.style = Some(value),
+            VoiceSetting::Speed => change.speed = Some(value),
+        }
+        change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_a_voice_by_name_and_keeps_other_settings() {
+        let voice = SpeakingVoice::default();
+        let change = VoiceChange {
+            voice: Some(String::from("kota")),
+            speed: Some(1.1),
+            ..VoiceChange::default()
+        };
+
+        let changed = voice.apply(&change, &built_in_voices()).unwrap();
+
+        assert_eq!(changed.id, KOTA_VOICE_ID);
+        assert_eq!(changed.name, "Kota");
+        assert_eq!(changed.settings.speed, 1.1);
+        assert_eq!(changed.settings.stability, voice.settings.stability);
+    }
+
+    #[test]
+    fn rejects_unknown_voices_and_out_of_range_settings() {
+        let voice = SpeakingVoice::default();
+        let voices = built_in_voices();
+
+        let unknown = VoiceChange {
+            voice: Some(String::from("Bob")),
+            ..VoiceChange::default()
+        };
+        assert!(voice.apply(&unknown, &voices).is_err());
+
+        let too_fast = VoiceChange {
+            speed: Some(2.0),
+            ..VoiceChange::default()
+        };
+        assert!(voice.apply(&too_fast, &voices).is_err());
+    }
+
+    #[test]
+    fn steps_stay_in_range() {
+        let settings = VoiceSettings::default();
+
+        assert_eq!(
+            VoiceSetting::Stability.step(&settings, 1).stability,
+            Some(0.55)
+        );
+        assert_eq!(VoiceSetting::Speed.step(&settings, 10).speed, Some(1.2));
+        assert_eq!(VoiceSetting::Style.step(&settings, -1).style, Some(0.0));
+    }
+}
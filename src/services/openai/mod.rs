@@ -33,8 +33,8 @@ pub async fn create_embedding(input: &str) -> Result<Vec<f32>, anyhow::Error> {
     };
 
     let resp = client
-        .post("https://api.openai.com/v1/embeddings")
-        .header("Authorization", format!("Bearer {}", CONFIG.openai_api_key))
+        .post(format!("{}/v1/embeddings", CONFIG.openai.base_url))
+        .header("Authorization", format!("Bearer {}", CONFIG.openai.api_key))
         .header("Content-Type", "application/json")
         .json(&input)
         .send()
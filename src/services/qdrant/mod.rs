@@ -1,5 +1,3 @@
-use std::env;
-
 use qdrant_client::{
     Payload, Qdrant,
     qdrant::{
@@ -9,6 +7,7 @@ use qdrant_client::{
 };
 
 use crate::{
+    config::CONFIG,
     services::openai::{EMBEDDINGS_DIMENSIONS, create_embedding},
     types::message::{ContentBlock, Message},
 };
@@ -25,8 +24,7 @@ impl QdrantService {
     }
 
     pub async fn init(&mut self) -> Result<(), anyhow::Error> {
-        let qdrant_url = env::var("QDRANT_URL").unwrap_or("http://localhost:6334".to_string());
-        let client = Qdrant::from_url(&qdrant_url).build()?;
+        let client = Qdrant::from_url(&CONFIG.qdrant.url).build()?;
 
         let create_collection_request = CreateCollectionBuilder::new(QDRANT_COLLECTION_NAME)
             .vectors_config(VectorParamsBuilder::new(
@@ -50,63 +48,40 @@ impl QdrantService {
         Ok(())
     }
 
-    pub async fn insert_message(&self, message: &Message) -> Result<(), anyhow::Error> {
-        let message = message.clone();
-
-        tokio::spawn(async move {
-            let qdrant_url = env::var("QDRANT_URL").unwrap_or("http://localhost:6334".to_string());
-
-            for content in &message.content {
-                if let ContentBlock::Text { text } = content {
-                    let client = match Qdrant::from_url(&qdrant_url).build() {
-                        Ok(client) => client,
-                        Err(_err) => {
-                            // TODO: log error
-                            return;
-                        }
-                    };
-
-                    let embedding = match create_embedding(text).await {
-                        Ok(embedding) => embedding,
-                        Err(_err) => {
-                            // TODO: log error
-                            return;
-                        }
-                    };
-
-                    let payload = serde_json::json!({
-                        "message_id": message._id.to_string(),
-                        "role": message.role,
-                        "created_at": message.created_at,
-                        "text": text,
-                    });
-
-                    let payload: Payload = match payload.try_into() {
-                        Ok(payload) => payload,
-                        Err(_err) => {
-                            // TODO: log error
-                            return;
-                        }
-                    };
-
-                    let points = vec![PointStruct::new(
-                        message._id.to_string(),
-                        embedding,
-                        payload,
-                    )];
-
-                    let res = client
-                        .upsert_points(UpsertPointsBuilder::new(QDRANT_COLLECTION_NAME, points))
-                        .await;
-
-                    if let Err(_err) = res {
-                        // let _ = self.event_sender.send(AppEvent::Log(format!(
-                        //     "Failed to insert message into Qdrant: {err}".to_string()
-                        // ));
-                    }
-                }
+    /// Embeds the text of `message` and stores it, creating the collection
+    /// first if needed.
+    pub async fn insert_message(&mut self, message: &Message) -> Result<(), anyhow::Error> {
+        if self.client.is_none() {
+            self.init().await?;
+        }
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Qdrant client isn't initialized"))?;
+
+        for content in &message.content {
+            if let ContentBlock::Text { text } = content {
+                let embedding = create_embedding(text).await?;
+
+                let payload: Payload = serde_json::json!({
+                    "message_id": message._id.to_string(),
+                    "role": message.role,
+                    "created_at": message.created_at,
+                    "text": text,
+                })
+                .try_into()?;
+
+                let points = vec![PointStruct::new(
+                    message._id.to_string(),
+                    embedding,
+                    payload,
+                )];
+
+                client
+                    .upsert_points(UpsertPointsBuilder::new(QDRANT_COLLECTION_NAME, points))
+                    .await?;
             }
-        });
+        }
 
         Ok(())
     }
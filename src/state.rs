@@ -1,6 +1,7 @@
 use std::{
     collections::HashMap,
     fmt::{self, Display},
+    time::Instant,
 };
 
 use mongodb::bson::oid::ObjectId;
@@ -9,17 +10,29 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    camera::{Camera, Region},
     emote::{Emote, color_to_char, get_color},
+    services::{
+        anthropic::types::{AnthropicContentBlockDelta, AnthropicMessageStreamEvent},
+        elevenlabs::voices::{SpeakingVoice, VoiceOption},
+    },
     types::{
+        diagnostics::Diagnostics,
         logs::Log,
-        message::{Message, Role},
+        message::{ContentBlock, ImageSource, Message, Role},
+        spoken::SpokenReply,
+    },
+    widgets::views::{
+        camera::CameraViewState, chat::ChatViewState, home::HomeViewState, logs::LogsViewState,
+        settings::SettingsViewState,
     },
-    widgets::views::{chat::ChatViewState, home::HomeViewState, logs::LogsViewState},
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PersistedState {
     color: Option<char>,
+    #[serde(default)]
+    voice: Option<SpeakingVoice>,
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
@@ -32,6 +45,12 @@ pub enum View {
     Logs,
 
     Chat,
+
+    /// View for picking the audio devices.
+    Settings,
+
+    /// Live camera preview and the image sent with the selected message.
+    Camera,
 }
 
 impl Display for View {
@@ -40,8 +59,75 @@ impl Display for View {
             View::Home => write!(f, "Home"),
             View::Logs => write!(f, "Logs"),
             View::Chat => write!(f, "Chat"),
+            View::Settings => write!(f, "Settings"),
+            View::Camera => write!(f, "Camera"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct WakeWordState {
+    /// The next recording is saved as a wake word template instead of being transcribed.
+    pub enrolling: bool,
+}
+
+/// What opened a recording that ends by itself once the speaker goes quiet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListeningOrigin {
+    WakeWord,
+    Conversation,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ListeningSession {
+    pub origin: ListeningOrigin,
+    pub started_at: Instant,
+    pub last_speech_at: Instant,
+    pub heard_speech: bool,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ConversationState {
+    /// Conversation mode is turned on in the config.
+    pub enabled: bool,
+    /// Listening windows open after each reply until the conversation ends.
+    pub active: bool,
+    /// When the user last said something to the robot.
+    pub last_turn_at: Option<Instant>,
+    /// The reply is generated, listening starts once it's done playing.
+    pub reply_finished: bool,
+    /// The assistant ended the conversation, it's over once the reply is played.
+    pub ending: bool,
+}
+
+impl ConversationState {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    /// Starts the conversation, or keeps it going, after the user said something.
+    pub fn user_turn(&mut self) {
+        if self.enabled {
+            self.active = true;
+            self.last_turn_at = Some(Instant::now());
         }
     }
+
+    pub fn end(&mut self) {
+        *self = Self::new(self.enabled);
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PresenceState {
+    /// Someone is in view of the camera.
+    pub present: bool,
+    /// Where they moved when they arrived.
+    pub region: Option<Region>,
+    pub last_greeted_at: Option<Instant>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -54,24 +140,51 @@ pub struct AppState {
     pub is_tts_running: bool,
     pub is_audio_recording_running: bool,
     pub is_audio_playback_running: bool,
+    /// How much of the current response has been played so far.
+    pub playback_played_ms: u64,
+    /// Drop the rest of the current response's speech, e.g. after "stop".
+    pub is_speech_muted: bool,
+    /// Word timings of the reply being spoken, to highlight it as it plays.
+    pub spoken_reply: SpokenReply,
 
-    pub audio_input_device: String,
-    pub audio_output_device: String,
+    pub audio_input_device: Option<String>,
+    pub audio_output_device: Option<String>,
     pub tool_input_buffers: HashMap<(ObjectId, usize), String>,
     pub emote: Emote,
     pub color: Color,
+    pub voice: SpeakingVoice,
+    /// Voices that can be switched to.
+    pub voices: Vec<VoiceOption>,
 
     pub view: View,
     pub home_view: HomeViewState,
     pub logs_view: LogsViewState,
     pub chat_view: ChatViewState,
+    pub settings_view: SettingsViewState,
+    pub camera_view: CameraViewState,
 
     pub audio_detected: bool,
     pub input_volume: f32,
 
+    pub wake_word: WakeWordState,
+    /// Set while a recording opened by the wake word or a conversation is running.
+    pub listening: Option<ListeningSession>,
+    /// Where the transcription in flight was recorded, `None` for push to talk.
+    pub transcript_origin: Option<ListeningOrigin>,
+    /// Archived recording of the transcription in flight.
+    pub transcript_audio_id: Option<String>,
+    pub conversation: ConversationState,
+    /// When an update was asked for by voice, it only runs once confirmed.
+    pub update_requested_at: Option<Instant>,
+    pub diagnostics: Diagnostics,
+
+    /// Frames from the camera stream, for the `look` tool.
+    pub camera: Camera,
+    pub presence: PresenceState,
     pub img_base64: Option<String>,
 
-    pub current_exchange: Vec<Message>,
+    /// Messages of the exchange going on, saved once the reply is done
+    pub current_exchange: Vec<ObjectId>,
 }
 
 impl AppState {
@@ -83,11 +196,124 @@ impl AppState {
         self.messages.iter_mut().find(|message| &message._id == id)
     }
 
-    pub fn get_assistant_message_count(&self) -> usize {
-        self.messages
+    /// What the user or the assistant said, as shown in the home view.
+    pub fn turns(&self) -> impl Iterator<Item = &Message> {
+        self.messages.iter().filter(|message| message.is_turn())
+    }
+
+    /// The turn selected in the home view.
+    pub fn get_selected_message(&self) -> Option<&Message> {
+        self.turns().nth(self.home_view.message_index)
+    }
+
+    /// The image sent with the selected message, or for a reply the last one
+    /// Claude saw since what it replies to, tool results included.
+    pub fn get_selected_message_image(&self) -> Option<&ImageSource> {
+        let selected = self.get_selected_message()?;
+        let position = self
+            .messages
+            .iter()
+            .position(|message| message._id == selected._id)?;
+        let end = match selected.role {
+            Role::User => position + 1,
+            Role::Assistant => position,
+        };
+
+        let start = self.messages[..end]
+            .iter()
+            .rposition(|message| {
+                message.role == Role::User && (message.is_turn() || message.context)
+            })
+            .unwrap_or(0);
+
+        self.messages[start..end]
             .iter()
-            .filter(|message| message.role == Role::Assistant)
-            .count()
+            .flat_map(Message::images)
+            .last()
+    }
+
+    pub fn get_turn_count(&self) -> usize {
+        self.turns().count()
+    }
+
+    /// Applies a streamed content block event to the message being generated.
+    ///
+    /// Tool inputs are buffered until their block stops and are then parsed as
+    /// JSON, falling back to the raw string if the JSON is invalid. Returns any
+    /// new text so it can be spoken.
+    pub fn apply_stream_event(
+        &mut self,
+        message_id: &ObjectId,
+        event: AnthropicMessageStreamEvent,
+    ) -> Option<String> {
+        match event {
+            AnthropicMessageStreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => {
+                if let ContentBlock::ToolUse { .. } = &content_block {
+                    let key = (*message_id, index);
+                    self.tool_input_buffers.insert(key, String::new());
+                }
+
+                if let Some(message) = self.get_message_mut(message_id) {
+                    message.content.insert(index, content_block);
+                }
+            }
+
+            AnthropicMessageStreamEvent::ContentBlockDelta { index, delta } => {
+                let key = (*message_id, index);
+                let block = self
+                    .messages
+                    .iter_mut()
+                    .find(|message| &message._id == message_id)?
+                    .content
+                    .get_mut(index)?;
+
+                match delta {
+                    AnthropicContentBlockDelta::Text { text } => {
+                        if let ContentBlock::Text { text: block_text } = block {
+                            block_text.push_str(&text);
+                        }
+
+                        return Some(text);
+                    }
+                    AnthropicContentBlockDelta::Thinking { text } => {
+                        if let ContentBlock::Thinking {
+                            content: block_content,
+                        } = block
+                        {
+                            block_content.push_str(&text);
+                        }
+                    }
+                    AnthropicContentBlockDelta::InputJson { partial_json } => {
+                        if let Some(input_buffer) = self.tool_input_buffers.get_mut(&key) {
+                            input_buffer.push_str(&partial_json);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            AnthropicMessageStreamEvent::ContentBlockStop { index } => {
+                let key = (*message_id, index);
+                let buffer = self.tool_input_buffers.remove(&key)?;
+
+                if let Some(ContentBlock::ToolUse { input, .. }) = self
+                    .get_message_mut(message_id)
+                    .and_then(|message| message.content.get_mut(index))
+                {
+                    *input = match serde_json::from_str::<serde_json::Value>(&buffer) {
+                        Ok(json_value) => json_value,
+                        Err(_) => serde_json::Value::String(buffer),
+                    };
+                }
+            }
+
+            _ => {}
+        }
+
+        None
     }
 
     pub fn log(&mut self, log: Log) {
@@ -97,6 +323,7 @@ impl AppState {
     pub async fn persist_state(&self) -> Result<(), anyhow::Error> {
         let state = PersistedState {
             color: color_to_char(self.color),
+            voice: Some(self.voice.clone()),
         };
 
         let serialized_state = serde_json::to_string(&state)?;
@@ -113,6 +340,125 @@ impl AppState {
             self.color = get_color(color).unwrap_or(Color::Yellow);
         }
 
+        if let Some(voice) = state.voice {
+            self.voice = voice;
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::DateTime;
+
+    use super::*;
+    use crate::testing::anthropic::{text_response, tool_use_response};
+
+    fn state_with_message(message_id: ObjectId) -> AppState {
+        let mut state = AppState::default();
+        state.messages.push(Message {
+            _id: message_id,
+            role: Role::Assistant,
+            content: vec![],
+            created_at: DateTime::now(),
+            audio_id: None,
+            context: false,
+        });
+        state
+    }
+
+    fn apply_all(
+        state: &mut AppState,
+        message_id: &ObjectId,
+        events: Vec<AnthropicMessageStreamEvent>,
+    ) -> String {
+        events
+            .into_iter()
+            .filter_map(|event| state.apply_stream_event(message_id, event))
+            .collect()
+    }
+
+    #[test]
+    fn parses_tool_input_on_content_block_stop() {
+        let message_id = ObjectId::new();
+        let mut state = state_with_message(message_id);
+
+        let events = tool_use_response("set_view", &[r#"{"vi"#, r#"ew": "Lo"#, r#"gs"}"#]);
+        let text = apply_all(&mut state, &message_id, events);
+
+        assert!(text.is_empty());
+        assert!(state.tool_input_buffers.is_empty());
+        assert_eq!(
+            state.messages[0].content,
+            [ContentBlock::ToolUse {
+                id: String::from("toolu_test"),
+                name: String::from("set_view"),
+                input: serde_json::json!({ "view": "Logs" }),
+            }]
+        );
+    }
+
+    #[test]
+    fn keeps_invalid_tool_input_as_string() {
+        let message_id = ObjectId::new();
+        let mut state = state_with_message(message_id);
+
+        let events = tool_use_response("set_view", &[r#"{"view": "#]);
+        apply_all(&mut state, &message_id, events);
+
+        let Some(ContentBlock::ToolUse { input, .. }) = state.messages[0].content.first() else {
+            panic!("expected a tool use block");
+        };
+        assert_eq!(
+            input,
+            &serde_json::Value::String(String::from(r#"{"view": "#))
+        );
+    }
+
+    #[test]
+    fn returns_text_deltas() {
+        let message_id = ObjectId::new();
+        let mut state = state_with_message(message_id);
+
+        let text = apply_all(&mut state, &message_id, text_response(&["Hi ", "there"]));
+
+        assert_eq!(text, "Hi there");
+        assert_eq!(
+            state.messages[0].content,
+            [ContentBlock::Text {
+                text: String::from("Hi there")
+            }]
+        );
+    }
+
+    #[test]
+    fn ignores_events_for_unknown_messages() {
+        let mut state = state_with_message(ObjectId::new());
+
+        let text = apply_all(&mut state, &ObjectId::new(), text_response(&["Hi"]));
+
+        assert!(text.is_empty());
+        assert!(state.messages[0].content.is_empty());
+    }
+
+    #[test]
+    fn conversations_only_start_when_enabled() {
+        let mut disabled = ConversationState::new(false);
+        disabled.user_turn();
+        assert!(!disabled.active);
+
+        let mut conversation = ConversationState::new(true);
+        conversation.user_turn();
+        conversation.reply_finished = true;
+        conversation.ending = true;
+        assert!(conversation.active);
+        assert!(conversation.last_turn_at.is_some());
+
+        conversation.end();
+        assert!(conversation.enabled);
+        assert!(!conversation.active);
+        assert!(!conversation.reply_finished && !conversation.ending);
+        assert!(conversation.last_turn_at.is_none());
+    }
+}
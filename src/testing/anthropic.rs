@@ -0,0 +1,154 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use futures::{StreamExt, stream};
+
+use crate::{
+    services::anthropic::types::{
+        AnthropicContentBlockDelta, AnthropicMessage, AnthropicMessageStreamEvent,
+    },
+    types::message::{ContentBlock, Role},
+};
+
+#[derive(Clone)]
+struct FakeAnthropicState {
+    body: Arc<String>,
+    requests: Arc<Mutex<Vec<serde_json::Value>>>,
+    /// Keep the stream open after the events, as if the model was still writing
+    hold_open: bool,
+}
+
+/// Fake Anthropic messages API that answers every request with the same canned SSE stream.
+pub struct FakeAnthropic {
+    pub url: String,
+    requests: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl FakeAnthropic {
+    pub async fn start(events: Vec<AnthropicMessageStreamEvent>) -> Self {
+        Self::serve(events, false).await
+    }
+
+    /// Like [`FakeAnthropic::start`], but the stream never ends after `events`.
+    pub async fn start_unfinished(events: Vec<AnthropicMessageStreamEvent>) -> Self {
+        Self::serve(events, true).await
+    }
+
+    async fn serve(events: Vec<AnthropicMessageStreamEvent>, hold_open: bool) -> Self {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let state = FakeAnthropicState {
+            body: Arc::new(to_sse_body(&events)),
+            requests: requests.clone(),
+            hold_open,
+        };
+
+        let router = Router::new()
+            .route("/v1/messages", post(messages))
+            .with_state(state);
+
+        Self {
+            url: super::serve(router).await,
+            requests,
+        }
+    }
+
+    /// The JSON bodies of all requests received so far.
+    pub fn requests(&self) -> Vec<serde_json::Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn messages(
+    State(state): State<FakeAnthropicState>,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    state.requests.lock().unwrap().push(body);
+
+    let events =
+        stream::once(async move { Ok::<_, std::io::Error>(state.body.as_str().to_owned()) });
+    let body = if state.hold_open {
+        Body::from_stream(events.chain(stream::pending()))
+    } else {
+        Body::from_stream(events)
+    };
+
+    ([(header::CONTENT_TYPE, "text/event-stream")], body).into_response()
+}
+
+fn to_sse_body(events: &[AnthropicMessageStreamEvent]) -> String {
+    events
+        .iter()
+        .map(|event| {
+            let data = serde_json::to_value(event).expect("stream event serializes");
+            let name = data["type"].as_str().unwrap_or("message").to_owned();
+            format!("event: {name}\ndata: {data}\n\n")
+        })
+        .collect()
+}
+
+/// A complete stream for a single text block made of `deltas`.
+pub fn text_response(deltas: &[&str]) -> Vec<AnthropicMessageStreamEvent> {
+    let deltas = deltas.iter().map(|text| AnthropicContentBlockDelta::Text {
+        text: text.to_string(),
+    });
+
+    block_response(
+        ContentBlock::Text {
+            text: String::new(),
+        },
+        deltas,
+    )
+}
+
+/// A complete stream for a single tool use block whose input arrives as `partial_json` pieces.
+pub fn tool_use_response(name: &str, partial_json: &[&str]) -> Vec<AnthropicMessageStreamEvent> {
+    let deltas = partial_json
+        .iter()
+        .map(|json| AnthropicContentBlockDelta::InputJson {
+            partial_json: json.to_string(),
+        });
+
+    block_response(
+        ContentBlock::ToolUse {
+            id: String::from("toolu_test"),
+            name: name.to_string(),
+            input: serde_json::json!({}),
+        },
+        deltas,
+    )
+}
+
+fn block_response(
+    content_block: ContentBlock,
+    deltas: impl Iterator<Item = AnthropicContentBlockDelta>,
+) -> Vec<AnthropicMessageStreamEvent> {
+    let mut events = vec![
+        AnthropicMessageStreamEvent::MessageStart {
+            message: AnthropicMessage {
+                role: Role::Assistant,
+                content: vec![],
+            },
+        },
+        AnthropicMessageStreamEvent::ContentBlockStart {
+            index: 0,
+            content_block,
+        },
+        AnthropicMessageStreamEvent::Ping,
+    ];
+
+    events.extend(
+        deltas.map(|delta| AnthropicMessageStreamEvent::ContentBlockDelta { index: 0, delta }),
+    );
+
+    events.push(AnthropicMessageStreamEvent::ContentBlockStop { index: 0 });
+    events.push(AnthropicMessageStreamEvent::MessageStop);
+    events
+}
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    Json, Router,
+    extract::{
+        State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    response::Response,
+    routing::{any, post},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+#[derive(Clone)]
+struct FakeElevenLabsState {
+    transcript: Arc<String>,
+    audio_chunks: Arc<Vec<Vec<u8>>>,
+    received_text: Arc<Mutex<Vec<String>>>,
+}
+
+/// Fake ElevenLabs API. Speech to text always returns `transcript`, and every
+/// text to speech stream answers with `audio_chunks` once the end of input
+/// message arrives.
+pub struct FakeElevenLabs {
+    pub url: String,
+    received_text: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeElevenLabs {
+    pub async fn start(transcript: &str, audio_chunks: Vec<Vec<u8>>) -> Self {
+        let received_text = Arc::new(Mutex::new(Vec::new()));
+
+        let state = FakeElevenLabsState {
+            transcript: Arc::new(transcript.to_string()),
+            audio_chunks: Arc::new(audio_chunks),
+            received_text: received_text.clone(),
+        };
+
+        let router = Router::new()
+            .route("/v1/speech-to-text", post(speech_to_text))
+            .route(
+                "/v1/text-to-speech/{voice_id}/stream-input",
+                any(text_to_speech),
+            )
+            .with_state(state);
+
+        Self {
+            url: super::serve(router).await,
+            received_text,
+        }
+    }
+
+    /// The text chunks sent over text to speech streams so far.
+    pub fn received_text(&self) -> Vec<String> {
+        self.received_text.lock().unwrap().clone()
+    }
+}
+
+async fn speech_to_text(State(state): State<FakeElevenLabsState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "text": state.transcript.as_str() }))
+}
+
+async fn text_to_speech(
+    State(state): State<FakeElevenLabsState>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| stream_input(socket, state))
+}
+
+async fn stream_input(mut socket: WebSocket, state: FakeElevenLabsState) {
+    while let Some(Ok(message)) = socket.recv().await {
+        let Message::Text(text) = message else {
+            continue;
+        };
+
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) else {
+            continue;
+        };
+
+        let text = json["text"].as_str().unwrap_or_default();
+
+        // The init message is a single space, the end of input message is empty.
+        if !text.is_empty() && json["flush"] == true {
+            state.received_text.lock().unwrap().push(text.to_string());
+        }
+
+        if text.is_empty() {
+            break;
+        }
+    }
+
+    for chunk in state.audio_chunks.iter() {
+        let output = serde_json::json!({ "audio": BASE64_STANDARD.encode(chunk) });
+        let _ = socket.send(Message::Text(output.to_string().into())).await;
+    }
+
+    let _ = socket
+        .send(Message::Text(r#"{"isFinal":true}"#.into()))
+        .await;
+    let _ = socket.send(Message::Close(None)).await;
+}
@@ -0,0 +1,24 @@
+//! In-process fakes of the external services, so the conversation pipeline can
+//! be exercised without network access, API keys or audio hardware.
+
+use axum::Router;
+use tokio::net::TcpListener;
+
+pub mod anthropic;
+pub mod elevenlabs;
+
+mod pipeline;
+
+/// Serves `router` on a random local port and returns its base URL.
+async fn serve(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("failed to bind fake server");
+    let addr = listener.local_addr().expect("fake server has no address");
+
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, router).await;
+    });
+
+    format!("http://{addr}")
+}
@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use mongodb::bson::{DateTime, oid::ObjectId};
+use tokio::{sync::mpsc, time::timeout};
+use tokio_tungstenite::tungstenite::Bytes;
+
+use crate::{
+    config::Config,
+    events::AppEvent,
+    services::{
+        anthropic::{AnthropicService, types::AnthropicMessageStreamEvent},
+        elevenlabs::ElevenLabsService,
+    },
+    state::AppState,
+    testing::{
+        anthropic::{FakeAnthropic, text_response},
+        elevenlabs::FakeElevenLabs,
+    },
+    text_processor::TextProcessor,
+    types::message::{ContentBlock, Message, Role},
+};
+
+/// Drives the services through a full exchange the same way `App` does:
+/// transcription, then the LLM stream, then text to speech.
+#[tokio::test]
+async fn transcription_to_speech() {
+    Config::init_for_tests();
+
+    let anthropic_server =
+        FakeAnthropic::start(text_response(&["Hello there. ", "How are you?"])).await;
+    let elevenlabs_server = FakeElevenLabs::start(
+        "hey jumo",
+        vec![vec![0x00, 0x40, 0x00, 0xc0], vec![0xff, 0x7f]],
+    )
+    .await;
+
+    let (event_sender, mut event_receiver) = mpsc::channel(100);
+    let mut anthropic =
+        AnthropicService::with_base_url(event_sender.clone(), &anthropic_server.url);
+    let mut elevenlabs =
+        ElevenLabsService::with_base_url(event_sender.clone(), &elevenlabs_server.url);
+    let mut text_processor = TextProcessor::new(event_sender.clone());
+    let mut state = AppState::default();
+
+    let mut sequence = Vec::new();
+    let mut audio: Vec<Bytes> = Vec::new();
+
+    elevenlabs.transcribe(vec![0; 44]);
+
+    while !sequence.contains(&"tts_finished") {
+        let event = timeout(Duration::from_secs(5), event_receiver.recv())
+            .await
+            .expect("timed out waiting for the pipeline")
+            .expect("event channel closed");
+
+        match event {
+            AppEvent::TranscriptionStarted => sequence.push("transcription_started"),
+            AppEvent::TranscriptionCompleted(text) => {
+                sequence.push("transcription_completed");
+
+                let message = Message {
+                    _id: ObjectId::new(),
+                    role: Role::User,
+                    content: vec![ContentBlock::Text { text }],
+                    created_at: DateTime::now(),
+                    audio_id: None,
+                    context: false,
+                };
+
+                anthropic.prompt(&message, &state.messages, &state);
+            }
+            AppEvent::LLMGenerationStarted(payload) => {
+                sequence.push("llm_started");
+
+                state.messages.push(Message {
+                    _id: payload.message_id,
+                    role: Role::Assistant,
+                    content: vec![],
+                    created_at: DateTime::now(),
+                    audio_id: None,
+                    context: false,
+                });
+
+                elevenlabs.start_stream(&state.voice).await.unwrap();
+            }
+            AppEvent::LLMStreamEvent(payload) => {
+                if let Some(text) = state.apply_stream_event(&payload.message_id, payload.event) {
+                    text_processor.process_delta(&text).await.unwrap();
+                }
+            }
+            AppEvent::LLMGenerationCompleted(_) => {
+                sequence.push("llm_completed");
+                text_processor.flush().await.unwrap();
+            }
+            AppEvent::TextProcessorTextChunk(payload) => {
+                sequence.push("text_chunk");
+                elevenlabs.send_text(&payload.text).await.unwrap();
+            }
+            AppEvent::TextProcessorFlushed => {
+                sequence.push("text_flushed");
+                elevenlabs.end_stream().await.unwrap();
+            }
+            AppEvent::TTSStreamOpened => sequence.push("tts_opened"),
+            AppEvent::TTSChunk(payload) => {
+                sequence.push("tts_chunk");
+                audio.push(payload.audio);
+            }
+            AppEvent::TTSStreamFinished => sequence.push("tts_finished"),
+            AppEvent::TranscriptionFailed(error)
+            | AppEvent::LLMGenerationError(error)
+            | AppEvent::TTSFailed(error) => panic!("pipeline failed: {error}"),
+            _ => {}
+        }
+    }
+
+    // Events from the spawned service tasks interleave with the ones sent while
+    // handling them, so only the causal order is fixed.
+    let position = |name: &str| sequence.iter().position(|event| *event == name).unwrap();
+    let last_text_chunk = sequence
+        .iter()
+        .rposition(|event| *event == "text_chunk")
+        .unwrap();
+
+    assert!(position("transcription_started") < position("transcription_completed"));
+    assert!(position("transcription_completed") < position("llm_started"));
+    assert!(position("llm_started") < position("text_chunk"));
+    assert!(position("llm_started") < position("llm_completed"));
+    assert!(position("llm_completed") < position("text_flushed"));
+    assert!(last_text_chunk < position("text_flushed"));
+    assert!(position("text_flushed") < position("tts_chunk"));
+    assert!(position("llm_started") < position("tts_opened"));
+    assert!(position("tts_opened") < position("tts_chunk"));
+    assert!(
+        sequence
+            .iter()
+            .rposition(|event| *event == "tts_chunk")
+            .unwrap()
+            < position("tts_finished")
+    );
+
+    let request = &anthropic_server.requests()[0];
+    assert_eq!(request["stream"], true);
+    assert_eq!(request["messages"][0]["content"][0]["text"], "hey jumo");
+
+    assert_eq!(
+        state.messages[0].content,
+        [ContentBlock::Text {
+            text: String::from("Hello there. How are you?")
+        }]
+    );
+
+    assert_eq!(
+        elevenlabs_server.received_text(),
+        ["Hello there.", "How are you?"]
+    );
+    assert_eq!(audio.len(), 2);
+    assert_eq!(audio[0].as_ref(), [0x00, 0x40, 0x00, 0xc0]);
+    assert_eq!(audio[1].as_ref(), [0xff, 0x7f]);
+}
+
+/// Stopping a reply stops its generation, nothing more arrives for it.
+#[tokio::test]
+async fn cancelled_generation_sends_nothing_more() {
+    Config::init_for_tests();
+
+    let anthropic_server = FakeAnthropic::start_unfinished(text_response(&["Hello"])).await;
+    let (event_sender, mut event_receiver) = mpsc::channel(100);
+    let mut anthropic = AnthropicService::with_base_url(event_sender, &anthropic_server.url);
+
+    let message = Message {
+        _id: ObjectId::new(),
+        role: Role::User,
+        content: vec![ContentBlock::Text {
+            text: String::from("Tell me a long story"),
+        }],
+        created_at: DateTime::now(),
+        audio_id: None,
+        context: false,
+    };
+    anthropic.prompt(&message, &[], &AppState::default());
+
+    // The whole canned stream arrives, then it hangs
+    let mut deltas = 0;
+    while deltas == 0 {
+        let event = timeout(Duration::from_secs(5), event_receiver.recv())
+            .await
+            .expect("timed out waiting for the stream")
+            .expect("event channel closed");
+
+        if let AppEvent::LLMStreamEvent(payload) = event
+            && let AnthropicMessageStreamEvent::ContentBlockDelta { .. } = payload.event
+        {
+            deltas += 1;
+        }
+    }
+
+    assert!(anthropic.cancel());
+    assert!(!anthropic.cancel());
+
+    // Once the service is gone, only the aborted task could keep the channel open
+    drop(anthropic);
+    while let Ok(Some(event)) = timeout(Duration::from_secs(5), event_receiver.recv()).await {
+        assert!(
+            !matches!(event, AppEvent::LLMGenerationCompleted(_)),
+            "cancelled generation completed"
+        );
+    }
+}
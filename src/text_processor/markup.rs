@@ -0,0 +1,285 @@
+//! Tags the model can put in a reply to direct how it's spoken, the way the
+//! emote emoji direct the face. They're turned into a small SSML subset for
+//! the TTS service and hidden from the displayed text.
+//!
+//! - `<pause/>`, `<pause 2s/>`, `<pause 300ms/>`
+//! - `<whisper>...</whisper>`
+//! - `<emphasis>...</emphasis>`
+//! - `<rate slow>...</rate>`, `<rate fast>`, `<rate 0.8>`
+//! - `<silent>...</silent>`, shown but not spoken
+
+use crate::text_processor::speech::normalize_for_speech;
+
+const DEFAULT_PAUSE_MS: u32 = 500;
+/// The longest break ElevenLabs accepts.
+const MAX_PAUSE_MS: u32 = 3000;
+const SLOW_RATE: f32 = 0.8;
+const FAST_RATE: f32 = 1.2;
+/// Tags are short, anything longer after a `<` is text.
+const MAX_TAG_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    Pause(u32),
+    Open(Span),
+    Close(SpanKind),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Span {
+    Whisper,
+    Emphasis,
+    Rate(f32),
+    Silent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpanKind {
+    Whisper,
+    Emphasis,
+    Rate,
+    Silent,
+}
+
+impl Span {
+    fn kind(self) -> SpanKind {
+        match self {
+            Span::Whisper => SpanKind::Whisper,
+            Span::Emphasis => SpanKind::Emphasis,
+            Span::Rate(_) => SpanKind::Rate,
+            Span::Silent => SpanKind::Silent,
+        }
+    }
+}
+
+/// The spans open at the end of what was rendered so far, since they may
+/// cover several sentences.
+#[derive(Debug, Default, Clone)]
+pub struct MarkupState {
+    open: Vec<Span>,
+}
+
+impl MarkupState {
+    /// A sentence as normalized speech, with SSML for the markup in it.
+    pub fn render(&mut self, sentence: &str) -> String {
+        let mut out = String::new();
+        let mut spaced = false;
+        let mut run_start = 0;
+        let mut i = 0;
+
+        while let Some(offset) = sentence[i..].find('<') {
+            let start = i + offset;
+            let Some((tag, len)) = parse_tag(&sentence[start..]) else {
+                i = start + 1;
+                continue;
+            };
+
+            self.push_run(&mut out, &sentence[run_start..start], &mut spaced);
+
+            match tag {
+                Tag::Pause(ms) => {
+                    if !self.is_silent() {
+                        push_separated(&mut out, &format!("<break time=\"{}\"/>", seconds(ms)));
+                        spaced = true;
+                    }
+                }
+                Tag::Open(span) => self.open.push(span),
+                Tag::Close(kind) => {
+                    if let Some(index) = self.open.iter().rposition(|span| span.kind() == kind) {
+                        self.open.remove(index);
+                    }
+                }
+            }
+
+            i = start + len;
+            run_start = i;
+        }
+
+        self.push_run(&mut out, &sentence[run_start..], &mut spaced);
+        out
+    }
+
+    fn is_silent(&self) -> bool {
+        self.open.contains(&Span::Silent)
+    }
+
+    /// Adds the text between two tags, `spaced` tracking whether the model put
+    /// whitespace between it and what came before.
+    fn push_run(&self, out: &mut String, run: &str, spaced: &mut bool) {
+        if run.is_empty() || self.is_silent() {
+            return;
+        }
+
+        let separated = *spaced || run.starts_with(char::is_whitespace);
+        *spaced = run.ends_with(char::is_whitespace);
+
+        let spoken = normalize_for_speech(run);
+        if spoken.is_empty() {
+            return;
+        }
+
+        let mut prosody = Vec::new();
+        if let Some(rate) = self.open.iter().rev().find_map(|span| match span {
+            Span::Rate(rate) => Some(*rate),
+            _ => None,
+        }) {
+            prosody.push(format!("rate=\"{:.0}%\"", rate * 100.0));
+        }
+        if self.open.contains(&Span::Whisper) {
+            prosody.push(String::from("volume=\"x-soft\""));
+        }
+
+        let mut text = spoken;
+        if self.open.contains(&Span::Emphasis) {
+            text = format!("<emphasis>{text}</emphasis>");
+        }
+        if !prosody.is_empty() {
+            text = format!("<prosody {}>{text}</prosody>", prosody.join(" "));
+        }
+
+        if separated {
+            push_separated(out, &text);
+        } else {
+            out.push_str(&text);
+        }
+    }
+}
+
+fn push_separated(out: &mut String, text: &str) {
+    if !out.is_empty() {
+        out.push(' ');
+    }
+    out.push_str(text);
+}
+
+fn seconds(ms: u32) -> String {
+    let seconds = format!("{:.2}", ms as f32 / 1000.0);
+    format!("{}s", seconds.trim_end_matches('0').trim_end_matches('.'))
+}
+
+/// The markup tag at the start of `text` and its length, `None` if it's not one.
+fn parse_tag(text: &str) -> Option<(Tag, usize)> {
+    let end = text
+        .char_indices()
+        .take(MAX_TAG_LEN)
+        .find(|(_, c)| *c == '>')
+        .map(|(i, _)| i)?;
+    let inner = text[1..end].trim().to_lowercase();
+    let inner = inner.trim_end_matches('/').trim_end();
+
+    let tag = if let Some(name) = inner.strip_prefix('/') {
+        Tag::Close(match name.trim() {
+            "whisper" => SpanKind::Whisper,
+            "emphasis" => SpanKind::Emphasis,
+            "rate" => SpanKind::Rate,
+            "silent" => SpanKind::Silent,
+            _ => return None,
+        })
+    } else {
+        let (name, argument) = inner.split_once(' ').unwrap_or((inner, ""));
+        let argument = argument.trim();
+
+        match (name, argument) {
+            ("pause", "") => Tag::Pause(DEFAULT_PAUSE_MS),
+            ("pause", duration) => Tag::Pause(parse_duration(duration)?.min(MAX_PAUSE_MS)),
+            ("whisper", "") => Tag::Open(Span::Whisper),
+            ("emphasis", "") => Tag::Open(Span::Emphasis),
+            ("silent", "") => Tag::Open(Span::Silent),
+            ("rate", "slow") => Tag::Open(Span::Rate(SLOW_RATE)),
+            ("rate", "fast") => Tag::Open(Span::Rate(FAST_RATE)),
+            ("rate", rate) => Tag::Open(Span::Rate(rate.parse::<f32>().ok()?.clamp(0.5, 2.0))),
+            _ => return None,
+        }
+    };
+
+    Some((tag, end + 1))
+}
+
+fn parse_duration(text: &str) -> Option<u32> {
+    if let Some(ms) = text.strip_suffix("ms") {
+        ms.trim().parse().ok()
+    } else {
+        let seconds: f32 = text.strip_suffix('s')?.trim().parse().ok()?;
+        Some((seconds.max(0.0) * 1000.0) as u32)
+    }
+}
+
+/// The text as shown, without the markup. Silent spans stay, they're only
+/// kept from being spoken.
+pub fn strip_markup(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while let Some(offset) = text[i..].find('<') {
+        let start = i + offset;
+        out.push_str(&text[i..start]);
+
+        match parse_tag(&text[start..]) {
+            Some((_, len)) => i = start + len,
+            None => {
+                out.push('<');
+                i = start + 1;
+            }
+        }
+    }
+
+    out.push_str(&text[i..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_markup_as_ssml() {
+        let mut state = MarkupState::default();
+
+        assert_eq!(
+            state.render("I <emphasis>never</emphasis> said that.<pause 1.5s/> Really."),
+            "I <emphasis>never</emphasis> said that. <break time=\"1.5s\"/> Really."
+        );
+        assert_eq!(
+            state.render("<rate slow><whisper>It's a secret</whisper></rate>!"),
+            "<prosody rate=\"80%\" volume=\"x-soft\">It's a secret</prosody>!"
+        );
+    }
+
+    #[test]
+    fn spans_carry_over_to_the_next_sentence() {
+        let mut state = MarkupState::default();
+
+        assert_eq!(
+            state.render("<whisper>Quiet now."),
+            "<prosody volume=\"x-soft\">Quiet now.</prosody>"
+        );
+        assert_eq!(
+            state.render("Still quiet.</whisper> Loud."),
+            "<prosody volume=\"x-soft\">Still quiet.</prosody> Loud."
+        );
+    }
+
+    #[test]
+    fn silent_spans_are_shown_but_not_spoken() {
+        let mut state = MarkupState::default();
+        let text = "Here you go. <silent>https://example.com/a/long/link</silent>";
+
+        assert_eq!(state.render(text), "Here you go.");
+        assert_eq!(
+            strip_markup(text),
+            "Here you go. https://example.com/a/long/link"
+        );
+    }
+
+    #[test]
+    fn leaves_other_angle_brackets_alone() {
+        let mut state = MarkupState::default();
+        let text = "Use Vec<u8> when x < y<pause/>";
+
+        assert_eq!(strip_markup(text), "Use Vec<u8> when x < y");
+        assert_eq!(
+            state.render(text),
+            "Use Vec<u8> when x < y <break time=\"0.5s\"/>"
+        );
+    }
+}
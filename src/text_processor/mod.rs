@@ -0,0 +1,282 @@
+pub mod markup;
+mod speech;
+
+use std::mem::take;
+
+use tokio::sync::mpsc;
+
+use crate::{
+    emote::{get_color, get_emote},
+    events::{AppEvent, TextProcessorChunkEventPayload},
+    text_processor::markup::MarkupState,
+};
+
+const CODE_FENCE: &str = "```";
+
+/// Splits the model's reply into sentences for TTS as it streams, normalized
+/// for speech with its markup as SSML, and picks out the emote and color emoji.
+pub struct TextProcessor {
+    event_sender: mpsc::Sender<AppEvent>,
+    /// Text not sent yet, as the model wrote it
+    pending_chunk: String,
+    /// Code blocks are shown but not read out
+    in_code_block: bool,
+    markup: MarkupState,
+}
+
+impl TextProcessor {
+    pub fn new(event_sender: mpsc::Sender<AppEvent>) -> Self {
+        Self {
+            event_sender,
+            pending_chunk: String::new(),
+            in_code_block: false,
+            markup: MarkupState::default(),
+        }
+    }
+
+    pub async fn process_delta(&mut self, delta: &str) -> Result<(), anyhow::Error> {
+        for c in delta.chars() {
+            if let Some(emote) = get_emote(c) {
+                self.event_sender.send(AppEvent::SetEmote(emote)).await?;
+            }
+
+            if let Some(color) = get_color(c) {
+                self.event_sender.send(AppEvent::SetColor(color)).await?;
+                continue;
+            }
+
+            self.pending_chunk.push(c);
+        }
+
+        self.send_sentences().await
+    }
+
+    pub async fn flush(&mut self) -> Result<(), anyhow::Error> {
+        let pending_chunk = take(&mut self.pending_chunk);
+        if !take(&mut self.in_code_block) {
+            self.send_chunk(&pending_chunk).await?;
+        }
+        // Spans the model left open end with the reply
+        self.markup = MarkupState::default();
+
+        // Sent even when nothing was spoken, e.g. for a reply that's only a
+        // tool call, so the TTS stream opened for it is always ended
+        self.event_sender
+            .send(AppEvent::TextProcessorFlushed)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Drops the text not sent yet, e.g. when the reply was stopped.
+    pub fn reset(&mut self) {
+        self.pending_chunk.clear();
+        self.in_code_block = false;
+        self.markup = MarkupState::default();
+    }
+
+    /// Sends every complete sentence, dropping code blocks.
+    async fn send_sentences(&mut self) -> Result<(), anyhow::Error> {
+        loop {
+            if self.in_code_block {
+                let Some(fence) = self.pending_chunk.find(CODE_FENCE) else {
+                    // Backticks at the end may be the start of the closing fence
+                    let keep =
+                        self.pending_chunk.len() - self.pending_chunk.trim_end_matches('`').len();
+                    self.pending_chunk.drain(..self.pending_chunk.len() - keep);
+                    return Ok(());
+                };
+
+                self.pending_chunk.drain(..fence + CODE_FENCE.len());
+                self.in_code_block = false;
+                continue;
+            }
+
+            let fence = self.pending_chunk.find(CODE_FENCE);
+            let before_fence = &self.pending_chunk[..fence.unwrap_or(self.pending_chunk.len())];
+
+            if let Some(end) = speech::find_sentence_end(before_fence) {
+                let sentence: String = self.pending_chunk.drain(..end).collect();
+                self.send_chunk(&sentence).await?;
+            } else if let Some(fence) = fence {
+                let sentence: String = self.pending_chunk.drain(..fence).collect();
+                self.send_chunk(&sentence).await?;
+                self.pending_chunk.drain(..CODE_FENCE.len());
+                self.in_code_block = true;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn send_chunk(&mut self, text: &str) -> Result<(), anyhow::Error> {
+        let text = self.markup.render(text);
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let payload = TextProcessorChunkEventPayload { text, flush: true };
+        self.event_sender
+            .send(AppEvent::TextProcessorTextChunk(payload))
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::Color;
+
+    use super::*;
+    use crate::emote::Emote;
+
+    fn drain(receiver: &mut mpsc::Receiver<AppEvent>) -> Vec<AppEvent> {
+        let mut events = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    fn chunks(events: &[AppEvent]) -> Vec<&str> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                AppEvent::TextProcessorTextChunk(payload) => Some(payload.text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn splits_on_punctuation_followed_by_whitespace() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut processor = TextProcessor::new(sender);
+
+        processor.process_delta("Hi there. How").await.unwrap();
+        assert_eq!(chunks(&drain(&mut receiver)), ["Hi there."]);
+
+        processor.process_delta(" are you?").await.unwrap();
+        assert!(drain(&mut receiver).is_empty());
+
+        processor.flush().await.unwrap();
+        let events = drain(&mut receiver);
+        assert_eq!(chunks(&events), ["How are you?"]);
+        assert!(matches!(
+            events.last(),
+            Some(AppEvent::TextProcessorFlushed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn splits_across_deltas() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut processor = TextProcessor::new(sender);
+
+        processor.process_delta("Hello.").await.unwrap();
+        assert!(drain(&mut receiver).is_empty());
+
+        processor.process_delta(" World.").await.unwrap();
+        processor.flush().await.unwrap();
+
+        assert_eq!(chunks(&drain(&mut receiver)), ["Hello.", "World."]);
+    }
+
+    #[tokio::test]
+    async fn splits_multiple_sentences_in_one_delta() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut processor = TextProcessor::new(sender);
+
+        processor
+            .process_delta("One! Two? Three;\nFour")
+            .await
+            .unwrap();
+
+        assert_eq!(chunks(&drain(&mut receiver)), ["One!", "Two?", "Three;"]);
+    }
+
+    #[tokio::test]
+    async fn emits_emotes_and_colors() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut processor = TextProcessor::new(sender);
+
+        processor.process_delta("🙂🟦 Hi").await.unwrap();
+
+        let events = drain(&mut receiver);
+        assert!(matches!(events[0], AppEvent::SetEmote(Emote::Smile)));
+        assert!(matches!(events[1], AppEvent::SetColor(Color::Blue)));
+        assert_eq!(events.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn skips_code_blocks_split_across_deltas() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut processor = TextProcessor::new(sender);
+
+        processor.process_delta("Try this:\n``").await.unwrap();
+        processor
+            .process_delta("`rust\nlet x = 1. + 2.;\n`")
+            .await
+            .unwrap();
+        processor
+            .process_delta("``\nIt adds **two** numbers.")
+            .await
+            .unwrap();
+        processor.flush().await.unwrap();
+
+        let events = drain(&mut receiver);
+        assert_eq!(chunks(&events), ["Try this:", "It adds two numbers."]);
+        assert!(matches!(
+            events.last(),
+            Some(AppEvent::TextProcessorFlushed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn parses_markup_split_across_deltas() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut processor = TextProcessor::new(sender);
+
+        processor.process_delta("Listen.<pau").await.unwrap();
+        processor.process_delta("se/> <whis").await.unwrap();
+        processor
+            .process_delta("per>Quietly.</whisper> <silent>Hidden.</silent>")
+            .await
+            .unwrap();
+        processor.flush().await.unwrap();
+
+        assert_eq!(
+            chunks(&drain(&mut receiver)),
+            ["Listen. <break time=\"0.5s\"/> <prosody volume=\"x-soft\">Quietly.</prosody>"]
+        );
+    }
+
+    #[tokio::test]
+    async fn flushes_after_the_last_sentence_was_sent() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut processor = TextProcessor::new(sender);
+
+        processor.process_delta("Done.\n").await.unwrap();
+        processor.flush().await.unwrap();
+
+        let events = drain(&mut receiver);
+        assert_eq!(chunks(&events), ["Done."]);
+        assert!(matches!(
+            events.last(),
+            Some(AppEvent::TextProcessorFlushed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn flush_without_pending_text_still_ends_the_stream() {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let mut processor = TextProcessor::new(sender);
+
+        processor.process_delta("  ").await.unwrap();
+        processor.flush().await.unwrap();
+
+        let events = drain(&mut receiver);
+        assert!(matches!(events[..], [AppEvent::TextProcessorFlushed]));
+    }
+}
@@ -0,0 +1,637 @@
+//! Turns model output into text meant to be heard. Markdown, emoji, links and
+//! symbols read fine on screen but TTS either spells them out or mangles them.
+
+/// Words that end with a period without ending the sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "e.g", "i.e", "approx", "fig", "cf",
+    "mt", "ave", "dept", "est", "a.m", "p.m",
+];
+
+/// Symbols spoken after a number, longest first where one is a prefix of another.
+const UNITS: &[(&str, &str, &str)] = &[
+    ("GHz", "gigahertz", "gigahertz"),
+    ("MHz", "megahertz", "megahertz"),
+    ("kHz", "kilohertz", "kilohertz"),
+    ("Hz", "hertz", "hertz"),
+    ("TB", "terabyte", "terabytes"),
+    ("GB", "gigabyte", "gigabytes"),
+    ("MB", "megabyte", "megabytes"),
+    ("KB", "kilobyte", "kilobytes"),
+    ("kB", "kilobyte", "kilobytes"),
+    ("km/h", "kilometer per hour", "kilometers per hour"),
+    ("km", "kilometer", "kilometers"),
+    ("cm", "centimeter", "centimeters"),
+    ("mm", "millimeter", "millimeters"),
+    ("mph", "mile per hour", "miles per hour"),
+    ("mAh", "milliamp hour", "milliamp hours"),
+    ("kWh", "kilowatt hour", "kilowatt hours"),
+    ("kg", "kilogram", "kilograms"),
+    ("mg", "milligram", "milligrams"),
+    ("ms", "millisecond", "milliseconds"),
+    ("lbs", "pound", "pounds"),
+    ("lb", "pound", "pounds"),
+    ("ft", "foot", "feet"),
+    ("°C", "degree Celsius", "degrees Celsius"),
+    ("°F", "degree Fahrenheit", "degrees Fahrenheit"),
+    ("V", "volt", "volts"),
+    ("W", "watt", "watts"),
+];
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const SCALES: [(u64, &str); 4] = [
+    (1_000_000_000_000, "trillion"),
+    (1_000_000_000, "billion"),
+    (1_000_000, "million"),
+    (1_000, "thousand"),
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Where the first sentence of `text` ends: after sentence punctuation that's
+/// followed by whitespace, or at a line break. `None` until it's known, since
+/// the next delta may continue the sentence.
+pub fn find_sentence_end(text: &str) -> Option<usize> {
+    let mut seen_text = false;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '\n' && seen_text {
+            return Some(i);
+        }
+
+        if matches!(c, '.' | '!' | '?' | ';')
+            && chars.peek().is_some_and(|(_, next)| next.is_whitespace())
+            && !(c == '.' && is_abbreviation(&text[..i]))
+        {
+            return Some(i + c.len_utf8());
+        }
+
+        seen_text |= !c.is_whitespace();
+    }
+
+    None
+}
+
+/// Whether the period after `before` belongs to a word rather than ending the
+/// sentence: a known abbreviation, an initial or a list number.
+fn is_abbreviation(before: &str) -> bool {
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let word_start = before
+        .rfind(char::is_whitespace)
+        .map_or(0, |i| i + 1)
+        .max(line_start);
+    let word = before[word_start..].trim_start_matches(['(', '"', '\'']);
+
+    let mut chars = word.chars();
+    let initial = matches!((chars.next(), chars.next()), (Some(c), None) if c.is_uppercase());
+    let list_number = !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_digit())
+        && before[line_start..word_start].trim().is_empty();
+
+    initial || list_number || ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+/// Text as it should be spoken, on a single line.
+pub fn normalize_for_speech(text: &str) -> String {
+    let text = text
+        .split('\n')
+        .map(strip_block_markdown)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = replace_links(&text);
+    let text = replace_urls(&text);
+    let text = strip_inline_markdown(&text);
+    let text: String = text.chars().filter(|c| !is_emoji(*c)).collect();
+    let text = verbalize_numbers(&text);
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Headings, bullets, quotes and rules only make sense visually.
+fn strip_block_markdown(line: &str) -> &str {
+    let line = line.trim_start();
+
+    if line.len() >= 3 && line.chars().all(|c| matches!(c, '-' | '*' | '_' | ' ')) {
+        return "";
+    }
+
+    let hashes = line.len() - line.trim_start_matches('#').len();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+        return &line[hashes..];
+    }
+
+    if let Some(rest) = line.strip_prefix('>') {
+        return rest;
+    }
+
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(bullet) {
+            return rest;
+        }
+    }
+
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0
+        && let Some(rest) = line[digits..]
+            .strip_prefix(". ")
+            .or_else(|| line[digits..].strip_prefix(") "))
+    {
+        return rest;
+    }
+
+    line
+}
+
+/// `[text](url)` and `![alt](url)` become their text.
+fn replace_links(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find("](").map(|i| open + i) else {
+            break;
+        };
+        let Some(end) = rest[close..].find(')').map(|i| close + i) else {
+            break;
+        };
+
+        let before = &rest[..open];
+        out.push_str(before.strip_suffix('!').unwrap_or(before));
+        out.push_str(&rest[open + 1..close]);
+        rest = &rest[end + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Bare URLs are spoken as their host, the path is rarely worth hearing.
+fn replace_urls(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = ["https://", "http://", "www."]
+        .iter()
+        .filter_map(|prefix| {
+            rest.match_indices(prefix)
+                .find(|(i, _)| {
+                    !rest[..*i]
+                        .chars()
+                        .next_back()
+                        .is_some_and(|c| c.is_alphanumeric() || c == '/')
+                })
+                .map(|(i, _)| i)
+        })
+        .min()
+    {
+        out.push_str(&rest[..start]);
+
+        let url_len = rest[start..]
+            .find(|c: char| c.is_whitespace() || matches!(c, ')' | ']' | '>' | '"' | '\''))
+            .unwrap_or(rest.len() - start);
+        // Punctuation after a URL usually belongs to the sentence
+        let url = rest[start..start + url_len].trim_end_matches(['.', ',', ';', ':', '!', '?']);
+
+        let host = url
+            .split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .split(['/', '?', '#', ':'])
+            .next()
+            .unwrap_or_default();
+        out.push_str(host.strip_prefix("www.").unwrap_or(host));
+
+        rest = &rest[start + url.len()..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Emphasis and code markers. Underscores inside words separate them, as in
+/// `snake_case`.
+fn strip_inline_markdown(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+
+    for (i, &c) in chars.iter().enumerate() {
+        match c {
+            '*' | '`' => {}
+            '~' if chars.get(i + 1) == Some(&'~') || i > 0 && chars[i - 1] == '~' => {}
+            '_' => {
+                let between_words = i > 0
+                    && chars[i - 1].is_alphanumeric()
+                    && chars.get(i + 1).is_some_and(|c| c.is_alphanumeric());
+                if between_words {
+                    out.push(' ');
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Emoji, including the ones the emote and color events are driven by.
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF
+            | 0x2600..=0x27BF
+            | 0x2B00..=0x2BFF
+            | 0xFE00..=0xFE0F
+            | 0x200D
+            | 0x20E3
+            | 0xE0020..=0xE007F
+    )
+}
+
+fn verbalize_numbers(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        // Digits glued to letters or other digits are names, versions and
+        // such, which TTS reads well enough as they are
+        let starts_word =
+            i == 0 || !(chars[i - 1].is_alphanumeric() || matches!(chars[i - 1], '.' | ','));
+
+        if starts_word && let Some((spoken, len)) = parse_number(&chars[i..]) {
+            out.push_str(&spoken);
+            i += len;
+        } else {
+            out.push(chars[i]);
+            i += 1;
+        }
+    }
+
+    out
+}
+
+/// The spoken form of the number at the start of `chars` along with anything
+/// it qualifies, and how many characters that took.
+fn parse_number(chars: &[char]) -> Option<(String, usize)> {
+    let digit_at = |i: usize| chars.get(i).is_some_and(char::is_ascii_digit);
+    let is_at = |i: usize, c: char| chars.get(i) == Some(&c);
+
+    let currency = is_at(0, '$');
+    let negative = is_at(0, '-');
+    let mut j = usize::from(currency || negative);
+
+    if !digit_at(j) {
+        return None;
+    }
+
+    if j == 0
+        && let Some(date) = parse_date(chars)
+    {
+        return Some(date);
+    }
+
+    let int_start = j;
+    while digit_at(j) {
+        j += 1;
+    }
+    let mut grouped = false;
+    while is_at(j, ',') && (1..=3).all(|k| digit_at(j + k)) && !digit_at(j + 4) {
+        grouped = true;
+        j += 4;
+    }
+    let int: String = chars[int_start..j].iter().filter(|c| **c != ',').collect();
+
+    let mut fraction = String::new();
+    if is_at(j, '.') && digit_at(j + 1) {
+        let mut k = j + 1;
+        while digit_at(k) {
+            k += 1;
+        }
+        // Version numbers and addresses
+        if is_at(k, '.') && digit_at(k + 1) {
+            return None;
+        }
+        fraction = chars[j + 1..k].iter().collect();
+        j = k;
+    }
+
+    let value = (int.len() <= 15 && !(int.len() > 1 && int.starts_with('0')))
+        .then(|| int.parse::<u64>().ok())
+        .flatten();
+    let Some(value) = value else {
+        // Too long or zero padded to be a quantity
+        let digits: Vec<_> = int.chars().map(digit_word).collect();
+        return Some((digits.join(" "), j));
+    };
+
+    let is_one = value == 1 && fraction.is_empty();
+    let mut spoken = if negative {
+        String::from("minus ")
+    } else {
+        String::new()
+    };
+
+    let suffix: String = chars[j..chars.len().min(j + 2)].iter().collect();
+    let after_suffix_is_word = chars.get(j + 2).is_some_and(|c| c.is_alphanumeric());
+
+    if currency {
+        spoken.push_str(&dollars(value, &fraction));
+    } else if is_at(j, '%') {
+        spoken.push_str(&decimal(value, &fraction));
+        spoken.push_str(" percent");
+        j += 1;
+    } else if fraction.is_empty()
+        && matches!(suffix.as_str(), "st" | "nd" | "rd" | "th")
+        && !after_suffix_is_word
+    {
+        spoken.push_str(&ordinal(value));
+        j += 2;
+    } else if let Some((unit_len, singular, plural)) = match_unit(&chars[j..]) {
+        spoken.push_str(&decimal(value, &fraction));
+        spoken.push(' ');
+        spoken.push_str(if is_one { singular } else { plural });
+        j += unit_len;
+    } else if chars.get(j).is_some_and(|c| c.is_alphanumeric()) {
+        return None;
+    } else if !negative
+        && !grouped
+        && fraction.is_empty()
+        && int.len() == 4
+        && (1100..2100).contains(&value)
+    {
+        spoken.push_str(&year(value));
+    } else {
+        spoken.push_str(&decimal(value, &fraction));
+    }
+
+    Some((spoken, j))
+}
+
+/// ISO dates, e.g. `2024-03-05`.
+fn parse_date(chars: &[char]) -> Option<(String, usize)> {
+    let digits = |range: std::ops::Range<usize>| -> Option<u64> {
+        let text: String = chars.get(range)?.iter().collect();
+        text.chars()
+            .all(|c| c.is_ascii_digit())
+            .then(|| text.parse().ok())
+            .flatten()
+    };
+
+    if chars.get(4) != Some(&'-') || chars.get(7) != Some(&'-') {
+        return None;
+    }
+    if chars.get(10).is_some_and(|c| c.is_alphanumeric()) {
+        return None;
+    }
+
+    let (year_value, month, day) = (digits(0..4)?, digits(5..7)?, digits(8..10)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let spoken = format!(
+        "{} {}, {}",
+        MONTHS[month as usize - 1],
+        ordinal(day),
+        year(year_value)
+    );
+
+    Some((spoken, 10))
+}
+
+fn match_unit(chars: &[char]) -> Option<(usize, &'static str, &'static str)> {
+    // Single letter units only attach directly, "5 W" is more likely a word
+    let (offset, chars) = match chars.first() {
+        Some(' ') => (1, &chars[1..]),
+        _ => (0, chars),
+    };
+
+    UNITS.iter().find_map(|(symbol, singular, plural)| {
+        let len = symbol.chars().count();
+        let matches = chars.len() >= len
+            && chars[..len].iter().copied().eq(symbol.chars())
+            && !chars.get(len).is_some_and(|c| c.is_alphanumeric())
+            && (offset == 0 || len > 1);
+
+        matches.then_some((offset + len, *singular, *plural))
+    })
+}
+
+fn dollars(value: u64, fraction: &str) -> String {
+    let unit = |count: u64, singular: &str, plural: &str| {
+        format!(
+            "{} {}",
+            cardinal(count),
+            if count == 1 { singular } else { plural }
+        )
+    };
+
+    match fraction.parse::<u64>() {
+        Ok(cents) if fraction.len() == 2 && cents > 0 => format!(
+            "{} and {}",
+            unit(value, "dollar", "dollars"),
+            unit(cents, "cent", "cents")
+        ),
+        Ok(_) if fraction.len() != 2 => format!("{} dollars", decimal(value, fraction)),
+        _ => unit(value, "dollar", "dollars"),
+    }
+}
+
+fn decimal(value: u64, fraction: &str) -> String {
+    let mut spoken = cardinal(value);
+
+    if !fraction.is_empty() {
+        spoken.push_str(" point");
+        for c in fraction.chars() {
+            spoken.push(' ');
+            spoken.push_str(digit_word(c));
+        }
+    }
+
+    spoken
+}
+
+fn digit_word(c: char) -> &'static str {
+    c.to_digit(10).map_or("", |digit| ONES[digit as usize])
+}
+
+fn cardinal(value: u64) -> String {
+    if value < 1000 {
+        return below_thousand(value);
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = value;
+
+    for (scale, name) in SCALES {
+        if rest >= scale {
+            parts.push(format!("{} {name}", cardinal(rest / scale)));
+            rest %= scale;
+        }
+    }
+    if rest > 0 {
+        parts.push(below_thousand(rest));
+    }
+
+    parts.join(" ")
+}
+
+fn below_thousand(value: u64) -> String {
+    let (hundreds, rest) = (value / 100, value % 100);
+    let rest_spoken = match rest {
+        0..20 => ONES[rest as usize].to_owned(),
+        _ if rest % 10 == 0 => TENS[rest as usize / 10].to_owned(),
+        _ => format!("{}-{}", TENS[rest as usize / 10], ONES[rest as usize % 10]),
+    };
+
+    match (hundreds, rest) {
+        (0, _) => rest_spoken,
+        (_, 0) => format!("{} hundred", ONES[hundreds as usize]),
+        _ => format!("{} hundred {rest_spoken}", ONES[hundreds as usize]),
+    }
+}
+
+fn ordinal(value: u64) -> String {
+    let spoken = cardinal(value);
+    let split = spoken.rfind([' ', '-']).map_or(0, |i| i + 1);
+    let (head, last) = spoken.split_at(split);
+
+    let last = match last {
+        "one" => "first".to_owned(),
+        "two" => "second".to_owned(),
+        "three" => "third".to_owned(),
+        "five" => "fifth".to_owned(),
+        "eight" => "eighth".to_owned(),
+        "nine" => "ninth".to_owned(),
+        "twelve" => "twelfth".to_owned(),
+        _ if last.ends_with('y') => format!("{}ieth", &last[..last.len() - 1]),
+        _ => format!("{last}th"),
+    };
+
+    format!("{head}{last}")
+}
+
+/// Years are read in pairs, "nineteen ninety-nine" rather than "one thousand
+/// nine hundred ninety-nine".
+fn year(value: u64) -> String {
+    let (century, rest) = (value / 100, value % 100);
+
+    match rest {
+        _ if (2000..2010).contains(&value) => cardinal(value),
+        0 => format!("{} hundred", cardinal(century)),
+        1..10 => format!("{} oh {}", cardinal(century), ONES[rest as usize]),
+        _ => format!("{} {}", cardinal(century), cardinal(rest)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentences(mut text: &str) -> Vec<&str> {
+        let mut sentences = Vec::new();
+        while let Some(end) = find_sentence_end(text) {
+            sentences.push(text[..end].trim());
+            text = &text[end..];
+        }
+        sentences.push(text.trim());
+        sentences
+    }
+
+    #[test]
+    fn splits_sentences_around_abbreviations() {
+        assert_eq!(
+            sentences("Ask Dr. Smith, e.g. at 3.5 p.m. today. J. R. R. Tolkien wrote it! Done"),
+            [
+                "Ask Dr. Smith, e.g. at 3.5 p.m. today.",
+                "J. R. R. Tolkien wrote it!",
+                "Done"
+            ]
+        );
+        assert_eq!(
+            sentences("Steps:\n1. Open it\n2. Close it"),
+            ["Steps:", "1. Open it", "2. Close it"]
+        );
+    }
+
+    #[test]
+    fn strips_markdown() {
+        assert_eq!(
+            normalize_for_speech("## **Big** news\n- the `read_file` tool\n> quoted ~~not~~"),
+            "Big news the read file tool quoted not"
+        );
+        assert_eq!(
+            normalize_for_speech(
+                "See [the docs](https://docs.rs/tokio) or https://www.rust-lang.org/learn."
+            ),
+            "See the docs or rust-lang.org."
+        );
+    }
+
+    #[test]
+    fn removes_emoji() {
+        assert_eq!(normalize_for_speech("🙂 Hello 👋🏽 there 🟦"), "Hello there");
+    }
+
+    #[test]
+    fn expands_numbers_units_and_dates() {
+        assert_eq!(
+            normalize_for_speech("A 3.5GHz chip with 16 GB, 1 km away."),
+            "A three point five gigahertz chip with sixteen gigabytes, one kilometer away."
+        );
+        assert_eq!(
+            normalize_for_speech("It costs $4.99, 20% off, since 2024-03-05."),
+            "It costs four dollars and ninety-nine cents, twenty percent off, since March fifth, twenty twenty-four."
+        );
+        assert_eq!(
+            normalize_for_speech("The 21st of 1,250,000 in 1999 was -3°C"),
+            "The twenty-first of one million two hundred fifty thousand in nineteen ninety-nine was minus three degrees Celsius"
+        );
+    }
+
+    #[test]
+    fn leaves_names_and_versions_alone() {
+        assert_eq!(
+            normalize_for_speech("Version 1.2.3 of mp3 in 4K"),
+            "Version 1.2.3 of mp3 in 4K"
+        );
+    }
+}
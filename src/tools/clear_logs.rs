@@ -6,6 +6,7 @@ use crate::{
     events::AppEvent,
     state::AppState,
     tools::{Tool, ToolInput},
+    types::message::ToolResultContent,
 };
 
 #[derive(Serialize, Deserialize, JsonSchema)]
@@ -39,8 +40,8 @@ impl Tool for ClearLogsTool {
         _input: &str,
         _state: &AppState,
         event_sender: mpsc::Sender<AppEvent>,
-    ) -> Result<String, anyhow::Error> {
+    ) -> Result<ToolResultContent, anyhow::Error> {
         event_sender.send(AppEvent::ClearLogs).await?;
-        Ok(String::new())
+        Ok(String::new().into())
     }
 }
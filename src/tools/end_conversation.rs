@@ -0,0 +1,44 @@
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    events::AppEvent,
+    state::AppState,
+    tools::{Tool, ToolInput},
+    types::message::ToolResultContent,
+};
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct EndConversationToolInputSchema {
+    /// Optional reason for ending the conversation, e.g. the user said goodbye.
+    pub reason: Option<String>,
+}
+
+pub struct EndConversationTool;
+
+impl Tool for EndConversationTool {
+    const NAME: &'static str = "end_conversation";
+
+    fn get_tool_input(&self) -> ToolInput {
+        ToolInput {
+            name: Self::NAME,
+            description: "Stop listening for a reply once you finish speaking. Use this when the user says goodbye or the conversation is clearly over.",
+            input_schema: schema_for!(EndConversationToolInputSchema),
+        }
+    }
+
+    async fn execute(
+        &self,
+        _input: &str,
+        _state: &AppState,
+        event_sender: mpsc::Sender<AppEvent>,
+    ) -> Result<ToolResultContent, anyhow::Error> {
+        event_sender.send(AppEvent::EndConversation).await?;
+
+        Ok(String::from(
+            "The conversation ends after this reply, the user has to wake you again to talk",
+        )
+        .into())
+    }
+}
@@ -0,0 +1,79 @@
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    camera::Region,
+    config::CONFIG,
+    events::AppEvent,
+    state::AppState,
+    tools::{Tool, ToolInput},
+    types::message::{ImageSource, MediaType, ToolResultBlock, ToolResultContent},
+};
+
+/// Larger images are scaled down by the API anyway.
+const MAX_DIMENSION: u32 = 1568;
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct LookToolInputSchema {
+    /// Longest side of the image in pixels, up to 1568. Leave out for the usual size, ask for more to read small details.
+    pub max_dimension: Option<u32>,
+    /// Part of the view to look at, as fractions of its width and height from the top left, e.g. {"x": 0.5, "y": 0, "width": 0.5, "height": 0.5} for the top right quarter.
+    pub region: Option<Region>,
+}
+
+pub struct LookTool;
+
+impl Tool for LookTool {
+    const NAME: &'static str = "look";
+
+    fn get_tool_input(&self) -> ToolInput {
+        ToolInput {
+            name: Self::NAME,
+            description: "See what your camera sees right now. Use it when seeing would help with what the user said or asked, not on every turn.",
+            input_schema: schema_for!(LookToolInputSchema),
+        }
+    }
+
+    async fn execute(
+        &self,
+        input: &str,
+        state: &AppState,
+        _event_sender: mpsc::Sender<AppEvent>,
+    ) -> Result<ToolResultContent, anyhow::Error> {
+        let input: LookToolInputSchema = serde_json::from_str(input)?;
+
+        if let Some(region) = &input.region
+            && !region.is_valid()
+        {
+            return Ok(String::from(
+                "The region has to be inside the view, with x + width and y + height at most 1",
+            )
+            .into());
+        }
+
+        let max_dimension = input
+            .max_dimension
+            .unwrap_or(CONFIG.camera.max_dimension)
+            .clamp(1, MAX_DIMENSION);
+
+        let image = match state.camera.snapshot(max_dimension, input.region) {
+            Ok(Some(image)) => image,
+            Ok(None) => return Ok(String::from("The camera has no picture right now").into()),
+            Err(e) => return Ok(format!("The camera failed: {e}").into()),
+        };
+
+        Ok(ToolResultContent::Blocks(vec![
+            ToolResultBlock::Text {
+                text: String::from("This is what you see right now"),
+            },
+            ToolResultBlock::Image {
+                source: ImageSource {
+                    image_type: String::from("base64"),
+                    media_type: MediaType::JPEG,
+                    data: image,
+                },
+            },
+        ]))
+    }
+}
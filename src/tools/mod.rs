@@ -2,11 +2,14 @@ use schemars::Schema;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
-use crate::{events::AppEvent, state::AppState};
+use crate::{events::AppEvent, state::AppState, types::message::ToolResultContent};
 
 pub mod clear_logs;
+pub mod end_conversation;
+pub mod look;
 pub mod pass;
 pub mod set_view;
+pub mod set_voice;
 pub mod tools;
 pub mod update;
 
@@ -25,5 +28,5 @@ pub trait Tool {
         input: &str,
         state: &AppState,
         event_sender: mpsc::Sender<AppEvent>,
-    ) -> Result<String, anyhow::Error>;
+    ) -> Result<ToolResultContent, anyhow::Error>;
 }
@@ -6,6 +6,7 @@ use crate::{
     events::AppEvent,
     state::AppState,
     tools::{Tool, ToolInput},
+    types::message::ToolResultContent,
 };
 
 #[derive(Serialize, Deserialize, JsonSchema)]
@@ -32,7 +33,7 @@ impl Tool for PassTool {
         _input: &str,
         _state: &AppState,
         _event_sender: mpsc::Sender<AppEvent>,
-    ) -> Result<String, anyhow::Error> {
-        Ok(String::from("Passed successfully"))
+    ) -> Result<ToolResultContent, anyhow::Error> {
+        Ok(String::from("Passed successfully").into())
     }
 }
@@ -6,6 +6,7 @@ use crate::{
     events::AppEvent,
     state::{AppState, View},
     tools::{Tool, ToolInput},
+    types::message::ToolResultContent,
 };
 
 #[derive(Serialize, Deserialize, JsonSchema)]
@@ -37,7 +38,7 @@ impl Tool for SetViewTool {
         input: &str,
         state: &AppState,
         event_sender: mpsc::Sender<AppEvent>,
-    ) -> Result<String, anyhow::Error> {
+    ) -> Result<ToolResultContent, anyhow::Error> {
         let parsed_input: SetViewToolInputSchema = serde_json::from_str(input)?;
 
         let output = SetViewToolOutput {
@@ -49,6 +50,6 @@ impl Tool for SetViewTool {
             .send(AppEvent::SetView(parsed_input.view))
             .await?;
 
-        Ok(serde_json::to_string(&output)?)
+        Ok(serde_json::to_string(&output)?.into())
     }
 }
@@ -0,0 +1,55 @@
+use schemars::schema_for;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    events::AppEvent,
+    services::elevenlabs::voices::{SpeakingVoice, VoiceChange},
+    state::AppState,
+    tools::{Tool, ToolInput},
+    types::message::ToolResultContent,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct SetVoiceToolOutput {
+    pub previous_voice: SpeakingVoice,
+    pub new_voice: SpeakingVoice,
+}
+
+pub struct SetVoiceTool;
+
+impl Tool for SetVoiceTool {
+    const NAME: &'static str = "set_voice";
+
+    fn get_tool_input(&self) -> ToolInput {
+        ToolInput {
+            name: Self::NAME,
+            description: "Change the voice you speak with or how it sounds, starting with your next reply. The available voices are listed in your status.",
+            input_schema: schema_for!(VoiceChange),
+        }
+    }
+
+    async fn execute(
+        &self,
+        input: &str,
+        state: &AppState,
+        event_sender: mpsc::Sender<AppEvent>,
+    ) -> Result<ToolResultContent, anyhow::Error> {
+        let change: VoiceChange = serde_json::from_str(input)?;
+
+        // Tell the model what was wrong so it can try again
+        let new_voice = match state.voice.apply(&change, &state.voices) {
+            Ok(voice) => voice,
+            Err(e) => return Ok(format!("The voice wasn't changed: {e}").into()),
+        };
+
+        let output = SetVoiceToolOutput {
+            previous_voice: state.voice.clone(),
+            new_voice,
+        };
+
+        event_sender.send(AppEvent::SetVoice(change)).await?;
+
+        Ok(serde_json::to_string(&output)?.into())
+    }
+}
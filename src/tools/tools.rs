@@ -1,19 +1,26 @@
 use tokio::sync::mpsc;
 
 use crate::{
+    camera::CapturePolicy,
+    config::CONFIG,
     events::AppEvent,
     state::{AppState, View},
     tools::{
-        Tool, ToolInput, clear_logs::ClearLogsTool, pass::PassTool, set_view::SetViewTool,
+        Tool, ToolInput, clear_logs::ClearLogsTool, end_conversation::EndConversationTool,
+        look::LookTool, pass::PassTool, set_view::SetViewTool, set_voice::SetVoiceTool,
         update::UpdateTool,
     },
+    types::message::ToolResultContent,
 };
 
 pub enum ToolType {
     Pass(PassTool),
     Update(UpdateTool),
     SetView(SetViewTool),
+    SetVoice(SetVoiceTool),
     ClearLogs(ClearLogsTool),
+    EndConversation(EndConversationTool),
+    Look(LookTool),
 }
 
 impl ToolType {
@@ -22,7 +29,10 @@ impl ToolType {
             ToolType::Pass(tool) => tool.get_tool_input(),
             ToolType::Update(tool) => tool.get_tool_input(),
             ToolType::SetView(tool) => tool.get_tool_input(),
+            ToolType::SetVoice(tool) => tool.get_tool_input(),
             ToolType::ClearLogs(tool) => tool.get_tool_input(),
+            ToolType::EndConversation(tool) => tool.get_tool_input(),
+            ToolType::Look(tool) => tool.get_tool_input(),
         }
     }
 
@@ -31,12 +41,21 @@ impl ToolType {
             Self::Pass(PassTool).to_tool_input(),
             Self::Update(UpdateTool).to_tool_input(),
             Self::SetView(SetViewTool).to_tool_input(),
+            Self::SetVoice(SetVoiceTool).to_tool_input(),
         ];
 
         if state.view == View::Logs {
             tools.push(Self::ClearLogs(ClearLogsTool).to_tool_input());
         }
 
+        if state.conversation.active {
+            tools.push(Self::EndConversation(EndConversationTool).to_tool_input());
+        }
+
+        if CONFIG.camera.capture != CapturePolicy::Never {
+            tools.push(Self::Look(LookTool).to_tool_input());
+        }
+
         tools
     }
 
@@ -45,11 +64,18 @@ impl ToolType {
         input: &str,
         state: &AppState,
         event_sender: mpsc::Sender<AppEvent>,
-    ) -> Result<String, anyhow::Error> {
+    ) -> Result<ToolResultContent, anyhow::Error> {
         match tool_name {
             PassTool::NAME => PassTool.execute(input, state, event_sender).await,
             UpdateTool::NAME => UpdateTool.execute(input, state, event_sender).await,
             SetViewTool::NAME => SetViewTool.execute(input, state, event_sender).await,
+            SetVoiceTool::NAME => SetVoiceTool.execute(input, state, event_sender).await,
+            EndConversationTool::NAME => {
+                EndConversationTool
+                    .execute(input, state, event_sender)
+                    .await
+            }
+            LookTool::NAME => LookTool.execute(input, state, event_sender).await,
             _ => Err(anyhow::anyhow!("Tool not found")),
         }
     }
@@ -6,6 +6,7 @@ use crate::{
     events::AppEvent,
     state::AppState,
     tools::{Tool, ToolInput},
+    types::message::ToolResultContent,
 };
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -36,9 +37,9 @@ impl Tool for UpdateTool {
         input: &str,
         _state: &AppState,
         _event_sender: mpsc::Sender<AppEvent>,
-    ) -> Result<String, anyhow::Error> {
+    ) -> Result<ToolResultContent, anyhow::Error> {
         let _parsed_input: PassToolInputSchema = serde_json::from_str(&input)?;
         let output = UpdateToolOutput { success: true };
-        Ok(serde_json::to_string(&output)?)
+        Ok(serde_json::to_string(&output)?.into())
     }
 }
@@ -0,0 +1,49 @@
+use serde::Serialize;
+
+use crate::{audio::echo::EchoStats, config::CONFIG, memory::StorageStatus};
+
+/// Counters and settings useful when tuning the robot, served by `GET /diagnostics`.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Diagnostics {
+    pub wake_word: WakeWordDiagnostics,
+    pub echo: EchoDiagnostics,
+    pub storage: StorageStatus,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self {
+            wake_word: WakeWordDiagnostics {
+                enabled: CONFIG.wake_word.enabled,
+                phrase: CONFIG.wake_word.phrase.clone(),
+                sensitivity: CONFIG.wake_word.sensitivity,
+                ..Default::default()
+            },
+            echo: EchoDiagnostics {
+                enabled: CONFIG.echo_cancellation.enabled,
+                last: None,
+            },
+            storage: StorageStatus::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct WakeWordDiagnostics {
+    pub enabled: bool,
+    pub phrase: String,
+    pub sensitivity: f32,
+    /// Times the wake word fired.
+    pub triggers: u32,
+    /// Triggers that were followed by silence or an empty transcript.
+    pub false_triggers: u32,
+    /// Match score of the last trigger.
+    pub last_score: Option<f32>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct EchoDiagnostics {
+    pub enabled: bool,
+    /// Last measurement while only the robot was talking.
+    pub last: Option<EchoStats>,
+}
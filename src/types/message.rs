@@ -39,17 +39,138 @@ pub enum ContentBlock {
     },
     ToolResult {
         tool_use_id: String,
-        content: String,
+        content: ToolResultContent,
     },
     Thinking {
         content: String,
     },
 }
 
+/// What a tool returned, plain text or text and images.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ToolResultContent {
+    Text(String),
+    Blocks(Vec<ToolResultBlock>),
+}
+
+impl std::fmt::Display for ToolResultContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolResultContent::Text(text) => write!(f, "{text}"),
+            ToolResultContent::Blocks(blocks) => {
+                let parts: Vec<&str> = blocks
+                    .iter()
+                    .map(|block| match block {
+                        ToolResultBlock::Text { text } => text.as_str(),
+                        ToolResultBlock::Image { .. } => "[image]",
+                    })
+                    .collect();
+                write!(f, "{}", parts.join(" "))
+            }
+        }
+    }
+}
+
+impl From<String> for ToolResultContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ToolResultBlock {
+    Text { text: String },
+    Image { source: ImageSource },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub _id: ObjectId,
     pub role: Role,
     pub content: Vec<ContentBlock>,
     pub created_at: DateTime,
+    /// Archived recording or spoken reply, see [`AudioArchive`](crate::audio::archive::AudioArchive)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_id: Option<String>,
+    /// Written by the robot to give the model context, like the arrival
+    /// prompt, rather than said by the user. It's sent along with the rest of
+    /// the session but isn't a turn and isn't remembered.
+    #[serde(skip)]
+    pub context: bool,
+}
+
+impl Message {
+    /// Whether the user or the assistant said it, rather than it being tool
+    /// results handed back to the model.
+    pub fn is_turn(&self) -> bool {
+        if self.context {
+            return false;
+        }
+
+        self.role == Role::Assistant
+            || self
+                .content
+                .iter()
+                .any(|block| matches!(block, ContentBlock::Text { .. }))
+    }
+
+    /// Images in the message, including ones returned by tools.
+    pub fn images(&self) -> impl Iterator<Item = &ImageSource> {
+        self.content.iter().flat_map(|block| match block {
+            ContentBlock::Image { source } => vec![source],
+            ContentBlock::ToolResult {
+                content: ToolResultContent::Blocks(blocks),
+                ..
+            } => blocks
+                .iter()
+                .filter_map(|block| match block {
+                    ToolResultBlock::Image { source } => Some(source),
+                    ToolResultBlock::Text { .. } => None,
+                })
+                .collect(),
+            _ => vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_results_are_text_or_blocks() {
+        // Stored before tool results could hold images
+        let stored = r#"{"type": "tool_result", "tool_use_id": "1", "content": "done"}"#;
+        let block: ContentBlock = serde_json::from_str(stored).unwrap();
+        assert_eq!(
+            block,
+            ContentBlock::ToolResult {
+                tool_use_id: String::from("1"),
+                content: ToolResultContent::Text(String::from("done")),
+            }
+        );
+
+        let content = ToolResultContent::Blocks(vec![
+            ToolResultBlock::Text {
+                text: String::from("Look"),
+            },
+            ToolResultBlock::Image {
+                source: ImageSource {
+                    image_type: String::from("base64"),
+                    media_type: MediaType::JPEG,
+                    data: String::from("abc"),
+                },
+            },
+        ]);
+        assert_eq!(
+            serde_json::to_value(&content).unwrap(),
+            serde_json::json!([
+                {"type": "text", "text": "Look"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/jpeg", "data": "abc"}},
+            ])
+        );
+        assert_eq!(content.to_string(), "Look [image]");
+    }
 }
@@ -1,2 +1,4 @@
+pub mod diagnostics;
 pub mod logs;
 pub mod message;
+pub mod spoken;
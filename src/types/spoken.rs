@@ -0,0 +1,212 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// How far ahead of the last matched word a spoken word is looked for in the
+/// displayed text, past words that aren't spoken like code or silent spans.
+const MATCH_LOOKAHEAD: usize = 30;
+
+/// A word of synthesized speech and when it's heard, relative to the start of
+/// the audio it came with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpokenWord {
+    pub text: String,
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+/// Groups character timings from the TTS service into words.
+pub fn words_from_alignment(
+    chars: &[String],
+    start_times_ms: &[u32],
+    durations_ms: &[u32],
+) -> Vec<SpokenWord> {
+    let mut words: Vec<SpokenWord> = Vec::new();
+    let mut in_word = false;
+
+    for ((c, &start), &duration) in chars.iter().zip(start_times_ms).zip(durations_ms) {
+        if c.trim().is_empty() {
+            in_word = false;
+            continue;
+        }
+
+        let end = (start + duration) as u64;
+        match words.last_mut() {
+            Some(word) if in_word => {
+                word.text.push_str(c);
+                word.end_ms = end;
+            }
+            _ => words.push(SpokenWord {
+                text: c.clone(),
+                start_ms: start as u64,
+                end_ms: end,
+            }),
+        }
+        in_word = true;
+    }
+
+    words
+}
+
+/// The words of the reply being spoken, timed from the start of its audio.
+#[derive(Debug, Default, Clone)]
+pub struct SpokenReply {
+    pub message_id: Option<ObjectId>,
+    pub words: Vec<SpokenWord>,
+    /// Audio of the reply handed to the player so far
+    pub queued_ms: u64,
+    /// Where in the reply the player's current playback started. Playback
+    /// restarts when the audio ran out before the next chunk arrived.
+    pub playback_start_ms: u64,
+}
+
+impl SpokenReply {
+    pub fn new(message_id: ObjectId) -> Self {
+        Self {
+            message_id: Some(message_id),
+            ..Self::default()
+        }
+    }
+
+    /// Adds a chunk of `duration_ms` audio, `restarts_playback` when the
+    /// player has nothing left to play before it.
+    pub fn push_chunk(&mut self, words: &[SpokenWord], duration_ms: u64, restarts_playback: bool) {
+        if restarts_playback {
+            self.playback_start_ms = self.queued_ms;
+        }
+
+        self.words.extend(words.iter().map(|word| SpokenWord {
+            text: word.text.clone(),
+            start_ms: self.queued_ms + word.start_ms,
+            end_ms: self.queued_ms + word.end_ms,
+        }));
+        self.queued_ms += duration_ms;
+    }
+
+    /// Where in the reply the player is, given what it played since its
+    /// playback started.
+    pub fn position_ms(&self, played_ms: u64) -> u64 {
+        self.playback_start_ms + played_ms
+    }
+
+    /// The index of the word in `display`, split on whitespace, being spoken
+    /// at `position_ms`. The spoken words are normalized for speech, so they're
+    /// matched loosely. A word that doesn't match, like a spelled out number,
+    /// is taken to be the next displayed word.
+    pub fn highlighted_word(&self, display: &[&str], position_ms: u64) -> Option<usize> {
+        let spoken = self
+            .words
+            .iter()
+            .take_while(|word| word.start_ms <= position_ms)
+            .count();
+        if spoken == 0 {
+            return None;
+        }
+
+        let display: Vec<String> = display.iter().map(|word| match_key(word)).collect();
+        let mut next = 0;
+        let mut current = None;
+
+        for word in &self.words[..spoken] {
+            let key = match_key(&word.text);
+            let found = (next..display.len().min(next + MATCH_LOOKAHEAD))
+                .find(|&i| words_match(&display[i], &key));
+
+            current = match found {
+                Some(i) => {
+                    next = i + 1;
+                    Some(i)
+                }
+                None if next < display.len() => Some(next),
+                None => current,
+            };
+        }
+
+        current
+    }
+}
+
+fn match_key(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Short words have to match exactly, longer ones may be part of a displayed
+/// word, e.g. a host of a URL.
+fn words_match(display: &str, spoken: &str) -> bool {
+    if spoken.is_empty() {
+        return false;
+    }
+
+    if spoken.chars().count() < 3 {
+        display == spoken
+    } else {
+        display.contains(spoken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timed(text: &str, start_ms: u64, end_ms: u64) -> SpokenWord {
+        SpokenWord {
+            text: text.to_string(),
+            start_ms,
+            end_ms,
+        }
+    }
+
+    #[test]
+    fn groups_aligned_characters_into_words() {
+        let chars: Vec<String> = "Hi, you".chars().map(String::from).collect();
+        let starts = [0, 50, 100, 150, 200, 250, 300];
+        let durations = [50; 7];
+
+        assert_eq!(
+            words_from_alignment(&chars, &starts, &durations),
+            [timed("Hi,", 0, 150), timed("you", 200, 350)]
+        );
+    }
+
+    #[test]
+    fn times_words_across_chunks_and_playback_restarts() {
+        let mut reply = SpokenReply::new(ObjectId::new());
+
+        reply.push_chunk(&[timed("One.", 0, 400)], 500, true);
+        reply.push_chunk(&[], 1000, false);
+        reply.push_chunk(&[timed("Two.", 100, 400)], 500, true);
+
+        assert_eq!(reply.words[1], timed("Two.", 1600, 1900));
+        assert_eq!(reply.position_ms(200), 1700);
+    }
+
+    #[test]
+    fn highlights_the_displayed_word_being_spoken() {
+        let mut reply = SpokenReply::new(ObjectId::new());
+        let spoken = [
+            "It",
+            "runs",
+            "at",
+            "three",
+            "point",
+            "five",
+            "gigahertz",
+            "now.",
+        ];
+        let words: Vec<_> = spoken
+            .iter()
+            .enumerate()
+            .map(|(i, text)| timed(text, i as u64 * 100, i as u64 * 100 + 90))
+            .collect();
+        reply.push_chunk(&words, 1000, true);
+
+        let display = ["It", "**runs**", "at", "3.5GHz", "now."];
+
+        assert_eq!(reply.highlighted_word(&display, 0), Some(0));
+        assert_eq!(reply.highlighted_word(&display, 150), Some(1));
+        assert_eq!(reply.highlighted_word(&display, 450), Some(3));
+        assert_eq!(reply.highlighted_word(&display, 750), Some(4));
+    }
+}
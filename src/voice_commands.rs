@@ -0,0 +1,247 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+use crate::{
+    config::{CONFIG, VoiceCommandsConfig},
+    state::View,
+};
+
+/// Words around a command that don't change its meaning.
+const FILLER_WORDS: &[&str] = &["please", "okay", "ok"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoiceCommand {
+    Stop,
+    Cancel,
+    Louder,
+    Quieter,
+    SetView(View),
+    Update,
+    ConfirmUpdate,
+}
+
+impl Display for VoiceCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoiceCommand::Stop => write!(f, "stop"),
+            VoiceCommand::Cancel => write!(f, "cancel"),
+            VoiceCommand::Louder => write!(f, "louder"),
+            VoiceCommand::Quieter => write!(f, "quieter"),
+            VoiceCommand::SetView(view) => write!(f, "show {view}"),
+            VoiceCommand::Update => write!(f, "update"),
+            VoiceCommand::ConfirmUpdate => write!(f, "confirm update"),
+        }
+    }
+}
+
+/// Matches transcriptions against the configured command phrases so simple
+/// requests don't need a round trip to the LLM.
+#[derive(Debug)]
+pub struct VoiceCommandMatcher {
+    enabled: bool,
+    phrases: HashMap<String, VoiceCommand>,
+    /// Ways of addressing the robot that may come before a command, longest first.
+    prefixes: Vec<String>,
+}
+
+impl VoiceCommandMatcher {
+    /// Fails when a phrase is empty or assigned to more than one command.
+    pub fn new(
+        config: &VoiceCommandsConfig,
+        robot_name: &str,
+        wake_phrase: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let grammar = [
+            (&config.stop, VoiceCommand::Stop),
+            (&config.cancel, VoiceCommand::Cancel),
+            (&config.louder, VoiceCommand::Louder),
+            (&config.quieter, VoiceCommand::Quieter),
+            (&config.show_home, VoiceCommand::SetView(View::Home)),
+            (&config.show_logs, VoiceCommand::SetView(View::Logs)),
+            (&config.show_chat, VoiceCommand::SetView(View::Chat)),
+            (&config.update, VoiceCommand::Update),
+            (&config.confirm_update, VoiceCommand::ConfirmUpdate),
+        ];
+
+        let mut phrases = HashMap::new();
+
+        for (command_phrases, command) in grammar {
+            for phrase in command_phrases {
+                let normalized = normalize(phrase);
+                if normalized.is_empty() {
+                    return Err(anyhow::anyhow!("`{command}` has an empty phrase"));
+                }
+
+                if let Some(existing) = phrases.insert(normalized, command)
+                    && existing != command
+                {
+                    return Err(anyhow::anyhow!(
+                        "\"{phrase}\" is used by both `{existing}` and `{command}`"
+                    ));
+                }
+            }
+        }
+
+        let robot_name = normalize(robot_name);
+        let mut prefixes = vec![
+            normalize(wake_phrase),
+            format!("hey {robot_name}"),
+            robot_name,
+        ];
+        prefixes.retain(|prefix| !prefix.is_empty());
+        prefixes.sort_by_key(|prefix| std::cmp::Reverse(prefix.len()));
+
+        Ok(Self {
+            enabled: config.enabled,
+            phrases,
+            prefixes,
+        })
+    }
+
+    pub fn from_config() -> Result<Self, anyhow::Error> {
+        Self::new(
+            &CONFIG.voice_commands,
+            &CONFIG.persona.name,
+            &CONFIG.wake_word.phrase,
+        )
+    }
+
+    /// The command `utterance` consists of, if any. Commands inside longer
+    /// sentences don't match, those go to the LLM as usual.
+    pub fn match_command(&self, utterance: &str) -> Option<VoiceCommand> {
+        if !self.enabled {
+            return None;
+        }
+
+        let normalized = normalize(utterance);
+        let mut words: &str = &normalized;
+
+        if let Some(rest) = self
+            .prefixes
+            .iter()
+            .find_map(|prefix| strip_words(words, prefix))
+        {
+            words = rest;
+        }
+
+        let mut words: Vec<&str> = words.split(' ').filter(|w| !w.is_empty()).collect();
+        words.retain(|word| !FILLER_WORDS.contains(word));
+
+        self.phrases.get(&words.join(" ")).copied()
+    }
+}
+
+/// Lowercase words separated by single spaces, without punctuation.
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .replace('\'', "")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `text` without the leading words in `prefix`, only on whole word boundaries.
+fn strip_words<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = text.strip_prefix(prefix)?;
+    if rest.is_empty() || rest.starts_with(' ') {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher() -> VoiceCommandMatcher {
+        VoiceCommandMatcher::new(&VoiceCommandsConfig::default(), "Jumo", "Hey Jumo").unwrap()
+    }
+
+    #[test]
+    fn matches_phrases_ignoring_case_and_punctuation() {
+        let matcher = matcher();
+
+        assert_eq!(matcher.match_command("Stop."), Some(VoiceCommand::Stop));
+        assert_eq!(
+            matcher.match_command("Never mind!"),
+            Some(VoiceCommand::Cancel)
+        );
+        assert_eq!(
+            matcher.match_command("  LOUDER  "),
+            Some(VoiceCommand::Louder)
+        );
+        assert_eq!(
+            matcher.match_command("Switch to logs."),
+            Some(VoiceCommand::SetView(View::Logs))
+        );
+    }
+
+    #[test]
+    fn strips_the_wake_phrase_name_and_fillers() {
+        let matcher = matcher();
+
+        assert_eq!(
+            matcher.match_command("Hey Jumo, be quiet."),
+            Some(VoiceCommand::Stop)
+        );
+        assert_eq!(
+            matcher.match_command("Jumo, update yourself please."),
+            Some(VoiceCommand::Update)
+        );
+        assert_eq!(
+            matcher.match_command("Okay, quieter"),
+            Some(VoiceCommand::Quieter)
+        );
+    }
+
+    #[test]
+    fn passes_other_utterances_through() {
+        let matcher = matcher();
+
+        assert_eq!(matcher.match_command("Don't stop believing"), None);
+        assert_eq!(matcher.match_command("What's the weather like?"), None);
+        assert_eq!(matcher.match_command("Jumonji"), None);
+        assert_eq!(matcher.match_command(""), None);
+        // Too easy to hear by accident for something that restarts the robot
+        assert_eq!(matcher.match_command("Uh, update."), None);
+    }
+
+    #[test]
+    fn matches_the_update_confirmation() {
+        let matcher = matcher();
+
+        assert_eq!(
+            matcher.match_command("Yes, update."),
+            Some(VoiceCommand::ConfirmUpdate)
+        );
+        assert_eq!(
+            matcher.match_command("Hey Jumo, yes, update yourself"),
+            Some(VoiceCommand::ConfirmUpdate)
+        );
+    }
+
+    #[test]
+    fn disabled_matches_nothing() {
+        let config = VoiceCommandsConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        let matcher = VoiceCommandMatcher::new(&config, "Jumo", "Hey Jumo").unwrap();
+
+        assert_eq!(matcher.match_command("stop"), None);
+    }
+
+    #[test]
+    fn rejects_phrases_shared_between_commands() {
+        let config = VoiceCommandsConfig {
+            louder: vec![String::from("Stop!")],
+            ..Default::default()
+        };
+
+        assert!(VoiceCommandMatcher::new(&config, "Jumo", "Hey Jumo").is_err());
+    }
+}
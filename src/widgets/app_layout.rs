@@ -10,7 +10,10 @@ use crate::{
         header::Header,
         nav_tabs::NavTabs,
         status_line::StatusLine,
-        views::{chat::ChatViewWidget, home::HomeViewWidget, logs::LogsViewWidget},
+        views::{
+            camera::CameraViewWidget, chat::ChatViewWidget, home::HomeViewWidget,
+            logs::LogsViewWidget, settings::SettingsViewWidget,
+        },
     },
 };
 
@@ -43,6 +46,8 @@ impl Widget for AppLayout<'_> {
             View::Home => HomeViewWidget::new(self.state).render(layout[2], buf),
             View::Logs => LogsViewWidget::new(self.state).render(layout[2], buf),
             View::Chat => ChatViewWidget::new(self.state).render(layout[2], buf),
+            View::Settings => SettingsViewWidget::new(self.state).render(layout[2], buf),
+            View::Camera => CameraViewWidget::new(self.state).render(layout[2], buf),
         }
 
         StatusLine::new(self.state).render(layout[3], buf);
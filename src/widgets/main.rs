@@ -8,6 +8,7 @@ use ratatui::{
 
 use crate::{
     state::AppState,
+    text_processor::markup::strip_markup,
     types::message::{ContentBlock, Role},
     widgets::{nav_tabs::NavTabs, status_line::StatusLine},
 };
@@ -53,7 +54,7 @@ impl Widget for MainWidget<'_> {
             for block in most_recent_assistant_message.content.iter() {
                 match block {
                     ContentBlock::Text { text } => {
-                        lines.push(Line::from(text.clone()));
+                        lines.push(Line::from(strip_markup(text)));
                         lines.push(Line::from(""));
                     }
                     _ => {}
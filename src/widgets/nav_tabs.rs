@@ -38,6 +38,14 @@ impl Widget for NavTabs<'_> {
                 title: String::from("Chat"),
                 is_active: self.state.view == View::Chat,
             },
+            NavTab {
+                title: String::from("Settings"),
+                is_active: self.state.view == View::Settings,
+            },
+            NavTab {
+                title: String::from("Camera"),
+                is_active: self.state.view == View::Camera,
+            },
         ];
 
         Line::from(
@@ -6,7 +6,7 @@ use ratatui::{
     widgets::Widget,
 };
 
-use crate::state::AppState;
+use crate::state::{AppState, ListeningOrigin};
 
 pub struct StatusLine<'a> {
     state: &'a AppState,
@@ -23,7 +23,25 @@ impl<'a> StatusLine<'a> {
     }
 
     fn get_statuses(&self) -> Vec<Status> {
-        vec![
+        let mut statuses = vec![];
+
+        let listening_origin = self.state.listening.map(|session| session.origin);
+
+        if self.state.diagnostics.wake_word.enabled {
+            statuses.push(Status {
+                code: "WAKE",
+                active: listening_origin == Some(ListeningOrigin::WakeWord),
+            });
+        }
+
+        if self.state.conversation.enabled {
+            statuses.push(Status {
+                code: "CONV",
+                active: self.state.conversation.active,
+            });
+        }
+
+        statuses.extend([
             Status {
                 code: "REC",
                 active: self.state.is_audio_recording_running,
@@ -40,7 +58,13 @@ impl<'a> StatusLine<'a> {
                 code: "TTS",
                 active: self.state.is_tts_running,
             },
-        ]
+            Status {
+                code: "PLAY",
+                active: self.state.is_audio_playback_running,
+            },
+        ]);
+
+        statuses
     }
 }
 
@@ -67,13 +91,27 @@ impl Widget for StatusLine<'_> {
             ));
         }
 
+        let storage = self.state.diagnostics.storage;
+        if storage.degraded {
+            spans.push(Span::styled(
+                format!(" DB {} queued ", storage.queued),
+                Style::new().fg(Color::Black).bg(Color::Red),
+            ));
+        }
+
         let volume = self.state.input_volume;
         let volume_db = 20.0 * volume.max(0.001).log10(); // Convert to dB
         let volume_percent = ((volume_db + 60.0) / 60.0 * 100.0).max(0.0); // -60dB to 0dB range
 
         spans.push(Span::styled(" IN ", Style::new().fg(self.state.color)));
         spans.push(Span::styled(
-            format!("({}) ", &self.state.audio_input_device),
+            format!(
+                "({}) ",
+                self.state
+                    .audio_input_device
+                    .as_deref()
+                    .unwrap_or("no device")
+            ),
             Style::new().fg(self.state.color),
         ));
 
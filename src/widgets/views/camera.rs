@@ -0,0 +1,294 @@
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose};
+
+use image::{DynamicImage, RgbImage, imageops::FilterType};
+use mongodb::bson::oid::ObjectId;
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, BorderType, Paragraph, Widget},
+};
+
+use crate::{camera::Region, state::AppState, types::message::ImageSource};
+
+/// How the frames are drawn with text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ArtStyle {
+    /// `▀` with the top pixel as the foreground and the bottom one as the
+    /// background, in colour
+    #[default]
+    HalfBlock,
+    /// 2x4 dots a cell, more detail but one colour per cell
+    Braille,
+}
+
+impl ArtStyle {
+    pub fn toggle(self) -> Self {
+        match self {
+            ArtStyle::HalfBlock => ArtStyle::Braille,
+            ArtStyle::Braille => ArtStyle::HalfBlock,
+        }
+    }
+
+    /// Pixels drawn by one cell.
+    fn cell_size(self) -> (u32, u32) {
+        match self {
+            ArtStyle::HalfBlock => (1, 2),
+            ArtStyle::Braille => (2, 4),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct CameraViewState {
+    pub art: ArtStyle,
+    /// The latest camera frame, downscaled by the camera while the view is
+    /// open.
+    pub preview: Option<Arc<RgbImage>>,
+    /// The image sent with the message `sent_for`.
+    pub sent: Option<RgbImage>,
+    pub sent_for: Option<ObjectId>,
+}
+
+pub struct CameraViewWidget<'a> {
+    state: &'a AppState,
+}
+
+impl<'a> CameraViewWidget<'a> {
+    pub fn new(state: &'a AppState) -> Self {
+        Self { state }
+    }
+}
+
+impl Widget for CameraViewWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let camera_view = &self.state.camera_view;
+        let art = match camera_view.art {
+            ArtStyle::HalfBlock => "braille",
+            ArtStyle::Braille => "blocks",
+        };
+
+        let layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(area);
+
+        let live = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .title(format!(" Live  [b] {art} "))
+            .style(Style::default().fg(self.state.color));
+        let sent = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .title(" Sent with the selected message  [←/→] select ")
+            .style(Style::default().fg(self.state.color));
+
+        let live_area = live.inner(layout[0]);
+        let sent_area = sent.inner(layout[1]);
+        live.render(layout[0], buf);
+        sent.render(layout[1], buf);
+
+        match &camera_view.preview {
+            Some(image) => {
+                let drawn = render_image(image, camera_view.art, live_area, buf);
+
+                let presence = &self.state.presence;
+                if presence.present
+                    && let Some(region) = presence.region
+                {
+                    Block::bordered()
+                        .border_style(Style::default().fg(self.state.color))
+                        .render(region_area(region, drawn), buf);
+                }
+            }
+            None => placeholder("No camera frames", live_area, buf),
+        }
+
+        match &camera_view.sent {
+            Some(image) => {
+                render_image(image, camera_view.art, sent_area, buf);
+            }
+            None => placeholder("No image was sent", sent_area, buf),
+        }
+    }
+}
+
+/// Decodes an image from a message, `None` when it's not valid base64 JPEG.
+pub fn decode_image(source: &ImageSource) -> Option<DynamicImage> {
+    let jpeg = general_purpose::STANDARD.decode(&source.data).ok()?;
+    image::load_from_memory(&jpeg).ok()
+}
+
+fn placeholder(text: &str, area: Rect, buf: &mut Buffer) {
+    Paragraph::new(Line::from(text).style(Style::default().fg(Color::DarkGray)))
+        .centered()
+        .render(area, buf);
+}
+
+/// Draws `image` scaled to fit and centred in `area`, returning where it was
+/// drawn.
+fn render_image(image: &RgbImage, art: ArtStyle, area: Rect, buf: &mut Buffer) -> Rect {
+    let (cell_width, cell_height) = art.cell_size();
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 || area.is_empty() {
+        return Rect::default();
+    }
+
+    let scale = f32::min(
+        (area.width as u32 * cell_width) as f32 / width as f32,
+        (area.height as u32 * cell_height) as f32 / height as f32,
+    );
+    let columns = ((width as f32 * scale) as u32 / cell_width).clamp(1, area.width as u32);
+    let rows = ((height as f32 * scale) as u32 / cell_height).clamp(1, area.height as u32);
+    let image = DynamicImage::ImageRgb8(image.clone())
+        .resize_exact(
+            columns * cell_width,
+            rows * cell_height,
+            FilterType::Triangle,
+        )
+        .to_rgb8();
+
+    let drawn = Rect {
+        x: area.x + (area.width - columns as u16) / 2,
+        y: area.y + (area.height - rows as u16) / 2,
+        width: columns as u16,
+        height: rows as u16,
+    };
+
+    match art {
+        ArtStyle::HalfBlock => render_half_blocks(&image, drawn, buf),
+        ArtStyle::Braille => render_braille(&image, drawn, buf),
+    }
+
+    drawn
+}
+
+fn render_half_blocks(image: &RgbImage, area: Rect, buf: &mut Buffer) {
+    let color = |x: u32, y: u32| {
+        let [r, g, b] = image.get_pixel(x, y).0;
+        Color::Rgb(r, g, b)
+    };
+
+    for row in 0..area.height {
+        for column in 0..area.width {
+            let (x, y) = (column as u32, row as u32 * 2);
+            buf[(area.x + column, area.y + row)]
+                .set_symbol("▀")
+                .set_fg(color(x, y))
+                .set_bg(color(x, y + 1));
+        }
+    }
+}
+
+/// Dots are lit where a pixel is brighter than the image on average, and
+/// each cell takes the average colour of its pixels.
+fn render_braille(image: &RgbImage, area: Rect, buf: &mut Buffer) {
+    /// Bit of the dot at `[y][x]` in a braille character
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let luma = |[r, g, b]: [u8; 3]| (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+    let pixel_count = image.width() * image.height();
+    let mean = image.pixels().map(|pixel| luma(pixel.0)).sum::<u32>() / pixel_count.max(1);
+
+    for row in 0..area.height {
+        for column in 0..area.width {
+            let mut bits = 0;
+            let mut sum = [0u32; 3];
+
+            for (dy, dots) in DOTS.iter().enumerate() {
+                for (dx, bit) in dots.iter().enumerate() {
+                    let x = column as u32 * 2 + dx as u32;
+                    let y = row as u32 * 4 + dy as u32;
+                    let pixel = image.get_pixel(x, y).0;
+
+                    if luma(pixel) > mean {
+                        bits |= bit;
+                    }
+                    for (total, channel) in sum.iter_mut().zip(pixel) {
+                        *total += channel as u32;
+                    }
+                }
+            }
+
+            let symbol = char::from_u32(0x2800 + bits).unwrap_or(' ');
+            let [r, g, b] = sum.map(|total| (total / 8) as u8);
+            buf[(area.x + column, area.y + row)]
+                .set_char(symbol)
+                .set_fg(Color::Rgb(r, g, b));
+        }
+    }
+}
+
+/// The cells covering `region` of an image drawn in `area`.
+fn region_area(region: Region, area: Rect) -> Rect {
+    let scale = |fraction: f32, size: u16| (fraction.clamp(0.0, 1.0) * size as f32).round() as u16;
+
+    let x = scale(region.x, area.width).min(area.width.saturating_sub(1));
+    let y = scale(region.y, area.height).min(area.height.saturating_sub(1));
+    Rect {
+        x: area.x + x,
+        y: area.y + y,
+        width: scale(region.width, area.width).clamp(1, (area.width - x).max(1)),
+        height: scale(region.height, area.height).clamp(1, (area.height - y).max(1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    #[test]
+    fn half_blocks_draw_two_pixels_a_cell() {
+        let mut image = RgbImage::from_pixel(4, 4, Rgb([0, 0, 255]));
+        for x in 0..4 {
+            for y in 0..2 {
+                image.put_pixel(x, y, Rgb([255, 0, 0]));
+            }
+        }
+        let mut buf = Buffer::empty(Rect::new(0, 0, 6, 2));
+
+        let drawn = render_image(&image, ArtStyle::HalfBlock, buf.area, &mut buf);
+
+        assert_eq!(drawn, Rect::new(1, 0, 4, 2));
+        let top = &buf[(1, 0)];
+        assert_eq!(top.symbol(), "▀");
+        assert_eq!(
+            (top.fg, top.bg),
+            (Color::Rgb(255, 0, 0), Color::Rgb(255, 0, 0))
+        );
+        let bottom = &buf[(1, 1)];
+        assert_eq!(
+            (bottom.fg, bottom.bg),
+            (Color::Rgb(0, 0, 255), Color::Rgb(0, 0, 255))
+        );
+    }
+
+    #[test]
+    fn braille_lights_the_brighter_pixels() {
+        let mut image = RgbImage::new(2, 4);
+        image.put_pixel(0, 0, Rgb([255, 255, 255]));
+        image.put_pixel(1, 3, Rgb([255, 255, 255]));
+        let mut buf = Buffer::empty(Rect::new(0, 0, 1, 1));
+
+        render_image(&image, ArtStyle::Braille, buf.area, &mut buf);
+
+        assert_eq!(buf[(0, 0)].symbol(), "⢁");
+    }
+
+    #[test]
+    fn detection_boxes_follow_the_drawn_image() {
+        let drawn = Rect::new(10, 5, 40, 20);
+        let region = Region {
+            x: 0.25,
+            y: 0.5,
+            width: 0.5,
+            height: 0.6,
+        };
+
+        assert_eq!(region_area(region, drawn), Rect::new(20, 15, 20, 10));
+    }
+}
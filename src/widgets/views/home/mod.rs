@@ -3,13 +3,14 @@ use ratatui::{
     buffer::Buffer,
     layout::Rect,
     style::{Color, Style, Stylize},
-    text::Line,
+    text::{Line, Span},
     widgets::{Block, BorderType, Paragraph, Widget, Wrap},
 };
 
 use crate::{
     state::AppState,
-    types::message::{ContentBlock, Role},
+    text_processor::markup::strip_markup,
+    types::message::{ContentBlock, Message, Role},
 };
 
 mod audio_bars;
@@ -29,6 +30,62 @@ impl<'a> HomeViewWidget<'a> {
     }
 }
 
+impl HomeViewWidget<'_> {
+    /// The word of `message` being spoken right now, counting the words of
+    /// its text blocks as displayed.
+    fn spoken_word(&self, message: &Message) -> Option<usize> {
+        let spoken_reply = &self.state.spoken_reply;
+        if !self.state.is_audio_playback_running || spoken_reply.message_id != Some(message._id) {
+            return None;
+        }
+
+        let text = message
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(strip_markup(text)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let words = text.split_whitespace().collect::<Vec<_>>();
+
+        spoken_reply.highlighted_word(
+            &words,
+            spoken_reply.position_ms(self.state.playback_played_ms),
+        )
+    }
+}
+
+/// A line of text with the `highlighted` word styled, `word_index` counting
+/// the words of the lines before it.
+fn highlight_word(
+    line: &str,
+    word_index: &mut usize,
+    highlighted: Option<usize>,
+    style: Style,
+) -> Line<'static> {
+    let mut spans = Vec::new();
+
+    for token in line.split_inclusive(char::is_whitespace) {
+        let word = token.trim_end();
+        if word.is_empty() {
+            spans.push(Span::raw(token.to_string()));
+            continue;
+        }
+
+        if highlighted == Some(*word_index) {
+            spans.push(Span::styled(word.to_string(), style));
+            spans.push(Span::raw(token[word.len()..].to_string()));
+        } else {
+            spans.push(Span::raw(token.to_string()));
+        }
+        *word_index += 1;
+    }
+
+    Line::from(spans)
+}
+
 impl Widget for HomeViewWidget<'_> {
     fn render(self, area: Rect, buf: &mut Buffer) {
         // let layout = Layout::default()
@@ -52,15 +109,10 @@ impl Widget for HomeViewWidget<'_> {
 
         let selected_message_index = self.state.home_view.message_index;
 
-        let assistant_messages = self
-            .state
-            .messages
-            .iter()
-            .filter(|message| message.role == Role::Assistant)
-            .collect::<Vec<_>>();
+        let turns = self.state.turns().collect::<Vec<_>>();
 
-        let selected_message = assistant_messages.get(selected_message_index);
-        let message_count = assistant_messages.len();
+        let selected_message = turns.get(selected_message_index);
+        let message_count = turns.len();
 
         let timestamp = match selected_message {
             Some(message) => {
@@ -76,8 +128,18 @@ impl Widget for HomeViewWidget<'_> {
             None => String::new(),
         };
 
+        let play_hint = match selected_message {
+            Some(message) if message.audio_id.is_some() => "  [p] play",
+            _ => "",
+        };
+
+        let speaker = match selected_message {
+            Some(message) if message.role == Role::User => "You, ",
+            _ => "",
+        };
+
         let nav_line = format!(
-            "[{}/{message_count}] {timestamp}",
+            "[{}/{message_count}] {speaker}{timestamp}{play_hint}",
             selected_message_index + 1
         );
 
@@ -89,11 +151,20 @@ impl Widget for HomeViewWidget<'_> {
         let mut lines = vec![];
 
         if let Some(selected_message) = selected_message {
+            let highlighted = self.spoken_word(selected_message);
+            let highlight_style = Style::default().fg(Color::Black).bg(self.state.color);
+            let mut word_index = 0;
+
             for block in selected_message.content.iter() {
                 match block {
                     ContentBlock::Text { text } => {
-                        for line in text.lines() {
-                            lines.push(Line::from(line));
+                        for line in strip_markup(text).lines() {
+                            lines.push(highlight_word(
+                                line,
+                                &mut word_index,
+                                highlighted,
+                                highlight_style,
+                            ));
                         }
                     }
                     ContentBlock::ToolUse { name, input, .. } => {
@@ -1,3 +1,5 @@
+pub mod camera;
 pub mod chat;
 pub mod home;
 pub mod logs;
+pub mod settings;
@@ -0,0 +1,218 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, BorderType, Paragraph, Widget},
+};
+
+use crate::{
+    audio::devices::{AudioDevices, AudioDirection},
+    services::elevenlabs::voices::VoiceSetting,
+    state::AppState,
+};
+
+#[derive(Default, Debug, Clone)]
+pub struct SettingsViewState {
+    pub devices: AudioDevices,
+    /// Index into the input devices, the output devices, the voices and then
+    /// the voice settings
+    pub selected: usize,
+}
+
+impl SettingsViewState {
+    pub fn device_count(&self) -> usize {
+        self.devices.inputs.len() + self.devices.outputs.len()
+    }
+
+    pub fn item_count(&self, voice_count: usize) -> usize {
+        self.device_count() + voice_count + VoiceSetting::ALL.len()
+    }
+
+    /// Index of the selected voice.
+    pub fn selected_voice(&self, voice_count: usize) -> Option<usize> {
+        self.selected
+            .checked_sub(self.device_count())
+            .filter(|index| *index < voice_count)
+    }
+
+    pub fn selected_voice_setting(&self, voice_count: usize) -> Option<VoiceSetting> {
+        self.selected
+            .checked_sub(self.device_count() + voice_count)
+            .and_then(|index| VoiceSetting::ALL.get(index).copied())
+    }
+
+    pub fn selected_device(&self) -> Option<(AudioDirection, &str)> {
+        let inputs = &self.devices.inputs;
+
+        match inputs.get(self.selected) {
+            Some(name) => Some((AudioDirection::Input, name)),
+            None => self
+                .devices
+                .outputs
+                .get(self.selected - inputs.len())
+                .map(|name| (AudioDirection::Output, name.as_str())),
+        }
+    }
+}
+
+pub struct SettingsViewWidget<'a> {
+    state: &'a AppState,
+}
+
+impl<'a> SettingsViewWidget<'a> {
+    pub fn new(state: &'a AppState) -> Self {
+        Self { state }
+    }
+}
+
+impl Widget for SettingsViewWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .title(" [↑/↓] select  [enter] use  [←/→] adjust  [r] refresh ")
+            .style(Style::default().fg(self.state.color));
+
+        let settings = &self.state.settings_view;
+        let sections = [
+            (
+                "Input devices",
+                &settings.devices.inputs,
+                &self.state.audio_input_device,
+            ),
+            (
+                "Output devices",
+                &settings.devices.outputs,
+                &self.state.audio_output_device,
+            ),
+        ];
+
+        let mut lines = Vec::new();
+        let mut index = 0;
+
+        let highlight = |line: Line<'static>, index: usize, in_use: bool| {
+            if index == settings.selected {
+                line.fg(Color::Black).bg(self.state.color)
+            } else if in_use {
+                line.fg(self.state.color)
+            } else {
+                line.fg(Color::Reset)
+            }
+        };
+
+        for (title, devices, current) in sections {
+            lines.push(Line::from(title).bold());
+
+            if devices.is_empty() {
+                lines.push(Line::from("  none found").fg(Color::DarkGray));
+            }
+
+            for name in devices {
+                let in_use = current.as_ref() == Some(name);
+                let marker = if in_use { "*" } else { " " };
+                let line = Line::from(format!(" {marker} {name}"));
+
+                lines.push(highlight(line, index, in_use));
+
+                index += 1;
+            }
+
+            lines.push(Line::from(""));
+        }
+
+        let voice = &self.state.voice;
+        lines.push(Line::from("Voices").bold());
+
+        for option in &self.state.voices {
+            let in_use = option.id == voice.id;
+            let marker = if in_use { "*" } else { " " };
+            let line = Line::from(format!(" {marker} {}", option.name));
+
+            lines.push(highlight(line, index, in_use));
+            index += 1;
+        }
+
+        lines.push(Line::from(""));
+        lines.push(Line::from("Voice settings").bold());
+
+        for setting in VoiceSetting::ALL {
+            let value = setting.value(&voice.settings);
+            let line = Line::from(format!("   {:<12}{value:.2}", setting.label()));
+
+            lines.push(highlight(line, index, false));
+            index += 1;
+        }
+
+        lines.push(Line::from(""));
+
+        let echo = &self.state.diagnostics.echo;
+        lines.push(Line::from("Echo cancellation").bold());
+        lines.push(match (echo.enabled, echo.last) {
+            (false, _) => Line::from("  off").fg(Color::DarkGray),
+            (true, None) => Line::from("  waiting for the robot to talk").fg(Color::DarkGray),
+            (true, Some(stats)) => Line::from(format!(
+                "  ERL {:.1} dB  ERLE {:.1} dB  delay {:.0} ms",
+                stats.erl_db, stats.erle_db, stats.delay_ms
+            ))
+            .fg(Color::Reset),
+        });
+
+        Paragraph::new(lines).block(block).render(area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_spans_inputs_then_outputs() {
+        let mut settings = SettingsViewState {
+            devices: AudioDevices {
+                inputs: vec![String::from("mic")],
+                outputs: vec![String::from("dac"), String::from("hdmi")],
+            },
+            selected: 0,
+        };
+
+        assert_eq!(settings.device_count(), 3);
+        assert_eq!(
+            settings.selected_device(),
+            Some((AudioDirection::Input, "mic"))
+        );
+
+        settings.selected = 2;
+        assert_eq!(
+            settings.selected_device(),
+            Some((AudioDirection::Output, "hdmi"))
+        );
+
+        settings.selected = 3;
+        assert_eq!(settings.selected_device(), None);
+    }
+
+    #[test]
+    fn voices_and_their_settings_follow_the_devices() {
+        let mut settings = SettingsViewState {
+            devices: AudioDevices {
+                inputs: vec![String::from("mic")],
+                outputs: vec![String::from("dac")],
+            },
+            selected: 1,
+        };
+
+        assert_eq!(settings.item_count(2), 8);
+        assert_eq!(settings.selected_voice(2), None);
+
+        settings.selected = 3;
+        assert_eq!(settings.selected_voice(2), Some(1));
+        assert_eq!(settings.selected_voice_setting(2), None);
+
+        settings.selected = 7;
+        assert_eq!(settings.selected_device(), None);
+        assert_eq!(
+            settings.selected_voice_setting(2),
+            Some(VoiceSetting::Speed)
+        );
+    }
+}